@@ -0,0 +1,539 @@
+//! 响应完整性校验与审计日志
+//!
+//! 对 /v1/messages 与 /v1/chat/completions 的请求体和响应体计算 SHA-256：
+//! 非流式响应的哈希通过 `x-proxy-content-sha256` 响应头返回；流式响应的哈希
+//! 按 SSE 事件的 data 载荷依次拼接增量计算，在最后一个数据帧之后以同名 trailer 返回。
+//! 每个请求追加一行以 HMAC-SHA256 签名的 JSONL 审计记录
+
+use crate::backends::hedge::HEDGE_WINNER_HEADER;
+use crate::error::ProxyError;
+use crate::handlers::REQUEST_BODY_LIMIT;
+use crate::middleware::request_id::current_request_id;
+use crate::monitor::usage_objects;
+use crate::router::Backend;
+use crate::streaming::sse::{SseEvent, SseParser};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{CONTENT_TYPE, TRAILER},
+        HeaderMap, HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures::StreamExt;
+use http_body::Frame;
+use http_body_util::StreamBody;
+use ring::{digest, hmac};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{mpsc, Arc};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 返回响应体 SHA-256（十六进制）的响应头
+pub const CONTENT_SHA256_HEADER: &str = "x-proxy-content-sha256";
+
+/// 需要审计的端点
+const AUDITED_PATHS: &[&str] = &["/v1/messages", "/v1/chat/completions"];
+
+/// 处理器附加到响应扩展中的实际后端，供审计记录归属
+#[derive(Debug, Clone, Copy)]
+pub struct BackendAttribution(pub Backend);
+
+/// 将实际后端附加到响应，供 [`record_audit`] 读取
+pub fn attribute_backend(response: &mut Response, backend: Backend) {
+    response.extensions_mut().insert(BackendAttribution(backend));
+}
+
+/// 追加写入的审计日志及其签名密钥；文件写入在专用线程中完成，不阻塞异步任务
+#[derive(Debug)]
+pub struct AuditLog {
+    writer: mpsc::Sender<WriterCommand>,
+    key: hmac::Key,
+}
+
+#[derive(Debug)]
+enum WriterCommand {
+    Line(String),
+    /// 之前发送的记录全部写入后应答
+    Flush(mpsc::SyncSender<()>),
+}
+
+/// 一条审计记录，签名覆盖除 `signature` 外的全部字段
+#[derive(Debug, Clone, Serialize)]
+struct AuditRecord {
+    timestamp: u64,
+    request_id: Option<String>,
+    path: String,
+    status: u16,
+    model: Option<String>,
+    backend: Option<&'static str>,
+    stream: bool,
+    /// 流式响应是否完整发送（客户端提前断开时为 false）
+    complete: bool,
+    request_sha256: String,
+    response_sha256: String,
+    usage: Option<Value>,
+}
+
+impl AuditLog {
+    pub fn open(path: &str, secret: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (writer, commands) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || write_records(file, commands))?;
+        Ok(Self {
+            writer,
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        })
+    }
+
+    /// 等待已提交的记录全部写入文件，关闭时调用
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::sync_channel(1);
+        if self.writer.send(WriterCommand::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    /// 序列化并签名：对不含 `signature` 的紧凑 JSON（键按字母序）计算 HMAC，
+    /// 校验时去掉 `signature` 重新序列化即可得到相同的输入
+    fn signed_line(&self, record: &AuditRecord) -> String {
+        let mut value = serde_json::to_value(record).unwrap_or_default();
+        let signature = hmac::sign(&self.key, value.to_string().as_bytes());
+        value["signature"] = Value::String(hex(signature.as_ref()));
+        value.to_string()
+    }
+
+    fn append(&self, record: &AuditRecord) {
+        if self.writer.send(WriterCommand::Line(self.signed_line(record))).is_err() {
+            tracing::error!("Audit writer stopped; dropping audit record");
+        }
+    }
+}
+
+/// 写线程：按提交顺序逐行追加记录
+fn write_records(mut file: File, commands: mpsc::Receiver<WriterCommand>) {
+    for command in commands {
+        match command {
+            WriterCommand::Line(line) => {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    tracing::error!("Failed to write audit record: {}", e);
+                }
+            }
+            WriterCommand::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(digest::digest(&digest::SHA256, bytes).as_ref())
+}
+
+/// 计算哈希并写审计记录的中间件，只处理 [`AUDITED_PATHS`]
+pub async fn record_audit(
+    Extension(audit): Extension<Arc<AuditLog>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    if !AUDITED_PATHS.contains(&path.as_str()) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    // 与处理器使用相同的请求体上限；读取失败不是转换失败，不计入 transform_failures_total
+    let body = match axum::body::to_bytes(body, REQUEST_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(e) => {
+            return ProxyError::Transform(format!("Failed to read request body: {}", e))
+                .into_response()
+        }
+    };
+    let model = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|v| v.get("model")?.as_str().map(String::from));
+    let mut record = AuditRecord {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        request_id: current_request_id(),
+        path,
+        status: 0,
+        model,
+        backend: None,
+        stream: false,
+        complete: true,
+        request_sha256: sha256_hex(&body),
+        response_sha256: String::new(),
+        usage: None,
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    record.status = response.status().as_u16();
+    // 对冲请求以实际胜出的后端为准
+    record.backend = response
+        .headers()
+        .get(HEDGE_WINNER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|name| Backend::from_str(name).as_str())
+        .or_else(|| {
+            response
+                .extensions()
+                .get::<BackendAttribution>()
+                .map(|backend| backend.0.as_str())
+        });
+    let is_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+
+    let (mut parts, body) = response.into_parts();
+    if is_stream {
+        record.stream = true;
+        record.complete = false;
+        let mut state = StreamAudit {
+            audit,
+            record,
+            digest: Some(digest::Context::new(&digest::SHA256)),
+            parser: SseParser::default(),
+            usage: Map::new(),
+            errored: false,
+        };
+        parts
+            .headers
+            .insert(TRAILER, HeaderValue::from_static(CONTENT_SHA256_HEADER));
+        let mut upstream = body.into_data_stream();
+        // 字节原样转发，只旁路解析；正常结束后追加携带哈希的 trailer
+        let stream = async_stream::stream! {
+            while let Some(chunk) = upstream.next().await {
+                match &chunk {
+                    Ok(bytes) => state.observe(bytes),
+                    Err(_) => state.errored = true,
+                }
+                yield chunk.map(Frame::data);
+            }
+            if let Some(trailers) = state.finish() {
+                yield Ok(Frame::trailers(trailers));
+            }
+        };
+        return Response::from_parts(parts, Body::new(StreamBody::new(stream)));
+    }
+
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to read response body for audit: {}", e);
+            return ProxyError::Internal(format!("Failed to read response body: {}", e))
+                .into_response();
+        }
+    };
+    record.response_sha256 = sha256_hex(&body);
+    record.usage = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|v| usage_objects(&v).next().cloned().map(Value::Object));
+    if let Ok(value) = HeaderValue::from_str(&record.response_sha256) {
+        parts.headers.insert(CONTENT_SHA256_HEADER, value);
+    }
+    audit.append(&record);
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// 流式响应的增量哈希与用量，流结束或被丢弃（客户端断开）时写记录
+struct StreamAudit {
+    audit: Arc<AuditLog>,
+    record: AuditRecord,
+    digest: Option<digest::Context>,
+    parser: SseParser,
+    /// 各事件中的用量字段合并，后出现的覆盖先出现的
+    usage: Map<String, Value>,
+    /// 上游响应体是否出现过错误块
+    errored: bool,
+}
+
+impl StreamAudit {
+    fn observe(&mut self, bytes: &[u8]) {
+        for event in self.parser.feed(bytes) {
+            self.observe_event(event);
+        }
+    }
+
+    fn observe_event(&mut self, event: SseEvent) {
+        if let Some(digest) = &mut self.digest {
+            digest.update(event.data.as_bytes());
+        }
+        if let Ok(value) = serde_json::from_str::<Value>(&event.data) {
+            for usage in usage_objects(&value) {
+                self.usage.extend(usage.clone());
+            }
+        }
+    }
+
+    /// 流结束时写记录；只有未出现错误块时才算完整，并返回携带哈希的 trailer
+    fn finish(&mut self) -> Option<HeaderMap> {
+        if let Some(event) = self.parser.finish() {
+            self.observe_event(event);
+        }
+        self.record.complete = !self.errored;
+        let hash = self.write_record()?;
+        if self.errored {
+            return None;
+        }
+        let mut trailers = HeaderMap::new();
+        trailers.insert(CONTENT_SHA256_HEADER, HeaderValue::from_str(&hash).ok()?);
+        Some(trailers)
+    }
+
+    /// 计算最终哈希并写记录，只执行一次
+    fn write_record(&mut self) -> Option<String> {
+        let digest = self.digest.take()?;
+        self.record.response_sha256 = hex(digest.finish().as_ref());
+        if !self.usage.is_empty() {
+            self.record.usage = Some(Value::Object(std::mem::take(&mut self.usage)));
+        }
+        self.audit.append(&self.record);
+        Some(self.record.response_sha256.clone())
+    }
+}
+
+impl Drop for StreamAudit {
+    fn drop(&mut self) {
+        self.write_record();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "audit-secret";
+
+    fn temp_log(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "anthropic-proxy-audit-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn records(path: &str) -> Vec<Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    /// 去掉 `signature` 后重新序列化并校验 HMAC
+    fn verify(record: &Value) -> bool {
+        let mut unsigned = record.clone();
+        let signature = unsigned.as_object_mut().unwrap().remove("signature").unwrap();
+        let expected = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, SECRET.as_bytes()),
+            unsigned.to_string().as_bytes(),
+        );
+        signature == hex(expected.as_ref())
+    }
+
+    async fn call(app: Router, path: &str, body: impl Into<Body>) -> Response {
+        app.oneshot(
+            Request::post(path)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.into())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn app(audit: &Arc<AuditLog>, handler: axum::routing::MethodRouter) -> Router {
+        Router::new()
+            .route("/v1/messages", handler.clone())
+            .route("/v1/chat/completions", handler)
+            .layer(axum::middleware::from_fn(record_audit))
+            .layer(Extension(audit.clone()))
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_hash_over_data_payloads() {
+        let log = temp_log("stream");
+        let fixture = include_str!("../tests/fixtures/streaming/anthropic_to_openai/text.sse");
+        let handler = post(move || async move {
+            let mut response = ([(CONTENT_TYPE, "text/event-stream")], fixture).into_response();
+            attribute_backend(&mut response, Backend::Anthropic);
+            response
+        });
+
+        let audit = Arc::new(AuditLog::open(&log, SECRET).unwrap());
+        let response = call(
+            app(&audit, handler),
+            "/v1/messages",
+            r#"{"model":"claude-3-5-sonnet","stream":true}"#,
+        )
+        .await;
+        // 流式响应头发出时哈希尚未算出，改由 trailer 返回
+        assert!(response.headers().get(CONTENT_SHA256_HEADER).is_none());
+        assert_eq!(response.headers()[TRAILER], CONTENT_SHA256_HEADER);
+        let collected = response.into_body().collect().await.unwrap();
+        let trailer = collected.trailers().unwrap()[CONTENT_SHA256_HEADER].clone();
+        let body = collected.to_bytes();
+        assert_eq!(body, fixture.as_bytes());
+        let payloads: String = SseParser::default()
+            .feed(fixture.as_bytes())
+            .into_iter()
+            .map(|event| event.data)
+            .collect();
+        assert_eq!(trailer, sha256_hex(payloads.as_bytes()).as_str());
+
+        audit.flush();
+        let records = records(&log);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(
+            record["response_sha256"],
+            "c326a2474ff3edca0ad4bce6df43bdf3187fd3e487d2d38319d48cd852dc2be5"
+        );
+        assert_eq!(
+            record["request_sha256"],
+            sha256_hex(br#"{"model":"claude-3-5-sonnet","stream":true}"#)
+        );
+        assert_eq!(record["stream"], true);
+        assert_eq!(record["complete"], true);
+        assert_eq!(record["backend"], "anthropic");
+        assert_eq!(record["model"], "claude-3-5-sonnet");
+        assert_eq!(record["usage"]["input_tokens"], 25);
+        assert_eq!(record["usage"]["output_tokens"], 12);
+        assert_eq!(record["response_sha256"], trailer.to_str().unwrap());
+        assert!(verify(record));
+        let _ = std::fs::remove_file(&log);
+    }
+
+    #[tokio::test]
+    async fn test_errored_stream_marked_incomplete() {
+        let log = temp_log("errored");
+        let handler = post(|| async {
+            let chunks: Vec<Result<&'static str, std::io::Error>> = vec![
+                Ok("data: {\"type\":\"ping\"}\n\n"),
+                Err(std::io::Error::other("upstream reset")),
+            ];
+            (
+                [(CONTENT_TYPE, "text/event-stream")],
+                Body::from_stream(futures::stream::iter(chunks)),
+            )
+                .into_response()
+        });
+
+        let audit = Arc::new(AuditLog::open(&log, SECRET).unwrap());
+        let response = call(
+            app(&audit, handler),
+            "/v1/messages",
+            r#"{"model":"claude-3-5-sonnet","stream":true}"#,
+        )
+        .await;
+        assert!(response.into_body().collect().await.is_err());
+
+        audit.flush();
+        let records = records(&log);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["stream"], true);
+        assert_eq!(records[0]["complete"], false);
+        assert!(verify(&records[0]));
+        let _ = std::fs::remove_file(&log);
+    }
+
+    #[tokio::test]
+    async fn test_signed_record_format() {
+        let log = temp_log("json");
+        let response_body =
+            r#"{"id":"chatcmpl-1","usage":{"prompt_tokens":3,"completion_tokens":5}}"#;
+        let handler = post(move || async move {
+            ([(CONTENT_TYPE, "application/json")], response_body).into_response()
+        });
+        let audit = Arc::new(AuditLog::open(&log, SECRET).unwrap());
+        let app = app(&audit, handler);
+
+        let response = call(app.clone(), "/v1/chat/completions", r#"{"model":"gpt-4o"}"#).await;
+        assert_eq!(
+            response.headers()[CONTENT_SHA256_HEADER],
+            sha256_hex(response_body.as_bytes()).as_str()
+        );
+        // 其他端点不审计
+        call(app, "/v1/embeddings", "{}").await;
+
+        audit.flush();
+        let records = records(&log);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        let mut keys: Vec<&str> = record.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "backend",
+                "complete",
+                "model",
+                "path",
+                "request_id",
+                "request_sha256",
+                "response_sha256",
+                "signature",
+                "status",
+                "stream",
+                "timestamp",
+                "usage",
+            ]
+        );
+        assert_eq!(record["path"], "/v1/chat/completions");
+        assert_eq!(record["status"], 200);
+        assert_eq!(record["response_sha256"], sha256_hex(response_body.as_bytes()));
+        assert_eq!(record["usage"]["completion_tokens"], 5);
+        assert_eq!(record["signature"].as_str().unwrap().len(), 64);
+        assert!(verify(record));
+
+        // 篡改任一字段后签名失效
+        let mut tampered = record.clone();
+        tampered["usage"]["completion_tokens"] = Value::from(1);
+        assert!(!verify(&tampered));
+        let _ = std::fs::remove_file(&log);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_body_rejected() {
+        let log = temp_log("oversized");
+        let audit = Arc::new(AuditLog::open(&log, SECRET).unwrap());
+        let body = "x".repeat(REQUEST_BODY_LIMIT + 1);
+
+        let response = call(app(&audit, post(|| async { "ok" })), "/v1/messages", body).await;
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        audit.flush();
+        assert!(records(&log).is_empty());
+        let _ = std::fs::remove_file(&log);
+    }
+}
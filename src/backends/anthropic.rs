@@ -2,28 +2,58 @@
 //!
 //! 处理与 Anthropic API 的通信
 
+use crate::backends::upstream::{retry_after_header, upstream_error};
+use crate::backends::{forwarded_headers, send_with_overload_retry, Backend, Clients};
 use crate::config::Config;
+use crate::context::RequestContext;
 use crate::error::{ProxyError, ProxyResult};
+use crate::log_sanitize::log_json;
 use crate::models::anthropic as models;
+use crate::monitor::TransformFailure;
+use crate::router::RequestFormat;
 use crate::streaming::anthropic_to_openai::create_stream;
+use crate::streaming::simulate;
 use crate::transform;
+use crate::transform::utils::OPENAI_COMPLETION_ID_PREFIX;
 use axum::{
     body::Body,
-    http::{HeaderMap, HeaderValue},
+    http::{HeaderMap, HeaderValue, Method},
     response::{IntoResponse, Response},
     Json,
 };
 use bytes::Bytes;
-use futures::StreamExt;
+use futures::stream::{Stream, StreamExt};
 use reqwest::Client;
 use std::sync::Arc;
-use std::time::Duration;
+
+/// 没有其他来源时使用的 `anthropic-version`
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// 为单个请求指定 `anthropic-version` 的请求头；客户端自己发送了 `anthropic-version` 时不生效
+pub const SET_ANTHROPIC_VERSION_HEADER: &str = "x-proxy-set-anthropic-version";
+
+/// 发往 Anthropic 的 `anthropic-version`，取以下来源中第一个非空值：
+/// 客户端的 `anthropic-version` 头 > `x-proxy-set-anthropic-version` 头 > ANTHROPIC_VERSION > 内置默认值
+pub fn resolve_anthropic_version<'a>(client_headers: &'a HeaderMap, config: &'a Config) -> &'a str {
+    let header = |name: &str| -> Option<&'a str> {
+        client_headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    header("anthropic-version")
+        .or_else(|| header(SET_ANTHROPIC_VERSION_HEADER))
+        .or(config.anthropic_version.as_deref())
+        .unwrap_or(DEFAULT_ANTHROPIC_VERSION)
+}
 
 /// 完全透传原始请求到 Anthropic API（不解析/重新序列化）
 pub async fn forward_raw_request(
     config: Arc<Config>,
-    client: Client,
+    clients: Clients,
     body: Bytes,
+    client_headers: &HeaderMap,
     is_streaming: bool,
 ) -> ProxyResult<Response> {
     let url = config.anthropic_messages_url();
@@ -32,27 +62,40 @@ pub async fn forward_raw_request(
         .as_ref()
         .ok_or_else(|| ProxyError::Config("ANTHROPIC_API_KEY not configured".into()))?;
 
+    // 原始 body 不经解析直接转发，至少确认它是 JSON 对象，避免上游返回难以理解的错误
+    if body.trim_ascii_start().first() != Some(&b'{') {
+        return Err(ProxyError::transform(
+            TransformFailure::InvalidJson,
+            "Request body must be a JSON object",
+        ));
+    }
+
     tracing::debug!("Forwarding raw request to Anthropic: {}", url);
 
     // 直接发送原始 body，不做任何解析
-    let req_builder = client
+    let req_builder = clients.for_backend(Backend::Anthropic)
         .post(&url)
         .body(body)
         .header("Content-Type", "application/json")
         .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .timeout(Duration::from_secs(300));
+        .header("anthropic-version", resolve_anthropic_version(client_headers, &config))
+        .headers(forwarded_headers(&config, client_headers));
 
-    let response = req_builder.send().await?;
+    let response = send_with_overload_retry(&config, req_builder).await?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_default();
         tracing::error!("Anthropic API error ({}): {}", status, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "Anthropic API returned {}: {}",
-            status, error_text
-        )));
+        let message = format!("Anthropic API returned {}: {}", status, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::Anthropic,
+        ));
     }
 
     if is_streaming {
@@ -67,7 +110,7 @@ pub async fn forward_raw_request(
 
         // 直接透传流
         let passthrough_stream = stream.map(|result| {
-            result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+            result.map_err(|e| std::io::Error::other(e.to_string()))
         });
 
         Ok((headers, Body::from_stream(passthrough_stream)).into_response())
@@ -80,12 +123,71 @@ pub async fn forward_raw_request(
     }
 }
 
+/// 透传无需转换的 Anthropic API 请求（Message Batches、Models），`url` 为含路径和查询参数的完整地址
+///
+/// 保留上游的状态码和 Content-Type（批次结果端点返回 JSONL），响应体以流的形式转发
+pub async fn forward_api_request(
+    config: Arc<Config>,
+    clients: Clients,
+    method: Method,
+    url: &str,
+    body: Bytes,
+    client_headers: &HeaderMap,
+) -> ProxyResult<Response> {
+    let api_key = config
+        .anthropic_api_key
+        .as_ref()
+        .ok_or_else(|| ProxyError::Config("ANTHROPIC_API_KEY not configured".into()))?;
+
+    tracing::debug!("Forwarding request to Anthropic: {} {}", method, url);
+
+    let mut req_builder = clients.for_backend(Backend::Anthropic)
+        .request(method, url)
+        .header("x-api-key", api_key)
+        .header("anthropic-version", resolve_anthropic_version(client_headers, &config))
+        .headers(forwarded_headers(&config, client_headers));
+    if !body.is_empty() {
+        req_builder = req_builder
+            .header("Content-Type", "application/json")
+            .body(body);
+    }
+
+    let response = send_with_overload_retry(&config, req_builder).await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let retry_after = retry_after_header(response.headers());
+        let error_text = response.text().await.unwrap_or_default();
+        tracing::error!("Anthropic API error ({}): {}", status, error_text);
+        let message = format!("Anthropic API returned {}: {}", status, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::Anthropic,
+        ));
+    }
+
+    let mut builder = Response::builder().status(response.status().as_u16());
+    if let Some(content_type) = response.headers().get(reqwest::header::CONTENT_TYPE) {
+        builder = builder.header("Content-Type", content_type.as_bytes());
+    }
+    let stream = response
+        .bytes_stream()
+        .map(|result| result.map_err(|e| std::io::Error::other(e.to_string())));
+    builder
+        .body(Body::from_stream(stream))
+        .map_err(|e| ProxyError::Internal(e.to_string()))
+}
+
 /// 透传请求到 Anthropic API（解析后重新序列化，用于需要修改的场景）
 #[allow(dead_code)]
 pub async fn forward_request(
     config: Arc<Config>,
-    client: Client,
+    clients: Clients,
     req: models::AnthropicRequest,
+    client_headers: &HeaderMap,
     is_streaming: bool,
 ) -> ProxyResult<Response> {
     let url = config.anthropic_messages_url();
@@ -96,23 +198,28 @@ pub async fn forward_request(
 
     tracing::debug!("Forwarding to Anthropic: {}", url);
 
-    let req_builder = client
+    let req_builder = clients.for_backend(Backend::Anthropic)
         .post(&url)
         .json(&req)
         .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .timeout(Duration::from_secs(300));
+        .header("anthropic-version", resolve_anthropic_version(client_headers, &config))
+        .headers(forwarded_headers(&config, client_headers));
 
-    let response = req_builder.send().await?;
+    let response = send_with_overload_retry(&config, req_builder).await?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_default();
         tracing::error!("Anthropic API error ({}): {}", status, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "Anthropic API returned {}: {}",
-            status, error_text
-        )));
+        let message = format!("Anthropic API returned {}: {}", status, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::Anthropic,
+        ));
     }
 
     if is_streaming {
@@ -127,7 +234,7 @@ pub async fn forward_request(
 
         // 直接透传流
         let passthrough_stream = stream.map(|result| {
-            result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+            result.map_err(|e| std::io::Error::other(e.to_string()))
         });
 
         Ok((headers, Body::from_stream(passthrough_stream)).into_response())
@@ -143,9 +250,38 @@ pub async fn forward_request(
 /// 处理转换后的非流式请求 (O→A)
 pub async fn handle_transformed_non_streaming(
     config: Arc<Config>,
-    client: Client,
+    clients: Clients,
     anthropic_req: models::AnthropicRequest,
+    client_headers: &HeaderMap,
+    ctx: Arc<RequestContext>,
 ) -> ProxyResult<Response> {
+    let anthropic_resp = send_non_streaming(&config, clients.for_backend(Backend::Anthropic), &anthropic_req, client_headers).await?;
+
+    let mut openai_resp = transform::anthropic_to_openai_response(anthropic_resp, config.multiple_text_blocks)?;
+    if config.strict_openai_shape {
+        openai_resp.fill_null_logprobs();
+    }
+    openai_resp.model = ctx.response_model(&openai_resp.model);
+    openai_resp.id = ctx.response_id(&openai_resp.id, OPENAI_COMPLETION_ID_PREFIX);
+
+    if config.verbose {
+        tracing::trace!(
+            "Transformed OpenAI response: {}",
+            log_json(&openai_resp, &config)
+        );
+    }
+
+    ctx.log_summary("ok");
+    Ok(Json(openai_resp).into_response())
+}
+
+/// 发送非流式请求并解析完整的 Anthropic 响应
+async fn send_non_streaming(
+    config: &Config,
+    client: &Client,
+    anthropic_req: &models::AnthropicRequest,
+    client_headers: &HeaderMap,
+) -> ProxyResult<models::AnthropicResponse> {
     let url = config.anthropic_messages_url();
     let api_key = config
         .anthropic_api_key
@@ -156,21 +292,26 @@ pub async fn handle_transformed_non_streaming(
 
     let req_builder = client
         .post(&url)
-        .json(&anthropic_req)
+        .json(anthropic_req)
         .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .timeout(Duration::from_secs(300));
+        .header("anthropic-version", resolve_anthropic_version(client_headers, config))
+        .headers(forwarded_headers(config, client_headers));
 
-    let response = req_builder.send().await?;
+    let response = send_with_overload_retry(config, req_builder).await?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
         tracing::error!("Anthropic error ({}): {}", status, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "Anthropic returned {}: {}",
-            status, error_text
-        )));
+        let message = format!("Anthropic returned {}: {}", status, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::OpenAI,
+        ));
     }
 
     let anthropic_resp: models::AnthropicResponse = response.json().await?;
@@ -178,27 +319,20 @@ pub async fn handle_transformed_non_streaming(
     if config.verbose {
         tracing::trace!(
             "Received Anthropic response: {}",
-            serde_json::to_string_pretty(&anthropic_resp).unwrap_or_default()
+            log_json(&anthropic_resp, config)
         );
     }
 
-    let openai_resp = transform::anthropic_to_openai_response(anthropic_resp)?;
-
-    if config.verbose {
-        tracing::trace!(
-            "Transformed OpenAI response: {}",
-            serde_json::to_string_pretty(&openai_resp).unwrap_or_default()
-        );
-    }
-
-    Ok(Json(openai_resp).into_response())
+    Ok(anthropic_resp)
 }
 
 /// 处理转换后的流式请求 (O→A)
 pub async fn handle_transformed_streaming(
     config: Arc<Config>,
-    client: Client,
+    clients: Clients,
     anthropic_req: models::AnthropicRequest,
+    client_headers: &HeaderMap,
+    ctx: Arc<RequestContext>,
 ) -> ProxyResult<Response> {
     let url = config.anthropic_messages_url();
     let api_key = config
@@ -208,28 +342,51 @@ pub async fn handle_transformed_streaming(
 
     tracing::debug!("Sending streaming request to Anthropic: {}", url);
 
-    let req_builder = client
+    let req_builder = clients.for_backend(Backend::Anthropic)
         .post(&url)
         .json(&anthropic_req)
         .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .timeout(Duration::from_secs(300));
-
-    let response = req_builder.send().await?;
+        .header("anthropic-version", resolve_anthropic_version(client_headers, &config))
+        .headers(forwarded_headers(&config, client_headers));
+
+    let response = match send_with_overload_retry(&config, req_builder).await {
+        Err(ProxyError::Http(e)) if e.is_connect() && config.fallback_to_non_streaming_on_connect_fail => {
+            tracing::warn!(
+                "Streaming connection to {} failed ({}), retrying once without streaming",
+                url,
+                e
+            );
+            let mut anthropic_req = anthropic_req;
+            anthropic_req.stream = Some(false);
+            let anthropic_resp =
+                send_non_streaming(&config, clients.for_backend(Backend::Anthropic), &anthropic_req, client_headers).await?;
+            return Ok(sse_response(simulate::openai_stream(&anthropic_resp, ctx)));
+        }
+        result => result?,
+    };
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
         tracing::error!("Anthropic error ({}) from {}: {}", status, url, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "Anthropic returned {} from {}: {}",
-            status, url, error_text
-        )));
+        let message = format!("Anthropic returned {} from {}: {}", status, url, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::OpenAI,
+        ));
     }
 
     let stream = response.bytes_stream();
-    let sse_stream = create_stream(stream);
+    Ok(sse_response(create_stream(stream, ctx)))
+}
 
+fn sse_response(
+    sse_stream: impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+) -> Response {
     let mut headers = HeaderMap::new();
     headers.insert(
         "Content-Type",
@@ -238,5 +395,64 @@ pub async fn handle_transformed_streaming(
     headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
     headers.insert("Connection", HeaderValue::from_static("keep-alive"));
 
-    Ok((headers, Body::from_stream(sse_stream)).into_response())
+    (headers, Body::from_stream(sse_stream)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RoutingMode;
+    use crate::test_utils::test_config;
+
+    fn create_test_config() -> Config {
+        Config {
+            routing_mode: RoutingMode::Passthrough,
+            anthropic_base_url: Some("https://api.anthropic.com".to_string()),
+            anthropic_api_key: Some("sk-ant".to_string()),
+            ..test_config()
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_anthropic_version_builtin_default() {
+        let config = create_test_config();
+        assert_eq!(resolve_anthropic_version(&HeaderMap::new(), &config), DEFAULT_ANTHROPIC_VERSION);
+    }
+
+    #[test]
+    fn test_anthropic_version_config_default() {
+        let mut config = create_test_config();
+        config.anthropic_version = Some("2024-01-01".to_string());
+        assert_eq!(resolve_anthropic_version(&HeaderMap::new(), &config), "2024-01-01");
+    }
+
+    #[test]
+    fn test_anthropic_version_request_override() {
+        let mut config = create_test_config();
+        config.anthropic_version = Some("2024-01-01".to_string());
+        let client_headers = headers(&[(SET_ANTHROPIC_VERSION_HEADER, "2024-06-01")]);
+        assert_eq!(resolve_anthropic_version(&client_headers, &config), "2024-06-01");
+    }
+
+    #[test]
+    fn test_anthropic_version_client_header_wins() {
+        let mut config = create_test_config();
+        config.anthropic_version = Some("2024-01-01".to_string());
+        let client_headers = headers(&[
+            ("anthropic-version", "2025-01-01"),
+            (SET_ANTHROPIC_VERSION_HEADER, "2024-06-01"),
+        ]);
+        assert_eq!(resolve_anthropic_version(&client_headers, &config), "2025-01-01");
+
+        // 空值视为未指定
+        let client_headers = headers(&[("anthropic-version", " "), (SET_ANTHROPIC_VERSION_HEADER, "")]);
+        assert_eq!(resolve_anthropic_version(&client_headers, &config), "2024-01-01");
+    }
 }
@@ -0,0 +1,153 @@
+//! 各后端独立的 HTTP 客户端
+//!
+//! 每个后端按自己的 HttpClientSettings 构造一个 `reqwest::Client`，
+//! 例如本地上游可以不走代理并使用较短的超时，远程 OpenAI 走公司代理。
+//! 所有客户端使用同一个 User-Agent，FORWARD_HEADERS 透传的客户端 User-Agent 优先
+
+use crate::config::{default_user_agent, Config, HttpClientSettings};
+use crate::router::Backend;
+use reqwest::{Client, Proxy};
+use std::time::Duration;
+
+/// 每个后端一个 HTTP 客户端，通过 `Extension` 在处理器间共享
+#[derive(Debug, Clone, Default)]
+pub struct Clients {
+    anthropic: Client,
+    openai: Client,
+    upstream: Client,
+}
+
+impl Clients {
+    pub fn from_config(config: &Config) -> reqwest::Result<Self> {
+        let user_agent = config
+            .upstream_user_agent
+            .clone()
+            .unwrap_or_else(default_user_agent);
+        Ok(Self {
+            anthropic: build_client(&config.anthropic_http, &user_agent)?,
+            openai: build_client(&config.openai_http, &user_agent)?,
+            upstream: build_client(&config.upstream_http, &user_agent)?,
+        })
+    }
+
+    /// 发往某个后端的请求使用的客户端
+    pub fn for_backend(&self, backend: Backend) -> &Client {
+        match backend {
+            Backend::Anthropic => &self.anthropic,
+            Backend::OpenAI => &self.openai,
+            Backend::Upstream => &self.upstream,
+        }
+    }
+}
+
+fn build_client(settings: &HttpClientSettings, user_agent: &str) -> reqwest::Result<Client> {
+    let builder = Client::builder()
+        .user_agent(user_agent)
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+        .pool_max_idle_per_host(10);
+    let builder = match settings.proxy.as_deref() {
+        None => builder,
+        Some(proxy) if proxy.eq_ignore_ascii_case("none") => builder.no_proxy(),
+        Some(proxy) => builder.proxy(Proxy::all(proxy)?),
+    };
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_config;
+    use axum::{routing::any, Router};
+
+    /// 启动一个以固定名称回复所有请求的 HTTP 服务，作为某个后端的代理
+    async fn spawn_named_proxy(name: &'static str) -> String {
+        let app = Router::new().fallback(any(move || async move { name }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_each_backend_uses_its_configured_client() {
+        let config = Config {
+            anthropic_http: HttpClientSettings {
+                proxy: Some(spawn_named_proxy("anthropic").await),
+                ..Default::default()
+            },
+            openai_http: HttpClientSettings {
+                proxy: Some(spawn_named_proxy("openai").await),
+                ..Default::default()
+            },
+            upstream_http: HttpClientSettings {
+                proxy: Some(spawn_named_proxy("upstream").await),
+                ..Default::default()
+            },
+            ..test_config()
+        };
+        let clients = Clients::from_config(&config).unwrap();
+
+        for (backend, expected) in [
+            (Backend::Anthropic, "anthropic"),
+            (Backend::OpenAI, "openai"),
+            (Backend::Upstream, "upstream"),
+        ] {
+            // 目标地址无法解析，只有经由该后端配置的代理才能得到回复
+            let body = clients
+                .for_backend(backend)
+                .get("http://backend.invalid/v1/models")
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_default_and_override() {
+        let mock = crate::test_utils::spawn_mock_upstream().await;
+        let url = format!("{}/v1/models/gpt-4o", mock);
+        let user_agent = |client: Client, forwarded: Option<&'static str>| {
+            let url = url.clone();
+            async move {
+                let mut request = client.get(url);
+                if let Some(value) = forwarded {
+                    request = request.header(reqwest::header::USER_AGENT, value);
+                }
+                let echo: serde_json::Value = request.send().await.unwrap().json().await.unwrap();
+                echo["user-agent"].as_str().unwrap().to_string()
+            }
+        };
+
+        let client = build_client(&HttpClientSettings::default(), &default_user_agent()).unwrap();
+        assert_eq!(
+            user_agent(client.clone(), None).await,
+            format!("anthropic-proxy/{} reqwest/0.12", env!("CARGO_PKG_VERSION"))
+        );
+        // 透传的客户端 User-Agent 覆盖默认值
+        assert_eq!(user_agent(client, Some("claude-cli/1.0")).await, "claude-cli/1.0");
+
+        let client = build_client(&HttpClientSettings::default(), "acme-gateway/2").unwrap();
+        assert_eq!(user_agent(client, None).await, "acme-gateway/2");
+    }
+
+    #[test]
+    fn test_invalid_proxy_is_rejected() {
+        let settings = HttpClientSettings {
+            proxy: Some("not a url".to_string()),
+            ..Default::default()
+        };
+        assert!(build_client(&settings, &default_user_agent()).is_err());
+        let settings = HttpClientSettings {
+            proxy: Some("NONE".to_string()),
+            ..Default::default()
+        };
+        assert!(build_client(&settings, &default_user_agent()).is_ok());
+    }
+}
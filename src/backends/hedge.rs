@@ -0,0 +1,249 @@
+//! 对冲请求
+//!
+//! 对延迟敏感的小请求（HEDGE_MODELS），主后端在 HEDGE_AFTER_MS 内没有响应时，
+//! 把同一请求发往另一个 OpenAI 兼容后端，先成功的响应胜出，另一个请求被取消。
+//! 只对冲非流式、且不带工具定义的请求，避免重复执行有副作用的调用
+
+use crate::config::Config;
+use crate::error::ProxyResult;
+use crate::monitor;
+use crate::router::{glob_match, Backend};
+use axum::http::HeaderValue;
+use axum::response::Response;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// 对冲请求实际使用的后端，添加到响应头
+pub const HEDGE_WINNER_HEADER: &str = "x-proxy-hedge-winner";
+
+/// 对冲中胜出的一方
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HedgeWinner {
+    Primary,
+    Secondary,
+}
+
+/// 一次对冲的结果统计
+#[derive(Debug, Clone, Copy)]
+pub struct HedgeReport {
+    pub winner: HedgeWinner,
+    /// 从发出主请求到得到结果的总耗时
+    pub latency: Duration,
+    /// 备用请求自身的耗时
+    pub secondary_latency: Duration,
+}
+
+impl HedgeReport {
+    /// 备用后端胜出时节省的时间：胜出时主请求已等待的时长减去备用请求自身的耗时
+    pub fn saved(&self) -> Option<Duration> {
+        (self.winner == HedgeWinner::Secondary)
+            .then(|| self.latency.saturating_sub(self.secondary_latency))
+    }
+}
+
+/// 请求可对冲时返回备用后端：启用了 HEDGE_AFTER_MS、模型匹配 HEDGE_MODELS、
+/// 非流式、没有工具定义，且另一个 OpenAI 兼容后端已配置
+pub fn hedge_backend(
+    config: &Config,
+    primary: Backend,
+    model: &str,
+    has_tools: bool,
+    is_streaming: bool,
+) -> Option<Backend> {
+    if config.hedge_after_ms == 0 || has_tools || is_streaming {
+        return None;
+    }
+    let model = model.to_lowercase();
+    if !config
+        .hedge_models
+        .iter()
+        .any(|pattern| glob_match(pattern.as_bytes(), model.as_bytes()))
+    {
+        return None;
+    }
+    match primary {
+        Backend::OpenAI if config.base_url.is_some() => Some(Backend::Upstream),
+        Backend::Upstream if config.openai_base_url.is_some() && config.openai_api_key.is_some() => {
+            Some(Backend::OpenAI)
+        }
+        _ => None,
+    }
+}
+
+/// 先发送主请求，`after` 内没有结果时启动备用请求，返回先成功的结果
+///
+/// 一方失败时继续等待另一方；两者都失败时返回后失败的一方的错误。
+/// 返回时未完成的请求随 future 一起被丢弃，底层连接随之取消。
+/// 主请求在 `after` 内完成时不会发出备用请求，此时没有 [`HedgeReport`]
+pub async fn race<P, S, F>(
+    after: Duration,
+    primary: P,
+    secondary: F,
+) -> (ProxyResult<Response>, Option<HedgeReport>)
+where
+    P: Future<Output = ProxyResult<Response>>,
+    F: FnOnce() -> S,
+    S: Future<Output = ProxyResult<Response>>,
+{
+    let started = Instant::now();
+    tokio::pin!(primary);
+    if let Ok(result) = tokio::time::timeout(after, &mut primary).await {
+        return (result, None);
+    }
+
+    let hedged_at = Instant::now();
+    let secondary = secondary();
+    tokio::pin!(secondary);
+
+    let (result, winner) = tokio::select! {
+        result = &mut primary => match result {
+            Ok(response) => (Ok(response), HedgeWinner::Primary),
+            Err(e) => {
+                tracing::warn!("Hedged primary request failed, waiting for secondary: {}", e);
+                (secondary.await, HedgeWinner::Secondary)
+            }
+        },
+        result = &mut secondary => match result {
+            Ok(response) => (Ok(response), HedgeWinner::Secondary),
+            Err(e) => {
+                tracing::warn!("Hedged secondary request failed, waiting for primary: {}", e);
+                (primary.await, HedgeWinner::Primary)
+            }
+        },
+    };
+
+    let report = HedgeReport {
+        winner,
+        latency: started.elapsed(),
+        secondary_latency: hedged_at.elapsed(),
+    };
+    (result, Some(report))
+}
+
+/// 按 HEDGE_AFTER_MS 对冲发送，胜出后端写入响应头并记录日志
+pub async fn send_hedged<P, S, F>(
+    config: &Config,
+    primary_backend: Backend,
+    secondary_backend: Backend,
+    primary: P,
+    secondary: F,
+) -> ProxyResult<Response>
+where
+    P: Future<Output = ProxyResult<Response>>,
+    F: FnOnce() -> S,
+    S: Future<Output = ProxyResult<Response>>,
+{
+    let after = Duration::from_millis(config.hedge_after_ms);
+    let (mut result, report) = race(after, primary, secondary).await;
+    let Some(report) = report else {
+        return result;
+    };
+
+    let (winner, loser) = match report.winner {
+        HedgeWinner::Primary => (primary_backend, secondary_backend),
+        HedgeWinner::Secondary => (secondary_backend, primary_backend),
+    };
+    // 备用后端胜出时，主后端在 latency_ms 之后仍未响应
+    let saved = report.saved();
+    if let Some(saved) = saved {
+        monitor::record_hedge_saved(saved);
+    }
+    tracing::info!(
+        winner = winner.as_str(),
+        cancelled = loser.as_str(),
+        latency_ms = report.latency.as_millis() as u64,
+        secondary_latency_ms = report.secondary_latency.as_millis() as u64,
+        saved_ms = saved.map(|d| d.as_millis() as u64),
+        "Hedged request completed"
+    );
+
+    if let Ok(response) = &mut result {
+        response
+            .headers_mut()
+            .insert(HEDGE_WINNER_HEADER, HeaderValue::from_static(winner.as_str()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ProxyError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// 被丢弃时置位；慢请求在测试期间不会完成，置位即说明被取消
+    struct CancelFlag(Arc<AtomicBool>);
+
+    impl Drop for CancelFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    async fn respond(delay: Duration, body: &'static str) -> ProxyResult<Response> {
+        tokio::time::sleep(delay).await;
+        Ok(Response::new(body.into()))
+    }
+
+    async fn body_of(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fast_primary_is_not_hedged() {
+        let started = Arc::new(AtomicBool::new(false));
+        let flag = started.clone();
+        let (result, report) = race(
+            Duration::from_millis(200),
+            respond(Duration::ZERO, "primary"),
+            move || {
+                flag.store(true, Ordering::SeqCst);
+                respond(Duration::ZERO, "secondary")
+            },
+        )
+        .await;
+
+        assert_eq!(body_of(result.unwrap()).await, "primary");
+        assert!(report.is_none());
+        assert!(!started.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_slow_primary_loses_and_is_cancelled() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let guard = CancelFlag(cancelled.clone());
+        let primary = async move {
+            let _guard = guard;
+            respond(Duration::from_secs(10), "primary").await
+        };
+
+        let (result, report) = race(Duration::from_millis(20), primary, || {
+            respond(Duration::ZERO, "secondary")
+        })
+        .await;
+
+        assert_eq!(body_of(result.unwrap()).await, "secondary");
+        let report = report.unwrap();
+        assert_eq!(report.winner, HedgeWinner::Secondary);
+        assert_eq!(report.saved(), Some(report.latency - report.secondary_latency));
+        assert!(report.saved().unwrap() >= Duration::from_millis(20));
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_failed_secondary_waits_for_primary() {
+        let (result, report) = race(
+            Duration::from_millis(10),
+            respond(Duration::from_millis(50), "primary"),
+            || async { Err(ProxyError::Upstream("boom".into())) },
+        )
+        .await;
+
+        assert_eq!(body_of(result.unwrap()).await, "primary");
+        let report = report.unwrap();
+        assert_eq!(report.winner, HedgeWinner::Primary);
+        assert_eq!(report.saved(), None);
+    }
+}
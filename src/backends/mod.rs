@@ -3,8 +3,136 @@
 //! 负责与各种 LLM API 后端的通信
 
 pub mod anthropic;
+mod clients;
+pub mod hedge;
 pub mod openai;
 pub mod upstream;
 
 // 重新导出 Backend 枚举
 pub use crate::router::Backend;
+pub use clients::Clients;
+
+use crate::config::{Config, ResponseModelMode};
+use crate::backends::upstream::retry_after_header;
+use crate::error::{ProxyError, ProxyResult, STATUS_OVERLOADED};
+use crate::middleware::client_ip::X_FORWARDED_FOR;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::Response;
+use reqwest::RequestBuilder;
+use std::future::Future;
+use std::time::Duration;
+
+/// 永不透传的逐跳/由 HTTP 客户端管理的请求头
+const NEVER_FORWARD: &[&str] = &["host", "content-length", "connection"];
+
+/// 按 FORWARD_HEADERS 白名单提取需要透传到上游的客户端请求头，
+/// 请求关联 ID 头（REQUEST_ID_HEADER）总是透传，开启 FORWARD_CLIENT_IP 时透传
+/// client_ip 中间件整理过的 X-Forwarded-For
+pub fn forwarded_headers(config: &Config, client_headers: &HeaderMap) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let request_id_header = config.request_id_header.to_lowercase();
+
+    for (name, value) in client_headers {
+        let name_str = name.as_str();
+        if NEVER_FORWARD.contains(&name_str) {
+            continue;
+        }
+        if name_str == request_id_header
+            || (config.forward_client_ip && name_str == X_FORWARDED_FOR)
+            || config.forward_headers.iter().any(|h| h == name_str)
+        {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+
+    headers
+}
+
+/// 使用了回退模型时添加到响应的头
+pub const MODEL_FALLBACK_HEADER: &str = "x-proxy-model-fallback";
+
+/// RESPONSE_MODEL_MODE=both 时返回客户端请求中的模型的响应头
+pub const REQUESTED_MODEL_HEADER: &str = "x-proxy-requested-model";
+
+/// RESPONSE_MODEL_MODE=both 时把客户端请求中的模型添加到响应头
+pub fn add_requested_model_header(mode: ResponseModelMode, response: &mut Response, model: &str) {
+    if mode != ResponseModelMode::Both {
+        return;
+    }
+    if let Ok(value) = HeaderValue::from_str(model) {
+        response.headers_mut().insert(REQUESTED_MODEL_HEADER, value);
+    }
+}
+
+/// 发送请求；上游报告模型不存在时按 MODEL_FALLBACKS 依次换用替代模型重试
+///
+/// `send` 以要使用的模型名发送一次请求。流式请求在收到上游成功状态前不会向客户端
+/// 发出任何数据，因此同样可以安全重试
+pub async fn with_model_fallbacks<F, Fut>(config: &Config, model: &str, mut send: F) -> ProxyResult<Response>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = ProxyResult<Response>>,
+{
+    let mut result = send(model.to_string()).await;
+
+    for fallback in config.model_fallbacks_for(model) {
+        let Err(ProxyError::ModelNotFound(message)) = &result else {
+            break;
+        };
+        tracing::warn!(
+            "Model '{}' not available upstream ({}), retrying with fallback '{}'",
+            model,
+            message,
+            fallback
+        );
+
+        result = send(fallback.clone()).await;
+        if let Ok(response) = &mut result {
+            if let Ok(value) = HeaderValue::from_str(fallback) {
+                response.headers_mut().insert(MODEL_FALLBACK_HEADER, value);
+            }
+        }
+    }
+
+    result
+}
+
+/// 上游过载时的基础重试延迟，比限流等待更长，每次重试翻倍
+pub const OVERLOAD_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// 上游过载时的最大重试次数
+const OVERLOAD_MAX_RETRIES: u32 = 2;
+
+/// 发送请求；上游返回 529（过载）时按 Retry-After 等待后重试，
+/// 没有 Retry-After 时从 OVERLOAD_BASE_DELAY 开始指数退避。
+/// 每次等待不超过 MAX_RETRY_AFTER_SECS，为 0 时不重试
+pub async fn send_with_overload_retry(
+    config: &Config,
+    mut req_builder: RequestBuilder,
+) -> ProxyResult<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let retry = (attempt < OVERLOAD_MAX_RETRIES && config.max_retry_after_secs > 0)
+            .then(|| req_builder.try_clone())
+            .flatten();
+        let response = req_builder.send().await?;
+
+        match retry {
+            Some(next) if response.status().as_u16() == STATUS_OVERLOADED => {
+                let delay = retry_after_header(response.headers())
+                    .unwrap_or(OVERLOAD_BASE_DELAY * 2u32.pow(attempt))
+                    .min(Duration::from_secs(config.max_retry_after_secs));
+                tracing::warn!(
+                    "Upstream overloaded, retrying in {}s (attempt {}/{})",
+                    delay.as_secs(),
+                    attempt + 1,
+                    OVERLOAD_MAX_RETRIES
+                );
+                tokio::time::sleep(delay).await;
+                req_builder = next;
+                attempt += 1;
+            }
+            _ => return Ok(response),
+        }
+    }
+}
@@ -2,50 +2,81 @@
 //!
 //! 处理与 OpenAI API 的通信
 
+use crate::backends::upstream::{retry_after_header, upstream_error};
+use crate::backends::{forwarded_headers, send_with_overload_retry, Clients};
 use crate::config::Config;
 use crate::error::{ProxyError, ProxyResult};
-use crate::models::openai as models;
+use crate::router::{Backend, RequestFormat};
 use axum::{
     body::Body,
-    http::{HeaderMap, HeaderValue},
+    http::{header::AUTHORIZATION, HeaderMap, HeaderName, HeaderValue},
     response::{IntoResponse, Response},
 };
+use bytes::Bytes;
 use futures::StreamExt;
-use reqwest::Client;
 use std::sync::Arc;
-use std::time::Duration;
 
-/// 透传请求到 OpenAI API
-pub async fn forward_request(
+/// 完全透传原始请求到 OpenAI API 或通用上游（不解析/重新序列化）
+///
+/// 通用上游（Transform 模式）使用 UPSTREAM_API_KEY，未配置时不带认证头
+pub async fn forward_raw_request(
     config: Arc<Config>,
-    client: Client,
-    req: models::OpenAIRequest,
+    clients: Clients,
+    backend: Backend,
+    body: Bytes,
+    client_headers: &HeaderMap,
     is_streaming: bool,
 ) -> ProxyResult<Response> {
-    let url = config.openai_chat_completions_url();
-    let api_key = config
-        .openai_api_key
-        .as_ref()
-        .ok_or_else(|| ProxyError::Config("OPENAI_API_KEY not configured".into()))?;
+    let (url, auth_headers, label) = match backend {
+        Backend::Upstream => {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = config
+                .api_key
+                .as_deref()
+                .and_then(|key| HeaderValue::from_str(&format!("Bearer {}", key)).ok())
+            {
+                headers.insert(AUTHORIZATION, value);
+            }
+            (config.chat_completions_url(), headers, "Upstream")
+        }
+        _ => {
+            let api_key = config
+                .openai_api_key
+                .as_ref()
+                .ok_or_else(|| ProxyError::Config("OPENAI_API_KEY not configured".into()))?;
+            (
+                config.openai_chat_completions_url(),
+                build_openai_headers(&config, api_key, client_headers),
+                "OpenAI API",
+            )
+        }
+    };
 
-    tracing::debug!("Forwarding to OpenAI: {}", url);
+    tracing::debug!("Forwarding raw request to {}: {}", label, url);
 
-    let req_builder = client
+    // 直接发送原始 body，不做任何解析
+    let req_builder = clients.for_backend(backend)
         .post(&url)
-        .json(&req)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .timeout(Duration::from_secs(300));
+        .body(body)
+        .header("Content-Type", "application/json")
+        .headers(forwarded_headers(&config, client_headers))
+        .headers(auth_headers);
 
-    let response = req_builder.send().await?;
+    let response = send_with_overload_retry(&config, req_builder).await?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_default();
-        tracing::error!("OpenAI API error ({}): {}", status, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "OpenAI API returned {}: {}",
-            status, error_text
-        )));
+        tracing::error!("{} error ({}): {}", label, status, error_text);
+        let message = format!("{} returned {}: {}", label, status, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::OpenAI,
+        ));
     }
 
     if is_streaming {
@@ -59,7 +90,7 @@ pub async fn forward_request(
         headers.insert("Connection", HeaderValue::from_static("keep-alive"));
 
         let passthrough_stream = stream.map(|result| {
-            result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+            result.map_err(|e| std::io::Error::other(e.to_string()))
         });
 
         Ok((headers, Body::from_stream(passthrough_stream)).into_response())
@@ -71,3 +102,191 @@ pub async fn forward_request(
             .unwrap())
     }
 }
+
+/// 原样透传 /v1/embeddings 请求（不做任何转换）
+pub async fn forward_embeddings(
+    config: Arc<Config>,
+    clients: Clients,
+    body: Bytes,
+    client_headers: &HeaderMap,
+) -> ProxyResult<Response> {
+    let (backend, url) = embeddings_backend(&config)?;
+    tracing::debug!("Forwarding embeddings request to {:?} backend: {}", backend, url);
+
+    let mut req_builder = clients.for_backend(backend)
+        .post(&url)
+        .body(body)
+        .header("Content-Type", "application/json")
+        .headers(forwarded_headers(&config, client_headers));
+    req_builder = match (backend, config.openai_api_key.as_deref(), config.api_key.as_deref()) {
+        (Backend::OpenAI, Some(api_key), _) => {
+            req_builder.headers(build_openai_headers(&config, api_key, client_headers))
+        }
+        (Backend::Upstream, _, Some(api_key)) => {
+            req_builder.header(AUTHORIZATION, format!("Bearer {}", api_key))
+        }
+        _ => req_builder,
+    };
+
+    let response = send_with_overload_retry(&config, req_builder).await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let retry_after = retry_after_header(response.headers());
+        let error_text = response.text().await.unwrap_or_default();
+        tracing::error!("Embeddings upstream error ({}): {}", status, error_text);
+        let message = format!("Upstream returned {}: {}", status, error_text);
+        return Err(upstream_error(
+            status,
+            retry_after,
+            &error_text,
+            message,
+            RequestFormat::OpenAI,
+        ));
+    }
+
+    let body = response.bytes().await?;
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap())
+}
+
+/// embeddings 的目标后端：优先 OpenAI 后端（需同时配置地址和密钥），否则使用通用上游
+fn embeddings_backend(config: &Config) -> ProxyResult<(Backend, String)> {
+    if config.openai_base_url.is_some() && config.openai_api_key.is_some() {
+        Ok((Backend::OpenAI, config.openai_embeddings_url()))
+    } else if config.base_url.is_some() {
+        Ok((Backend::Upstream, config.embeddings_url()))
+    } else {
+        Err(ProxyError::Config(
+            "No OpenAI-compatible backend configured for embeddings. \
+            Set OPENAI_BASE_URL + OPENAI_API_KEY or UPSTREAM_BASE_URL."
+                .into(),
+        ))
+    }
+}
+
+/// 客户端可透传到 OpenAI 的认证与组织/项目头
+const FORWARDED_AUTH_HEADERS: &[&str] = &["authorization", "openai-organization", "openai-project"];
+
+/// 构造发往 OpenAI 的认证与组织/项目头
+///
+/// 开启 FORWARD_AUTHORIZATION 时，客户端请求中的同名头优先于配置
+pub fn build_openai_headers(config: &Config, api_key: &str, client_headers: &HeaderMap) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", api_key)) {
+        headers.insert(AUTHORIZATION, value);
+    }
+    if let Some(value) = config
+        .openai_organization
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert(HeaderName::from_static("openai-organization"), value);
+    }
+    if let Some(value) = config
+        .openai_project
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert(HeaderName::from_static("openai-project"), value);
+    }
+
+    if config.forward_authorization {
+        for &name in FORWARDED_AUTH_HEADERS {
+            if let Some(value) = client_headers.get(name) {
+                headers.insert(HeaderName::from_static(name), value.clone());
+            }
+        }
+    }
+
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_config;
+
+    fn create_test_config() -> Config {
+        Config {
+            openai_base_url: Some("https://api.openai.com".to_string()),
+            openai_api_key: Some("sk-proxy".to_string()),
+            ..test_config()
+        }
+    }
+
+    fn client_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer sk-client"));
+        headers.insert("openai-organization", HeaderValue::from_static("org-client"));
+        headers
+    }
+
+    #[test]
+    fn test_build_openai_headers_configured() {
+        let mut config = create_test_config();
+        config.openai_organization = Some("org-proxy".to_string());
+        config.openai_project = Some("proj-proxy".to_string());
+
+        let headers = build_openai_headers(&config, "sk-proxy", &client_headers());
+
+        assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Bearer sk-proxy");
+        assert_eq!(headers.get("openai-organization").unwrap(), "org-proxy");
+        assert_eq!(headers.get("openai-project").unwrap(), "proj-proxy");
+    }
+
+    #[test]
+    fn test_build_openai_headers_forward_authorization() {
+        let mut config = create_test_config();
+        config.openai_organization = Some("org-proxy".to_string());
+        config.forward_authorization = true;
+
+        let headers = build_openai_headers(&config, "sk-proxy", &client_headers());
+
+        assert_eq!(headers.get(AUTHORIZATION).unwrap(), "Bearer sk-client");
+        assert_eq!(headers.get("openai-organization").unwrap(), "org-client");
+        assert!(headers.get("openai-project").is_none());
+    }
+
+    #[test]
+    fn test_forwarded_headers_never_forwards_hop_headers() {
+        let mut config = create_test_config();
+        config.forward_headers = vec![
+            "x-gateway-route".to_string(),
+            "host".to_string(),
+            "connection".to_string(),
+        ];
+        let mut headers = client_headers();
+        headers.insert("x-gateway-route", HeaderValue::from_static("eu-west"));
+        headers.insert("host", HeaderValue::from_static("client.example.com"));
+        headers.insert("connection", HeaderValue::from_static("keep-alive"));
+
+        let forwarded = forwarded_headers(&config, &headers);
+
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded.get("x-gateway-route").unwrap(), "eu-west");
+    }
+
+    #[test]
+    fn test_embeddings_backend_fallback_order() {
+        let mut config = create_test_config();
+        config.base_url = Some("https://upstream.example.com/".to_string());
+        assert_eq!(
+            embeddings_backend(&config).unwrap(),
+            (Backend::OpenAI, "https://api.openai.com/v1/embeddings".to_string())
+        );
+
+        // OpenAI 后端缺少密钥时退回通用上游
+        config.openai_api_key = None;
+        assert_eq!(
+            embeddings_backend(&config).unwrap(),
+            (Backend::Upstream, "https://upstream.example.com/v1/embeddings".to_string())
+        );
+
+        config.base_url = None;
+        assert!(matches!(embeddings_backend(&config), Err(ProxyError::Config(_))));
+    }
+}
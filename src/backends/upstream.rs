@@ -3,51 +3,64 @@
 //! 处理 Anthropic → OpenAI 转换后的请求
 
 use crate::config::Config;
-use crate::error::{ProxyError, ProxyResult};
+use crate::context::RequestContext;
+use crate::error::{ProxyError, ProxyResult, STATUS_OVERLOADED};
+use crate::log_sanitize::log_json;
+use crate::backends::{forwarded_headers, send_with_overload_retry, Clients, OVERLOAD_BASE_DELAY};
 use crate::models::openai as models;
-use crate::router::Backend;
+use crate::router::{Backend, RequestFormat};
 use crate::streaming::openai_to_anthropic::create_stream;
 use crate::transform;
+use crate::transform::utils::ANTHROPIC_MESSAGE_ID_PREFIX;
 use axum::{
     body::Body,
-    http::{HeaderMap, HeaderValue},
+    http::{header::RETRY_AFTER, HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
     Json,
 };
-use reqwest::Client;
+use reqwest::StatusCode;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 /// 处理非流式请求 (A→O)
 pub async fn handle_non_streaming(
     config: Arc<Config>,
-    client: Client,
+    clients: Clients,
     openai_req: models::OpenAIRequest,
     backend: Backend,
+    client_headers: &HeaderMap,
+    ctx: Arc<RequestContext>,
 ) -> ProxyResult<Response> {
     let (url, api_key) = get_backend_config(&config, backend)?;
 
     tracing::debug!("Sending non-streaming request to {}", url);
 
-    let mut req_builder = client
+    let mut req_builder = clients.for_backend(backend)
         .post(&url)
-        .json(&openai_req)
-        .timeout(Duration::from_secs(300));
+        .json(&openai_req);
 
     if let Some(key) = &api_key {
         req_builder = req_builder.header("Authorization", format!("Bearer {}", key));
     }
+    req_builder = req_builder.headers(forwarded_headers(&config, client_headers));
 
-    let response = req_builder.send().await?;
+    let response = send_with_overload_retry(&config, req_builder).await?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_delay(&config, status, response.headers());
+        let server_retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
         tracing::error!("Upstream error ({}): {}", status, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "Upstream returned {}: {}",
-            status, error_text
-        )));
+        wait_retry_after(retry_after).await;
+        let message = format!("Upstream returned {}: {}", status, error_text);
+        return Err(upstream_error(
+            status,
+            server_retry_after,
+            &error_text,
+            message,
+            RequestFormat::Anthropic,
+        ));
     }
 
     let openai_resp: models::OpenAIResponse = response.json().await?;
@@ -55,56 +68,69 @@ pub async fn handle_non_streaming(
     if config.verbose {
         tracing::trace!(
             "Received OpenAI response: {}",
-            serde_json::to_string_pretty(&openai_resp).unwrap_or_default()
+            log_json(&openai_resp, &config)
         );
     }
 
-    let anthropic_resp = transform::openai_to_anthropic(openai_resp)?;
+    let mut anthropic_resp = transform::openai_to_anthropic(openai_resp)?;
+    ctx.restore_tool_names(&mut anthropic_resp);
+    anthropic_resp.model = ctx.response_model(&anthropic_resp.model);
+    anthropic_resp.id = ctx.response_id(&anthropic_resp.id, ANTHROPIC_MESSAGE_ID_PREFIX);
 
     if config.verbose {
         tracing::trace!(
             "Transformed Anthropic response: {}",
-            serde_json::to_string_pretty(&anthropic_resp).unwrap_or_default()
+            log_json(&anthropic_resp, &config)
         );
     }
 
+    ctx.log_summary("ok");
     Ok(Json(anthropic_resp).into_response())
 }
 
 /// 处理流式请求 (A→O)
 pub async fn handle_streaming(
     config: Arc<Config>,
-    client: Client,
+    clients: Clients,
     openai_req: models::OpenAIRequest,
     backend: Backend,
+    client_headers: &HeaderMap,
+    ctx: Arc<RequestContext>,
 ) -> ProxyResult<Response> {
     let (url, api_key) = get_backend_config(&config, backend)?;
 
     tracing::debug!("Sending streaming request to {}", url);
 
-    let mut req_builder = client
+    let mut req_builder = clients.for_backend(backend)
         .post(&url)
-        .json(&openai_req)
-        .timeout(Duration::from_secs(300));
+        .json(&openai_req);
 
     if let Some(key) = &api_key {
         req_builder = req_builder.header("Authorization", format!("Bearer {}", key));
     }
+    req_builder = req_builder.headers(forwarded_headers(&config, client_headers));
 
-    let response = req_builder.send().await?;
+    let response = send_with_overload_retry(&config, req_builder).await?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = retry_after_delay(&config, status, response.headers());
+        let server_retry_after = retry_after_header(response.headers());
         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
         tracing::error!("Upstream error ({}) from {}: {}", status, url, error_text);
-        return Err(ProxyError::Upstream(format!(
-            "Upstream returned {} from {}: {}",
-            status, url, error_text
-        )));
+        wait_retry_after(retry_after).await;
+        let message = format!("Upstream returned {} from {}: {}", status, url, error_text);
+        return Err(upstream_error(
+            status,
+            server_retry_after,
+            &error_text,
+            message,
+            RequestFormat::Anthropic,
+        ));
     }
 
     let stream = response.bytes_stream();
-    let sse_stream = create_stream(stream);
+    let sse_stream = create_stream(stream, ctx);
 
     let mut headers = HeaderMap::new();
     headers.insert(
@@ -131,3 +157,196 @@ fn get_backend_config(config: &Config, backend: Backend) -> ProxyResult<(String,
         _ => Err(ProxyError::Internal("Invalid backend for A→O".into())),
     }
 }
+
+/// 上游返回 429 时按 Retry-After 计算返回错误前的等待时间，上限为 MAX_RETRY_AFTER_SECS
+fn retry_after_delay(config: &Config, status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS || config.max_retry_after_secs == 0 {
+        return None;
+    }
+    let delay = retry_after_header(headers)?;
+    Some(delay.min(Duration::from_secs(config.max_retry_after_secs)))
+}
+
+/// 读取响应中的 Retry-After 头
+pub fn retry_after_header(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// 由代理等待限流结束，客户端无需自行实现重试等待
+async fn wait_retry_after(delay: Option<Duration>) {
+    if let Some(delay) = delay.filter(|d| !d.is_zero()) {
+        tracing::warn!(
+            "Upstream rate limited, waiting {}s before responding to client",
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// 解析 Retry-After 头：整数秒或 HTTP 日期（已过去的日期视为 0）
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+/// 将上游错误响应归类：模型不存在、限流（429）、过载（529 或 `overloaded_error`），其余为一般上游错误
+///
+/// `client_format` 为调用方使用的 API 格式，决定过载错误返回给客户端的状态码
+pub fn upstream_error(
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    error_text: &str,
+    message: String,
+    client_format: RequestFormat,
+) -> ProxyError {
+    if is_model_not_found(status, error_text) {
+        return ProxyError::ModelNotFound(message);
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return ProxyError::UpstreamRateLimited {
+            message,
+            client_format,
+            retry_after_secs: retry_after.map(|d| d.as_secs()),
+        };
+    }
+    if status.as_u16() == STATUS_OVERLOADED || error_text.contains("overloaded_error") {
+        let retry_after = retry_after.unwrap_or(OVERLOAD_BASE_DELAY);
+        return ProxyError::Overloaded {
+            message,
+            client_format,
+            retry_after_secs: retry_after.as_secs().max(1),
+        };
+    }
+    ProxyError::Upstream(message)
+}
+
+/// 判断上游错误是否为"模型不存在"
+///
+/// 兼容 OpenAI (`code: model_not_found`)、OpenRouter (`is not a valid model ID`)、
+/// Anthropic (`not_found_error`) 以及 vLLM/Ollama 等常见的错误文案
+pub fn is_model_not_found(status: StatusCode, error_text: &str) -> bool {
+    if status != StatusCode::NOT_FOUND && status != StatusCode::BAD_REQUEST {
+        return false;
+    }
+
+    let text = error_text.to_lowercase();
+    text.contains("model_not_found")
+        || (text.contains("model")
+            && (text.contains("not found")
+                || text.contains("not_found_error")
+                || text.contains("does not exist")
+                || text.contains("not a valid model")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_model_not_found_openai() {
+        let body = r#"{"error":{"message":"The model `gpt-9` does not exist or you do not have access to it.","type":"invalid_request_error","param":null,"code":"model_not_found"}}"#;
+        assert!(is_model_not_found(StatusCode::NOT_FOUND, body));
+    }
+
+    #[test]
+    fn test_is_model_not_found_openrouter() {
+        let body = r#"{"error":{"message":"foo/bar is not a valid model ID","code":400}}"#;
+        assert!(is_model_not_found(StatusCode::BAD_REQUEST, body));
+    }
+
+    #[test]
+    fn test_is_model_not_found_ignores_other_errors() {
+        let body = r#"{"error":{"message":"max_tokens is too large","code":400}}"#;
+        assert!(!is_model_not_found(StatusCode::BAD_REQUEST, body));
+        assert!(!is_model_not_found(StatusCode::INTERNAL_SERVER_ERROR, "model not found"));
+    }
+
+    #[test]
+    fn test_is_model_not_found_anthropic() {
+        let body = r#"{"type":"error","error":{"type":"not_found_error","message":"model: claude-2.0"}}"#;
+        assert!(is_model_not_found(StatusCode::NOT_FOUND, body));
+    }
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        assert_eq!(
+            parse_retry_after(" 30 ", SystemTime::now()),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after("soon", SystemTime::now()), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after_http_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:45 GMT", now),
+            Some(Duration::from_secs(45))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_upstream_error_status_matrix() {
+        use axum::response::IntoResponse;
+
+        let overloaded = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let status_529 = StatusCode::from_u16(STATUS_OVERLOADED).unwrap();
+
+        for (format, expected) in [(RequestFormat::Anthropic, 529), (RequestFormat::OpenAI, 503)] {
+            let error = upstream_error(status_529, None, overloaded, "overloaded".into(), format);
+            assert!(matches!(
+                error,
+                ProxyError::Overloaded { retry_after_secs: 2, .. }
+            ));
+            assert_eq!(error.into_response().status().as_u16(), expected);
+        }
+
+        // 部分网关以 500/503 包装过载错误
+        let error = upstream_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            Some(Duration::from_secs(7)),
+            overloaded,
+            "overloaded".into(),
+            RequestFormat::Anthropic,
+        );
+        assert!(matches!(
+            error,
+            ProxyError::Overloaded { retry_after_secs: 7, .. }
+        ));
+
+        // 等待 Retry-After 后仍然限流：返回 429 并带回上游的 Retry-After
+        let error = upstream_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(30)),
+            r#"{"error":{"message":"Rate limit reached","code":"rate_limit_exceeded"}}"#,
+            "rate limited".into(),
+            RequestFormat::Anthropic,
+        );
+        assert!(matches!(
+            error,
+            ProxyError::UpstreamRateLimited { retry_after_secs: Some(30), .. }
+        ));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+
+        let not_found = r#"{"type":"error","error":{"type":"not_found_error","message":"model: x"}}"#;
+        assert!(matches!(
+            upstream_error(StatusCode::NOT_FOUND, None, not_found, "x".into(), RequestFormat::Anthropic),
+            ProxyError::ModelNotFound(_)
+        ));
+        assert!(matches!(
+            upstream_error(StatusCode::BAD_GATEWAY, None, "bad", "x".into(), RequestFormat::OpenAI),
+            ProxyError::Upstream(_)
+        ));
+    }
+}
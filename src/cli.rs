@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{builder::PossibleValuesParser, builder::TypedValueParser, Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -26,10 +26,27 @@ pub struct Cli {
     #[arg(short, long)]
     pub verbose: bool,
 
+    /// Log level for the proxy: error, warn, info, debug or trace (overrides DEBUG, VERBOSE and RUST_LOG)
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = PossibleValuesParser::new(["error", "warn", "info", "debug", "trace"])
+            .map(|level| level.parse::<tracing::Level>().unwrap())
+    )]
+    pub log_level: Option<tracing::Level>,
+
+    /// IP address to bind to (overrides BIND_ADDRESS/HOST env var)
+    #[arg(long, value_name = "ADDR")]
+    pub host: Option<String>,
+
     /// Port to listen on (overrides PORT env var)
     #[arg(short, long, value_name = "PORT")]
     pub port: Option<u16>,
 
+    /// Number of tokio worker threads (overrides WORKERS env var; 1 serializes async execution for debugging)
+    #[arg(long, value_name = "N")]
+    pub workers: Option<usize>,
+
     /// Run as background daemon
     #[arg(long)]
     pub daemon: bool,
@@ -47,6 +64,10 @@ pub enum Command {
         #[arg(long, value_name = "FILE", default_value = "/tmp/anthropic-proxy.pid")]
         pid_file: PathBuf,
     },
+    /// Validate the configuration and print it with secrets masked
+    Check,
+    /// Diagnose the configuration and probe each configured backend
+    Doctor,
     /// Check daemon status
     Status {
         /// PID file path
@@ -1,5 +1,15 @@
+use crate::router::{glob_match, Backend};
+use crate::transform::request::anthropic_to_openai::DEFAULT_FILTERED_TOOL_TYPES;
+use crate::transform::schema::DEFAULT_STRIP_KEYWORDS;
 use anyhow::Result;
-use std::{env, fmt, path::PathBuf};
+use axum::http::HeaderName;
+use std::{
+    collections::HashMap,
+    env, fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 
 /// 路由模式
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
@@ -27,19 +37,596 @@ impl fmt::Display for RoutingMode {
 }
 
 impl RoutingMode {
+    /// 未知取值返回错误，由调用方决定如何提示
+    pub fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "transform" | "translate" => Ok(RoutingMode::Transform),
+            "passthrough" | "anthropic" | "forward" | "direct" => Ok(RoutingMode::Passthrough),
+            "auto" => Ok(RoutingMode::Auto),
+            "gateway" => Ok(RoutingMode::Gateway),
+            _ => Err(format!(
+                "unknown ROUTING_MODE '{}' (expected transform, passthrough, auto or gateway)",
+                s
+            )),
+        }
+    }
+
+    /// 来自其他代理命名习惯的别名，返回应改用的取值
+    pub fn deprecated_alias(s: &str) -> Option<&'static str> {
+        match s.trim().to_lowercase().as_str() {
+            "translate" => Some("transform"),
+            "forward" | "direct" => Some("passthrough"),
+            _ => None,
+        }
+    }
+}
+
+/// A→O 转换时工具定义的 strict 模式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToolsStrictMode {
+    /// 不设置 strict（默认）
+    #[default]
+    Off,
+    /// 仅当 schema 已满足 strict 约束时设置
+    Auto,
+    /// 始终设置 strict，必要时改写 schema
+    Force,
+}
+
+impl fmt::Display for ToolsStrictMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolsStrictMode::Off => write!(f, "off"),
+            ToolsStrictMode::Auto => write!(f, "auto"),
+            ToolsStrictMode::Force => write!(f, "force"),
+        }
+    }
+}
+
+impl ToolsStrictMode {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "auto" => ToolsStrictMode::Auto,
+            "force" => ToolsStrictMode::Force,
+            _ => ToolsStrictMode::Off,
+        }
+    }
+}
+
+/// 转换后的响应中 model 字段的取值（模型被 COMPLETION_MODEL 等覆盖时与请求不同）
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ResponseModelMode {
+    /// 上游实际使用的模型（默认）
+    #[default]
+    Upstream,
+    /// 客户端请求中的模型
+    Requested,
+    /// 上游模型，另以 `x-proxy-requested-model` 头返回请求中的模型
+    Both,
+}
+
+impl fmt::Display for ResponseModelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseModelMode::Upstream => write!(f, "upstream"),
+            ResponseModelMode::Requested => write!(f, "requested"),
+            ResponseModelMode::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl ResponseModelMode {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "requested" => ResponseModelMode::Requested,
+            "both" => ResponseModelMode::Both,
+            _ => ResponseModelMode::Upstream,
+        }
+    }
+}
+
+/// A→O 转换时历史消息中 thinking 块的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ThinkingInHistory {
+    /// 丢弃 thinking 块（默认）
+    #[default]
+    Strip,
+    /// 将 assistant 的 thinking 块转换为 `<thinking>` 标签包裹的文本
+    Wrap,
+    /// 将 assistant 的 thinking 块放入 `reasoning_content` 字段
+    Passthrough,
+}
+
+impl fmt::Display for ThinkingInHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThinkingInHistory::Strip => write!(f, "strip"),
+            ThinkingInHistory::Wrap => write!(f, "wrap"),
+            ThinkingInHistory::Passthrough => write!(f, "passthrough"),
+        }
+    }
+}
+
+impl ThinkingInHistory {
     pub fn from_str(s: &str) -> Self {
         match s.to_lowercase().as_str() {
-            "passthrough" | "anthropic" => RoutingMode::Passthrough,
-            "auto" => RoutingMode::Auto,
-            "gateway" => RoutingMode::Gateway,
-            _ => RoutingMode::Transform,
+            "wrap" => ThinkingInHistory::Wrap,
+            "passthrough" => ThinkingInHistory::Passthrough,
+            _ => ThinkingInHistory::Strip,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// O→A 转换时 `developer` 角色消息的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeveloperMessageHandling {
+    /// 追加到 Anthropic system 提示（默认）
+    #[default]
+    AsSystem,
+    /// 作为 user 消息发送
+    AsUser,
+    /// 丢弃
+    Drop,
+}
+
+impl fmt::Display for DeveloperMessageHandling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeveloperMessageHandling::AsSystem => write!(f, "as_system"),
+            DeveloperMessageHandling::AsUser => write!(f, "as_user"),
+            DeveloperMessageHandling::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+impl DeveloperMessageHandling {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "as_user" | "user" => DeveloperMessageHandling::AsUser,
+            "drop" => DeveloperMessageHandling::Drop,
+            _ => DeveloperMessageHandling::AsSystem,
+        }
+    }
+}
+
+/// 请求校验级别（VALIDATION）
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ValidationMode {
+    /// 额外按调用方的 API 格式检查上游通常会拒绝的问题（如 Anthropic 的角色交替）
+    Strict,
+    /// 只拒绝所有上游都会拒绝的请求（默认）
+    #[default]
+    Lenient,
+    /// 不校验，原样交给上游
+    Off,
+}
+
+impl fmt::Display for ValidationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationMode::Strict => write!(f, "strict"),
+            ValidationMode::Lenient => write!(f, "lenient"),
+            ValidationMode::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl ValidationMode {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "strict" => ValidationMode::Strict,
+            "off" | "none" | "false" => ValidationMode::Off,
+            _ => ValidationMode::Lenient,
+        }
+    }
+}
+
+/// 后端基础 URL 以 `/v1` 结尾时的处理方式（URL_V1_CHECK）
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UrlV1Check {
+    /// 启动时和 doctor 中给出警告（默认）
+    #[default]
+    Warn,
+    /// 加载配置失败
+    Error,
+    /// 不检查，用于 `/v1` 确实属于基础路径的上游
+    Off,
+}
+
+impl fmt::Display for UrlV1Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlV1Check::Warn => write!(f, "warn"),
+            UrlV1Check::Error => write!(f, "error"),
+            UrlV1Check::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl UrlV1Check {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "error" => UrlV1Check::Error,
+            "off" | "none" | "false" => UrlV1Check::Off,
+            _ => UrlV1Check::Warn,
+        }
+    }
+}
+
+/// 以 `/v1` 结尾（忽略末尾的 `/`）的基础 URL 去掉 `/v1` 后的部分，请求路径会重复 `/v1`
+pub fn strip_v1_suffix(url: &str) -> Option<&str> {
+    url.trim_end_matches('/').strip_suffix("/v1")
+}
+
+/// URL_V1_CHECK=error 时，任一后端基础 URL 以 `/v1` 结尾即加载失败；warn 由启动时的静态检查输出
+fn check_v1_suffixes(mode: UrlV1Check, urls: &[(&str, &Option<String>)]) -> Result<()> {
+    if mode != UrlV1Check::Error {
+        return Ok(());
+    }
+    for (name, url) in urls {
+        if let Some(base) = url.as_deref().and_then(strip_v1_suffix) {
+            return Err(anyhow::anyhow!(
+                "{} ends with '/v1', so requests would go to {}/v1/v1/...\n\
+                Set it to '{}', or set URL_V1_CHECK=warn or off if '/v1' is part of the base path.",
+                name,
+                base,
+                base
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// 默认 User-Agent 中的 reqwest 版本；reqwest 不提供版本常量，需与 Cargo.toml 中的依赖保持一致
+const REQWEST_VERSION: &str = "0.12";
+
+/// 发往上游请求的默认 User-Agent
+pub fn default_user_agent() -> String {
+    format!("anthropic-proxy/{} reqwest/{}", env!("CARGO_PKG_VERSION"), REQWEST_VERSION)
+}
+
+/// 后端 HTTP 客户端的默认整体超时秒数
+pub const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 300;
+
+/// 后端 HTTP 客户端的默认连接超时秒数
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// 单个后端的 HTTP 客户端设置（`<后端>_TIMEOUT_SECS` 等），每个后端使用独立的客户端
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpClientSettings {
+    /// 整个请求（含流式响应体）的超时秒数
+    pub timeout_secs: u64,
+    /// 建立连接的超时秒数
+    pub connect_timeout_secs: u64,
+    /// 代理地址；None 时沿用 HTTPS_PROXY 等系统代理环境变量，`none` 表示不使用任何代理
+    pub proxy: Option<String>,
+}
+
+impl Default for HttpClientSettings {
+    fn default() -> Self {
+        Self {
+            timeout_secs: DEFAULT_HTTP_TIMEOUT_SECS,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            proxy: None,
+        }
+    }
+}
+
+impl fmt::Display for HttpClientSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 代理地址可能带有认证信息，只显示去掉用户名和密码后的地址
+        let proxy = self.proxy.as_deref().map(|proxy| match reqwest::Url::parse(proxy) {
+            Ok(mut url) => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.to_string()
+            }
+            Err(_) => proxy.to_string(),
+        });
+        write!(
+            f,
+            "timeout={}s connect_timeout={}s proxy={}",
+            self.timeout_secs,
+            self.connect_timeout_secs,
+            proxy.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+impl HttpClientSettings {
+    /// 读取 `<prefix>_TIMEOUT_SECS`、`<prefix>_CONNECT_TIMEOUT_SECS` 和 `<prefix>_HTTP_PROXY`
+    fn from_env(prefix: &str) -> Self {
+        let secs = |name: &str, default: u64| {
+            env::var(format!("{}_{}", prefix, name))
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n: &u64| n > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            timeout_secs: secs("TIMEOUT_SECS", DEFAULT_HTTP_TIMEOUT_SECS),
+            connect_timeout_secs: secs("CONNECT_TIMEOUT_SECS", DEFAULT_CONNECT_TIMEOUT_SECS),
+            proxy: env::var(format!("{}_HTTP_PROXY", prefix))
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+/// 加载配置的过程信息
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// 实际加载的 .env 文件，None 表示只使用环境变量
+    pub source: Option<PathBuf>,
+    /// 配置警告
+    pub warnings: Vec<String>,
+}
+
+impl LoadReport {
+    /// 输出到日志，须在 tracing 初始化之后调用
+    pub fn log(&self) {
+        match &self.source {
+            Some(path) => tracing::info!(path = %path.display(), "Loaded config file"),
+            None => tracing::info!("No .env file found, using environment variables only"),
+        }
+        for warning in &self.warnings {
+            tracing::warn!("{}", warning);
+        }
+    }
+}
+
+/// Anthropic 非流式响应包含多个文本块时转换为 OpenAI 响应的方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MultipleTextBlocksMode {
+    /// 以空行拼接为一条消息（默认）
+    #[default]
+    Concatenate,
+    /// 只保留第一个文本块
+    FirstOnly,
+    /// 每个文本块作为一个独立的 choice（类似 `n` > 1 的响应）
+    AllChoices,
+}
+
+impl fmt::Display for MultipleTextBlocksMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipleTextBlocksMode::Concatenate => write!(f, "concatenate"),
+            MultipleTextBlocksMode::FirstOnly => write!(f, "first_only"),
+            MultipleTextBlocksMode::AllChoices => write!(f, "all_choices"),
+        }
+    }
+}
+
+impl MultipleTextBlocksMode {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "first_only" | "first" => MultipleTextBlocksMode::FirstOnly,
+            "all_choices" | "choices" => MultipleTextBlocksMode::AllChoices,
+            _ => MultipleTextBlocksMode::Concatenate,
+        }
+    }
+}
+
+/// A→O 转换时工具 schema 的清理档位
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SchemaProfile {
+    /// 最小清理（默认）
+    #[default]
+    Minimal,
+    /// 内联 $ref、展开组合关键字并移除不支持的关键字
+    Aggressive,
+}
+
+impl fmt::Display for SchemaProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaProfile::Minimal => write!(f, "minimal"),
+            SchemaProfile::Aggressive => write!(f, "aggressive"),
+        }
+    }
+}
+
+impl SchemaProfile {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "aggressive" => SchemaProfile::Aggressive,
+            _ => SchemaProfile::Minimal,
+        }
+    }
+}
+
+/// 模型回退规则：上游报告模型不存在时依次尝试的替代模型
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelFallback {
+    /// 模型名或 `*`/`?` 通配符（小写）
+    pub pattern: String,
+    pub fallbacks: Vec<String>,
+}
+
+impl ModelFallback {
+    /// 解析 `模型=替代1,替代2;模型2=替代3` 格式的规则列表，忽略格式错误的条目
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        value
+            .split(';')
+            .filter_map(|entry| {
+                let (pattern, fallbacks) = entry.split_once('=')?;
+                let pattern = pattern.trim().to_lowercase();
+                let fallbacks: Vec<String> = fallbacks
+                    .split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect();
+                (!pattern.is_empty() && !fallbacks.is_empty()).then_some(Self { pattern, fallbacks })
+            })
+            .collect()
+    }
+}
+
+/// 默认的按模型 max_tokens 下限：o 系列模型接受低于全局下限的值
+pub const DEFAULT_MIN_MAX_TOKENS_PER_MODEL: [(&str, u32); 2] = [("o1", 1), ("o3", 1)];
+
+/// 解析 `模型=下限,模型2=下限` 格式的按模型下限，在默认值基础上添加或覆盖，忽略格式错误的条目
+pub fn parse_min_max_tokens_per_model(value: &str) -> HashMap<String, u32> {
+    let mut limits: HashMap<String, u32> = DEFAULT_MIN_MAX_TOKENS_PER_MODEL
+        .iter()
+        .map(|&(model, min)| (model.to_string(), min))
+        .collect();
+    limits.extend(parse_per_model_values(value));
+    limits
+}
+
+/// 解析 `模型=token 数,模型2=token 数` 格式的按模型上下文窗口，忽略格式错误的条目
+pub fn parse_context_window_limits(value: &str) -> HashMap<String, u32> {
+    parse_per_model_values(value).collect()
+}
+
+/// `模型=数值` 条目，模型名转为小写
+fn parse_per_model_values(value: &str) -> impl Iterator<Item = (String, u32)> + '_ {
+    value.split(',').filter_map(|entry| {
+        let (model, n) = entry.split_once('=')?;
+        let model = model.trim().to_lowercase();
+        match (model.is_empty(), n.trim().parse()) {
+            (false, Ok(n)) => Some((model, n)),
+            _ => None,
+        }
+    })
+}
+
+/// 按模型取值：忽略 `provider/` 前缀后取最长匹配的键，键也匹配 `名称-` 开头的变体
+fn per_model_value(values: &HashMap<String, u32>, model: &str) -> Option<u32> {
+    let model = model.to_lowercase();
+    let name = model.rsplit('/').next().unwrap_or(&model);
+    values
+        .iter()
+        .filter(|(key, _)| {
+            name.strip_prefix(key.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+        })
+        .max_by_key(|(key, _)| key.len())
+        .map(|(_, &n)| n)
+}
+
+/// 模型限流规则：每个时间窗口内允许的请求数
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    /// 模型名或 `*`/`?` 通配符（小写）
+    pub pattern: String,
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    /// 解析 `模型=次数/单位,模型2=次数/单位` 格式的规则列表（单位为 sec/min/hour），忽略格式错误的条目
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        value
+            .split(',')
+            .filter_map(|entry| {
+                let (pattern, limit) = entry.split_once('=')?;
+                let pattern = pattern.trim().to_lowercase();
+                let (requests, unit) = limit.split_once('/')?;
+                let requests: u32 = requests.trim().parse().ok()?;
+                let period = match unit.trim().to_lowercase().as_str() {
+                    "s" | "sec" | "second" => Duration::from_secs(1),
+                    "m" | "min" | "minute" => Duration::from_secs(60),
+                    "h" | "hour" => Duration::from_secs(3600),
+                    _ => return None,
+                };
+                (!pattern.is_empty() && requests > 0).then_some(Self {
+                    pattern,
+                    requests,
+                    period,
+                })
+            })
+            .collect()
+    }
+}
+
+/// 模型单价（美元 / 百万 token），用于监控面板的费用统计
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelPrice {
+    /// 模型名或 `*`/`?` 通配符（小写）
+    pub pattern: String,
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+impl ModelPrice {
+    /// 解析 `模型=输入单价:输出单价,模型2=...` 格式的列表，忽略格式错误的条目
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        value
+            .split(',')
+            .filter_map(|entry| {
+                let (pattern, prices) = entry.split_once('=')?;
+                let pattern = pattern.trim().to_lowercase();
+                let (input, output) = prices.split_once(':')?;
+                let input_per_mtok: f64 = input.trim().parse().ok()?;
+                let output_per_mtok: f64 = output.trim().parse().ok()?;
+                let valid = |price: f64| price.is_finite() && price >= 0.0;
+                (!pattern.is_empty() && valid(input_per_mtok) && valid(output_per_mtok)).then_some(Self {
+                    pattern,
+                    input_per_mtok,
+                    output_per_mtok,
+                })
+            })
+            .collect()
+    }
+}
+
+/// IP 网段（CIDR），不带前缀长度的单个地址视为 /32 或 /128
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (value.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    /// 地址是否在网段内；IPv4 映射的 IPv6 地址（`::ffff:a.b.c.d`）按 IPv4 比较
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[derive(Clone)]
 pub struct Config {
+    /// 监听地址（IP），默认 0.0.0.0
+    pub host: String,
     pub port: u16,
+    /// tokio 工作线程数，None 表示使用默认值（每个 CPU 一个线程）
+    pub workers: Option<usize>,
+    /// 单独提供 /metrics 与 /health 的端口，None 表示不提供 /metrics
+    pub metrics_port: Option<u16>,
 
     // 路由配置
     pub routing_mode: RoutingMode,
@@ -47,87 +634,351 @@ pub struct Config {
     // Anthropic 后端配置
     pub anthropic_base_url: Option<String>,
     pub anthropic_api_key: Option<String>,
+    /// 注入到发往 Anthropic 的请求中的固定 `metadata.user_id`
+    pub anthropic_metadata_user_id: Option<String>,
+    /// 客户端和请求头都没有指定时发往 Anthropic 的 `anthropic-version`
+    pub anthropic_version: Option<String>,
 
     // OpenAI 后端配置
     pub openai_base_url: Option<String>,
     pub openai_api_key: Option<String>,
+    pub openai_organization: Option<String>,
+    pub openai_project: Option<String>,
+    /// 透传客户端的 Authorization 及 OpenAI-Organization/OpenAI-Project 头到 OpenAI 后端
+    pub forward_authorization: bool,
 
     // 转换后端配置（兼容现有）
     pub base_url: Option<String>,
     pub api_key: Option<String>,
+    /// 基础 URL 以 `/v1` 结尾时的处理方式
+    pub url_v1_check: UrlV1Check,
+    /// 通用上游的 API 路径前缀（如 `/api/v1`），未设置时为 `/v1`，空字符串表示没有前缀
+    pub upstream_path_prefix: Option<String>,
+    /// 通用上游的对话补全路径（如 Ollama 的 `/chat`），未设置时为 `/chat/completions`
+    pub upstream_chat_completions_path: Option<String>,
+
+    // 请求头透传
+    /// 透传到上游的客户端请求头（小写），host/content-length/connection 永不透传
+    pub forward_headers: Vec<String>,
+    /// 覆盖发往所有后端请求的默认 User-Agent（UPSTREAM_USER_AGENT）
+    pub upstream_user_agent: Option<String>,
+    /// 受信任的反向代理网段（TRUSTED_PROXIES），来自这些地址的请求按 X-Forwarded-For 确定客户端 IP
+    pub trusted_proxies: Vec<Cidr>,
+    /// 把整理后的 X-Forwarded-For 链转发到上游（FORWARD_CLIENT_IP）
+    pub forward_client_ip: bool,
+    /// 请求关联 ID 使用的请求头名称，读取自客户端请求并回写到响应和上游请求
+    pub request_id_header: String,
+    /// 请求体未设置 `stream` 时，`Accept: text/event-stream` 视为请求流式响应
+    pub stream_from_accept: bool,
+    /// 路由和转发前从模型名中去掉的前缀（STRIP_MODEL_PREFIX，如 `openrouter/`）
+    pub strip_model_prefixes: Vec<String>,
+
+    // 自动路由配置（Auto/Gateway 模式）
+    /// 路由到 Anthropic 的模型匹配规则（子串或 `*`/`?` 通配符），优先于内置规则
+    pub anthropic_model_patterns: Vec<String>,
+    /// 路由到 OpenAI 的模型匹配规则，优先于内置规则
+    pub openai_model_patterns: Vec<String>,
+    /// 所有规则都不匹配时使用的后端
+    pub default_backend: Backend,
+    /// Transform 模式下拒绝 /v1/chat/completions，而不是透传到上游
+    pub transform_reject_openai: bool,
 
     // 模型路由配置
     pub reasoning_model: Option<String>,
     pub completion_model: Option<String>,
+    /// 推理模型在上游不存在时，回退到 completion/原始模型重试一次
+    pub reasoning_model_fallback: bool,
+    /// 上游报告模型不存在时按顺序尝试的替代模型（MODEL_FALLBACKS）
+    pub model_fallbacks: Vec<ModelFallback>,
+    /// 主后端超过该毫秒数未响应时向另一个 OpenAI 兼容后端发出对冲请求（0 表示关闭）
+    pub hedge_after_ms: u64,
+    /// 允许对冲的模型通配符（小写）
+    pub hedge_models: Vec<String>,
+
+    // 转换行为配置
+    /// O→A 转换时合并相邻的同角色消息
+    pub merge_consecutive_messages: bool,
+    /// A→O 转换时 max_tokens 的下限（0 表示不限制）
+    pub min_max_tokens: u32,
+    /// 按模型覆盖 min_max_tokens（小写模型名，也匹配 `名称-` 开头的变体）
+    pub min_max_tokens_per_model: HashMap<String, u32>,
+    /// A→O 转换后按模型预检的上下文窗口（CONTEXT_WINDOW_LIMITS，小写模型名，匹配规则同上），空表示不检查
+    pub context_window_limits: HashMap<String, u32>,
+    /// A→O 转换时工具定义的 strict 模式
+    pub tools_strict_mode: ToolsStrictMode,
+    /// A→O 转换时丢弃的 Anthropic 内置工具类型（如 computer use 工具）
+    pub filtered_tool_types: Vec<String>,
+    /// A→O 转换时按名称去重工具定义，保留最后一个（DEDUPLICATE_TOOLS）
+    pub deduplicate_tools: bool,
+    /// 转换时允许的最大工具数量（MAX_TOOLS），None 表示不限制
+    pub max_tools: Option<usize>,
+    /// 转换时工具参数 schema 允许的最大嵌套深度（MAX_SCHEMA_DEPTH），None 表示不限制
+    pub max_schema_depth: Option<usize>,
+    /// 合并到每个请求停止序列中的默认停止序列（DEFAULT_STOP）
+    pub default_stop: Vec<String>,
+    /// A→O 转换时系统提示包含该标记则强制 OpenAI JSON 模式（JSON_MODE_SYSTEM_MARKER）
+    pub json_mode_system_marker: Option<String>,
+    /// O→A 转换时以 400 拒绝带有 Anthropic 不支持的 OpenAI 专有参数的请求，而不是丢弃它们
+    pub strict_params: bool,
+    /// 转发前的请求校验级别
+    pub validation: ValidationMode,
+    /// 内容策略规则文件（POLICY_RULES_FILE），收到 SIGHUP 时重新加载
+    pub policy_rules_file: Option<String>,
+    /// O→A 转换时把音频输入替换为文本占位，而不是以 400 拒绝请求
+    pub audio_input_placeholder: bool,
+    /// O→A 转换时 `developer` 角色消息的处理方式
+    pub developer_message_handling: DeveloperMessageHandling,
+    /// A→O 转换时历史消息中 thinking 块的处理方式
+    pub thinking_in_history: ThinkingInHistory,
+    /// A→O 非流式响应中多个文本块的处理方式
+    pub multiple_text_blocks: MultipleTextBlocksMode,
+    /// 转换出的 OpenAI 响应和流式 chunk 的每个 choice 都带 `"logprobs": null`，供严格校验的客户端使用
+    pub strict_openai_shape: bool,
+    /// 转换后的响应中 model 字段的取值（RESPONSE_MODEL_MODE）
+    pub response_model_mode: ResponseModelMode,
+    /// 转换后的响应 id 不符合目标格式前缀（`msg_` / `chatcmpl-`）时改写
+    pub normalize_response_ids: bool,
+    /// A→O 流式响应中承载 thinking 增量的字段名（如 reasoning、reasoning_content）
+    pub reasoning_field: String,
+    /// OpenAI 后端使用的 schema 清理档位
+    pub openai_schema_profile: SchemaProfile,
+    /// 通用上游使用的 schema 清理档位
+    pub upstream_schema_profile: SchemaProfile,
+    /// aggressive 档位移除的关键字
+    pub schema_strip_keywords: Vec<String>,
+    /// aggressive 档位是否将 "类型 + null" 的 anyOf/oneOf 合并为 nullable
+    pub schema_collapse_nullable: bool,
+
+    // 上游限流
+    /// 上游返回 429 且带 Retry-After 时，返回错误前最多等待的秒数（0 表示不等待）
+    pub max_retry_after_secs: u64,
+    /// 流式响应中两次收到上游数据之间允许的最长间隔秒数，超过后以错误事件结束流（0 表示不限制）
+    pub stream_idle_timeout_secs: u64,
+    /// 转换流中合并连续文本/参数增量的窗口毫秒数（0 表示不合并）
+    pub stream_coalesce_ms: u64,
+    /// O→A 流式请求无法连接 Anthropic 时，改用非流式请求重试一次并模拟流式输出
+    pub fallback_to_non_streaming_on_connect_fail: bool,
+
+    // HTTP 客户端
+    /// 发往 Anthropic 后端的客户端设置
+    pub anthropic_http: HttpClientSettings,
+    /// 发往 OpenAI 后端的客户端设置
+    pub openai_http: HttpClientSettings,
+    /// 发往通用上游的客户端设置
+    pub upstream_http: HttpClientSettings,
+
+    // 本地限流
+    /// 按模型的请求速率限制（RATE_LIMITS）
+    pub rate_limits: Vec<RateLimit>,
+
+    // 批处理配置
+    /// /v1/batch 单次请求内的最大并发数
+    pub batch_max_concurrency: usize,
+
+    // 管理与监控配置
+    /// 是否启用 /dashboard 监控面板
+    pub dashboard_enabled: bool,
+    /// 访问管理端点（如 /dashboard）所需的密钥
+    pub admin_key: Option<String>,
+    /// 按模型的 token 单价（MODEL_PRICING），面板据此累计费用
+    pub model_pricing: Vec<ModelPrice>,
+    /// 审计日志文件（JSONL，追加写入），未设置时不记录
+    pub audit_log: Option<String>,
+    /// 审计记录 HMAC-SHA256 签名密钥，设置 AUDIT_LOG 时必填
+    pub audit_hmac_secret: Option<String>,
 
     // 日志配置
     pub debug: bool,
     pub verbose: bool,
     pub log_raw_json: bool,
+    /// 日志中单个字符串的最大字节数，超出部分截断；0 表示不截断
+    pub log_max_text_len: usize,
+    /// 单条日志中请求/响应体的最大字节数；0 表示不限制
+    pub log_max_body_bytes: usize,
 }
 
 impl Config {
-    fn load_dotenv(custom_path: Option<PathBuf>) -> Option<PathBuf> {
+    fn load_dotenv(custom_path: Option<PathBuf>, warnings: &mut Vec<String>) -> Option<PathBuf> {
         if let Some(path) = custom_path {
-            if path.exists() {
-                if let Ok(_) = dotenvy::from_path(&path) {
-                    return Some(path);
-                }
+            if path.exists() && dotenvy::from_path(&path).is_ok() {
+                return Some(path);
             }
-            eprintln!("⚠️  WARNING: Custom config file not found: {}", path.display());
+            warnings.push(format!("Custom config file not found: {}", path.display()));
         }
 
         if let Ok(path) = dotenvy::dotenv() {
             return Some(path);
         }
 
-        if let Some(home) = env::var("HOME").ok() {
+        if let Ok(home) = env::var("HOME") {
             let home_config = PathBuf::from(home).join(".anthropic-proxy.env");
-            if home_config.exists() {
-                if let Ok(_) = dotenvy::from_path(&home_config) {
-                    return Some(home_config);
-                }
+            if home_config.exists() && dotenvy::from_path(&home_config).is_ok() {
+                return Some(home_config);
             }
         }
 
         let etc_config = PathBuf::from("/etc/anthropic-proxy/.env");
-        if etc_config.exists() {
-            if let Ok(_) = dotenvy::from_path(&etc_config) {
-                return Some(etc_config);
-            }
+        if etc_config.exists() && dotenvy::from_path(&etc_config).is_ok() {
+            return Some(etc_config);
         }
 
         None
     }
 
-    pub fn from_env() -> Result<Self> {
-        Self::from_env_with_path(None)
+    /// 解析 URL 路径覆盖：必须以 `/` 开头，去掉末尾的 `/`（只有 `/` 时为空路径）
+    fn parse_url_path(var: &str, value: Option<String>) -> Result<Option<String>> {
+        let Some(value) = value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) else {
+            return Ok(None);
+        };
+        if !value.starts_with('/') {
+            return Err(anyhow::anyhow!("{} must start with '/', got '{}'", var, value));
+        }
+        Ok(Some(value.trim_end_matches('/').to_string()))
     }
 
-    pub fn from_env_with_path(custom_path: Option<PathBuf>) -> Result<Self> {
-        if let Some(path) = Self::load_dotenv(custom_path) {
-            eprintln!("📄 Loaded config from: {}", path.display());
-        } else {
-            eprintln!("ℹ️  No .env file found, using environment variables only");
-        }
+    /// 解析逗号分隔的模型匹配规则（统一转为小写）
+    fn parse_model_patterns(var: &str) -> Vec<String> {
+        env::var(var)
+            .map(|v| {
+                v.split(',')
+                    .map(|p| p.trim().to_lowercase())
+                    .filter(|p| !p.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// 从环境变量（及 .env 文件）加载配置
+    ///
+    /// 同时返回加载报告：此时 tracing 尚未初始化，由调用方在初始化日志后输出
+    pub fn from_env_with_path(custom_path: Option<PathBuf>) -> Result<(Self, LoadReport)> {
+        let mut warnings = Vec::new();
+
+        let source = Self::load_dotenv(custom_path, &mut warnings);
 
         let port = env::var("PORT")
             .ok()
             .and_then(|p| p.parse().ok())
             .unwrap_or(3000);
 
+        let host = env::var("BIND_ADDRESS")
+            .or_else(|_| env::var("HOST"))
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "0.0.0.0".to_string());
+        bind_address(&host, port)?;
+
+        let workers = env::var("WORKERS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0);
+
+        let metrics_port = env::var("METRICS_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
         // 路由模式
-        let routing_mode = env::var("ROUTING_MODE")
-            .map(|s| RoutingMode::from_str(&s))
-            .unwrap_or_default();
+        // 空值视为未设置；未知取值回退到 Transform 并给出警告，别名可用但提示改用正式取值
+        let routing_mode = match env::var("ROUTING_MODE").ok().filter(|s| !s.trim().is_empty()) {
+            Some(s) => {
+                if let Some(canonical) = RoutingMode::deprecated_alias(&s) {
+                    warnings.push(format!(
+                        "⚠️ ROUTING_MODE={} is deprecated, use {}",
+                        s.trim(),
+                        canonical
+                    ));
+                }
+                RoutingMode::from_str(&s).unwrap_or_else(|e| {
+                    warnings.push(format!("{}; falling back to Transform mode", e));
+                    RoutingMode::Transform
+                })
+            }
+            None => RoutingMode::default(),
+        };
 
         // Anthropic 后端配置
         let anthropic_base_url = env::var("ANTHROPIC_BASE_URL").ok();
         let anthropic_api_key = env::var("ANTHROPIC_API_KEY").ok();
+        let anthropic_metadata_user_id = env::var("ANTHROPIC_METADATA_USER_ID")
+            .ok()
+            .filter(|id| !id.is_empty());
+        let anthropic_version = env::var("ANTHROPIC_VERSION")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
 
         // OpenAI 后端配置
         let openai_base_url = env::var("OPENAI_BASE_URL").ok();
         let openai_api_key = env::var("OPENAI_API_KEY").ok();
+        let openai_organization = env::var("OPENAI_ORGANIZATION").ok().filter(|v| !v.is_empty());
+        let openai_project = env::var("OPENAI_PROJECT").ok().filter(|v| !v.is_empty());
+        let forward_authorization = env::var("FORWARD_AUTHORIZATION")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let forward_headers = env::var("FORWARD_HEADERS")
+            .map(|v| {
+                v.split(',')
+                    .map(|h| h.trim().to_lowercase())
+                    .filter(|h| !h.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let trusted_proxies = match env::var("TRUSTED_PROXIES") {
+            Ok(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    Cidr::parse(entry).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Invalid TRUSTED_PROXIES entry '{}': expected an IP address or CIDR range",
+                            entry
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Err(_) => Vec::new(),
+        };
+        let forward_client_ip = env::var("FORWARD_CLIENT_IP")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let upstream_user_agent = env::var("UPSTREAM_USER_AGENT")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        let request_id_header = env::var("REQUEST_ID_HEADER")
+            .ok()
+            .filter(|h| HeaderName::from_bytes(h.trim().as_bytes()).is_ok())
+            .map(|h| h.trim().to_string())
+            .unwrap_or_else(|| "X-Request-Id".to_string());
+
+        let stream_from_accept = env::var("STREAM_FROM_ACCEPT")
+            .map(|v| !(v == "0" || v.to_lowercase() == "false"))
+            .unwrap_or(true);
+
+        // 自动路由配置
+        let strip_model_prefixes = env::var("STRIP_MODEL_PREFIX")
+            .map(|v| {
+                v.split(',')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let anthropic_model_patterns = Self::parse_model_patterns("ANTHROPIC_MODEL_PATTERNS");
+        let openai_model_patterns = Self::parse_model_patterns("OPENAI_MODEL_PATTERNS");
+        let default_backend = env::var("DEFAULT_BACKEND")
+            .map(|s| Backend::from_str(&s))
+            .unwrap_or_default();
+        let transform_reject_openai = env::var("TRANSFORM_REJECT_OPENAI")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
 
         // 转换后端配置（兼容现有）
         let base_url = env::var("UPSTREAM_BASE_URL")
@@ -139,6 +990,26 @@ impl Config {
             .ok()
             .filter(|k| !k.is_empty());
 
+        let url_v1_check = env::var("URL_V1_CHECK")
+            .map(|s| UrlV1Check::from_str(&s))
+            .unwrap_or_default();
+        let upstream_path_prefix =
+            Self::parse_url_path("UPSTREAM_PATH_PREFIX", env::var("UPSTREAM_PATH_PREFIX").ok())?;
+        let upstream_chat_completions_path = Self::parse_url_path(
+            "UPSTREAM_CHAT_COMPLETIONS_PATH",
+            env::var("UPSTREAM_CHAT_COMPLETIONS_PATH").ok(),
+        )?;
+        // 设置了 UPSTREAM_PATH_PREFIX 时上游路径由用户决定，不检查 `/v1` 结尾
+        let checked_base_url = base_url.clone().filter(|_| upstream_path_prefix.is_none());
+        check_v1_suffixes(
+            url_v1_check,
+            &[
+                ("ANTHROPIC_BASE_URL", &anthropic_base_url),
+                ("OPENAI_BASE_URL", &openai_base_url),
+                ("UPSTREAM_BASE_URL", &checked_base_url),
+            ],
+        )?;
+
         // 验证配置
         match routing_mode {
             RoutingMode::Transform => {
@@ -185,6 +1056,185 @@ impl Config {
         let reasoning_model = env::var("REASONING_MODEL").ok();
         let completion_model = env::var("COMPLETION_MODEL").ok();
 
+        let reasoning_model_fallback = env::var("REASONING_MODEL_FALLBACK")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let model_fallbacks = env::var("MODEL_FALLBACKS")
+            .map(|v| ModelFallback::parse_list(&v))
+            .unwrap_or_default();
+
+        let hedge_after_ms = env::var("HEDGE_AFTER_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let hedge_models = Self::parse_model_patterns("HEDGE_MODELS");
+
+        let merge_consecutive_messages = env::var("MERGE_CONSECUTIVE_MESSAGES")
+            .map(|v| !(v == "0" || v.to_lowercase() == "false"))
+            .unwrap_or(true);
+
+        let min_max_tokens = env::var("MIN_MAX_TOKENS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(16);
+        let min_max_tokens_per_model =
+            parse_min_max_tokens_per_model(&env::var("MIN_MAX_TOKENS_PER_MODEL").unwrap_or_default());
+        let context_window_limits =
+            parse_context_window_limits(&env::var("CONTEXT_WINDOW_LIMITS").unwrap_or_default());
+
+        let tools_strict_mode = env::var("TOOLS_STRICT_MODE")
+            .map(|s| ToolsStrictMode::from_str(&s))
+            .unwrap_or_default();
+
+        let filtered_tool_types = env::var("FILTERED_TOOL_TYPES")
+            .map(|v| {
+                v.split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|_| DEFAULT_FILTERED_TOOL_TYPES.iter().map(|t| t.to_string()).collect());
+
+        let deduplicate_tools = env::var("DEDUPLICATE_TOOLS")
+            .map(|v| !(v == "0" || v.to_lowercase() == "false"))
+            .unwrap_or(true);
+
+        let max_tools = env::var("MAX_TOOLS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0);
+
+        let max_schema_depth = env::var("MAX_SCHEMA_DEPTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0);
+
+        let default_stop = env::var("DEFAULT_STOP")
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let json_mode_system_marker = env::var("JSON_MODE_SYSTEM_MARKER").ok().filter(|m| !m.is_empty());
+
+        let strict_params = env::var("STRICT_PARAMS")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let validation = env::var("VALIDATION")
+            .map(|s| ValidationMode::from_str(&s))
+            .unwrap_or_default();
+
+        let policy_rules_file = env::var("POLICY_RULES_FILE").ok().filter(|s| !s.is_empty());
+
+        let audio_input_placeholder = env::var("AUDIO_INPUT_PLACEHOLDER")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let developer_message_handling = env::var("DEVELOPER_MESSAGE_HANDLING")
+            .map(|s| DeveloperMessageHandling::from_str(&s))
+            .unwrap_or_default();
+
+        let thinking_in_history = env::var("THINKING_IN_HISTORY")
+            .map(|s| ThinkingInHistory::from_str(&s))
+            .unwrap_or_default();
+
+        let multiple_text_blocks = env::var("MULTIPLE_TEXT_BLOCKS")
+            .map(|s| MultipleTextBlocksMode::from_str(&s))
+            .unwrap_or_default();
+
+        let strict_openai_shape = env::var("STRICT_OPENAI_SHAPE")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let response_model_mode = env::var("RESPONSE_MODEL_MODE")
+            .map(|s| ResponseModelMode::from_str(&s))
+            .unwrap_or_default();
+        let normalize_response_ids = env::var("NORMALIZE_RESPONSE_IDS")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let reasoning_field = env::var("REASONING_FIELD")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "reasoning".to_string());
+
+        let openai_schema_profile = env::var("OPENAI_SCHEMA_PROFILE")
+            .map(|s| SchemaProfile::from_str(&s))
+            .unwrap_or_default();
+
+        let upstream_schema_profile = env::var("UPSTREAM_SCHEMA_PROFILE")
+            .map(|s| SchemaProfile::from_str(&s))
+            .unwrap_or_default();
+
+        let schema_strip_keywords = env::var("SCHEMA_STRIP_KEYWORDS")
+            .map(|v| {
+                v.split(',')
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|_| DEFAULT_STRIP_KEYWORDS.iter().map(|k| k.to_string()).collect());
+
+        let schema_collapse_nullable = env::var("SCHEMA_COLLAPSE_NULLABLE")
+            .map(|v| !(v == "0" || v.to_lowercase() == "false"))
+            .unwrap_or(true);
+
+        let max_retry_after_secs = env::var("MAX_RETRY_AFTER_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+
+        let stream_idle_timeout_secs = env::var("STREAM_IDLE_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+
+        let stream_coalesce_ms = env::var("STREAM_COALESCE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let fallback_to_non_streaming_on_connect_fail =
+            env::var("FALLBACK_TO_NON_STREAMING_ON_CONNECT_FAIL")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(false);
+
+        let anthropic_http = HttpClientSettings::from_env("ANTHROPIC");
+        let openai_http = HttpClientSettings::from_env("OPENAI");
+        let upstream_http = HttpClientSettings::from_env("UPSTREAM");
+
+        let rate_limits = env::var("RATE_LIMITS")
+            .map(|v| RateLimit::parse_list(&v))
+            .unwrap_or_default();
+
+        let batch_max_concurrency = env::var("BATCH_MAX_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(4);
+
+        let dashboard_enabled = env::var("DASHBOARD_ENABLED")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+        let admin_key = env::var("ADMIN_KEY").ok().filter(|k| !k.is_empty());
+        let model_pricing = env::var("MODEL_PRICING")
+            .map(|v| ModelPrice::parse_list(&v))
+            .unwrap_or_default();
+
+        let audit_log = env::var("AUDIT_LOG").ok().filter(|p| !p.is_empty());
+        let audit_hmac_secret = env::var("AUDIT_HMAC_SECRET").ok().filter(|k| !k.is_empty());
+        if audit_log.is_some() && audit_hmac_secret.is_none() {
+            return Err(anyhow::anyhow!(
+                "AUDIT_LOG is set but AUDIT_HMAC_SECRET is not; audit records must be signed"
+            ));
+        }
+
         let debug = env::var("DEBUG")
             .map(|v| v == "1" || v.to_lowercase() == "true")
             .unwrap_or(false);
@@ -197,35 +1247,187 @@ impl Config {
             .map(|v| v == "1" || v.to_lowercase() == "true")
             .unwrap_or(false);
 
-        // 警告检查
-        if let Some(ref url) = base_url {
-            if url.ends_with("/v1") {
-                eprintln!("⚠️  WARNING: UPSTREAM_BASE_URL ends with '/v1'");
-                eprintln!("   This will result in URLs like: {}/v1/chat/completions", url);
-                eprintln!("   Consider removing '/v1' from UPSTREAM_BASE_URL");
-            }
-        }
+        let log_max_text_len = env::var("LOG_MAX_TEXT_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4096);
+
+        let log_max_body_bytes = env::var("LOG_MAX_BODY_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(65536);
 
-        Ok(Config {
+        let config = Config {
+            host,
             port,
+            workers,
+            metrics_port,
             routing_mode,
             anthropic_base_url,
             anthropic_api_key,
+            anthropic_metadata_user_id,
+            anthropic_version,
             openai_base_url,
             openai_api_key,
+            openai_organization,
+            openai_project,
+            forward_authorization,
             base_url,
             api_key,
+            url_v1_check,
+            upstream_path_prefix,
+            upstream_chat_completions_path,
+            forward_headers,
+            trusted_proxies,
+            forward_client_ip,
+            upstream_user_agent,
+            request_id_header,
+            stream_from_accept,
+            strip_model_prefixes,
+            anthropic_model_patterns,
+            openai_model_patterns,
+            default_backend,
+            transform_reject_openai,
             reasoning_model,
             completion_model,
+            reasoning_model_fallback,
+            model_fallbacks,
+            hedge_after_ms,
+            hedge_models,
+            merge_consecutive_messages,
+            min_max_tokens,
+            min_max_tokens_per_model,
+            context_window_limits,
+            tools_strict_mode,
+            filtered_tool_types,
+            deduplicate_tools,
+            max_tools,
+            max_schema_depth,
+            default_stop,
+            json_mode_system_marker,
+            strict_params,
+            validation,
+            policy_rules_file,
+            audio_input_placeholder,
+            developer_message_handling,
+            thinking_in_history,
+            multiple_text_blocks,
+            strict_openai_shape,
+            response_model_mode,
+            normalize_response_ids,
+            reasoning_field,
+            openai_schema_profile,
+            upstream_schema_profile,
+            schema_strip_keywords,
+            schema_collapse_nullable,
+            max_retry_after_secs,
+            stream_idle_timeout_secs,
+            stream_coalesce_ms,
+            fallback_to_non_streaming_on_connect_fail,
+            anthropic_http,
+            openai_http,
+            upstream_http,
+            rate_limits,
+            batch_max_concurrency,
+            dashboard_enabled,
+            admin_key,
+            model_pricing,
+            audit_log,
+            audit_hmac_secret,
             debug,
             verbose,
             log_raw_json,
-        })
+            log_max_text_len,
+            log_max_body_bytes,
+        };
+
+        Ok((config, LoadReport { source, warnings }))
+    }
+
+    /// 去掉模型名中第一个匹配的 STRIP_MODEL_PREFIX 前缀（不区分大小写），
+    /// 没有匹配或去掉后为空时原样返回
+    pub fn strip_model_prefix<'a>(&self, model: &'a str) -> &'a str {
+        self.strip_model_prefixes
+            .iter()
+            .find_map(|prefix| {
+                model
+                    .get(..prefix.len())
+                    .filter(|head| head.eq_ignore_ascii_case(prefix))
+                    .map(|_| &model[prefix.len()..])
+            })
+            .filter(|rest| !rest.is_empty())
+            .unwrap_or(model)
+    }
+
+    /// 指定模型的回退列表（第一条匹配的规则），没有规则时为空
+    pub fn model_fallbacks_for(&self, model: &str) -> &[String] {
+        let model = model.to_lowercase();
+        self.model_fallbacks
+            .iter()
+            .find(|rule| glob_match(rule.pattern.as_bytes(), model.as_bytes()))
+            .map(|rule| rule.fallbacks.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// 指定模型的 max_tokens 下限：忽略 `provider/` 前缀后取最长匹配的按模型下限，没有时为全局下限
+    pub fn min_max_tokens_for(&self, model: &str) -> u32 {
+        per_model_value(&self.min_max_tokens_per_model, model).unwrap_or(self.min_max_tokens)
+    }
+
+    /// 指定模型的上下文窗口（CONTEXT_WINDOW_LIMITS），未配置时为 None
+    pub fn context_window_limit_for(&self, model: &str) -> Option<u32> {
+        per_model_value(&self.context_window_limits, model)
+    }
+
+    /// 指定模型的限流规则（第一条匹配的规则）
+    pub fn rate_limit_for(&self, model: &str) -> Option<&RateLimit> {
+        let model = model.to_lowercase();
+        self.rate_limits
+            .iter()
+            .find(|rule| glob_match(rule.pattern.as_bytes(), model.as_bytes()))
+    }
+
+    /// 指定模型的单价（第一条匹配的规则）
+    pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        let model = model.to_lowercase();
+        self.model_pricing
+            .iter()
+            .find(|price| glob_match(price.pattern.as_bytes(), model.as_bytes()))
+    }
+
+    /// 指定后端使用的 schema 清理档位
+    pub fn schema_profile_for(&self, backend: Backend) -> SchemaProfile {
+        match backend {
+            Backend::OpenAI => self.openai_schema_profile,
+            _ => self.upstream_schema_profile,
+        }
+    }
+
+    /// 隐藏密钥后的配置，用于日志与 `check` 子命令输出
+    pub fn display_safe(&self) -> ConfigDisplay<'_> {
+        ConfigDisplay(self)
+    }
+
+    /// 通用上游的 API 路径前缀（UPSTREAM_PATH_PREFIX，默认 `/v1`）
+    pub fn upstream_path_prefix(&self) -> &str {
+        self.upstream_path_prefix.as_deref().unwrap_or("/v1")
+    }
+
+    /// 通用上游的对话补全路径（UPSTREAM_CHAT_COMPLETIONS_PATH，默认 `/chat/completions`）
+    pub fn upstream_chat_completions_path(&self) -> &str {
+        self.upstream_chat_completions_path
+            .as_deref()
+            .unwrap_or("/chat/completions")
     }
 
     pub fn chat_completions_url(&self) -> String {
         if let Some(ref url) = self.base_url {
-            format!("{}/v1/chat/completions", url.trim_end_matches('/'))
+            format!(
+                "{}{}{}",
+                url.trim_end_matches('/'),
+                self.upstream_path_prefix(),
+                self.upstream_chat_completions_path()
+            )
         } else {
             String::new()
         }
@@ -239,6 +1441,46 @@ impl Config {
         }
     }
 
+    pub fn anthropic_count_tokens_url(&self) -> String {
+        if let Some(ref url) = self.anthropic_base_url {
+            format!("{}/v1/messages/count_tokens", url.trim_end_matches('/'))
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn anthropic_message_batches_url(&self) -> String {
+        if let Some(ref url) = self.anthropic_base_url {
+            format!("{}/v1/messages/batches", url.trim_end_matches('/'))
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn anthropic_model_url(&self, model_id: &str) -> String {
+        if let Some(ref url) = self.anthropic_base_url {
+            format!("{}/v1/models/{}", url.trim_end_matches('/'), model_id)
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn embeddings_url(&self) -> String {
+        if let Some(ref url) = self.base_url {
+            format!("{}{}/embeddings", url.trim_end_matches('/'), self.upstream_path_prefix())
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn openai_embeddings_url(&self) -> String {
+        if let Some(ref url) = self.openai_base_url {
+            format!("{}/v1/embeddings", url.trim_end_matches('/'))
+        } else {
+            String::new()
+        }
+    }
+
     pub fn openai_chat_completions_url(&self) -> String {
         if let Some(ref url) = self.openai_base_url {
             format!("{}/v1/chat/completions", url.trim_end_matches('/'))
@@ -248,39 +1490,217 @@ impl Config {
     }
 }
 
+/// 由监听地址和端口构造 socket 地址；地址须为 IP，IPv6 可带方括号（如 `[::1]`）
+pub fn bind_address(host: &str, port: u16) -> Result<SocketAddr> {
+    let ip = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let ip: IpAddr = ip.parse().map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid bind address '{}': expected an IP address such as 127.0.0.1, 0.0.0.0 or [::1]",
+            host
+        )
+    })?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// `{:?}` 同样隐藏密钥，避免 tracing 输出泄露
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.display_safe(), f)
+    }
+}
+
+/// 隐藏密钥：只保留最后 4 个字符，不超过 8 个字符的密钥完全隐藏
+pub fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{}", tail)
+}
+
+/// 每行一个配置项的配置展示，API 密钥与管理密钥已隐藏
+pub struct ConfigDisplay<'a>(&'a Config);
+
+impl fmt::Display for ConfigDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = self.0;
+        let plain = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let secret = |value: &Option<String>| {
+            value.as_deref().map(mask_secret).unwrap_or_else(|| "-".to_string())
+        };
+        let list = |values: &[String]| {
+            if values.is_empty() {
+                "-".to_string()
+            } else {
+                values.join(",")
+            }
+        };
+
+        writeln!(f, "host: {}", config.host)?;
+        writeln!(f, "port: {}", config.port)?;
+        writeln!(
+            f,
+            "workers: {}",
+            config.workers.map_or_else(|| "-".to_string(), |w| w.to_string())
+        )?;
+        writeln!(
+            f,
+            "metrics_port: {}",
+            config.metrics_port.map_or_else(|| "-".to_string(), |p| p.to_string())
+        )?;
+        writeln!(f, "routing_mode: {}", config.routing_mode)?;
+        writeln!(f, "anthropic_base_url: {}", plain(&config.anthropic_base_url))?;
+        writeln!(f, "anthropic_api_key: {}", secret(&config.anthropic_api_key))?;
+        writeln!(f, "anthropic_metadata_user_id: {}", plain(&config.anthropic_metadata_user_id))?;
+        writeln!(f, "anthropic_version: {}", plain(&config.anthropic_version))?;
+        writeln!(f, "openai_base_url: {}", plain(&config.openai_base_url))?;
+        writeln!(f, "openai_api_key: {}", secret(&config.openai_api_key))?;
+        writeln!(f, "openai_organization: {}", plain(&config.openai_organization))?;
+        writeln!(f, "openai_project: {}", plain(&config.openai_project))?;
+        writeln!(f, "forward_authorization: {}", config.forward_authorization)?;
+        writeln!(f, "base_url: {}", plain(&config.base_url))?;
+        writeln!(f, "api_key: {}", secret(&config.api_key))?;
+        writeln!(f, "url_v1_check: {}", config.url_v1_check)?;
+        writeln!(f, "upstream_path_prefix: {}", config.upstream_path_prefix())?;
+        writeln!(f, "upstream_chat_completions_path: {}", config.upstream_chat_completions_path())?;
+        writeln!(f, "forward_headers: {}", list(&config.forward_headers))?;
+        let trusted_proxies: Vec<String> = config.trusted_proxies.iter().map(ToString::to_string).collect();
+        writeln!(f, "trusted_proxies: {}", list(&trusted_proxies))?;
+        writeln!(f, "forward_client_ip: {}", config.forward_client_ip)?;
+        writeln!(f, "upstream_user_agent: {}", plain(&config.upstream_user_agent))?;
+        writeln!(f, "request_id_header: {}", config.request_id_header)?;
+        writeln!(f, "stream_from_accept: {}", config.stream_from_accept)?;
+        writeln!(f, "strip_model_prefixes: {}", list(&config.strip_model_prefixes))?;
+        writeln!(f, "anthropic_model_patterns: {}", list(&config.anthropic_model_patterns))?;
+        writeln!(f, "openai_model_patterns: {}", list(&config.openai_model_patterns))?;
+        writeln!(f, "default_backend: {:?}", config.default_backend)?;
+        writeln!(f, "transform_reject_openai: {}", config.transform_reject_openai)?;
+        writeln!(f, "reasoning_model: {}", plain(&config.reasoning_model))?;
+        writeln!(f, "completion_model: {}", plain(&config.completion_model))?;
+        writeln!(f, "reasoning_model_fallback: {}", config.reasoning_model_fallback)?;
+        writeln!(f, "model_fallbacks: {:?}", config.model_fallbacks)?;
+        writeln!(f, "hedge_after_ms: {}", config.hedge_after_ms)?;
+        writeln!(f, "hedge_models: {:?}", config.hedge_models)?;
+        writeln!(f, "merge_consecutive_messages: {}", config.merge_consecutive_messages)?;
+        writeln!(f, "min_max_tokens: {}", config.min_max_tokens)?;
+        let mut per_model: Vec<_> = config.min_max_tokens_per_model.iter().collect();
+        per_model.sort();
+        writeln!(f, "min_max_tokens_per_model: {:?}", per_model)?;
+        let mut context_windows: Vec<_> = config.context_window_limits.iter().collect();
+        context_windows.sort();
+        writeln!(f, "context_window_limits: {:?}", context_windows)?;
+        writeln!(f, "tools_strict_mode: {}", config.tools_strict_mode)?;
+        writeln!(f, "filtered_tool_types: {}", list(&config.filtered_tool_types))?;
+        writeln!(f, "deduplicate_tools: {}", config.deduplicate_tools)?;
+        writeln!(
+            f,
+            "max_tools: {}",
+            config.max_tools.map_or_else(|| "-".to_string(), |n| n.to_string())
+        )?;
+        writeln!(
+            f,
+            "max_schema_depth: {}",
+            config.max_schema_depth.map_or_else(|| "-".to_string(), |n| n.to_string())
+        )?;
+        writeln!(f, "default_stop: {:?}", config.default_stop)?;
+        writeln!(f, "json_mode_system_marker: {}", plain(&config.json_mode_system_marker))?;
+        writeln!(f, "strict_params: {}", config.strict_params)?;
+        writeln!(f, "audio_input_placeholder: {}", config.audio_input_placeholder)?;
+        writeln!(f, "developer_message_handling: {}", config.developer_message_handling)?;
+        writeln!(f, "validation: {}", config.validation)?;
+        writeln!(f, "policy_rules_file: {}", plain(&config.policy_rules_file))?;
+        writeln!(f, "thinking_in_history: {}", config.thinking_in_history)?;
+        writeln!(f, "multiple_text_blocks: {}", config.multiple_text_blocks)?;
+        writeln!(f, "strict_openai_shape: {}", config.strict_openai_shape)?;
+        writeln!(f, "response_model_mode: {}", config.response_model_mode)?;
+        writeln!(f, "normalize_response_ids: {}", config.normalize_response_ids)?;
+        writeln!(f, "reasoning_field: {}", config.reasoning_field)?;
+        writeln!(f, "openai_schema_profile: {}", config.openai_schema_profile)?;
+        writeln!(f, "upstream_schema_profile: {}", config.upstream_schema_profile)?;
+        writeln!(f, "schema_strip_keywords: {}", list(&config.schema_strip_keywords))?;
+        writeln!(f, "schema_collapse_nullable: {}", config.schema_collapse_nullable)?;
+        writeln!(f, "max_retry_after_secs: {}", config.max_retry_after_secs)?;
+        writeln!(f, "stream_idle_timeout_secs: {}", config.stream_idle_timeout_secs)?;
+        writeln!(f, "stream_coalesce_ms: {}", config.stream_coalesce_ms)?;
+        writeln!(
+            f,
+            "fallback_to_non_streaming_on_connect_fail: {}",
+            config.fallback_to_non_streaming_on_connect_fail
+        )?;
+        writeln!(f, "anthropic_http: {}", config.anthropic_http)?;
+        writeln!(f, "openai_http: {}", config.openai_http)?;
+        writeln!(f, "upstream_http: {}", config.upstream_http)?;
+        writeln!(f, "rate_limits: {:?}", config.rate_limits)?;
+        writeln!(f, "batch_max_concurrency: {}", config.batch_max_concurrency)?;
+        writeln!(f, "dashboard_enabled: {}", config.dashboard_enabled)?;
+        writeln!(f, "admin_key: {}", secret(&config.admin_key))?;
+        writeln!(f, "model_pricing: {:?}", config.model_pricing)?;
+        writeln!(f, "audit_log: {}", plain(&config.audit_log))?;
+        writeln!(f, "audit_hmac_secret: {}", secret(&config.audit_hmac_secret))?;
+        writeln!(f, "debug: {}", config.debug)?;
+        writeln!(f, "verbose: {}", config.verbose)?;
+        writeln!(f, "log_raw_json: {}", config.log_raw_json)?;
+        writeln!(f, "log_max_text_len: {}", config.log_max_text_len)?;
+        write!(f, "log_max_body_bytes: {}", config.log_max_body_bytes)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::test_config;
 
     #[test]
     fn test_routing_mode_from_str_transform() {
-        assert_eq!(RoutingMode::from_str("transform"), RoutingMode::Transform);
-        assert_eq!(RoutingMode::from_str("TRANSFORM"), RoutingMode::Transform);
+        assert_eq!(RoutingMode::from_str("transform"), Ok(RoutingMode::Transform));
+        assert_eq!(RoutingMode::from_str("TRANSFORM"), Ok(RoutingMode::Transform));
     }
 
     #[test]
     fn test_routing_mode_from_str_passthrough() {
-        assert_eq!(RoutingMode::from_str("passthrough"), RoutingMode::Passthrough);
-        assert_eq!(RoutingMode::from_str("anthropic"), RoutingMode::Passthrough);
+        assert_eq!(RoutingMode::from_str("passthrough"), Ok(RoutingMode::Passthrough));
+        assert_eq!(RoutingMode::from_str("anthropic"), Ok(RoutingMode::Passthrough));
     }
 
     #[test]
     fn test_routing_mode_from_str_auto() {
-        assert_eq!(RoutingMode::from_str("auto"), RoutingMode::Auto);
-        assert_eq!(RoutingMode::from_str("AUTO"), RoutingMode::Auto);
+        assert_eq!(RoutingMode::from_str("auto"), Ok(RoutingMode::Auto));
+        assert_eq!(RoutingMode::from_str(" AUTO "), Ok(RoutingMode::Auto));
     }
 
     #[test]
     fn test_routing_mode_from_str_gateway() {
-        assert_eq!(RoutingMode::from_str("gateway"), RoutingMode::Gateway);
-        assert_eq!(RoutingMode::from_str("GATEWAY"), RoutingMode::Gateway);
+        assert_eq!(RoutingMode::from_str("gateway"), Ok(RoutingMode::Gateway));
+        assert_eq!(RoutingMode::from_str("GATEWAY"), Ok(RoutingMode::Gateway));
+    }
+
+    #[test]
+    fn test_routing_mode_from_str_translate_alias() {
+        assert_eq!(RoutingMode::from_str("translate"), Ok(RoutingMode::Transform));
+        assert_eq!(RoutingMode::deprecated_alias("Translate"), Some("transform"));
+    }
+
+    #[test]
+    fn test_routing_mode_from_str_forward_alias() {
+        assert_eq!(RoutingMode::from_str("forward"), Ok(RoutingMode::Passthrough));
+        assert_eq!(RoutingMode::deprecated_alias("forward"), Some("passthrough"));
+    }
+
+    #[test]
+    fn test_routing_mode_from_str_direct_alias() {
+        assert_eq!(RoutingMode::from_str(" DIRECT "), Ok(RoutingMode::Passthrough));
+        assert_eq!(RoutingMode::deprecated_alias(" DIRECT "), Some("passthrough"));
+        assert_eq!(RoutingMode::deprecated_alias("passthrough"), None);
     }
 
     #[test]
-    fn test_routing_mode_from_str_default() {
-        assert_eq!(RoutingMode::from_str("unknown"), RoutingMode::Transform);
-        assert_eq!(RoutingMode::from_str(""), RoutingMode::Transform);
+    fn test_routing_mode_from_str_unknown() {
+        assert!(RoutingMode::from_str("unknown").unwrap_err().contains("'unknown'"));
+        assert!(RoutingMode::from_str("").is_err());
     }
 
     #[test]
@@ -291,22 +1711,50 @@ mod tests {
         assert_eq!(format!("{}", RoutingMode::Gateway), "Gateway");
     }
 
+    #[test]
+    fn test_tools_strict_mode_from_str() {
+        assert_eq!(ToolsStrictMode::from_str("auto"), ToolsStrictMode::Auto);
+        assert_eq!(ToolsStrictMode::from_str("FORCE"), ToolsStrictMode::Force);
+        assert_eq!(ToolsStrictMode::from_str("off"), ToolsStrictMode::Off);
+        assert_eq!(ToolsStrictMode::from_str(""), ToolsStrictMode::Off);
+    }
+
+    #[test]
+    fn test_multiple_text_blocks_from_str() {
+        assert_eq!(MultipleTextBlocksMode::from_str("first_only"), MultipleTextBlocksMode::FirstOnly);
+        assert_eq!(MultipleTextBlocksMode::from_str("ALL_CHOICES"), MultipleTextBlocksMode::AllChoices);
+        assert_eq!(MultipleTextBlocksMode::from_str("unknown"), MultipleTextBlocksMode::Concatenate);
+    }
+
+    #[test]
+    fn test_thinking_in_history_from_str() {
+        assert_eq!(ThinkingInHistory::from_str("wrap"), ThinkingInHistory::Wrap);
+        assert_eq!(ThinkingInHistory::from_str("PASSTHROUGH"), ThinkingInHistory::Passthrough);
+        assert_eq!(ThinkingInHistory::from_str("strip"), ThinkingInHistory::Strip);
+        assert_eq!(ThinkingInHistory::from_str("unknown"), ThinkingInHistory::Strip);
+    }
+
+    #[test]
+    fn test_developer_message_handling_from_str() {
+        assert_eq!(DeveloperMessageHandling::from_str("AS_USER"), DeveloperMessageHandling::AsUser);
+        assert_eq!(DeveloperMessageHandling::from_str("drop"), DeveloperMessageHandling::Drop);
+        assert_eq!(DeveloperMessageHandling::from_str("as_system"), DeveloperMessageHandling::AsSystem);
+        assert_eq!(DeveloperMessageHandling::from_str("unknown"), DeveloperMessageHandling::AsSystem);
+    }
+
+    #[test]
+    fn test_schema_profile_from_str() {
+        assert_eq!(SchemaProfile::from_str("aggressive"), SchemaProfile::Aggressive);
+        assert_eq!(SchemaProfile::from_str("MINIMAL"), SchemaProfile::Minimal);
+        assert_eq!(SchemaProfile::from_str(""), SchemaProfile::Minimal);
+    }
+
     #[test]
     fn test_chat_completions_url() {
         let config = Config {
-            port: 3000,
             routing_mode: RoutingMode::Transform,
-            anthropic_base_url: None,
-            anthropic_api_key: None,
-            openai_base_url: None,
-            openai_api_key: None,
             base_url: Some("https://api.example.com".to_string()),
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         };
 
         assert_eq!(config.chat_completions_url(), "https://api.example.com/v1/chat/completions");
@@ -315,40 +1763,43 @@ mod tests {
     #[test]
     fn test_chat_completions_url_with_trailing_slash() {
         let config = Config {
-            port: 3000,
             routing_mode: RoutingMode::Transform,
-            anthropic_base_url: None,
-            anthropic_api_key: None,
-            openai_base_url: None,
-            openai_api_key: None,
             base_url: Some("https://api.example.com/".to_string()),
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         };
 
         assert_eq!(config.chat_completions_url(), "https://api.example.com/v1/chat/completions");
+
+        // 非标准路径的上游
+        let mut config = config;
+        config.upstream_path_prefix = Some("/api/v1".to_string());
+        assert_eq!(config.chat_completions_url(), "https://api.example.com/api/v1/chat/completions");
+        assert_eq!(config.embeddings_url(), "https://api.example.com/api/v1/embeddings");
+        config.upstream_path_prefix = Some("/api".to_string());
+        config.upstream_chat_completions_path = Some("/chat".to_string());
+        assert_eq!(config.chat_completions_url(), "https://api.example.com/api/chat");
+        config.upstream_path_prefix = Some(String::new());
+        assert_eq!(config.chat_completions_url(), "https://api.example.com/chat");
+    }
+
+    #[test]
+    fn test_parse_url_path() {
+        let parse = |value: &str| Config::parse_url_path("UPSTREAM_PATH_PREFIX", Some(value.to_string()));
+        assert_eq!(parse("/api/v1/").unwrap(), Some("/api/v1".to_string()));
+        assert_eq!(parse("/").unwrap(), Some(String::new()));
+        assert_eq!(parse("  ").unwrap(), None);
+        assert_eq!(Config::parse_url_path("UPSTREAM_PATH_PREFIX", None).unwrap(), None);
+        let err = parse("api/v1").unwrap_err().to_string();
+        assert!(err.contains("UPSTREAM_PATH_PREFIX must start with '/'"));
     }
 
     #[test]
     fn test_anthropic_messages_url() {
         let config = Config {
-            port: 3000,
             routing_mode: RoutingMode::Passthrough,
             anthropic_base_url: Some("https://api.anthropic.com".to_string()),
             anthropic_api_key: Some("test".to_string()),
-            openai_base_url: None,
-            openai_api_key: None,
-            base_url: None,
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         };
 
         assert_eq!(config.anthropic_messages_url(), "https://api.anthropic.com/v1/messages");
@@ -357,21 +1808,166 @@ mod tests {
     #[test]
     fn test_openai_chat_completions_url() {
         let config = Config {
-            port: 3000,
-            routing_mode: RoutingMode::Auto,
-            anthropic_base_url: None,
-            anthropic_api_key: None,
             openai_base_url: Some("https://api.openai.com".to_string()),
             openai_api_key: Some("test".to_string()),
-            base_url: None,
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         };
 
         assert_eq!(config.openai_chat_completions_url(), "https://api.openai.com/v1/chat/completions");
     }
+
+    #[test]
+    fn test_model_fallback_parse_list() {
+        let rules = ModelFallback::parse_list(
+            "Old-Model = new-a, new-b ; anthropic/claude-2*=anthropic/claude-3.5-sonnet;broken;empty=",
+        );
+        assert_eq!(
+            rules,
+            vec![
+                ModelFallback {
+                    pattern: "old-model".to_string(),
+                    fallbacks: vec!["new-a".to_string(), "new-b".to_string()],
+                },
+                ModelFallback {
+                    pattern: "anthropic/claude-2*".to_string(),
+                    fallbacks: vec!["anthropic/claude-3.5-sonnet".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_parse_list() {
+        let rules = RateLimit::parse_list("GPT-4o=30/min, claude-3-opus=10/hour,broken=5,zero=0/min,bad=1/day");
+        assert_eq!(
+            rules,
+            vec![
+                RateLimit {
+                    pattern: "gpt-4o".to_string(),
+                    requests: 30,
+                    period: Duration::from_secs(60),
+                },
+                RateLimit {
+                    pattern: "claude-3-opus".to_string(),
+                    requests: 10,
+                    period: Duration::from_secs(3600),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_model_price_parse_list() {
+        let prices = ModelPrice::parse_list("Claude-3-5-Sonnet*=3:15, gpt-4o=2.5:10,broken=1,neg=-1:2,nan=x:1");
+        assert_eq!(
+            prices,
+            vec![
+                ModelPrice {
+                    pattern: "claude-3-5-sonnet*".to_string(),
+                    input_per_mtok: 3.0,
+                    output_per_mtok: 15.0,
+                },
+                ModelPrice {
+                    pattern: "gpt-4o".to_string(),
+                    input_per_mtok: 2.5,
+                    output_per_mtok: 10.0,
+                },
+            ]
+        );
+
+        let config = Config {
+            model_pricing: prices,
+            ..test_config()
+        };
+        assert_eq!(config.price_for("claude-3-5-sonnet-20241022").unwrap().output_per_mtok, 15.0);
+        assert!(config.price_for("gpt-4o-mini").is_none());
+    }
+
+    #[test]
+    fn test_parse_min_max_tokens_per_model() {
+        let limits = parse_min_max_tokens_per_model("Mistral-Large=100, o1=4,broken,bad=x,=5");
+        assert_eq!(limits.len(), 3);
+        assert_eq!(limits["mistral-large"], 100);
+        assert_eq!(limits["o1"], 4);
+        assert_eq!(limits["o3"], 1);
+
+        assert_eq!(parse_min_max_tokens_per_model("").len(), DEFAULT_MIN_MAX_TOKENS_PER_MODEL.len());
+    }
+
+    #[test]
+    fn test_context_window_limit_for() {
+        let config = Config {
+            context_window_limits: parse_context_window_limits("GPT-4=8192, gpt-4o=128000,broken"),
+            ..test_config()
+        };
+        assert_eq!(config.context_window_limits.len(), 2);
+        assert_eq!(config.context_window_limit_for("gpt-4"), Some(8192));
+        assert_eq!(config.context_window_limit_for("openai/gpt-4-0613"), Some(8192));
+        assert_eq!(config.context_window_limit_for("gpt-4o-mini"), Some(128000));
+        assert_eq!(config.context_window_limit_for("gpt-4.1"), None);
+        assert!(parse_context_window_limits("").is_empty());
+    }
+
+    #[test]
+    fn test_url_v1_check() {
+        assert_eq!(UrlV1Check::from_str("ERROR"), UrlV1Check::Error);
+        assert_eq!(UrlV1Check::from_str("off"), UrlV1Check::Off);
+        assert_eq!(UrlV1Check::from_str("bogus"), UrlV1Check::Warn);
+        assert_eq!(strip_v1_suffix("https://openrouter.ai/api/v1/"), Some("https://openrouter.ai/api"));
+        assert_eq!(strip_v1_suffix("https://example.com/v1beta"), None);
+
+        let with_v1 = Some("https://api.openai.com/v1".to_string());
+        let urls = [
+            ("ANTHROPIC_BASE_URL", &None),
+            ("OPENAI_BASE_URL", &with_v1),
+        ];
+        let err = check_v1_suffixes(UrlV1Check::Error, &urls).unwrap_err();
+        assert!(err.to_string().starts_with("OPENAI_BASE_URL ends with '/v1'"));
+        // warn 由 doctor 的静态检查在启动时输出，off 完全不检查
+        assert!(check_v1_suffixes(UrlV1Check::Warn, &urls).is_ok());
+        assert!(check_v1_suffixes(UrlV1Check::Off, &urls).is_ok());
+
+        let without_v1 = Some("https://api.openai.com".to_string());
+        assert!(check_v1_suffixes(UrlV1Check::Error, &[("OPENAI_BASE_URL", &without_v1)]).is_ok());
+    }
+
+    #[test]
+    fn test_display_safe_masks_keys() {
+        assert_eq!(mask_secret("sk-ant-api03-abcdefgh1234"), "****1234");
+        assert_eq!(mask_secret("short"), "****");
+
+        let mut config = Config {
+            openai_base_url: Some("https://api.openai.com".to_string()),
+            openai_api_key: Some("test".to_string()),
+            ..test_config()
+        };
+        config.anthropic_api_key = Some("sk-ant-REDACTED".to_string());
+        config.openai_api_key = Some("sk-proj-secretvalue-9876".to_string());
+        config.api_key = Some("sk-or-v1-secretvalue-5555".to_string());
+        config.admin_key = Some("admin-secretvalue-0000".to_string());
+
+        for output in [config.display_safe().to_string(), format!("{:?}", config)] {
+            assert!(!output.contains("secretvalue"), "{}", output);
+            assert!(output.contains("anthropic_api_key: ****wxyz"));
+            assert!(output.contains("openai_api_key: ****9876"));
+            assert!(output.contains("api_key: ****5555"));
+            assert!(output.contains("admin_key: ****0000"));
+        }
+    }
+
+    #[test]
+    fn test_bind_address() {
+        assert_eq!(
+            bind_address("127.0.0.1", 3000).unwrap(),
+            "127.0.0.1:3000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            bind_address("[::1]", 8080).unwrap(),
+            "[::1]:8080".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(bind_address("::", 80).unwrap().to_string(), "[::]:80");
+        assert!(bind_address("localhost", 3000).is_err());
+        assert!(bind_address("127.0.0.1:3000", 3000).is_err());
+        assert!(bind_address("[127.0.0.1", 3000).is_err());
+    }
 }
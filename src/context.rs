@@ -0,0 +1,220 @@
+//! 请求上下文
+//!
+//! 单个请求在 handler → transform → backend → stream 之间共享的状态，
+//! 由 handler 创建后以 `Arc` 传入后端和流转换器，请求结束时输出摘要
+
+use crate::config::{Config, ResponseModelMode};
+use crate::middleware::client_ip::current_client_ip;
+use crate::middleware::request_id::current_request_id;
+use crate::models::anthropic;
+use crate::transform::utils::{normalize_response_id, upstream_tool_name};
+use axum::http::{
+    header::{AUTHORIZATION, USER_AGENT},
+    HeaderMap,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// 单个请求的上下文
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    /// 请求关联 ID（来自 request_id 中间件）
+    pub request_id: Option<String>,
+    /// 客户端标识：优先使用客户端 API 密钥指纹，其次 `metadata.user_id`，最后 User-Agent
+    pub client_identity: Option<String>,
+    /// 客户端 IP（经 TRUSTED_PROXIES 解析）
+    pub client_ip: Option<IpAddr>,
+    /// 请求中的终端用户标识（Anthropic `metadata.user_id` / OpenAI `user`）
+    pub user_id: Option<String>,
+    /// 客户端请求中的模型
+    pub original_model: String,
+    /// 实际发往上游的模型（转换后确定）
+    pub resolved_model: Option<String>,
+    /// 上游工具名 → 客户端原始工具名，仅包含被改写过的工具名
+    pub tool_name_map: HashMap<String, String>,
+    /// 转换过程中对请求所做的改写说明
+    pub transform_report: Vec<String>,
+    /// OpenAI 请求中的 `stream_options.include_usage`
+    pub include_usage: bool,
+    /// thinking 增量在 OpenAI delta 中使用的字段名
+    pub reasoning_field: String,
+    /// 流式 chunk 的 choice 是否带 `"logprobs": null`（STRICT_OPENAI_SHAPE）
+    pub strict_openai_shape: bool,
+    /// 转换后的响应中 model 字段的取值（RESPONSE_MODEL_MODE）
+    pub response_model_mode: ResponseModelMode,
+    /// 改写不符合目标格式前缀的响应 id（NORMALIZE_RESPONSE_IDS）
+    pub normalize_response_ids: bool,
+    /// 流式响应中等待上游下一个数据块的最长时间（STREAM_IDLE_TIMEOUT），None 表示不限制
+    pub stream_idle_timeout: Option<Duration>,
+    /// 合并连续流式增量的窗口（STREAM_COALESCE_MS），None 表示不合并
+    pub stream_coalesce: Option<Duration>,
+    /// 请求开始处理的时间
+    pub started_at: Instant,
+}
+
+impl Default for RequestContext {
+    fn default() -> Self {
+        Self {
+            request_id: None,
+            client_identity: None,
+            client_ip: None,
+            user_id: None,
+            original_model: String::new(),
+            resolved_model: None,
+            tool_name_map: HashMap::new(),
+            transform_report: Vec::new(),
+            include_usage: false,
+            reasoning_field: "reasoning".to_string(),
+            strict_openai_shape: false,
+            response_model_mode: ResponseModelMode::Upstream,
+            normalize_response_ids: false,
+            stream_idle_timeout: None,
+            stream_coalesce: None,
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl RequestContext {
+    pub fn new(
+        config: &Config,
+        headers: &HeaderMap,
+        original_model: &str,
+        user_id: Option<&str>,
+    ) -> Self {
+        let user_agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok());
+        let client_identity = client_key_fingerprint(headers)
+            .or_else(|| user_id.map(|id| format!("user:{}", id)))
+            .or_else(|| user_agent.map(String::from));
+
+        Self {
+            request_id: current_request_id(),
+            client_identity,
+            client_ip: current_client_ip(),
+            user_id: user_id.map(String::from),
+            original_model: original_model.to_string(),
+            reasoning_field: config.reasoning_field.clone(),
+            strict_openai_shape: config.strict_openai_shape,
+            response_model_mode: config.response_model_mode,
+            normalize_response_ids: config.normalize_response_ids,
+            stream_idle_timeout: (config.stream_idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.stream_idle_timeout_secs)),
+            stream_coalesce: (config.stream_coalesce_ms > 0)
+                .then(|| Duration::from_millis(config.stream_coalesce_ms)),
+            ..Default::default()
+        }
+    }
+
+    /// 返回给客户端的模型名：RESPONSE_MODEL_MODE=requested 时为请求中的模型，否则为上游模型
+    pub fn response_model(&self, upstream_model: &str) -> String {
+        if self.response_model_mode == ResponseModelMode::Requested && !self.original_model.is_empty() {
+            self.original_model.clone()
+        } else {
+            upstream_model.to_string()
+        }
+    }
+
+    /// 返回给客户端的响应 id：开启 NORMALIZE_RESPONSE_IDS 时改写为目标格式的前缀
+    pub fn response_id(&self, upstream_id: &str, prefix: &str) -> String {
+        if self.normalize_response_ids {
+            normalize_response_id(upstream_id, prefix)
+        } else {
+            upstream_id.to_string()
+        }
+    }
+
+    /// 记录 A→O 转换中被改写的工具名，供响应转换时还原
+    pub fn record_tool_names(&mut self, tools: &[anthropic::Tool]) {
+        for tool in tools {
+            let upstream = upstream_tool_name(&tool.name);
+            if upstream != tool.name {
+                self.transform_report
+                    .push(format!("tool name '{}' sent as '{}'", tool.name, upstream));
+                self.tool_name_map
+                    .insert(upstream.into_owned(), tool.name.clone());
+            }
+        }
+    }
+
+    /// 上游返回的工具名对应的客户端原始工具名
+    pub fn original_tool_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.tool_name_map
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or(name)
+    }
+
+    /// 还原非流式 Anthropic 响应中的工具名
+    pub fn restore_tool_names(&self, resp: &mut anthropic::AnthropicResponse) {
+        if self.tool_name_map.is_empty() {
+            return;
+        }
+        for block in &mut resp.content {
+            if let anthropic::ResponseContent::ToolUse { name, .. } = block {
+                if let Some(original) = self.tool_name_map.get(name.as_str()) {
+                    *name = original.clone();
+                }
+            }
+        }
+    }
+
+    /// 请求结束时输出摘要
+    pub fn log_summary(&self, outcome: &str) {
+        tracing::debug!(
+            "Request {} completed: {} (client: {}, ip: {}, user: {}, model: {} -> {}, {} ms)",
+            self.request_id.as_deref().unwrap_or("-"),
+            outcome,
+            self.client_identity.as_deref().unwrap_or("-"),
+            self.client_ip.map(|ip| ip.to_string()).as_deref().unwrap_or("-"),
+            self.user_id.as_deref().unwrap_or("-"),
+            self.original_model,
+            self.resolved_model.as_deref().unwrap_or(&self.original_model),
+            self.started_at.elapsed().as_millis()
+        );
+        for note in &self.transform_report {
+            tracing::debug!("Transform report: {}", note);
+        }
+    }
+}
+
+/// 客户端所用 API 密钥（`x-api-key` 或 `Authorization: Bearer`）的指纹，避免在日志中出现明文密钥
+fn client_key_fingerprint(headers: &HeaderMap) -> Option<String> {
+    let key = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        })
+        .filter(|key| !key.is_empty())?;
+
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    Some(format!("key:{:016x}", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_identity_precedence() {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, "claude-cli/1.0".parse().unwrap());
+        assert_eq!(client_key_fingerprint(&headers), None);
+
+        headers.insert("x-api-key", "sk-client".parse().unwrap());
+        let fingerprint = client_key_fingerprint(&headers).unwrap();
+        assert!(fingerprint.starts_with("key:"));
+        assert!(!fingerprint.contains("sk-client"));
+
+        headers.remove("x-api-key");
+        headers.insert(AUTHORIZATION, "Bearer sk-client".parse().unwrap());
+        assert_eq!(client_key_fingerprint(&headers), Some(fingerprint));
+    }
+}
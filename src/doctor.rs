@@ -0,0 +1,576 @@
+//! 配置诊断
+//!
+//! 静态检查只看配置本身（URL 格式、密钥格式、互相冲突的设置），启动时以警告输出；
+//! `doctor` 子命令另外探测每个已配置后端的连通性、协议和密钥，并给出修复建议
+
+use crate::backends::anthropic::DEFAULT_ANTHROPIC_VERSION;
+use crate::backends::{Backend, Clients};
+use crate::config::{strip_v1_suffix, Config, LoadReport, RoutingMode, UrlV1Check};
+use crate::router;
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use std::fmt;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 单个后端探测的超时
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 检查结果的严重程度
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// 一条检查结果及修复建议
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn error(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.fix {
+            Some(fix) => write!(f, "{} (fix: {})", self.message, fix),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// 不访问网络的配置检查，只返回有问题的项
+pub fn static_checks(config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for (name, url) in [
+        ("ANTHROPIC_BASE_URL", &config.anthropic_base_url),
+        ("OPENAI_BASE_URL", &config.openai_base_url),
+        ("UPSTREAM_BASE_URL", &config.base_url),
+    ] {
+        if let Some(url) = url {
+            // 设置了 UPSTREAM_PATH_PREFIX 时上游路径由用户决定，不检查 `/v1` 结尾
+            let v1_check = if name == "UPSTREAM_BASE_URL" && config.upstream_path_prefix.is_some() {
+                UrlV1Check::Off
+            } else {
+                config.url_v1_check
+            };
+            check_base_url(name, url, v1_check, &mut findings);
+        }
+    }
+
+    for (name, key) in [
+        ("ANTHROPIC_API_KEY", &config.anthropic_api_key),
+        ("OPENAI_API_KEY", &config.openai_api_key),
+        ("UPSTREAM_API_KEY", &config.api_key),
+        ("ADMIN_KEY", &config.admin_key),
+    ] {
+        if let Some(key) = key {
+            check_secret(name, key, &mut findings);
+        }
+    }
+
+    check_key_kinds(config, &mut findings);
+    check_conflicts(config, &mut findings);
+    findings
+}
+
+fn check_base_url(name: &str, url: &str, v1_check: UrlV1Check, findings: &mut Vec<Finding>) {
+    if url.trim() != url || is_quoted(url) {
+        findings.push(Finding::error(
+            format!("{} has surrounding whitespace or quotes: {:?}", name, url),
+            "remove them from the value",
+        ));
+        return;
+    }
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        findings.push(Finding::error(
+            format!("{} is not an http(s) URL: '{}'", name, url),
+            "use a full URL such as https://api.example.com",
+        ));
+        return;
+    }
+    if let Some(base) = strip_v1_suffix(url) {
+        let message = format!("{} ends with '/v1', so requests go to {}/v1/v1/...", name, base);
+        let fix = format!(
+            "set {} to '{}', or URL_V1_CHECK=off if '/v1' is part of the base path",
+            name, base
+        );
+        match v1_check {
+            UrlV1Check::Warn => findings.push(Finding::warning(message, fix)),
+            UrlV1Check::Error => findings.push(Finding::error(message, fix)),
+            UrlV1Check::Off => {}
+        }
+    }
+    if name == "ANTHROPIC_BASE_URL" && url.contains("api.openai.com") {
+        findings.push(Finding::warning(
+            format!("{} points at the OpenAI API: '{}'", name, url),
+            "configure OpenAI-compatible endpoints as OPENAI_BASE_URL or UPSTREAM_BASE_URL",
+        ));
+    }
+    if name != "ANTHROPIC_BASE_URL" && url.contains("api.anthropic.com") {
+        findings.push(Finding::warning(
+            format!("{} points at the Anthropic API: '{}'", name, url),
+            "configure the Anthropic API as ANTHROPIC_BASE_URL",
+        ));
+    }
+}
+
+fn check_secret(name: &str, value: &str, findings: &mut Vec<Finding>) {
+    if value.trim() != value {
+        findings.push(Finding::error(
+            format!("{} has leading or trailing whitespace", name),
+            "remove the whitespace; it is sent as part of the key",
+        ));
+    } else if is_quoted(value) {
+        findings.push(Finding::error(
+            format!("{} is wrapped in quotes", name),
+            "remove the quotes; environment files loaded by systemd or docker keep them literally",
+        ));
+    }
+}
+
+fn is_quoted(value: &str) -> bool {
+    let value = value.trim();
+    value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')))
+}
+
+/// 密钥与后端类型不符：Anthropic 密钥以 `sk-ant-` 开头
+fn check_key_kinds(config: &Config, findings: &mut Vec<Finding>) {
+    let official_anthropic = config
+        .anthropic_base_url
+        .as_deref()
+        .is_some_and(|url| url.contains("api.anthropic.com"));
+    if let Some(key) = &config.anthropic_api_key {
+        if official_anthropic && !key.trim().starts_with("sk-ant-") {
+            findings.push(Finding::warning(
+                "ANTHROPIC_API_KEY does not look like an Anthropic key (expected 'sk-ant-...')",
+                "copy the key from the Anthropic console",
+            ));
+        }
+    }
+    for (name, key) in [
+        ("OPENAI_API_KEY", &config.openai_api_key),
+        ("UPSTREAM_API_KEY", &config.api_key),
+    ] {
+        if key.as_deref().is_some_and(|k| k.trim().starts_with("sk-ant-")) {
+            findings.push(Finding::warning(
+                format!("{} looks like an Anthropic key", name),
+                "set it as ANTHROPIC_API_KEY, or use the key of the OpenAI-compatible provider",
+            ));
+        }
+    }
+}
+
+/// 互相冲突或不会生效的设置
+fn check_conflicts(config: &Config, findings: &mut Vec<Finding>) {
+    if config.routing_mode == RoutingMode::Passthrough {
+        for (name, set) in [
+            ("REASONING_MODEL", config.reasoning_model.is_some()),
+            ("COMPLETION_MODEL", config.completion_model.is_some()),
+            ("UPSTREAM_BASE_URL", config.base_url.is_some()),
+            ("OPENAI_BASE_URL", config.openai_base_url.is_some()),
+        ] {
+            if set {
+                findings.push(Finding::warning(
+                    format!("{} is ignored in Passthrough mode", name),
+                    "remove it, or set ROUTING_MODE to transform, auto or gateway",
+                ));
+            }
+        }
+    }
+    if !config.hedge_models.is_empty() && config.hedge_after_ms == 0 {
+        findings.push(Finding::warning(
+            "HEDGE_MODELS is set but hedging is disabled (HEDGE_AFTER_MS is 0)",
+            "set HEDGE_AFTER_MS to the delay before sending the hedged request",
+        ));
+    }
+    if config.dashboard_enabled && config.admin_key.is_none() {
+        findings.push(Finding::warning(
+            "DASHBOARD_ENABLED is set but ADMIN_KEY is not, so the dashboard rejects every request",
+            "set ADMIN_KEY",
+        ));
+    }
+}
+
+/// 探测每个已配置的后端，使用各后端自己的 HTTP 客户端（代理等设置与实际转发一致）；
+/// 也被 /dashboard 的后台健康检查复用
+pub async fn probe_backends(config: &Config, clients: &Clients) -> Vec<(Backend, Finding)> {
+    let mut findings = Vec::new();
+    if let Some(base_url) = &config.anthropic_base_url {
+        findings.push((
+            Backend::Anthropic,
+            probe_anthropic(
+                clients.for_backend(Backend::Anthropic),
+                base_url,
+                config.anthropic_api_key.as_deref(),
+            )
+            .await,
+        ));
+    }
+    for (name, backend, base_url, path_prefix, api_key) in [
+        ("OPENAI_BASE_URL", Backend::OpenAI, &config.openai_base_url, "/v1", &config.openai_api_key),
+        ("UPSTREAM_BASE_URL", Backend::Upstream, &config.base_url, config.upstream_path_prefix(), &config.api_key),
+    ] {
+        if let Some(base_url) = base_url {
+            let client = clients.for_backend(backend);
+            let url = format!("{}{}/models", base_url.trim().trim_end_matches('/'), path_prefix);
+            findings.push((backend, probe_openai(client, name, &url, api_key.as_deref()).await));
+        }
+    }
+    findings
+}
+
+/// 向 /v1/messages 发送空请求：Anthropic 协议的端点以 400 和 `"type": "error"` 拒绝，不消耗 token
+async fn probe_anthropic(client: &Client, base_url: &str, api_key: Option<&str>) -> Finding {
+    let url = format!("{}/v1/messages", base_url.trim().trim_end_matches('/'));
+    let mut request = client
+        .post(&url)
+        .header("anthropic-version", DEFAULT_ANTHROPIC_VERSION)
+        .json(&serde_json::json!({}))
+        .timeout(PROBE_TIMEOUT);
+    if let Some(key) = api_key {
+        request = request.header("x-api-key", key.trim());
+    }
+    let (status, body) = match send(request).await {
+        Ok(response) => response,
+        Err(finding) => return finding.named("ANTHROPIC_BASE_URL", &url),
+    };
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Finding::error(
+            format!("ANTHROPIC_API_KEY was rejected by {} ({})", url, status),
+            "check ANTHROPIC_API_KEY",
+        ),
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => Finding::error(
+            format!("{} returned {}: the endpoint does not speak the Anthropic Messages API", url, status),
+            "point ANTHROPIC_BASE_URL at an Anthropic-compatible API, or configure this endpoint as OPENAI_BASE_URL or UPSTREAM_BASE_URL",
+        ),
+        StatusCode::BAD_REQUEST if body["type"] == "error" => {
+            Finding::ok(format!("ANTHROPIC_BASE_URL speaks the Anthropic Messages API ({})", url))
+        }
+        _ => Finding::warning(
+            format!("{} answered an empty Messages request with {} in a non-Anthropic format", url, status),
+            "make sure ANTHROPIC_BASE_URL is an Anthropic-compatible API",
+        ),
+    }
+}
+
+/// 请求 `<前缀>/models`：OpenAI 协议的端点返回带 `data` 数组的模型列表
+async fn probe_openai(client: &Client, name: &str, url: &str, api_key: Option<&str>) -> Finding {
+    let mut request = client.get(url).timeout(PROBE_TIMEOUT);
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key.trim());
+    }
+    let (status, body) = match send(request).await {
+        Ok(response) => response,
+        Err(finding) => return finding.named(name, url),
+    };
+    let key_name = name.replace("_BASE_URL", "_API_KEY");
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Finding::error(
+            format!("{} was rejected by {} ({})", key_name, url, status),
+            format!("check {}", key_name),
+        ),
+        StatusCode::OK => match body["data"].as_array() {
+            Some(models) => Finding::ok(format!(
+                "{} speaks the OpenAI API ({}, {} models)",
+                name,
+                url,
+                models.len()
+            )),
+            None => Finding::warning(
+                format!("{} did not return an OpenAI model list", url),
+                format!("make sure {} is an OpenAI-compatible API", name),
+            ),
+        },
+        _ => Finding::warning(
+            format!("{} returned {}; could not confirm the endpoint speaks the OpenAI API", url, status),
+            "some providers do not list models; if requests fail, check the base URL",
+        ),
+    }
+}
+
+/// 连接失败时的结果，后端名称与 URL 在调用处补上
+struct Unreachable(String);
+
+impl Unreachable {
+    fn named(self, name: &str, url: &str) -> Finding {
+        Finding::error(
+            format!("{} is unreachable ({}): {}", name, url, self.0),
+            "check the URL, DNS and any proxy or firewall between this host and the backend",
+        )
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Result<(StatusCode, Value), Unreachable> {
+    let response = request.send().await.map_err(|e| Unreachable(e.to_string()))?;
+    let status = response.status();
+    let body = response.json().await.unwrap_or(Value::Null);
+    Ok((status, body))
+}
+
+/// `doctor` 子命令：输出配置、路由和后端检查报告，有错误时返回 false
+pub async fn run(config: &Config, load_report: &LoadReport) -> bool {
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut ok = true;
+    let mut print = |finding: &Finding| {
+        ok &= finding.severity != Severity::Error;
+        print_finding(finding, color);
+    };
+
+    println!("Configuration");
+    match &load_report.source {
+        Some(path) => print(&Finding::ok(format!("loaded {}", path.display()))),
+        None => print(&Finding::ok("no .env file found, using environment variables only")),
+    }
+    for warning in &load_report.warnings {
+        print(&Finding::warning(warning.clone(), "see the configuration table in the README"));
+    }
+    let findings = static_checks(config);
+    if findings.is_empty() {
+        print(&Finding::ok("no problems found in the configuration values"));
+    }
+    findings.iter().for_each(&mut print);
+
+    println!("\nRouting ({} mode)", config.routing_mode);
+    for diagnostic in router::serving_diagnostics(config) {
+        let finding = match diagnostic.result {
+            Ok(_) => Finding::ok(diagnostic.to_string()),
+            Err(_) => Finding::warning(diagnostic.to_string(), "configure the missing backend"),
+        };
+        print(&finding);
+    }
+
+    println!("\nBackends");
+    let findings = match Clients::from_config(config) {
+        Ok(clients) => probe_backends(config, &clients)
+            .await
+            .into_iter()
+            .map(|(_, finding)| finding)
+            .collect(),
+        Err(e) => vec![Finding::error(
+            format!("cannot build the HTTP clients: {}", e),
+            "check ANTHROPIC_HTTP_PROXY, OPENAI_HTTP_PROXY and UPSTREAM_HTTP_PROXY",
+        )],
+    };
+    if findings.is_empty() {
+        print(&Finding::error(
+            "no backend is configured",
+            "set ANTHROPIC_BASE_URL, OPENAI_BASE_URL or UPSTREAM_BASE_URL",
+        ));
+    }
+    findings.iter().for_each(&mut print);
+
+    ok
+}
+
+fn print_finding(finding: &Finding, color: bool) {
+    let (symbol, code) = match finding.severity {
+        Severity::Ok => ("✓", "32"),
+        Severity::Warning => ("⚠", "33"),
+        Severity::Error => ("✗", "31"),
+    };
+    if color {
+        println!("  \x1b[{}m{}\x1b[0m {}", code, symbol, finding.message);
+    } else {
+        println!("  {} {}", symbol, finding.message);
+    }
+    if let Some(fix) = &finding.fix {
+        if color {
+            println!("    \x1b[2mfix: {}\x1b[0m", fix);
+        } else {
+            println!("    fix: {}", fix);
+        }
+    }
+}
+
+/// 加载配置并运行诊断
+pub fn run_doctor(config_path: Option<PathBuf>) -> anyhow::Result<bool> {
+    let (config, load_report) = Config::from_env_with_path(config_path)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    Ok(runtime.block_on(run(&config, &load_report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_config;
+
+    fn create_test_config() -> Config {
+        Config {
+            anthropic_base_url: Some("https://api.anthropic.com".to_string()),
+            anthropic_api_key: Some("sk-ant-test".to_string()),
+            ..test_config()
+        }
+    }
+
+    fn messages(config: &Config) -> Vec<String> {
+        static_checks(config).into_iter().map(|f| f.message).collect()
+    }
+
+    #[test]
+    fn test_clean_config_has_no_findings() {
+        assert!(static_checks(&create_test_config()).is_empty());
+    }
+
+    #[test]
+    fn test_base_url_v1_suffix() {
+        let mut config = create_test_config();
+        config.base_url = Some("https://openrouter.ai/api/v1/".to_string());
+        let findings = static_checks(&config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert!(findings[0].message.starts_with("UPSTREAM_BASE_URL ends with '/v1'"));
+        assert_eq!(
+            findings[0].fix.as_deref(),
+            Some("set UPSTREAM_BASE_URL to 'https://openrouter.ai/api', or URL_V1_CHECK=off if '/v1' is part of the base path")
+        );
+
+        config.url_v1_check = UrlV1Check::Error;
+        config.openai_base_url = Some("https://api.openai.com/v1".to_string());
+        let findings = static_checks(&config);
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.severity == Severity::Error));
+        assert!(findings[0].message.starts_with("OPENAI_BASE_URL ends with '/v1'"));
+
+        config.url_v1_check = UrlV1Check::Off;
+        assert!(static_checks(&config).is_empty());
+
+        // 自定义路径前缀时 UPSTREAM_BASE_URL 以 /v1 结尾是有意为之
+        config.url_v1_check = UrlV1Check::Error;
+        config.openai_base_url = None;
+        config.upstream_path_prefix = Some(String::new());
+        assert!(static_checks(&config).is_empty());
+    }
+
+    #[test]
+    fn test_base_url_format() {
+        let mut config = create_test_config();
+        config.openai_base_url = Some("api.openai.com".to_string());
+        config.base_url = Some("\"https://example.com\"".to_string());
+        let findings = static_checks(&config);
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.severity == Severity::Error));
+        assert!(findings[0].message.contains("OPENAI_BASE_URL is not an http(s) URL"));
+        assert!(findings[1].message.contains("UPSTREAM_BASE_URL has surrounding"));
+    }
+
+    #[test]
+    fn test_base_url_protocol_mismatch() {
+        let mut config = create_test_config();
+        config.anthropic_base_url = Some("https://api.openai.com".to_string());
+        config.base_url = Some("https://api.anthropic.com".to_string());
+        let messages = messages(&config);
+        assert!(messages.iter().any(|m| m.starts_with("ANTHROPIC_BASE_URL points at the OpenAI API")));
+        assert!(messages.iter().any(|m| m.starts_with("UPSTREAM_BASE_URL points at the Anthropic API")));
+    }
+
+    #[test]
+    fn test_secret_whitespace_and_quotes() {
+        let mut config = create_test_config();
+        config.anthropic_api_key = Some("sk-ant-test\n".to_string());
+        config.openai_api_key = Some("'sk-test'".to_string());
+        let findings = static_checks(&config);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].message, "ANTHROPIC_API_KEY has leading or trailing whitespace");
+        assert_eq!(findings[1].message, "OPENAI_API_KEY is wrapped in quotes");
+        assert!(findings.iter().all(|f| f.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_key_kind_mismatch() {
+        let mut config = create_test_config();
+        config.anthropic_api_key = Some("sk-or-v1-abc".to_string());
+        config.api_key = Some("sk-ant-abc".to_string());
+        let messages = messages(&config);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("ANTHROPIC_API_KEY does not look like an Anthropic key"));
+        assert_eq!(messages[1], "UPSTREAM_API_KEY looks like an Anthropic key");
+
+        // 非官方地址的 Anthropic 兼容服务可以使用其他格式的密钥
+        config.anthropic_base_url = Some("https://proxy.example.com".to_string());
+        config.api_key = None;
+        assert!(static_checks(&config).is_empty());
+    }
+
+    #[test]
+    fn test_passthrough_conflicts() {
+        let mut config = create_test_config();
+        config.routing_mode = RoutingMode::Passthrough;
+        config.completion_model = Some("gpt-4o-mini".to_string());
+        config.base_url = Some("https://openrouter.ai/api".to_string());
+        assert_eq!(
+            messages(&config),
+            vec![
+                "COMPLETION_MODEL is ignored in Passthrough mode",
+                "UPSTREAM_BASE_URL is ignored in Passthrough mode",
+            ]
+        );
+
+        config.routing_mode = RoutingMode::Transform;
+        assert!(static_checks(&config).is_empty());
+    }
+
+    #[test]
+    fn test_hedge_and_dashboard_conflicts() {
+        let mut config = create_test_config();
+        config.hedge_models = vec!["gpt-4o-mini".to_string()];
+        config.dashboard_enabled = true;
+        let messages = messages(&config);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("HEDGE_MODELS is set but hedging is disabled"));
+        assert!(messages[1].starts_with("DASHBOARD_ENABLED is set but ADMIN_KEY is not"));
+    }
+
+    #[test]
+    fn test_finding_display_includes_fix() {
+        let finding = Finding::warning("problem", "do this");
+        assert_eq!(finding.to_string(), "problem (fix: do this)");
+        assert_eq!(Finding::ok("fine").to_string(), "fine");
+    }
+
+    #[tokio::test]
+    async fn test_probe_unreachable_backend() {
+        let mut config = create_test_config();
+        config.anthropic_base_url = Some("http://127.0.0.1:1".to_string());
+        let findings = probe_backends(&config, &Clients::default()).await;
+        assert_eq!(findings.len(), 1);
+        let (backend, finding) = &findings[0];
+        assert_eq!(*backend, Backend::Anthropic);
+        assert_eq!(finding.severity, Severity::Error);
+        assert!(finding.message.starts_with("ANTHROPIC_BASE_URL is unreachable"));
+    }
+}
@@ -1,11 +1,17 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use crate::middleware::request_id::current_request_id;
+use crate::monitor::{record_transform_failure, TransformFailure};
+use crate::router::RequestFormat;
+use serde_json::{json, Value};
 use thiserror::Error;
 
+/// Anthropic 表示过载的 HTTP 状态码
+pub const STATUS_OVERLOADED: u16 = 529;
+
 /// Application-specific errors
 #[derive(Error, Debug)]
 pub enum ProxyError {
@@ -18,6 +24,9 @@ pub enum ProxyError {
     #[error("Upstream API error: {0}")]
     Upstream(String),
 
+    #[error("Upstream model not found: {0}")]
+    ModelNotFound(String),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
@@ -27,19 +36,128 @@ pub enum ProxyError {
     #[error("Internal error: {0}")]
     Internal(String),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// 本地限流：消息与建议的重试等待秒数
+    #[error("Rate limit exceeded: {0}")]
+    RateLimited(String, u64),
+
+    /// 上游限流（HTTP 429），已按 Retry-After 等待后仍未恢复；原样带回上游的 Retry-After
+    #[error("Upstream rate limited: {message}")]
+    UpstreamRateLimited {
+        message: String,
+        client_format: RequestFormat,
+        retry_after_secs: Option<u64>,
+    },
+
+    /// 上游过载（HTTP 529 / overloaded_error），按调用方的 API 格式返回可重试的状态码
+    #[error("Upstream overloaded: {message}")]
+    Overloaded {
+        message: String,
+        client_format: RequestFormat,
+        retry_after_secs: u64,
+    },
+
     #[error("Unsupported operation: {0}")]
     UnsupportedOperation(String),
 
-    #[error("Routing error: {0}")]
-    Routing(String),
+    /// 请求的 Content-Type 不是 JSON
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    /// 请求体无法解析为对应 API 的请求：解析错误与请求中实际出现的顶层字段
+    #[error("Invalid request: {message}")]
+    InvalidRequest {
+        message: String,
+        received_fields: Vec<String>,
+    },
+
+    /// 请求未通过转发前的校验：出错的字段路径（如 `messages[2].role`）与原因
+    #[error("Invalid request: {param}: {message}")]
+    Validation {
+        param: String,
+        message: String,
+        client_format: RequestFormat,
+    },
+
+    /// 请求命中 block 动作的内容策略规则；只记录规则 id，不回显匹配的内容
+    #[error("Request blocked by content policy rule '{rule_id}'")]
+    PolicyViolation {
+        rule_id: String,
+        client_format: RequestFormat,
+    },
+}
+
+impl ProxyError {
+    /// 转换失败，按原因计入 transform_failures_total
+    pub fn transform(reason: TransformFailure, message: impl Into<String>) -> Self {
+        record_transform_failure(reason);
+        ProxyError::Transform(message.into())
+    }
+
+    /// 请求体反序列化失败，错误中附带请求里实际出现的顶层字段；按 schema 原因计入 transform_failures_total
+    pub fn invalid_request(error: &serde_json::Error, raw_json: &Value) -> Self {
+        record_transform_failure(TransformFailure::Schema);
+        let received_fields = raw_json
+            .as_object()
+            .map(|obj| obj.keys().cloned().collect())
+            .unwrap_or_default();
+        ProxyError::InvalidRequest {
+            message: format!("Failed to deserialize: {}", error),
+            received_fields,
+        }
+    }
 }
 
 impl IntoResponse for ProxyError {
     fn into_response(self) -> Response {
+        let retry_after = match &self {
+            ProxyError::RateLimited(_, secs) => Some(*secs),
+            ProxyError::Overloaded { retry_after_secs, .. } => Some(*retry_after_secs),
+            ProxyError::UpstreamRateLimited { retry_after_secs, .. } => *retry_after_secs,
+            _ => None,
+        };
+        let error_type = match &self {
+            ProxyError::Overloaded { .. } => "overloaded_error",
+            ProxyError::UpstreamRateLimited { .. } => "rate_limit_error",
+            ProxyError::UnsupportedOperation(_)
+            | ProxyError::UnsupportedMediaType(_)
+            | ProxyError::InvalidRequest { .. }
+            | ProxyError::Validation { .. }
+            | ProxyError::PolicyViolation { .. } => "invalid_request_error",
+            _ => "proxy_error",
+        };
+        let received_fields = match &self {
+            ProxyError::InvalidRequest { received_fields, .. } => Some(received_fields.clone()),
+            _ => None,
+        };
+        // OpenAI 错误对象用 param 字段指出出错的参数
+        let param = match &self {
+            ProxyError::Validation {
+                param,
+                client_format: RequestFormat::OpenAI,
+                ..
+            } => Some(param.clone()),
+            _ => None,
+        };
+        // OpenAI 错误对象用 code 区分被内容策略拒绝和被上游限流的请求
+        let code = match &self {
+            ProxyError::PolicyViolation {
+                client_format: RequestFormat::OpenAI,
+                ..
+            } => Some("content_policy_violation"),
+            ProxyError::UpstreamRateLimited {
+                client_format: RequestFormat::OpenAI,
+                ..
+            } => Some("rate_limit_exceeded"),
+            _ => None,
+        };
         let (status, error_message) = match self {
             ProxyError::Config(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             ProxyError::Transform(msg) => (StatusCode::BAD_REQUEST, msg),
             ProxyError::Upstream(msg) => (StatusCode::BAD_GATEWAY, msg),
+            ProxyError::ModelNotFound(msg) => (StatusCode::BAD_GATEWAY, msg),
             ProxyError::Serialization(err) => {
                 (StatusCode::BAD_REQUEST, format!("JSON error: {}", err))
             }
@@ -47,20 +165,138 @@ impl IntoResponse for ProxyError {
                 (StatusCode::BAD_GATEWAY, format!("HTTP error: {}", err))
             }
             ProxyError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+            ProxyError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            ProxyError::RateLimited(msg, _) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            ProxyError::UpstreamRateLimited { message, .. } => (StatusCode::TOO_MANY_REQUESTS, message),
+            // OpenAI SDK 不识别 529，对 OpenAI 格式的调用方使用 503
+            ProxyError::Overloaded {
+                message,
+                client_format: RequestFormat::Anthropic,
+                ..
+            } => (StatusCode::from_u16(STATUS_OVERLOADED).unwrap(), message),
+            ProxyError::Overloaded { message, .. } => (StatusCode::SERVICE_UNAVAILABLE, message),
             ProxyError::UnsupportedOperation(msg) => (StatusCode::BAD_REQUEST, msg),
-            ProxyError::Routing(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+            ProxyError::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg),
+            ProxyError::InvalidRequest { message, .. } => (StatusCode::BAD_REQUEST, message),
+            ProxyError::Validation { param, message, .. } => {
+                (StatusCode::BAD_REQUEST, format!("{}: {}", param, message))
+            }
+            ProxyError::PolicyViolation { rule_id, .. } => (
+                StatusCode::BAD_REQUEST,
+                format!("Request blocked by content policy rule '{}'", rule_id),
+            ),
         };
 
-        let body = Json(json!({
-            "error": {
-                "type": "proxy_error",
-                "message": error_message,
-            }
-        }));
+        let mut error = json!({
+            "type": error_type,
+            "message": error_message,
+        });
+        if let Some(fields) = received_fields {
+            error["received_fields"] = json!(fields);
+        }
+        if let Some(param) = param {
+            error["param"] = json!(param);
+        }
+        if let Some(code) = code {
+            error["code"] = json!(code);
+        }
+        if let Some(request_id) = current_request_id() {
+            error["request_id"] = json!(request_id);
+        }
+        let body = Json(json!({ "error": error }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
 
 /// Result type for proxy operations
 pub type ProxyResult<T> = Result<T, ProxyError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overloaded(client_format: RequestFormat) -> Response {
+        ProxyError::Overloaded {
+            message: "Overloaded".to_string(),
+            client_format,
+            retry_after_secs: 5,
+        }
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_status_per_client_format() {
+        let response = overloaded(RequestFormat::Anthropic);
+        assert_eq!(response.status().as_u16(), STATUS_OVERLOADED);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "overloaded_error");
+
+        let response = overloaded(RequestFormat::OpenAI);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+    }
+
+    #[tokio::test]
+    async fn test_policy_violation_names_rule_per_client_format() {
+        for (client_format, code) in [
+            (RequestFormat::Anthropic, serde_json::Value::Null),
+            (RequestFormat::OpenAI, serde_json::json!("content_policy_violation")),
+        ] {
+            let response = ProxyError::PolicyViolation {
+                rule_id: "aws-key".to_string(),
+                client_format,
+            }
+            .into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+            assert_eq!(body["error"]["message"], "Request blocked by content policy rule 'aws-key'");
+            assert_eq!(body["error"]["code"], code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upstream_rate_limited_per_client_format() {
+        for (client_format, code) in [
+            (RequestFormat::Anthropic, serde_json::Value::Null),
+            (RequestFormat::OpenAI, serde_json::json!("rate_limit_exceeded")),
+        ] {
+            let response = ProxyError::UpstreamRateLimited {
+                message: "slow down".to_string(),
+                client_format,
+                retry_after_secs: Some(12),
+            }
+            .into_response();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(response.headers()[RETRY_AFTER], "12");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["type"], "rate_limit_error");
+            assert_eq!(body["error"]["code"], code);
+        }
+
+        // 上游没有给出 Retry-After 时不补
+        let response = ProxyError::UpstreamRateLimited {
+            message: "slow down".to_string(),
+            client_format: RequestFormat::Anthropic,
+            retry_after_secs: None,
+        }
+        .into_response();
+        assert!(response.headers().get(RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_other_errors_keep_proxy_error_status() {
+        let response = ProxyError::Upstream("boom".into()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(response.headers().get(RETRY_AFTER).is_none());
+    }
+}
@@ -1,93 +1,1501 @@
-//! Anthropic API 端点处理器 (/v1/messages)
+//! Anthropic API 端点处理器 (/v1/messages、/v1/messages/batches、/v1/models/:model_id)
 
-use crate::backends::{self, Backend};
-use crate::config::Config;
+use crate::audit;
+use crate::backends::{self, Backend, Clients};
+use crate::config::{Config, RoutingMode};
+use crate::context::RequestContext;
 use crate::error::{ProxyError, ProxyResult};
-use crate::models::anthropic;
-use crate::router::{RequestFormat, RoutingDecision};
+use crate::log_sanitize::{log_json, truncate_for_log};
+use crate::policy::ContentPolicy;
+use crate::rate_limit::{self, RateLimitBuckets};
+use crate::handlers::overrides::apply_header_overrides;
+use crate::handlers::{
+    body_with_model, check_json_content_type, is_streaming_request, set_detected_format,
+    strip_model_prefix, DETECTED_FORMAT_HEADER,
+};
+use crate::models::{anthropic, openai};
+use crate::monitor::{self, TransformFailure};
+use crate::router::{detect_request_format, RequestFormat, RoutingDecision};
+use crate::tokens;
 use crate::transform;
-use axum::{response::Response, Extension};
-use reqwest::Client;
+use crate::validation;
+use crate::transform::request::anthropic_to_openai::has_thinking;
+use crate::transform::utils::{
+    merge_stop_field, metadata_user_id, parse_model_with_effort, set_metadata_user_id,
+    strip_response_format_extension,
+};
+use axum::{
+    body::Body,
+    extract::{Path, Request},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, Method, Uri,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use serde_json::json;
 use std::sync::Arc;
 
 /// Anthropic API 端点处理器
 pub async fn anthropic_handler(
     Extension(config): Extension<Arc<Config>>,
-    Extension(client): Extension<Client>,
+    Extension(clients): Extension<Clients>,
+    Extension(rate_limits): Extension<RateLimitBuckets>,
+    Extension(policy): Extension<ContentPolicy>,
+    headers: HeaderMap,
     body: axum::body::Bytes,
 ) -> ProxyResult<Response> {
+    check_json_content_type(&headers)?;
+
     // 解析请求为 JSON Value（保留原始结构）
     let raw_json: serde_json::Value = serde_json::from_slice(&body).map_err(|e| {
         tracing::error!("Failed to parse request as JSON: {}", e);
-        tracing::debug!("Raw request body: {}", String::from_utf8_lossy(&body));
-        ProxyError::Transform(format!("Invalid JSON: {}", e))
+        tracing::debug!(
+            "Raw request body: {}",
+            truncate_for_log(&String::from_utf8_lossy(&body), config.log_max_body_bytes)
+        );
+        ProxyError::transform(TransformFailure::InvalidJson, format!("Invalid JSON: {}", e))
     })?;
 
     if config.debug && config.log_raw_json {
         tracing::debug!(
             "Raw request JSON: {}",
-            serde_json::to_string_pretty(&raw_json).unwrap_or_default()
+            log_json(&raw_json, &config)
         );
     }
 
-    // 提取必要字段用于路由决策
-    let model = raw_json
-        .get("model")
-        .and_then(|v| v.as_str())
-        .unwrap_or("unknown");
-    let is_streaming = raw_json
-        .get("stream")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
+    // Gateway 模式下，发到本端点的 OpenAI 格式请求按 OpenAI 端点处理
+    if config.routing_mode == RoutingMode::Gateway
+        && detect_request_format(RequestFormat::Anthropic, &raw_json) == RequestFormat::OpenAI
+    {
+        tracing::info!("OpenAI-format request received on /v1/messages, handling as OpenAI");
+        // 错误也按 OpenAI 格式返回并标注格式，anthropic_error_envelope 据此跳过
+        let mut response = super::openai::handle_openai_request(
+            config,
+            clients,
+            rate_limits,
+            policy,
+            headers,
+            body,
+            raw_json,
+        )
+        .await
+        .unwrap_or_else(IntoResponse::into_response);
+        set_detected_format(&mut response, RequestFormat::OpenAI);
+        return Ok(response);
+    }
+
+    handle_anthropic_request(config, clients, rate_limits, policy, headers, body, raw_json).await
+}
+
+/// Anthropic 端点的错误响应使用 `{"type": "error", "error": {...}}` 信封。
+/// 已带顶层 `type` 的错误（透传的上游响应）和 Gateway 模式下按 OpenAI 格式处理的请求保持原样
+pub async fn anthropic_error_envelope(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json_error = !response.status().is_success()
+        && response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+    let handled_as_openai = response
+        .headers()
+        .get(DETECTED_FORMAT_HEADER)
+        .is_some_and(|v| v == RequestFormat::OpenAI.as_str());
+    if !is_json_error || handled_as_openai {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Failed to read error response body: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let mut value: serde_json::Value = match serde_json::from_slice(&bytes) {
+        Ok(value) => value,
+        Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    match value.as_object_mut() {
+        Some(obj) if obj.contains_key("error") && !obj.contains_key("type") => {
+            obj.insert("type".to_string(), json!("error"));
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(value.to_string()))
+        }
+        _ => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+/// 按 Anthropic 格式处理已解析的请求
+pub(crate) async fn handle_anthropic_request(
+    config: Arc<Config>,
+    clients: Clients,
+    rate_limits: RateLimitBuckets,
+    policy: ContentPolicy,
+    mut headers: HeaderMap,
+    body: axum::body::Bytes,
+    mut raw_json: serde_json::Value,
+) -> ProxyResult<Response> {
+    // x-proxy-set-* 请求头覆盖请求体字段；没有这些头时透传的原始 body 保持不变
+    let overridden =
+        apply_header_overrides(&mut headers, &mut raw_json, RequestFormat::Anthropic)?;
+    validation::validate(&raw_json, RequestFormat::Anthropic, config.validation)?;
+    // 内容策略检查，redact 规则改写过的请求体需要重新序列化
+    let overridden = policy.inspect(&mut raw_json, RequestFormat::Anthropic)? || overridden;
+
+    // 提取必要字段用于路由决策；缺少 model 时无法路由，不受 VALIDATION 模式影响
+    let requested_model = match raw_json.get("model") {
+        Some(serde_json::Value::String(model)) => model.clone(),
+        other => {
+            return Err(ProxyError::InvalidRequest {
+                message: match other {
+                    None | Some(serde_json::Value::Null) => "'model' is required".to_string(),
+                    Some(value) => format!("'model' must be a string, got {}", value),
+                },
+                received_fields: raw_json
+                    .as_object()
+                    .map(|obj| obj.keys().cloned().collect())
+                    .unwrap_or_default(),
+            })
+        }
+    };
+    // 去掉 STRIP_MODEL_PREFIX 前缀后再路由和转发，客户端请求的原始模型名用于响应回显
+    let (model, stripped) = strip_model_prefix(&config, &mut raw_json, &requested_model);
+    let overridden = stripped || overridden;
+    rate_limit::check(&config, &rate_limits, &model)?;
+
+    let body_stream = raw_json.get("stream").and_then(|v| v.as_bool());
+    let is_streaming = is_streaming_request(&headers, body_stream, config.stream_from_accept);
+
+    let user_id = metadata_user_id(raw_json.get("metadata")).map(String::from);
+    let mut ctx = RequestContext::new(&config, &headers, &requested_model, user_id.as_deref());
+
+    // 由 Accept 头推断出流式时，把 stream 写回请求体，确保上游按流式返回
+    let body = match raw_json.as_object_mut() {
+        Some(obj) if is_streaming && body_stream != Some(true) => {
+            obj.insert("stream".to_string(), serde_json::Value::Bool(true));
+            axum::body::Bytes::from(serde_json::to_vec(&raw_json)?)
+        }
+        _ if overridden => axum::body::Bytes::from(serde_json::to_vec(&raw_json)?),
+        _ => body,
+    };
 
     tracing::debug!("Received Anthropic request for model: {}", model);
     tracing::debug!("Streaming: {}", is_streaming);
 
     // 路由决策
-    let decision = RoutingDecision::decide(RequestFormat::Anthropic, model, &config)?;
+    let decision = RoutingDecision::decide(RequestFormat::Anthropic, &model, &config)?;
 
     tracing::debug!(
-        "Routing decision: backend={:?}, needs_transform={}, direction={:?}",
-        decision.backend,
-        decision.needs_transform,
-        decision.transform_direction
+        model = %model,
+        backend = ?decision.backend,
+        needs_transform = decision.needs_transform,
+        direction = ?decision.transform_direction,
+        target_url = decision.target_url.as_deref().unwrap_or("-"),
+        "Routing decision"
     );
 
     if config.verbose {
         tracing::trace!(
             "Incoming Anthropic request: {}",
-            serde_json::to_string_pretty(&raw_json).unwrap_or_default()
+            log_json(&raw_json, &config)
         );
     }
 
-    match (decision.backend, decision.needs_transform) {
+    let response_model_mode = config.response_model_mode;
+    let mut response = match (decision.backend, decision.needs_transform) {
         // 完全透传到 Anthropic（不解析结构体，直接转发原始 body）
         (Backend::Anthropic, false) => {
-            backends::anthropic::forward_raw_request(config, client, body, is_streaming).await
+            // 配置了 ANTHROPIC_METADATA_USER_ID 时改写 metadata.user_id，
+            // 配置了 DEFAULT_STOP 时合并 stop_sequences，并删除只对 OpenAI 后端有意义的
+            // response_format 扩展字段，否则原样转发
+            let mut rewritten = false;
+            if let Some(user_id) = &config.anthropic_metadata_user_id {
+                set_metadata_user_id(&mut raw_json, user_id);
+                rewritten = true;
+            }
+            rewritten |= merge_stop_field(&mut raw_json, "stop_sequences", &config.default_stop, None);
+            rewritten |= strip_response_format_extension(&mut raw_json);
+            let body = if rewritten {
+                axum::body::Bytes::from(serde_json::to_vec(&raw_json)?)
+            } else {
+                body
+            };
+            // 模型不存在时按 MODEL_FALLBACKS 回退，每次尝试改写请求体中的模型
+            let response = backends::with_model_fallbacks(&config, &model, |model| {
+                let body = body_with_model(&raw_json, &body, &model);
+                let (config, clients, headers) = (config.clone(), clients.clone(), &headers);
+                async move {
+                    backends::anthropic::forward_raw_request(config, clients, body?, headers, is_streaming)
+                        .await
+                }
+            })
+            .await?;
+            ctx.log_summary("passthrough");
+            Ok(response)
         }
         // 需要转换，先解析为结构体
         (Backend::OpenAI | Backend::Upstream, true) => {
             let req: anthropic::AnthropicRequest =
                 serde_json::from_value(raw_json.clone()).map_err(|e| {
                     tracing::error!("Failed to deserialize request: {}", e);
-                    ProxyError::Transform(format!("Failed to deserialize: {}", e))
+                    ProxyError::invalid_request(&e, &raw_json)
                 })?;
 
-            let openai_req = transform::anthropic_to_openai(req, &config)?;
+            if let Some(tools) = &req.tools {
+                ctx.record_tool_names(tools);
+            }
+
+            // 使用推理模型覆盖时保留原始请求，以便模型不存在时回退重试
+            let fallback_req = (config.reasoning_model_fallback
+                && config.reasoning_model.is_some()
+                && has_thinking(&req))
+            .then(|| req.clone());
+
+            // 满足条件的小请求可对冲到另一个 OpenAI 兼容后端，需要按该后端单独转换
+            let has_tools = req.tools.as_ref().is_some_and(|tools| !tools.is_empty());
+            let hedge = backends::hedge::hedge_backend(
+                &config,
+                decision.backend,
+                &model,
+                has_tools,
+                is_streaming,
+            )
+            .map(|backend| (backend, req.clone()));
+
+            let openai_req =
+                transform::anthropic_to_openai(req, &config, decision.backend, &mut ctx.transform_report)?;
+            validation::context_window(&config, &openai_req, RequestFormat::Anthropic)?;
+            ctx.resolved_model = Some(openai_req.model.clone());
 
             if config.verbose {
                 tracing::trace!(
                     "Transformed OpenAI request: {}",
-                    serde_json::to_string_pretty(&openai_req).unwrap_or_default()
+                    log_json(&openai_req, &config)
                 );
             }
 
+            let result = match hedge {
+                Some((secondary_backend, hedge_req)) => {
+                    // 对冲请求的改写与主请求相同，不重复记录
+                    let secondary_req =
+                        transform::anthropic_to_openai(hedge_req, &config, secondary_backend, &mut Vec::new())?;
+                    backends::hedge::send_hedged(
+                        &config,
+                        decision.backend,
+                        secondary_backend,
+                        send_transformed(
+                            config.clone(),
+                            clients.clone(),
+                            openai_req,
+                            decision.backend,
+                            &headers,
+                            ctx.clone(),
+                            is_streaming,
+                        ),
+                        || {
+                            send_transformed(
+                                config.clone(),
+                                clients.clone(),
+                                secondary_req,
+                                secondary_backend,
+                                &headers,
+                                ctx.clone(),
+                                is_streaming,
+                            )
+                        },
+                    )
+                    .await
+                }
+                None => {
+                    send_transformed(
+                        config.clone(),
+                        clients.clone(),
+                        openai_req,
+                        decision.backend,
+                        &headers,
+                        ctx.clone(),
+                        is_streaming,
+                    )
+                    .await
+                }
+            };
+
+            match (result, fallback_req) {
+                (Err(ProxyError::ModelNotFound(msg)), Some(req)) => {
+                    tracing::warn!(
+                        "Reasoning model not available upstream, retrying with fallback model: {}",
+                        msg
+                    );
+                    // 去掉推理模型覆盖：有 COMPLETION_MODEL 时使用它，否则使用请求中的原始模型
+                    let fallback_config = Config {
+                        reasoning_model: config.completion_model.clone(),
+                        ..(*config).clone()
+                    };
+                    let openai_req = transform::anthropic_to_openai(
+                        req,
+                        &fallback_config,
+                        decision.backend,
+                        &mut Vec::new(),
+                    )?;
+                    validation::context_window(&config, &openai_req, RequestFormat::Anthropic)?;
+                    ctx.transform_report.push(format!(
+                        "reasoning model fallback: {} -> {}",
+                        ctx.resolved_model.as_deref().unwrap_or("-"),
+                        openai_req.model
+                    ));
+                    ctx.resolved_model = Some(openai_req.model.clone());
+                    send_transformed(
+                        config,
+                        clients,
+                        openai_req,
+                        decision.backend,
+                        &headers,
+                        ctx,
+                        is_streaming,
+                    )
+                    .await
+                }
+                (result, _) => result,
+            }
+        }
+        _ => Err(ProxyError::Internal("Invalid routing decision".into())),
+    }?;
+
+    backends::add_requested_model_header(response_model_mode, &mut response, &requested_model);
+    monitor::attribute_user(&mut response, user_id);
+    audit::attribute_backend(&mut response, decision.backend);
+    Ok(response)
+}
+
+/// Token 计数端点处理器 (/v1/messages/count_tokens)
+///
+/// 路由到 Anthropic 后端的请求转发到上游的 count_tokens 端点；转换到 OpenAI 兼容后端的
+/// 请求在本地估算输入 token 数，不访问上游，`max_tokens` 可省略
+pub async fn count_tokens_handler(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(clients): Extension<Clients>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> ProxyResult<Response> {
+    let mut raw_json: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| ProxyError::transform(TransformFailure::InvalidJson, format!("Invalid JSON: {}", e)))?;
+    let requested_model = raw_json
+        .get("model")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let (model, stripped) = strip_model_prefix(&config, &mut raw_json, &requested_model);
+
+    let decision = RoutingDecision::decide(RequestFormat::Anthropic, &model, &config)?;
+    if decision.backend == Backend::Anthropic && !decision.needs_transform {
+        let body = if stripped {
+            axum::body::Bytes::from(serde_json::to_vec(&raw_json)?)
+        } else {
+            body
+        };
+        let url = config.anthropic_count_tokens_url();
+        return backends::anthropic::forward_api_request(config, clients, Method::POST, &url, body, &headers)
+            .await;
+    }
+
+    if let Some(obj) = raw_json.as_object_mut() {
+        obj.entry("max_tokens").or_insert(serde_json::json!(1));
+    }
+    let req = anthropic::AnthropicRequest::deserialize(&raw_json)
+        .map_err(|e| ProxyError::invalid_request(&e, &raw_json))?;
+
+    let input_tokens = tokens::estimate(&req.model, &req);
+    Ok(Json(serde_json::json!({ "input_tokens": input_tokens })).into_response())
+}
+
+/// Message Batches API 处理器 (/v1/messages/batches 及其子路径)
+///
+/// 创建、查询、列出、取消、删除批次和获取结果，原样透传到 Anthropic 后端
+pub async fn message_batches_handler(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(clients): Extension<Clients>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> ProxyResult<Response> {
+    let decision = RoutingDecision::decide_message_batches(&config)?;
+    let base_url = decision.target_url.unwrap_or_default();
+    let suffix = uri.path().strip_prefix("/v1/messages/batches").unwrap_or_default();
+    let url = match uri.query() {
+        Some(query) => format!("{}{}?{}", base_url, suffix, query),
+        None => format!("{}{}", base_url, suffix),
+    };
+
+    backends::anthropic::forward_api_request(config, clients, method, &url, body, &headers).await
+}
+
+/// 合成的模型信息中的 created_at：转换到其他后端的模型没有真实的发布时间
+const SYNTHESIZED_MODEL_CREATED_AT: &str = "1970-01-01T00:00:00Z";
+
+/// 模型信息处理器 (/v1/models/:model_id)
+///
+/// Anthropic SDK 在使用模型前以此校验模型是否存在。路由到 Anthropic 后端的模型原样透传；
+/// 会被转换发往 OpenAI 兼容后端的模型，按映射后的上游模型合成响应，避免校验失败
+pub async fn model_info_handler(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(clients): Extension<Clients>,
+    Path(model_id): Path<String>,
+    headers: HeaderMap,
+) -> ProxyResult<Response> {
+    let decision = RoutingDecision::decide(RequestFormat::Anthropic, &model_id, &config)?;
+    if decision.backend == Backend::Anthropic && !decision.needs_transform {
+        let url = config.anthropic_model_url(&model_id);
+        let body = axum::body::Bytes::new();
+        return backends::anthropic::forward_api_request(config, clients, Method::GET, &url, body, &headers)
+            .await;
+    }
+
+    let (upstream_model, _) =
+        parse_model_with_effort(config.completion_model.as_deref().unwrap_or(&model_id));
+    tracing::debug!(
+        "Synthesizing model info for {} (upstream model {})",
+        model_id,
+        upstream_model
+    );
+    Ok(Json(json!({
+        "type": "model",
+        "id": model_id,
+        "display_name": upstream_model,
+        "created_at": SYNTHESIZED_MODEL_CREATED_AT,
+    }))
+    .into_response())
+}
+
+/// 发送 A→O 转换后的请求到上游，模型不存在时按 MODEL_FALLBACKS 回退
+async fn send_transformed(
+    config: Arc<Config>,
+    clients: Clients,
+    openai_req: openai::OpenAIRequest,
+    backend: Backend,
+    client_headers: &HeaderMap,
+    ctx: RequestContext,
+    is_streaming: bool,
+) -> ProxyResult<Response> {
+    let model = openai_req.model.clone();
+    backends::with_model_fallbacks(&config, &model, |model| {
+        let ctx = Arc::new(RequestContext {
+            resolved_model: Some(model.clone()),
+            ..ctx.clone()
+        });
+        let openai_req = openai::OpenAIRequest {
+            model,
+            ..openai_req.clone()
+        };
+        let (config, clients) = (config.clone(), clients.clone());
+        async move {
             if is_streaming {
-                backends::upstream::handle_streaming(config, client, openai_req, decision.backend).await
+                backends::upstream::handle_streaming(
+                    config,
+                    clients,
+                    openai_req,
+                    backend,
+                    client_headers,
+                    ctx,
+                )
+                .await
             } else {
-                backends::upstream::handle_non_streaming(config, client, openai_req, decision.backend).await
+                backends::upstream::handle_non_streaming(
+                    config,
+                    clients,
+                    openai_req,
+                    backend,
+                    client_headers,
+                    ctx,
+                )
+                .await
             }
         }
-        _ => Err(ProxyError::Internal("Invalid routing decision".into())),
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{spawn_mock_upstream, test_config};
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    fn create_test_config(base_url: String) -> Config {
+        Config {
+            routing_mode: crate::config::RoutingMode::Transform,
+            base_url: Some(base_url),
+            reasoning_model: Some("missing-reasoning".to_string()),
+            reasoning_model_fallback: true,
+            ..test_config()
+        }
+    }
+
+    async fn call_handler(config: Config, body: axum::body::Bytes) -> ProxyResult<Response> {
+        call_handler_with_headers(config, HeaderMap::new(), body).await
+    }
+
+    async fn call_handler_with_headers(
+        config: Config,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    ) -> ProxyResult<Response> {
+        anthropic_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            headers,
+            body,
+        )
+        .await
+    }
+
+    fn thinking_request() -> axum::body::Bytes {
+        axum::body::Bytes::from(
+            json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Think about this"}],
+                "thinking": {"type": "enabled", "budget_tokens": 1024}
+            })
+            .to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_model_fallback_on_model_not_found() {
+        let config = create_test_config(spawn_mock_upstream().await);
+
+        let response = call_handler(config, thinking_request()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "claude-3-sonnet");
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_model_not_found_without_fallback() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model_fallback = false;
+
+        let result = call_handler(config, thinking_request()).await;
+
+        assert!(matches!(result, Err(ProxyError::ModelNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_model_returns_429() {
+        use axum::response::IntoResponse;
+
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.rate_limits = crate::config::RateLimit::parse_list("claude-3-sonnet=1/min");
+        let config = Arc::new(config);
+        let rate_limits = RateLimitBuckets::default();
+
+        let first = anthropic_handler(
+            Extension(config.clone()),
+            Extension(Clients::default()),
+            Extension(rate_limits.clone()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            thinking_request(),
+        )
+        .await;
+        assert!(first.is_ok());
+
+        let error = anthropic_handler(
+            Extension(config),
+            Extension(Clients::default()),
+            Extension(rate_limits),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            thinking_request(),
+        )
+        .await
+        .unwrap_err();
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_rejected_before_upstream() {
+        // 监听但从不响应的上游：任何转发都会在这里留下一个待接受的连接
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = create_test_config(format!("http://{}", upstream.local_addr().unwrap()));
+        config.reasoning_model = None;
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 0,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let result = call_handler(config, body).await;
+
+        match result {
+            Err(ProxyError::Validation { param, .. }) => assert_eq!(param, "max_tokens"),
+            other => panic!("expected validation error, got {:?}", other.map(|r| r.status())),
+        }
+        let accepted =
+            tokio::time::timeout(std::time::Duration::from_millis(100), upstream.accept()).await;
+        assert!(accepted.is_err(), "request reached the upstream");
+    }
+
+    #[tokio::test]
+    async fn test_context_window_exceeded_rejected_before_upstream() {
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = create_test_config(format!("http://{}", upstream.local_addr().unwrap()));
+        config.reasoning_model = None;
+        config.completion_model = Some("gpt-4".to_string());
+        config.context_window_limits = crate::config::parse_context_window_limits("gpt-4=1000");
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 4096,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let error = call_handler(config, body).await.unwrap_err();
+        match &error {
+            ProxyError::Validation { param, message, .. } => {
+                assert_eq!(param, "max_tokens");
+                assert!(message.ends_with("exceed the 1000 token context window of 'gpt-4'"), "{}", message);
+            }
+            other => panic!("expected validation error, got {:?}", other),
+        }
+        assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
+        let accepted =
+            tokio::time::timeout(std::time::Duration::from_millis(100), upstream.accept()).await;
+        assert!(accepted.is_err(), "request reached the upstream");
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_overloaded_returns_529() {
+        use axum::response::IntoResponse;
+
+        let mut config = create_test_config(String::new());
+        config.routing_mode = crate::config::RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+
+        let error = call_handler(
+            config,
+            axum::body::Bytes::from(
+                json!({
+                    "model": "claude-overloaded",
+                    "max_tokens": 100,
+                    "messages": [{"role": "user", "content": "Hi"}]
+                })
+                .to_string(),
+            ),
+        )
+        .await
+        .unwrap_err();
+        let response = error.into_response();
+        assert_eq!(response.status().as_u16(), 529);
+        assert_eq!(response.headers()["retry-after"], "1");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "overloaded_error");
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_model_fallbacks() {
+        let mut config = create_test_config(String::new());
+        config.routing_mode = crate::config::RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+        config.model_fallbacks = crate::config::ModelFallback::parse_list("claude-missing-*=claude-stable");
+
+        let response = call_handler(
+            config,
+            axum::body::Bytes::from(
+                json!({
+                    "model": "claude-missing-old",
+                    "max_tokens": 100,
+                    "messages": [{"role": "user", "content": "Hi"}]
+                })
+                .to_string(),
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.headers()[backends::MODEL_FALLBACK_HEADER], "claude-stable");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "claude-stable");
+    }
+
+    #[tokio::test]
+    async fn test_gateway_handles_openai_body_as_openai() {
+        let mock = spawn_mock_upstream().await;
+        let mut config = create_test_config(mock.clone());
+        config.routing_mode = crate::config::RoutingMode::Gateway;
+        config.openai_base_url = Some(mock);
+        config.openai_api_key = Some("sk-openai".to_string());
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let response = call_handler(config.clone(), body.clone()).await.unwrap();
+
+        assert_eq!(response.headers()[crate::handlers::DETECTED_FORMAT_HEADER], "openai");
+        let response_body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response_body: Value = serde_json::from_slice(&response_body).unwrap();
+        assert_eq!(response_body["object"], "chat.completion");
+
+        // 其他模式保持严格的端点格式
+        config.routing_mode = crate::config::RoutingMode::Auto;
+        let result = call_handler(config, body).await;
+        assert!(matches!(result, Err(ProxyError::InvalidRequest { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_forward_headers_allowlist() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.forward_headers = vec!["x-gateway-route".to_string()];
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-gateway-route", "eu-west".parse().unwrap());
+        headers.insert("x-not-listed", "secret".parse().unwrap());
+
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "echo-headers",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+        let response = call_handler_with_headers(config, headers, body).await.unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let received: Vec<&str> = body["content"][0]["text"].as_str().unwrap().split(',').collect();
+        assert!(received.contains(&"x-gateway-route"));
+        assert!(!received.contains(&"x-not-listed"));
+    }
+
+    /// 透传模式下回显请求体的调用，返回上游收到的原始 body
+    async fn passthrough_echo_body(
+        metadata_user_id: Option<&str>,
+        headers: HeaderMap,
+        body: &str,
+    ) -> String {
+        let mut config = create_test_config(String::new());
+        config.routing_mode = crate::config::RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+        config.anthropic_metadata_user_id = metadata_user_id.map(String::from);
+
+        let response = call_handler_with_headers(
+            config,
+            headers,
+            axum::body::Bytes::from(body.to_string()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response.extensions().get::<monitor::UserAttribution>().map(|u| u.0.as_str()),
+            Some("client-user")
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        body["content"][0]["text"].as_str().unwrap().to_string()
+    }
+
+    async fn message_batches(config: Config, method: Method, uri: &str, body: &str) -> ProxyResult<Response> {
+        message_batches_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            method,
+            uri.parse().unwrap(),
+            HeaderMap::new(),
+            axum::body::Bytes::from(body.to_string()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_message_batches_forwarded_to_anthropic() {
+        let mut config = create_test_config(String::new());
+        config.routing_mode = RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+
+        let create = r#"{"requests":[{"custom_id":"a","params":{"model":"claude-3","max_tokens":10,"messages":[]}}]}"#;
+        let cases = [
+            (Method::POST, "/v1/messages/batches", create),
+            (Method::GET, "/v1/messages/batches?limit=2", ""),
+            (Method::GET, "/v1/messages/batches/msgbatch_1", ""),
+            (Method::GET, "/v1/messages/batches/msgbatch_1/results", ""),
+            (Method::POST, "/v1/messages/batches/msgbatch_1/cancel", ""),
+            (Method::DELETE, "/v1/messages/batches/msgbatch_1", ""),
+        ];
+        for (method, uri, body) in cases {
+            let response = message_batches(config.clone(), method.clone(), uri, body)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/x-jsonl");
+
+            let received = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let received: Value = serde_json::from_slice(&received).unwrap();
+            let uri: axum::http::Uri = uri.parse().unwrap();
+            assert_eq!(received["method"], method.as_str());
+            assert_eq!(received["path"], uri.path());
+            assert_eq!(received["query"].as_str(), uri.query());
+            assert_eq!(received["x-api-key"], "sk-ant");
+            assert_eq!(received["anthropic-version"], "2023-06-01");
+            assert_eq!(received["body"], body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_batches_rejected_in_transform_mode() {
+        let config = create_test_config(spawn_mock_upstream().await);
+        let result = message_batches(config, Method::GET, "/v1/messages/batches", "").await;
+        assert!(matches!(result, Err(ProxyError::UnsupportedOperation(_))));
+    }
+
+    async fn model_info(config: Config, model_id: &str) -> Value {
+        let response = model_info_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Path(model_id.to_string()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_model_info_forwarded_to_anthropic() {
+        let mut config = create_test_config(String::new());
+        config.routing_mode = RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+
+        let received = model_info(config, "claude-sonnet-4-5").await;
+        assert_eq!(received["method"], "GET");
+        assert_eq!(received["path"], "/v1/models/claude-sonnet-4-5");
+        assert_eq!(received["x-api-key"], "sk-ant");
+        assert_eq!(received["anthropic-version"], "2023-06-01");
+    }
+
+    #[tokio::test]
+    async fn test_model_info_synthesized_in_transform_mode() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.completion_model = Some("gpt-5.1-codex-high".to_string());
+
+        let info = model_info(config, "claude-sonnet-4-5").await;
+        assert_eq!(info["type"], "model");
+        assert_eq!(info["id"], "claude-sonnet-4-5");
+        assert_eq!(info["display_name"], "gpt-5.1-codex");
+        assert_eq!(info["created_at"], SYNTHESIZED_MODEL_CREATED_AT);
+    }
+
+    async fn count_tokens(config: Config, body: &Value) -> ProxyResult<Value> {
+        let response = count_tokens_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            HeaderMap::new(),
+            axum::body::Bytes::from(body.to_string()),
+        )
+        .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        Ok(serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_without_max_tokens() {
+        let body = json!({
+            "model": "unknown-model",
+            "messages": [{"role": "user", "content": "abcdefgh"}]
+        });
+        let result = count_tokens(create_test_config(String::new()), &body).await.unwrap();
+        assert_eq!(result["input_tokens"], 8);
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_forwarded_to_anthropic() {
+        let mut config = create_test_config(String::new());
+        config.routing_mode = RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "abcdefgh"}]
+        });
+
+        let received = count_tokens(config, &body).await.unwrap();
+        assert_eq!(received["method"], "POST");
+        assert_eq!(received["path"], "/v1/messages/count_tokens");
+        assert_eq!(received["x-api-key"], "sk-ant");
+        // 原样转发，不补 max_tokens
+        assert_eq!(received["body"], body.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_request_counts_transform_failure() {
+        let before = crate::monitor::transform_failures(TransformFailure::InvalidJson);
+        let result = call_handler(
+            create_test_config(String::new()),
+            axum::body::Bytes::from(r#"{"model": "claude-3", "#),
+        )
+        .await;
+
+        assert!(matches!(result, Err(ProxyError::Transform(_))));
+        // 计数是进程级的，并行运行的其他测试也可能增加它
+        assert!(crate::monitor::transform_failures(TransformFailure::InvalidJson) > before);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_lists_received_fields() {
+        let body = json!({"model": "claude-3", "max_tokens": 10});
+        let err = count_tokens(create_test_config(String::new()), &body).await.unwrap_err();
+        match &err {
+            ProxyError::InvalidRequest { message, received_fields } => {
+                assert!(message.contains("missing field `messages`"), "{}", message);
+                assert_eq!(received_fields, &vec!["max_tokens".to_string(), "model".to_string()]);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(body["error"]["received_fields"], json!(["max_tokens", "model"]));
+    }
+
+    #[tokio::test]
+    async fn test_missing_model_rejected_as_invalid_request() {
+        let mut config = create_test_config("http://127.0.0.1:9".to_string());
+        config.validation = crate::config::ValidationMode::Off;
+        let app = axum::Router::new()
+            .route(
+                "/v1/messages",
+                axum::routing::post(anthropic_handler)
+                    .layer(axum::middleware::from_fn(anthropic_error_envelope)),
+            )
+            .layer(Extension(Arc::new(config)))
+            .layer(Extension(Clients::default()))
+            .layer(Extension(RateLimitBuckets::default()))
+            .layer(Extension(ContentPolicy::default()));
+        let request = axum::http::Request::post("/v1/messages")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"max_tokens":10,"messages":[{"role":"user","content":"Hi"}]}"#))
+            .unwrap();
+
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(body["error"]["message"], "'model' is required");
+        assert_eq!(body["error"]["received_fields"], json!(["max_tokens", "messages"]));
+    }
+
+    #[tokio::test]
+    async fn test_error_envelope_skips_openai_and_wrapped_errors() {
+        async fn envelope(response: Response) -> Value {
+            // 处理器需要 Clone，响应只取一次
+            let response = Arc::new(std::sync::Mutex::new(Some(response)));
+            let handler = move || {
+                let response = response.lock().unwrap().take().unwrap();
+                async move { response }
+            };
+            let app = axum::Router::new().route(
+                "/",
+                axum::routing::get(handler).layer(axum::middleware::from_fn(anthropic_error_envelope)),
+            );
+            let request = axum::http::Request::get("/").body(Body::empty()).unwrap();
+            let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice(&body).unwrap()
+        }
+
+        // Gateway 模式下按 OpenAI 格式处理的请求
+        let mut response = ProxyError::Upstream("boom".into()).into_response();
+        set_detected_format(&mut response, RequestFormat::OpenAI);
+        assert!(envelope(response).await.get("type").is_none());
+
+        // 透传的上游错误已经带信封
+        let upstream = json!({"type": "error", "error": {"type": "not_found_error", "message": "x"}});
+        let response = (StatusCode::NOT_FOUND, Json(upstream.clone())).into_response();
+        assert_eq!(envelope(response).await, upstream);
+
+        // 成功响应不受影响
+        let response = Json(json!({"error": null})).into_response();
+        assert_eq!(envelope(response).await, json!({"error": null}));
+    }
+
+    const ECHO_BODY_REQUEST: &str = r#"{"model":"echo-body",  "max_tokens":100,"messages":[{"role":"user","content":"Hi"}],"metadata":{"user_id":"client-user"}}"#;
+
+    #[tokio::test]
+    async fn test_passthrough_body_untouched_without_metadata_injection() {
+        let received = passthrough_echo_body(None, HeaderMap::new(), ECHO_BODY_REQUEST).await;
+        assert_eq!(received, ECHO_BODY_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_injects_metadata_user_id() {
+        let received = passthrough_echo_body(Some("proxy-tenant"), HeaderMap::new(), ECHO_BODY_REQUEST).await;
+        let received: Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(received["metadata"]["user_id"], "proxy-tenant");
+        assert_eq!(received["messages"][0]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_strips_response_format_extension() {
+        let request = r#"{"model":"echo-body","max_tokens":100,"messages":[{"role":"user","content":"Hi"}],"response_format":{"type":"json_object"},"metadata":{"user_id":"client-user","response_format":{"type":"json_object"}}}"#;
+        let received = passthrough_echo_body(None, HeaderMap::new(), request).await;
+        let received: Value = serde_json::from_str(&received).unwrap();
+        assert!(received.get("response_format").is_none());
+        assert_eq!(received["metadata"], json!({"user_id": "client-user"}));
+    }
+
+    #[tokio::test]
+    async fn test_non_json_content_type_rejected_with_415() {
+        use axum::response::IntoResponse;
+
+        let call = |content_type: &'static str| async move {
+            let mut headers = HeaderMap::new();
+            headers.insert(axum::http::header::CONTENT_TYPE, content_type.parse().unwrap());
+            let mut config = create_test_config(String::new());
+            config.routing_mode = crate::config::RoutingMode::Passthrough;
+            config.anthropic_base_url = Some(spawn_mock_upstream().await);
+            config.anthropic_api_key = Some("sk-ant".to_string());
+            call_handler_with_headers(
+                config,
+                headers,
+                axum::body::Bytes::from(ECHO_BODY_REQUEST),
+            )
+            .await
+        };
+
+        let response = call("text/plain").await.unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert!(body["error"]["message"].as_str().unwrap().contains("text/plain"));
+
+        for content_type in ["application/json", "Application/JSON; charset=utf-8"] {
+            assert!(call(content_type).await.is_ok(), "{}", content_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_rejects_non_object_body() {
+        let mut config = create_test_config(String::new());
+        config.routing_mode = crate::config::RoutingMode::Passthrough;
+        config.anthropic_base_url = Some(spawn_mock_upstream().await);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+
+        let result = backends::anthropic::forward_raw_request(
+            Arc::new(config),
+            Clients::default(),
+            axum::body::Bytes::from(" \n[{\"model\": \"claude-3-5-sonnet\"}]"),
+            &HeaderMap::new(),
+            false,
+        )
+        .await;
+        match result {
+            Err(ProxyError::Transform(message)) => assert_eq!(message, "Request body must be a JSON object"),
+            other => panic!("expected transform error, got {:?}", other.map(|r| r.status())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_header_overrides() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-proxy-set-max-tokens", "32".parse().unwrap());
+        headers.insert("x-proxy-set-stop", "END".parse().unwrap());
+        let received = passthrough_echo_body(None, headers, ECHO_BODY_REQUEST).await;
+        let received: Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(received["max_tokens"], 32);
+        assert_eq!(received["stop_sequences"], json!(["END"]));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_override_header_returns_400() {
+        use axum::response::IntoResponse;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-proxy-set-temperature", "warm".parse().unwrap());
+        let error = call_handler_with_headers(
+            create_test_config(spawn_mock_upstream().await),
+            headers,
+            axum::body::Bytes::from(ECHO_BODY_REQUEST),
+        )
+        .await
+        .unwrap_err();
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("x-proxy-set-temperature"));
+    }
+
+    fn long_tool_request(stream: bool) -> (String, axum::body::Bytes) {
+        let tool_name = format!("mcp__{}__lookup", "knowledge_base".repeat(5));
+        let body = json!({
+            "model": "echo-tool",
+            "max_tokens": 100,
+            "stream": stream,
+            "messages": [{"role": "user", "content": "Look it up"}],
+            "tools": [{"name": tool_name, "input_schema": {"type": "object"}}]
+        });
+        (tool_name, axum::body::Bytes::from(body.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_tool_name_restored_in_response() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        let (tool_name, body) = long_tool_request(false);
+
+        let response = call_handler(config, body).await.unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let upstream_name = body["content"][0]["text"].as_str().unwrap();
+        assert!(upstream_name.len() <= 64);
+        assert_ne!(upstream_name, tool_name);
+        assert_eq!(body["content"][1]["name"], tool_name);
+    }
+
+    #[tokio::test]
+    async fn test_tool_name_restored_in_stream() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        let (tool_name, body) = long_tool_request(true);
+
+        let response = call_handler(config, body).await.unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let events: Vec<Value> = String::from_utf8_lossy(&body)
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let tool_start = events
+            .iter()
+            .find(|e| e["type"] == "content_block_start" && e["content_block"]["type"] == "tool_use")
+            .unwrap();
+        assert_eq!(tool_start["content_block"]["name"], tool_name);
+    }
+
+    #[tokio::test]
+    async fn test_model_fallbacks_on_model_not_found() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.model_fallbacks = crate::config::ModelFallback::parse_list(
+            "missing-*=missing-too,stable-model",
+        );
+
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "missing-old",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+        let response = call_handler(config, body).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[backends::MODEL_FALLBACK_HEADER],
+            "stable-model"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "stable-model");
+    }
+
+    #[tokio::test]
+    async fn test_model_fallbacks_for_streaming_request() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.model_fallbacks = crate::config::ModelFallback::parse_list("missing-old=echo-tool");
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "missing-old",
+                "max_tokens": 100,
+                "stream": true,
+                "messages": [{"role": "user", "content": "Hello"}],
+                "tools": [{"name": "lookup", "input_schema": {"type": "object"}}]
+            })
+            .to_string(),
+        );
+
+        let response = call_handler(config, body).await.unwrap();
+
+        assert_eq!(response.headers()[backends::MODEL_FALLBACK_HEADER], "echo-tool");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("event: message_stop"));
+    }
+
+    fn override_request(stream: bool) -> axum::body::Bytes {
+        axum::body::Bytes::from(
+            json!({
+                "model": "claude-sonnet-4",
+                "max_tokens": 100,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Hello"}],
+                "tools": [{"name": "lookup", "input_schema": {"type": "object"}}]
+            })
+            .to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_response_model_mode_requested() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.completion_model = Some("echo-tool".to_string());
+        config.response_model_mode = crate::config::ResponseModelMode::Requested;
+
+        for stream in [false, true] {
+            let response = call_handler(config.clone(), override_request(stream)).await.unwrap();
+
+            assert!(response.headers().get(backends::REQUESTED_MODEL_HEADER).is_none());
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains(r#""model":"claude-sonnet-4""#), "{}", body);
+            assert!(!body.contains("echo-tool"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_model_mode_both() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.completion_model = Some("echo-tool".to_string());
+        config.response_model_mode = crate::config::ResponseModelMode::Both;
+
+        for stream in [false, true] {
+            let response = call_handler(config.clone(), override_request(stream)).await.unwrap();
+
+            assert_eq!(
+                response.headers()[backends::REQUESTED_MODEL_HEADER],
+                "claude-sonnet-4"
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(String::from_utf8_lossy(&body).contains(r#""model":"echo-tool""#));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strip_model_prefix_before_routing() {
+        let mock = spawn_mock_upstream().await;
+        let mut config = create_test_config(mock.clone());
+        config.routing_mode = crate::config::RoutingMode::Auto;
+        config.anthropic_base_url = Some(mock);
+        config.anthropic_api_key = Some("sk-ant".to_string());
+        // 通配符匹配整个模型名，只有去掉前缀后才路由到 Anthropic
+        config.anthropic_model_patterns = vec!["echo-*".to_string()];
+        config.strip_model_prefixes = vec!["openrouter/".to_string()];
+        config.response_model_mode = crate::config::ResponseModelMode::Both;
+
+        let response = call_handler(
+            config,
+            axum::body::Bytes::from(
+                json!({
+                    "model": "OpenRouter/echo-body",
+                    "max_tokens": 100,
+                    "messages": [{"role": "user", "content": "Hi"}]
+                })
+                .to_string(),
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response.headers()[backends::REQUESTED_MODEL_HEADER],
+            "OpenRouter/echo-body"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        // Anthropic 端点回显收到的请求体
+        let received: Value = serde_json::from_str(body["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(received["model"], "echo-body");
+    }
+
+    #[tokio::test]
+    async fn test_strip_model_prefix_transformed_request() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        config.completion_model = None;
+        config.strip_model_prefixes = vec!["openrouter/".to_string()];
+        config.response_model_mode = crate::config::ResponseModelMode::Requested;
+
+        let response = call_handler(
+            config,
+            axum::body::Bytes::from(
+                json!({
+                    "model": "openrouter/echo-body",
+                    "max_tokens": 100,
+                    "messages": [{"role": "user", "content": "Hi"}]
+                })
+                .to_string(),
+            ),
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        // 上游收到去掉前缀的模型名，响应回显客户端请求的原始模型名
+        let received: Value = serde_json::from_str(body["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(received["model"], "echo-body");
+        assert_eq!(body["model"], "openrouter/echo-body");
+    }
+
+    #[tokio::test]
+    async fn test_accept_header_implies_streaming() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.reasoning_model = None;
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "echo-tool",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}],
+                "tools": [{"name": "lookup", "input_schema": {"type": "object"}}]
+            })
+            .to_string(),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", "text/event-stream".parse().unwrap());
+
+        let response = call_handler_with_headers(config, headers, body).await.unwrap();
+
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("event: message_stop"));
+    }
+
+    #[tokio::test]
+    async fn test_hedged_request_returns_secondary_and_cancels_primary() {
+        use crate::test_utils::spawn_slow_upstream;
+        use std::time::Duration;
+
+        let (slow_url, primary_cancelled) = spawn_slow_upstream(Duration::from_secs(5)).await;
+        let mut config = create_test_config(slow_url);
+        config.reasoning_model = None;
+        config.openai_base_url = Some(spawn_mock_upstream().await);
+        config.openai_api_key = Some("sk-openai".to_string());
+        config.hedge_after_ms = 50;
+        config.hedge_models = vec!["*haiku*".to_string()];
+
+        let request = |model: &str, tools: Option<Value>| {
+            let mut body = json!({
+                "model": model,
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}]
+            });
+            if let Some(tools) = tools {
+                body["tools"] = tools;
+            }
+            axum::body::Bytes::from(body.to_string())
+        };
+
+        let response = call_handler(config.clone(), request("claude-3-5-haiku", None))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[backends::hedge::HEDGE_WINNER_HEADER], "openai");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["content"][0]["text"], "ok");
+
+        // 主请求的连接被丢弃后，模拟上游中止处理
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(primary_cancelled.load(std::sync::atomic::Ordering::SeqCst));
+
+        // 带工具的请求从不对冲：等待慢速主后端
+        config.hedge_after_ms = 10;
+        let (slow_url, _) = spawn_slow_upstream(Duration::from_millis(100)).await;
+        config.base_url = Some(slow_url);
+        let tools = json!([{"name": "search", "input_schema": {"type": "object"}}]);
+        let response = call_handler(config, request("claude-3-5-haiku", Some(tools)))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(backends::hedge::HEDGE_WINNER_HEADER));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["content"][0]["text"], "slow");
     }
 }
@@ -0,0 +1,248 @@
+//! 批处理端点处理器 (/v1/batch)
+//!
+//! 在一次 HTTP 调用中并发执行多个独立的非流式请求
+
+use crate::backends::Clients;
+use crate::config::Config;
+use crate::error::{ProxyError, ProxyResult};
+use crate::monitor::TransformFailure;
+use crate::policy::ContentPolicy;
+use crate::rate_limit::RateLimitBuckets;
+use crate::handlers::{anthropic_handler, openai_handler};
+use axum::{
+    body::Bytes,
+    http::{header::ACCEPT, HeaderMap},
+    response::Response,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// 批处理中的单个请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchItem {
+    pub custom_id: String,
+    /// 请求格式："anthropic"（默认）或 "openai"
+    #[serde(default)]
+    pub format: BatchItemFormat,
+    /// 请求体，与对应端点的请求格式相同
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchItemFormat {
+    #[default]
+    Anthropic,
+    OpenAI,
+}
+
+/// 批处理中的单个结果，`response` 与 `error` 二选一
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub custom_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+/// 批处理端点处理器
+pub async fn batch_handler(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(clients): Extension<Clients>,
+    Extension(rate_limits): Extension<RateLimitBuckets>,
+    Extension(policy): Extension<ContentPolicy>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ProxyResult<Json<Vec<BatchResult>>> {
+    let items: Vec<BatchItem> = serde_json::from_slice(&body).map_err(|e| {
+        tracing::error!("Failed to parse batch request: {}", e);
+        ProxyError::transform(TransformFailure::InvalidJson, format!("Invalid batch request: {}", e))
+    })?;
+
+    tracing::debug!("Received batch with {} requests", items.len());
+
+    let semaphore = Arc::new(Semaphore::new(config.batch_max_concurrency));
+
+    // join_all 保持结果顺序与请求顺序一致
+    let results = futures::future::join_all(items.into_iter().map(|item| {
+        let config = config.clone();
+        let clients = clients.clone();
+        let rate_limits = rate_limits.clone();
+        let policy = policy.clone();
+        let semaphore = semaphore.clone();
+        let headers = headers.clone();
+        async move {
+            let _permit = semaphore.acquire().await;
+            let custom_id = item.custom_id.clone();
+            match execute_item(config, clients, rate_limits, policy, headers, item).await {
+                Ok(response) => BatchResult {
+                    custom_id,
+                    response: Some(response),
+                    error: None,
+                },
+                Err(e) => {
+                    tracing::debug!("Batch item {} failed: {}", custom_id, e);
+                    BatchResult {
+                        custom_id,
+                        response: None,
+                        error: Some(json!({
+                            "type": "proxy_error",
+                            "message": e.to_string(),
+                        })),
+                    }
+                }
+            }
+        }
+    }))
+    .await;
+
+    Ok(Json(results))
+}
+
+/// 执行单个批处理请求，复用对应端点的路由与非流式后端
+async fn execute_item(
+    config: Arc<Config>,
+    clients: Clients,
+    rate_limits: RateLimitBuckets,
+    policy: ContentPolicy,
+    mut headers: HeaderMap,
+    item: BatchItem,
+) -> ProxyResult<Value> {
+    if item.params.get("stream").and_then(|v| v.as_bool()) == Some(true) {
+        return Err(ProxyError::transform(
+            TransformFailure::UnsupportedBlock,
+            "Streaming requests are not supported in batches",
+        ));
+    }
+    // 批处理项总是非流式，避免由 Accept 头推断出流式
+    headers.remove(ACCEPT);
+
+    let body = Bytes::from(serde_json::to_vec(&item.params)?);
+    let response = match item.format {
+        BatchItemFormat::Anthropic => {
+            anthropic_handler(
+                Extension(config),
+                Extension(clients),
+                Extension(rate_limits),
+                Extension(policy),
+                headers,
+                body,
+            )
+            .await?
+        }
+        BatchItemFormat::OpenAI => {
+            openai_handler(
+                Extension(config),
+                Extension(clients),
+                Extension(rate_limits),
+                Extension(policy),
+                headers,
+                body,
+            )
+            .await?
+        }
+    };
+
+    read_json_body(response).await
+}
+
+async fn read_json_body(response: Response) -> ProxyResult<Value> {
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .map_err(|e| ProxyError::Internal(format!("Failed to read response body: {}", e)))?;
+    // 端点以错误响应（而非 Err）返回时同样记为失败，如 Gateway 模式下按 OpenAI 格式处理的请求
+    if !status.is_success() {
+        return Err(ProxyError::Upstream(format!("{}: {}", status, String::from_utf8_lossy(&bytes))));
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{spawn_mock_upstream, test_config};
+
+    fn create_test_config(base_url: String) -> Config {
+        Config {
+            routing_mode: crate::config::RoutingMode::Transform,
+            base_url: Some(base_url),
+            batch_max_concurrency: 2,
+            ..test_config()
+        }
+    }
+
+    fn anthropic_params(model: &str) -> Value {
+        json!({
+            "model": model,
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_batch_mixed_results_preserve_order() {
+        let config = create_test_config(spawn_mock_upstream().await);
+        let body = json!([
+            {"custom_id": "first", "params": anthropic_params("model-a")},
+            {"custom_id": "missing", "params": anthropic_params("missing-reasoning")},
+            {"custom_id": "streaming", "params": {
+                "model": "model-b",
+                "max_tokens": 100,
+                "stream": true,
+                "messages": [{"role": "user", "content": "Hello"}]
+            }},
+            {"custom_id": "openai", "format": "openai", "params": {
+                "model": "gpt-4",
+                "messages": [{"role": "user", "content": "Hello"}]
+            }},
+            {"custom_id": "last", "params": anthropic_params("model-c")}
+        ]);
+
+        let Json(results) = batch_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            Bytes::from(body.to_string()),
+        )
+        .await
+        .unwrap();
+
+        let ids: Vec<&str> = results.iter().map(|r| r.custom_id.as_str()).collect();
+        assert_eq!(ids, vec!["first", "missing", "streaming", "openai", "last"]);
+
+        assert_eq!(results[0].response.as_ref().unwrap()["model"], "model-a");
+        assert!(results[0].error.is_none());
+        assert!(results[1].error.is_some());
+        assert!(results[2].error.as_ref().unwrap()["message"]
+            .as_str()
+            .unwrap()
+            .contains("Streaming"));
+        // Transform 模式下 OpenAI 请求透传到同一上游
+        assert_eq!(results[3].response.as_ref().unwrap()["model"], "gpt-4");
+        assert_eq!(results[4].response.as_ref().unwrap()["model"], "model-c");
+    }
+
+    #[tokio::test]
+    async fn test_batch_rejects_invalid_body() {
+        let config = create_test_config("http://127.0.0.1:1".to_string());
+
+        let result = batch_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            Bytes::from(r#"{"not": "an array"}"#),
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+}
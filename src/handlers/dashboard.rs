@@ -0,0 +1,418 @@
+//! 监控面板处理器 (/dashboard)
+//!
+//! 内嵌单页面板本身不含数据，无需鉴权；页面轮询的 JSON 端点只接受请求头中的 ADMIN_KEY。
+//! EventSource 无法设置请求头，因此 SSE 事件流另外接受由 `POST /dashboard/api/events-token`
+//! 签发的短期令牌（`?token=`），管理密钥本身不会出现在 URL 中
+
+use crate::backends::{Backend, Clients};
+use crate::config::Config;
+use crate::doctor::{probe_backends, Finding, Severity};
+use crate::error::{ProxyError, ProxyResult};
+use crate::log_tail::LogTail;
+use crate::monitor::{Monitor, RequestSummary};
+use axum::{
+    extract::Query,
+    http::{header::AUTHORIZATION, HeaderMap},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html,
+    },
+    Extension, Json,
+};
+use futures::stream::Stream;
+use ring::hmac;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// 事件流令牌的有效期
+pub const EVENTS_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// 后台健康检查的间隔
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 签名事件流令牌时的用途前缀，避免与其他用途的签名混用
+const EVENTS_TOKEN_PURPOSE: &str = "dashboard-events";
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn admin_key(config: &Config) -> ProxyResult<&str> {
+    config
+        .admin_key
+        .as_deref()
+        .ok_or_else(|| ProxyError::Unauthorized("ADMIN_KEY not configured".into()))
+}
+
+/// 常量时间比较：对两边各做一次 HMAC 后由 `hmac::verify` 比较，耗时与不匹配的位置无关
+fn keys_match(expected: &str, provided: &str) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, expected.as_bytes());
+    let tag = hmac::sign(&key, expected.as_bytes());
+    hmac::verify(&key, provided.as_bytes(), tag.as_ref()).is_ok()
+}
+
+/// 校验管理密钥：只接受 `Authorization: Bearer` 或 `x-admin-key` 请求头
+fn authorize(config: &Config, headers: &HeaderMap) -> ProxyResult<()> {
+    let admin_key = admin_key(config)?;
+
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-key").and_then(|v| v.to_str().ok()));
+
+    match provided {
+        Some(key) if keys_match(admin_key, key) => Ok(()),
+        _ => Err(ProxyError::Unauthorized("Invalid admin key".into())),
+    }
+}
+
+/// 签发在 `expires_at`（Unix 秒）前有效的事件流令牌：`<expires_at>.<hmac 十六进制>`
+fn issue_events_token(admin_key: &str, expires_at: u64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, admin_key.as_bytes());
+    let tag = hmac::sign(&key, format!("{}:{}", EVENTS_TOKEN_PURPOSE, expires_at).as_bytes());
+    format!("{}.{}", expires_at, crate::audit::hex(tag.as_ref()))
+}
+
+fn verify_events_token(admin_key: &str, token: &str, now: u64) -> bool {
+    let Some((expires_at, signature)) = token.split_once('.') else {
+        return false;
+    };
+    let Ok(expires_at) = expires_at.parse::<u64>() else {
+        return false;
+    };
+    if expires_at < now {
+        return false;
+    }
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    let key = hmac::Key::new(hmac::HMAC_SHA256, admin_key.as_bytes());
+    hmac::verify(
+        &key,
+        format!("{}:{}", EVENTS_TOKEN_PURPOSE, expires_at).as_bytes(),
+        &signature,
+    )
+    .is_ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 各后端最近一次健康检查的结果
+#[derive(Default)]
+pub struct BackendHealth {
+    results: RwLock<Vec<(Backend, Finding)>>,
+    /// 最近一次检查完成的 Unix 秒，0 表示尚未检查
+    checked_at: RwLock<u64>,
+}
+
+impl BackendHealth {
+    /// 探测所有已配置的后端并替换上一次的结果
+    pub async fn check(&self, config: &Config, clients: &Clients) {
+        let results = probe_backends(config, clients).await;
+        *self.results.write().unwrap_or_else(|e| e.into_inner()) = results;
+        *self.checked_at.write().unwrap_or_else(|e| e.into_inner()) = unix_secs();
+    }
+
+    fn status(&self, backend: Backend) -> Value {
+        let results = self.results.read().unwrap_or_else(|e| e.into_inner());
+        match results.iter().find(|(b, _)| *b == backend) {
+            Some((_, finding)) => json!({
+                "status": match finding.severity {
+                    Severity::Ok => "ok",
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                },
+                "message": finding.message,
+                "fix": finding.fix,
+            }),
+            None => json!({"status": "unknown"}),
+        }
+    }
+}
+
+/// 启动后台健康检查：立即探测一次，之后每 [`HEALTH_CHECK_INTERVAL`] 探测一次
+pub fn spawn_health_checks(health: Arc<BackendHealth>, config: Arc<Config>, clients: Clients) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            health.check(&config, &clients).await;
+        }
+    });
+}
+
+/// 面板页面
+pub async fn dashboard_page() -> Html<&'static str> {
+    Html(DASHBOARD_HTML)
+}
+
+/// 路由模式、后端配置与健康状态、累计计数
+pub async fn dashboard_status(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(monitor): Extension<Arc<Monitor>>,
+    Extension(health): Extension<Arc<BackendHealth>>,
+    headers: HeaderMap,
+) -> ProxyResult<Json<Value>> {
+    authorize(&config, &headers)?;
+
+    let backend = |backend: Backend, name: &str, url: &Option<String>, configured: bool| {
+        json!({
+            "name": name,
+            "url": url,
+            "configured": configured,
+            "health": health.status(backend),
+        })
+    };
+
+    Ok(Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "routing_mode": config.routing_mode.to_string(),
+        "backends": [
+            backend(
+                Backend::Anthropic,
+                "anthropic",
+                &config.anthropic_base_url,
+                config.anthropic_base_url.is_some() && config.anthropic_api_key.is_some(),
+            ),
+            backend(
+                Backend::OpenAI,
+                "openai",
+                &config.openai_base_url,
+                config.openai_base_url.is_some() && config.openai_api_key.is_some(),
+            ),
+            backend(Backend::Upstream, "upstream", &config.base_url, config.base_url.is_some()),
+        ],
+        "health_checked_at": *health.checked_at.read().unwrap_or_else(|e| e.into_inner()),
+        "counters": monitor.counters(),
+    })))
+}
+
+/// 最近的请求历史
+pub async fn dashboard_requests(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(monitor): Extension<Arc<Monitor>>,
+    headers: HeaderMap,
+) -> ProxyResult<Json<Vec<RequestSummary>>> {
+    authorize(&config, &headers)?;
+    Ok(Json(monitor.recent()))
+}
+
+/// 签发事件流令牌，供 EventSource 以 `?token=` 连接 /dashboard/events
+pub async fn dashboard_events_token(
+    Extension(config): Extension<Arc<Config>>,
+    headers: HeaderMap,
+) -> ProxyResult<Json<Value>> {
+    authorize(&config, &headers)?;
+    let expires_at = unix_secs() + EVENTS_TOKEN_TTL.as_secs();
+    Ok(Json(json!({
+        "token": issue_events_token(admin_key(&config)?, expires_at),
+        "expires_in": EVENTS_TOKEN_TTL.as_secs(),
+    })))
+}
+
+/// 实时事件流：`request` 为请求摘要，`log` 为日志行（连接时先推送最近的日志）
+pub async fn dashboard_events(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(monitor): Extension<Arc<Monitor>>,
+    Extension(log_tail): Extension<Arc<LogTail>>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> ProxyResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    // 令牌只在建立连接时校验，过期后已建立的连接不受影响
+    let token_valid = match (admin_key(&config), params.get("token")) {
+        (Ok(admin_key), Some(token)) => verify_events_token(admin_key, token, unix_secs()),
+        _ => false,
+    };
+    if !token_valid {
+        authorize(&config, &headers)?;
+    }
+
+    let mut requests = monitor.subscribe();
+    let mut logs = log_tail.subscribe();
+    let backlog = log_tail.recent();
+    let stream = async_stream::stream! {
+        for line in backlog {
+            if let Ok(event) = Event::default().event("log").json_data(&line) {
+                yield Ok(event);
+            }
+        }
+        loop {
+            let event = tokio::select! {
+                received = requests.recv() => match received {
+                    Ok(summary) => Event::default().event("request").json_data(&summary),
+                    // 落后太多时跳过丢失的事件继续推送
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!("Dashboard subscriber lagged, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                received = logs.recv() => match received {
+                    Ok(line) => Event::default().event("log").json_data(&line),
+                    // 不在这里记日志，否则每条日志都会再产生一条日志
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            if let Ok(event) = event {
+                yield Ok(event);
+            }
+        }
+    };
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::DEFAULT_HISTORY_CAPACITY;
+    use crate::test_utils::test_config;
+
+    fn create_test_config(admin_key: Option<&str>) -> Config {
+        Config {
+            anthropic_base_url: Some("https://api.anthropic.com".to_string()),
+            anthropic_api_key: Some("sk-ant".to_string()),
+            dashboard_enabled: true,
+            admin_key: admin_key.map(String::from),
+            ..test_config()
+        }
+    }
+
+    async fn status(config: Config, headers: HeaderMap) -> ProxyResult<Json<Value>> {
+        dashboard_status(
+            Extension(Arc::new(config)),
+            Extension(Arc::new(Monitor::new(DEFAULT_HISTORY_CAPACITY))),
+            Extension(Arc::new(BackendHealth::default())),
+            headers,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_requires_admin_key() {
+        let result = status(create_test_config(Some("secret")), HeaderMap::new()).await;
+        assert!(matches!(result, Err(ProxyError::Unauthorized(_))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-key", "wrong".parse().unwrap());
+        let result = status(create_test_config(Some("secret")), headers).await;
+        assert!(matches!(result, Err(ProxyError::Unauthorized(_))));
+
+        // 前缀或多出字符都不匹配
+        for key in ["secre", "secret2", ""] {
+            assert!(!keys_match("secret", key));
+        }
+        assert!(keys_match("secret", "secret"));
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_rejects_when_admin_key_not_configured() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-key", "".parse().unwrap());
+        let result = status(create_test_config(None), headers).await;
+        assert!(matches!(result, Err(ProxyError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_accepts_valid_admin_key() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let Json(body) = status(create_test_config(Some("secret")), headers).await.unwrap();
+        assert_eq!(body["routing_mode"], "Auto");
+        assert_eq!(body["backends"][0]["configured"], true);
+        assert_eq!(body["backends"][1]["configured"], false);
+        assert_eq!(body["backends"][0]["health"]["status"], "unknown");
+        assert_eq!(body["counters"]["input_tokens"], 0);
+    }
+
+    #[test]
+    fn test_events_token() {
+        let token = issue_events_token("secret", 1_000);
+        assert!(verify_events_token("secret", &token, 1_000));
+        // 过期
+        assert!(!verify_events_token("secret", &token, 1_001));
+        // 其他密钥签发
+        assert!(!verify_events_token("other", &token, 1_000));
+        // 篡改有效期
+        let (_, signature) = token.split_once('.').unwrap();
+        assert!(!verify_events_token("secret", &format!("2000.{}", signature), 1_000));
+        // 管理密钥本身不是令牌
+        assert!(!verify_events_token("secret", "secret", 0));
+        assert!(!verify_events_token("secret", "1000.zz", 0));
+    }
+
+    #[tokio::test]
+    async fn test_events_require_token_or_header() {
+        let config = Arc::new(create_test_config(Some("secret")));
+        let events = |params: HashMap<String, String>| {
+            dashboard_events(
+                Extension(config.clone()),
+                Extension(Arc::new(Monitor::new(DEFAULT_HISTORY_CAPACITY))),
+                Extension(Arc::new(LogTail::new(10))),
+                HeaderMap::new(),
+                Query(params),
+            )
+        };
+
+        let key = HashMap::from([("key".to_string(), "secret".to_string())]);
+        assert!(matches!(events(key).await, Err(ProxyError::Unauthorized(_))));
+
+        let Json(issued) = dashboard_events_token(Extension(config.clone()), {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-admin-key", "secret".parse().unwrap());
+            headers
+        })
+        .await
+        .unwrap();
+        assert_eq!(issued["expires_in"], 60);
+        let token = HashMap::from([("token".to_string(), issued["token"].as_str().unwrap().to_string())]);
+        assert!(events(token).await.is_ok());
+
+        // 未配置 ADMIN_KEY 时不能用空密钥签发的令牌绕过鉴权
+        let forged = HashMap::from([("token".to_string(), issue_events_token("", unix_secs() + 60))]);
+        let result = dashboard_events(
+            Extension(Arc::new(create_test_config(None))),
+            Extension(Arc::new(Monitor::new(DEFAULT_HISTORY_CAPACITY))),
+            Extension(Arc::new(LogTail::new(10))),
+            HeaderMap::new(),
+            Query(forged),
+        )
+        .await;
+        assert!(matches!(result, Err(ProxyError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_unreachable_backend() {
+        let config = Config {
+            anthropic_base_url: Some("http://127.0.0.1:1".to_string()),
+            ..create_test_config(Some("secret"))
+        };
+        let health = BackendHealth::default();
+        health.check(&config, &Clients::default()).await;
+
+        let anthropic = health.status(Backend::Anthropic);
+        assert_eq!(anthropic["status"], "error");
+        assert!(anthropic["message"].as_str().unwrap().contains("unreachable"));
+        assert_eq!(health.status(Backend::OpenAI)["status"], "unknown");
+        assert!(*health.checked_at.read().unwrap() > 0);
+    }
+}
@@ -0,0 +1,178 @@
+//! 指标处理器 (/metrics)
+//!
+//! 以 Prometheus 文本格式输出监控器的累计计数，只在 METRICS_PORT 指定的独立端口上提供
+
+use crate::monitor::{hedge_saved, transform_failures, Monitor, RequestCategory, TransformFailure};
+use crate::policy::ContentPolicy;
+use axum::{http::header::CONTENT_TYPE, response::IntoResponse, Extension};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Prometheus 文本格式的 Content-Type
+const CONTENT_TYPE_PROMETHEUS: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// 渲染指标文本
+pub fn render(monitor: &Monitor, policy: &ContentPolicy) -> String {
+    let counters = monitor.counters();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, labels: &str, value: u64| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    };
+    metric(
+        "anthropic_proxy_build_info",
+        "gauge",
+        "Proxy version.",
+        &format!("{{version=\"{}\"}}", env!("CARGO_PKG_VERSION")),
+        1,
+    );
+    metric(
+        "anthropic_proxy_requests_total",
+        "counter",
+        "Requests handled on the API port.",
+        "",
+        counters.total_requests,
+    );
+    metric(
+        "anthropic_proxy_request_errors_total",
+        "counter",
+        "Requests answered with a 4xx or 5xx status.",
+        "",
+        counters.error_requests,
+    );
+    let (hedge_wins, hedge_saved_ms) = hedge_saved();
+    metric(
+        "anthropic_proxy_hedge_secondary_wins_total",
+        "counter",
+        "Hedged requests won by the secondary backend.",
+        "",
+        hedge_wins,
+    );
+    metric(
+        "anthropic_proxy_hedge_saved_milliseconds_total",
+        "counter",
+        "Time saved by hedged requests won by the secondary backend.",
+        "",
+        hedge_saved_ms,
+    );
+    metric(
+        "anthropic_proxy_uptime_seconds",
+        "gauge",
+        "Seconds since the proxy started.",
+        "",
+        counters.uptime_secs,
+    );
+
+    let name = "anthropic_proxy_requests_by_category_total";
+    let _ = writeln!(out, "# HELP {} Requests handled on the API port, by endpoint category.", name);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for category in RequestCategory::ALL {
+        let _ = writeln!(
+            out,
+            "{}{{category=\"{}\"}} {}",
+            name,
+            category.as_str(),
+            monitor.category_requests(category)
+        );
+    }
+
+    let name = "anthropic_proxy_transform_failures_total";
+    let _ = writeln!(out, "# HELP {} Requests or responses that failed to transform, by reason.", name);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for reason in TransformFailure::ALL {
+        let _ = writeln!(
+            out,
+            "{}{{reason=\"{}\"}} {}",
+            name,
+            reason.as_str(),
+            transform_failures(reason)
+        );
+    }
+
+    let rule_hits = policy.rule_hits();
+    if !rule_hits.is_empty() {
+        let name = "anthropic_proxy_policy_rule_matches_total";
+        let _ = writeln!(out, "# HELP {} Requests matched by each content policy rule.", name);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (rule, action, hits) in rule_hits {
+            let _ = writeln!(
+                out,
+                "{}{{rule=\"{}\",action=\"{}\"}} {}",
+                name,
+                rule.replace('\\', "\\\\").replace('"', "\\\""),
+                action.as_str(),
+                hits
+            );
+        }
+    }
+    out
+}
+
+/// 指标端点
+pub async fn metrics_handler(
+    Extension(monitor): Extension<Arc<Monitor>>,
+    Extension(policy): Extension<ContentPolicy>,
+) -> impl IntoResponse {
+    ([(CONTENT_TYPE, CONTENT_TYPE_PROMETHEUS)], render(&monitor, &policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::{RequestSummary, DEFAULT_HISTORY_CAPACITY};
+
+    #[test]
+    fn test_render_counters() {
+        let monitor = Monitor::new(DEFAULT_HISTORY_CAPACITY);
+        for status in [200, 502] {
+            monitor.record(RequestSummary {
+                timestamp: 0,
+                method: "POST".to_string(),
+                path: "/v1/messages".to_string(),
+                category: RequestCategory::Messages,
+                status,
+                duration_ms: 1,
+                user_id: None,
+                client_ip: None,
+            });
+        }
+
+        let text = render(&monitor, &ContentPolicy::default());
+        assert!(text.contains("# TYPE anthropic_proxy_requests_total counter\nanthropic_proxy_requests_total 2\n"));
+        assert!(text.contains("\nanthropic_proxy_request_errors_total 1\n"));
+        assert!(text.contains("\nanthropic_proxy_requests_by_category_total{category=\"messages\"} 2\n"));
+        assert!(text.contains("\nanthropic_proxy_requests_by_category_total{category=\"embeddings\"} 0\n"));
+        assert!(text.contains(&format!(
+            "anthropic_proxy_build_info{{version=\"{}\"}} 1",
+            env!("CARGO_PKG_VERSION")
+        )));
+        for reason in ["invalid_json", "unsupported_block", "schema", "tool_pairing"] {
+            assert!(text.contains(&format!(
+                "\nanthropic_proxy_transform_failures_total{{reason=\"{}\"}} ",
+                reason
+            )));
+        }
+        assert!(text.contains("# TYPE anthropic_proxy_hedge_saved_milliseconds_total counter\n"));
+        // 未配置内容策略时不输出规则计数
+        assert!(!text.contains("anthropic_proxy_policy_rule_matches_total"));
+    }
+
+    #[test]
+    fn test_render_policy_rule_matches() {
+        let path = std::env::temp_dir().join(format!("anthropic-proxy-metrics-policy-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"id": "api-key", "pattern": "sk-[a-z]+", "action": "redact"}, {"id": "pii", "pattern": "ssn", "action": "log"}]"#,
+        )
+        .unwrap();
+        let policy = ContentPolicy::load(path.to_str()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut request = serde_json::json!({"messages": [{"role": "user", "content": "sk-abc"}]});
+        policy.inspect(&mut request, crate::router::RequestFormat::OpenAI).unwrap();
+
+        let text = render(&Monitor::new(DEFAULT_HISTORY_CAPACITY), &policy);
+        assert!(text.contains("\nanthropic_proxy_policy_rule_matches_total{rule=\"api-key\",action=\"redact\"} 1\n"));
+        assert!(text.contains("\nanthropic_proxy_policy_rule_matches_total{rule=\"pii\",action=\"log\"} 0\n"));
+    }
+}
@@ -1,9 +1,145 @@
 //! 请求处理器模块
 //!
-//! 包含 Anthropic 和 OpenAI API 端点的处理器
+//! 包含 Anthropic、OpenAI API 端点、embeddings 透传、批处理端点、监控面板及指标端点的处理器
 
 pub mod anthropic;
+pub mod batch;
+pub mod dashboard;
+pub mod embeddings;
+pub mod metrics;
 pub mod openai;
+mod overrides;
 
-pub use anthropic::anthropic_handler;
+pub use anthropic::{
+    anthropic_handler, count_tokens_handler, message_batches_handler, model_info_handler,
+};
+pub use batch::batch_handler;
+pub use embeddings::embeddings_handler;
 pub use openai::openai_handler;
+
+use crate::config::Config;
+use crate::error::{ProxyError, ProxyResult};
+use crate::router::RequestFormat;
+use axum::http::{
+    header::{ACCEPT, CONTENT_TYPE},
+    HeaderMap, HeaderValue,
+};
+use axum::response::Response;
+use serde_json::Value;
+
+/// 请求体大小上限（与 axum 默认的 `DefaultBodyLimit` 相同），在读取请求体的中间件中也按此限制
+pub const REQUEST_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Gateway 模式下按嗅探结果改用另一种格式处理时，标注实际格式的响应头
+pub const DETECTED_FORMAT_HEADER: &str = "x-proxy-detected-format";
+
+/// 在响应上标注嗅探出的请求格式
+pub fn set_detected_format(response: &mut Response, format: RequestFormat) {
+    response.headers_mut().insert(
+        DETECTED_FORMAT_HEADER,
+        HeaderValue::from_static(format.as_str()),
+    );
+}
+
+/// 按 STRIP_MODEL_PREFIX 去掉请求体中模型名的前缀，返回用于路由和转发的模型名，
+/// 以及请求体是否被改写
+pub fn strip_model_prefix(config: &Config, raw_json: &mut Value, requested_model: &str) -> (String, bool) {
+    let model = config.strip_model_prefix(requested_model);
+    if model == requested_model {
+        return (model.to_string(), false);
+    }
+    tracing::debug!("Stripped model prefix: {} -> {}", requested_model, model);
+    if let Some(obj) = raw_json.as_object_mut() {
+        obj.insert("model".to_string(), Value::String(model.to_string()));
+    }
+    (model.to_string(), true)
+}
+
+/// 透传请求按 MODEL_FALLBACKS 换用模型时改写请求体中的模型名；模型未变时原样返回请求体
+pub fn body_with_model(raw_json: &Value, body: &axum::body::Bytes, model: &str) -> ProxyResult<axum::body::Bytes> {
+    if raw_json.get("model").and_then(Value::as_str) == Some(model) {
+        return Ok(body.clone());
+    }
+    let mut raw_json = raw_json.clone();
+    if let Some(obj) = raw_json.as_object_mut() {
+        obj.insert("model".to_string(), Value::String(model.to_string()));
+    }
+    Ok(axum::body::Bytes::from(serde_json::to_vec(&raw_json)?))
+}
+
+/// 检查请求的 Content-Type：未设置时按 JSON 处理，设置了其他类型（如 `text/plain`）时返回 415
+pub fn check_json_content_type(headers: &HeaderMap) -> ProxyResult<()> {
+    let Some(value) = headers.get(CONTENT_TYPE) else {
+        return Ok(());
+    };
+    let media_type = value
+        .to_str()
+        .unwrap_or_default()
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim();
+    if media_type.eq_ignore_ascii_case("application/json") {
+        Ok(())
+    } else {
+        Err(ProxyError::UnsupportedMediaType(format!(
+            "Content-Type must be application/json, got '{}'",
+            String::from_utf8_lossy(value.as_bytes())
+        )))
+    }
+}
+
+/// 判断请求是否为流式
+///
+/// 请求体中的 `stream` 优先；未设置时，开启 `stream_from_accept` 则
+/// `Accept: text/event-stream` 视为请求流式。`stream: true` 却只接受 JSON 时记录警告
+pub fn is_streaming_request(
+    headers: &HeaderMap,
+    body_stream: Option<bool>,
+    stream_from_accept: bool,
+) -> bool {
+    let accept = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_lowercase();
+    let accepts_event_stream = accept.contains("text/event-stream");
+
+    match body_stream {
+        Some(true) => {
+            if accept.contains("application/json") && !accepts_event_stream {
+                tracing::warn!(
+                    "Request sets stream: true but the client only accepts application/json"
+                );
+            }
+            true
+        }
+        Some(false) => false,
+        None => stream_from_accept && accepts_event_stream,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_accept_header_implies_streaming() {
+        assert!(is_streaming_request(&accept("text/event-stream"), None, true));
+        assert!(!is_streaming_request(&accept("text/event-stream"), None, false));
+        assert!(!is_streaming_request(&accept("application/json"), None, true));
+        assert!(!is_streaming_request(&HeaderMap::new(), None, true));
+    }
+
+    #[test]
+    fn test_body_stream_takes_precedence() {
+        assert!(!is_streaming_request(&accept("text/event-stream"), Some(false), true));
+        assert!(is_streaming_request(&accept("application/json"), Some(true), true));
+    }
+}
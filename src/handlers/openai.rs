@@ -1,83 +1,528 @@
 //! OpenAI API 端点处理器 (/v1/chat/completions)
 
-use crate::backends::{self, Backend};
-use crate::config::Config;
+use crate::audit;
+use crate::backends::{self, Backend, Clients};
+use crate::config::{Config, RoutingMode};
+use crate::context::RequestContext;
 use crate::error::{ProxyError, ProxyResult};
-use crate::models::openai;
-use crate::router::{RequestFormat, RoutingDecision};
+use crate::log_sanitize::log_json;
+use crate::policy::ContentPolicy;
+use crate::rate_limit::{self, RateLimitBuckets};
+use crate::handlers::overrides::apply_header_overrides;
+use crate::handlers::{body_with_model, is_streaming_request, set_detected_format, strip_model_prefix};
+use crate::models::{anthropic, openai};
+use crate::monitor::{self, TransformFailure};
+use crate::router::{detect_request_format, RequestFormat, RoutingDecision};
 use crate::transform;
-use axum::{response::Response, Extension};
-use reqwest::Client;
+use crate::validation;
+use crate::transform::request::openai_to_anthropic::{check_content_parts, check_unsupported_fields};
+use crate::transform::utils::{merge_stop_field, OPENAI_MAX_STOP};
+use axum::{http::HeaderMap, response::Response, Extension};
+use serde::Deserialize;
 use std::sync::Arc;
 
 /// OpenAI API 端点处理器
 pub async fn openai_handler(
     Extension(config): Extension<Arc<Config>>,
-    Extension(client): Extension<Client>,
+    Extension(clients): Extension<Clients>,
+    Extension(rate_limits): Extension<RateLimitBuckets>,
+    Extension(policy): Extension<ContentPolicy>,
+    headers: HeaderMap,
     body: axum::body::Bytes,
 ) -> ProxyResult<Response> {
     // 解析请求
     let raw_json: serde_json::Value = serde_json::from_slice(&body).map_err(|e| {
         tracing::error!("Failed to parse request as JSON: {}", e);
-        ProxyError::Transform(format!("Invalid JSON: {}", e))
+        ProxyError::transform(TransformFailure::InvalidJson, format!("Invalid JSON: {}", e))
     })?;
 
     if config.debug && config.log_raw_json {
         tracing::debug!(
             "Raw OpenAI request JSON: {}",
-            serde_json::to_string_pretty(&raw_json).unwrap_or_default()
+            log_json(&raw_json, &config)
         );
     }
 
-    let req: openai::OpenAIRequest = serde_json::from_value(raw_json.clone()).map_err(|e| {
-        tracing::error!("Failed to deserialize OpenAI request: {}", e);
-        ProxyError::Transform(format!("Failed to deserialize: {}", e))
-    })?;
+    // Gateway 模式下，发到本端点的 Anthropic 格式请求按 Anthropic 端点处理
+    if config.routing_mode == RoutingMode::Gateway
+        && detect_request_format(RequestFormat::OpenAI, &raw_json) == RequestFormat::Anthropic
+    {
+        tracing::info!(
+            "Anthropic-format request received on /v1/chat/completions, handling as Anthropic"
+        );
+        let mut response = super::anthropic::handle_anthropic_request(
+            config,
+            clients,
+            rate_limits,
+            policy,
+            headers,
+            body,
+            raw_json,
+        )
+        .await?;
+        set_detected_format(&mut response, RequestFormat::Anthropic);
+        return Ok(response);
+    }
+
+    handle_openai_request(config, clients, rate_limits, policy, headers, body, raw_json).await
+}
+
+/// 按 OpenAI 格式处理已解析的请求
+pub(crate) async fn handle_openai_request(
+    config: Arc<Config>,
+    clients: Clients,
+    rate_limits: RateLimitBuckets,
+    policy: ContentPolicy,
+    mut headers: HeaderMap,
+    body: axum::body::Bytes,
+    mut raw_json: serde_json::Value,
+) -> ProxyResult<Response> {
+    // x-proxy-set-* 请求头覆盖请求体字段；没有这些头时透传的原始 body 保持不变
+    let overridden = apply_header_overrides(&mut headers, &mut raw_json, RequestFormat::OpenAI)?;
+    validation::validate(&raw_json, RequestFormat::OpenAI, config.validation)?;
+    // 内容策略检查，redact 规则改写过的请求体需要重新序列化
+    let overridden = policy.inspect(&mut raw_json, RequestFormat::OpenAI)? || overridden;
 
-    let is_streaming = req.stream.unwrap_or(false);
+    // 提取必要字段用于路由决策
+    let requested_model = raw_json
+        .get("model")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+    // 去掉 STRIP_MODEL_PREFIX 前缀后再路由和转发，客户端请求的原始模型名用于响应回显
+    let (model, stripped) = strip_model_prefix(&config, &mut raw_json, &requested_model);
+    let overridden = stripped || overridden;
+    rate_limit::check(&config, &rate_limits, &model)?;
 
-    tracing::debug!("Received OpenAI request for model: {}", req.model);
+    let body_stream = raw_json.get("stream").and_then(|v| v.as_bool());
+    let is_streaming = is_streaming_request(&headers, body_stream, config.stream_from_accept);
+
+    let user_id = raw_json
+        .get("user")
+        .and_then(|v| v.as_str())
+        .map(String::from);
+    let mut ctx = RequestContext::new(&config, &headers, &requested_model, user_id.as_deref());
+    ctx.include_usage = raw_json
+        .pointer("/stream_options/include_usage")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    // 由 Accept 头推断出流式时，把 stream 写回请求体，确保上游按流式返回
+    let body = match raw_json.as_object_mut() {
+        Some(obj) if is_streaming && body_stream != Some(true) => {
+            obj.insert("stream".to_string(), serde_json::Value::Bool(true));
+            axum::body::Bytes::from(serde_json::to_vec(&raw_json)?)
+        }
+        _ if overridden => axum::body::Bytes::from(serde_json::to_vec(&raw_json)?),
+        _ => body,
+    };
+
+    tracing::debug!("Received OpenAI request for model: {}", model);
     tracing::debug!("Streaming: {}", is_streaming);
 
     // 路由决策
-    let decision = RoutingDecision::decide(RequestFormat::OpenAI, &req.model, &config)?;
+    let decision = RoutingDecision::decide(RequestFormat::OpenAI, &model, &config)?;
 
     tracing::debug!(
-        "Routing decision: backend={:?}, needs_transform={}, direction={:?}",
-        decision.backend,
-        decision.needs_transform,
-        decision.transform_direction
+        model = %model,
+        backend = ?decision.backend,
+        needs_transform = decision.needs_transform,
+        direction = ?decision.transform_direction,
+        target_url = decision.target_url.as_deref().unwrap_or("-"),
+        "Routing decision"
     );
 
     if config.verbose {
         tracing::trace!(
             "Incoming OpenAI request: {}",
-            serde_json::to_string_pretty(&req).unwrap_or_default()
+            log_json(&raw_json, &config)
         );
     }
 
-    match (decision.backend, decision.needs_transform) {
-        // 透传到 OpenAI
-        (Backend::OpenAI, false) => {
-            backends::openai::forward_request(config, client, req, is_streaming).await
+    let response_model_mode = config.response_model_mode;
+    let mut response = match (decision.backend, decision.needs_transform) {
+        // 完全透传到 OpenAI 或通用上游（不解析结构体，直接转发原始 body，保留结构体未建模的字段）
+        (backend @ (Backend::OpenAI | Backend::Upstream), false) => {
+            // 配置了 DEFAULT_STOP 时合并 stop
+            let body = if merge_stop_field(&mut raw_json, "stop", &config.default_stop, Some(OPENAI_MAX_STOP)) {
+                axum::body::Bytes::from(serde_json::to_vec(&raw_json)?)
+            } else {
+                body
+            };
+            // 模型不存在时按 MODEL_FALLBACKS 回退，每次尝试改写请求体中的模型
+            let response = backends::with_model_fallbacks(&config, &model, |model| {
+                let body = body_with_model(&raw_json, &body, &model);
+                let (config, clients, headers) = (config.clone(), clients.clone(), &headers);
+                async move {
+                    backends::openai::forward_raw_request(config, clients, backend, body?, headers, is_streaming)
+                        .await
+                }
+            })
+            .await?;
+            ctx.log_summary("passthrough");
+            Ok(response)
         }
-        // 转换后发送到 Anthropic
+        // 需要转换，先解析为结构体后发送到 Anthropic
         (Backend::Anthropic, true) => {
+            // Anthropic 没有对应参数的字段会被丢弃：STRICT_PARAMS 下直接拒绝
+            check_unsupported_fields(&raw_json, config.strict_params, &mut ctx.transform_report)?;
+            let mut req = openai::OpenAIRequest::deserialize(&raw_json).map_err(|e| {
+                tracing::error!("Failed to deserialize OpenAI request: {}", e);
+                ProxyError::invalid_request(&e, &raw_json)
+            })?;
+            check_content_parts(&mut req, config.audio_input_placeholder, &mut ctx.transform_report)?;
             let anthropic_req = transform::openai_to_anthropic_request(req, &config)?;
 
             if config.verbose {
                 tracing::trace!(
                     "Transformed Anthropic request: {}",
-                    serde_json::to_string_pretty(&anthropic_req).unwrap_or_default()
+                    log_json(&anthropic_req, &config)
                 );
             }
 
-            if is_streaming {
-                backends::anthropic::handle_transformed_streaming(config, client, anthropic_req).await
-            } else {
-                backends::anthropic::handle_transformed_non_streaming(config, client, anthropic_req).await
-            }
+            // 模型不存在时按 MODEL_FALLBACKS 回退
+            let model = anthropic_req.model.clone();
+            backends::with_model_fallbacks(&config, &model, |model| {
+                let ctx = Arc::new(RequestContext {
+                    resolved_model: Some(model.clone()),
+                    ..ctx.clone()
+                });
+                let anthropic_req = anthropic::AnthropicRequest {
+                    model,
+                    ..anthropic_req.clone()
+                };
+                let (config, clients, headers) = (config.clone(), clients.clone(), &headers);
+                async move {
+                    if is_streaming {
+                        backends::anthropic::handle_transformed_streaming(
+                            config,
+                            clients,
+                            anthropic_req,
+                            headers,
+                            ctx,
+                        )
+                        .await
+                    } else {
+                        backends::anthropic::handle_transformed_non_streaming(
+                            config,
+                            clients,
+                            anthropic_req,
+                            headers,
+                            ctx,
+                        )
+                        .await
+                    }
+                }
+            })
+            .await
         }
         _ => Err(ProxyError::Internal("Invalid routing decision".into())),
+    }?;
+
+    backends::add_requested_model_header(response_model_mode, &mut response, &requested_model);
+    monitor::attribute_user(&mut response, user_id);
+    audit::attribute_backend(&mut response, decision.backend);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{spawn_mock_upstream, test_config};
+    use serde_json::{json, Value};
+
+    fn create_test_config(base_url: String) -> Config {
+        Config {
+            anthropic_base_url: Some(base_url),
+            anthropic_api_key: Some("sk-ant".to_string()),
+            model_fallbacks: crate::config::ModelFallback::parse_list(
+                "claude-missing-*=claude-stable",
+            ),
+            ..test_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_model_fallbacks_for_anthropic_backend() {
+        let config = create_test_config(spawn_mock_upstream().await);
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "claude-missing-old",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let response = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            body,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response.headers()[backends::MODEL_FALLBACK_HEADER],
+            "claude-stable"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "claude-stable");
+        assert_eq!(body["choices"][0]["message"]["content"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_response_model_mode_requested_and_both() {
+        let upstream = spawn_mock_upstream().await;
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "claude-missing-old",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        for (mode, expected_model, expected_header) in [
+            (crate::config::ResponseModelMode::Requested, "claude-missing-old", None),
+            (crate::config::ResponseModelMode::Both, "claude-stable", Some("claude-missing-old")),
+        ] {
+            let mut config = create_test_config(upstream.clone());
+            config.response_model_mode = mode;
+            let response = openai_handler(
+                Extension(Arc::new(config)),
+                Extension(Clients::default()),
+                Extension(RateLimitBuckets::default()),
+                Extension(ContentPolicy::default()),
+                HeaderMap::new(),
+                body.clone(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(
+                response
+                    .headers()
+                    .get(backends::REQUESTED_MODEL_HEADER)
+                    .map(|v| v.to_str().unwrap()),
+                expected_header
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["model"], expected_model);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_anthropic_backend_returns_503() {
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+
+        let config = create_test_config(spawn_mock_upstream().await);
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "claude-overloaded",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let error = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            body,
+        )
+        .await
+        .unwrap_err();
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn test_transform_mode_passthrough_to_upstream() {
+        let mut config = create_test_config("http://127.0.0.1:1".to_string());
+        config.routing_mode = crate::config::RoutingMode::Transform;
+        config.base_url = Some(spawn_mock_upstream().await);
+        config.api_key = Some("sk-upstream".to_string());
+        let raw = r#"{"model":"echo-body","messages":[{"role":"user","content":"Hello"}],"logit_bias":{"42":-100}}"#;
+
+        let response = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            axum::body::Bytes::from(raw),
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        // 原始请求体原样到达上游，未建模的字段也保留
+        assert_eq!(body["choices"][0]["message"]["content"], raw);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_model_fallbacks() {
+        let mut config = create_test_config("http://127.0.0.1:1".to_string());
+        config.routing_mode = crate::config::RoutingMode::Transform;
+        config.base_url = Some(spawn_mock_upstream().await);
+        config.api_key = Some("sk-upstream".to_string());
+        config.model_fallbacks = crate::config::ModelFallback::parse_list("gpt-missing-*=echo-body");
+        let raw = r#"{"model":"gpt-missing-old","messages":[{"role":"user","content":"Hello"}],"logit_bias":{"42":-100}}"#;
+
+        let response = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            axum::body::Bytes::from(raw),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.headers()[backends::MODEL_FALLBACK_HEADER], "echo-body");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        // 重试时只改写模型名，其余字段原样保留
+        let forwarded: Value =
+            serde_json::from_str(body["choices"][0]["message"]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(forwarded["model"], "echo-body");
+        assert_eq!(forwarded["logit_bias"]["42"], -100);
+    }
+
+    #[tokio::test]
+    async fn test_transform_mode_rejects_with_bad_request() {
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.routing_mode = crate::config::RoutingMode::Transform;
+        config.transform_reject_openai = true;
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let error = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            body,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(error, ProxyError::UnsupportedOperation(_)));
+        assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_handles_anthropic_body_as_anthropic() {
+        let mut config = create_test_config(spawn_mock_upstream().await);
+        config.routing_mode = crate::config::RoutingMode::Gateway;
+        let body = axum::body::Bytes::from(
+            json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 100,
+                "system": "Be brief",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        );
+
+        let response = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            body,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.headers()[crate::handlers::DETECTED_FORMAT_HEADER], "anthropic");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["type"], "message");
+        assert_eq!(body["content"][0]["text"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_forwards_raw_body() {
+        let mut config = create_test_config(String::new());
+        config.openai_base_url = Some(spawn_mock_upstream().await);
+        config.openai_api_key = Some("sk-openai".to_string());
+        // 结构体未建模的字段和原始格式都应原样到达上游
+        let request = r#"{"model":"echo-body", "messages":[{"role":"user","content":[{"type":"text","text":"Hi"},{"type":"input_audio","input_audio":{"data":"UklGRg==","format":"wav"}}]}],"response_format":{"type":"json_object"},"store":true,"metadata":{"tag":"a"},"logit_bias":{"50256":-100}}"#;
+
+        let response = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            axum::body::Bytes::from(request),
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], request);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_merges_default_stop() {
+        let mut config = create_test_config(String::new());
+        config.openai_base_url = Some(spawn_mock_upstream().await);
+        config.openai_api_key = Some("sk-openai".to_string());
+        config.default_stop = vec!["</tool>".to_string(), "END".to_string()];
+        let request = r#"{"model":"echo-body","messages":[{"role":"user","content":"Hi"}],"stop":["a","b","END"]}"#;
+
+        let response = openai_handler(
+            Extension(Arc::new(config)),
+            Extension(Clients::default()),
+            Extension(RateLimitBuckets::default()),
+            Extension(ContentPolicy::default()),
+            HeaderMap::new(),
+            axum::body::Bytes::from(request),
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let received: Value =
+            serde_json::from_str(body["choices"][0]["message"]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(received["stop"], json!(["a", "b", "END", "</tool>"]));
     }
 }
@@ -0,0 +1,202 @@
+//! 请求头触发的请求体覆盖
+//!
+//! 无法修改的客户端可以通过 `x-proxy-set-*` 请求头覆盖请求体中的少数字段。
+//! 请求中没有这些头时请求体保持不变（透传模式仍按原始字节转发）
+
+use crate::error::{ProxyError, ProxyResult};
+use crate::monitor::TransformFailure;
+use crate::router::RequestFormat;
+use axum::http::HeaderMap;
+use serde_json::{json, Value};
+
+/// 覆盖 `max_tokens`
+pub const SET_MAX_TOKENS_HEADER: &str = "x-proxy-set-max-tokens";
+/// 覆盖 `temperature`
+pub const SET_TEMPERATURE_HEADER: &str = "x-proxy-set-temperature";
+/// 覆盖停止序列（Anthropic `stop_sequences` / OpenAI `stop`）：单个序列，或 JSON 字符串数组
+pub const SET_STOP_HEADER: &str = "x-proxy-set-stop";
+
+/// 应用 `x-proxy-set-*` 请求头中的覆盖并从请求头中移除它们，返回请求体是否被修改
+///
+/// 头的值无效时返回指明该头的 400 错误
+pub fn apply_header_overrides(
+    headers: &mut HeaderMap,
+    request: &mut Value,
+    format: RequestFormat,
+) -> ProxyResult<bool> {
+    let max_tokens = take_header(headers, SET_MAX_TOKENS_HEADER)?
+        .map(|value| parse_max_tokens(&value))
+        .transpose()?;
+    let temperature = take_header(headers, SET_TEMPERATURE_HEADER)?
+        .map(|value| parse_temperature(&value, format))
+        .transpose()?;
+    let stop = take_header(headers, SET_STOP_HEADER)?
+        .map(|value| parse_stop(&value))
+        .transpose()?;
+
+    if max_tokens.is_none() && temperature.is_none() && stop.is_none() {
+        return Ok(false);
+    }
+    let Some(obj) = request.as_object_mut() else {
+        return Ok(false);
+    };
+
+    if let Some(max_tokens) = max_tokens {
+        tracing::debug!("Overriding max_tokens with {} from request header", max_tokens);
+        obj.insert("max_tokens".to_string(), json!(max_tokens));
+    }
+    if let Some(temperature) = temperature {
+        tracing::debug!("Overriding temperature with {} from request header", temperature);
+        obj.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(stop) = stop {
+        let field = match format {
+            RequestFormat::Anthropic => "stop_sequences",
+            RequestFormat::OpenAI => "stop",
+        };
+        tracing::debug!("Overriding {} from request header", field);
+        obj.insert(field.to_string(), json!(stop));
+    }
+
+    Ok(true)
+}
+
+/// 取出并移除请求头，值不是合法字符串时报错
+fn take_header(headers: &mut HeaderMap, name: &str) -> ProxyResult<Option<String>> {
+    headers
+        .remove(name)
+        .map(|value| {
+            value
+                .to_str()
+                .map(|v| v.trim().to_string())
+                .map_err(|_| invalid(name, "value is not valid text"))
+        })
+        .transpose()
+}
+
+fn invalid(header: &str, reason: &str) -> ProxyError {
+    ProxyError::transform(TransformFailure::Schema, format!("Invalid {} header: {}", header, reason))
+}
+
+fn parse_max_tokens(value: &str) -> ProxyResult<u32> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|tokens| *tokens > 0)
+        .ok_or_else(|| invalid(SET_MAX_TOKENS_HEADER, "expected a positive integer"))
+}
+
+/// Anthropic 的 temperature 范围为 0-1，OpenAI 为 0-2
+fn parse_temperature(value: &str, format: RequestFormat) -> ProxyResult<f64> {
+    let max = match format {
+        RequestFormat::Anthropic => 1.0,
+        RequestFormat::OpenAI => 2.0,
+    };
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|t| (0.0..=max).contains(t))
+        .ok_or_else(|| {
+            invalid(
+                SET_TEMPERATURE_HEADER,
+                &format!("expected a number between 0 and {}", max),
+            )
+        })
+}
+
+fn parse_stop(value: &str) -> ProxyResult<Vec<String>> {
+    if value.starts_with('[') {
+        return serde_json::from_str::<Vec<String>>(value)
+            .map_err(|_| invalid(SET_STOP_HEADER, "expected a JSON array of strings"));
+    }
+    if value.is_empty() {
+        return Err(invalid(SET_STOP_HEADER, "stop sequence must not be empty"));
+    }
+    Ok(vec![value.to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_no_headers_leaves_request_untouched() {
+        let mut request = json!({"model": "claude-3", "max_tokens": 10});
+        let original = request.clone();
+        let changed =
+            apply_header_overrides(&mut HeaderMap::new(), &mut request, RequestFormat::Anthropic)
+                .unwrap();
+        assert!(!changed);
+        assert_eq!(request, original);
+    }
+
+    #[test]
+    fn test_overrides_applied_and_headers_stripped() {
+        let mut h = headers(&[
+            (SET_MAX_TOKENS_HEADER, "256"),
+            (SET_TEMPERATURE_HEADER, "0.5"),
+            (SET_STOP_HEADER, r#"["END", "STOP"]"#),
+            ("x-api-key", "sk-client"),
+        ]);
+        let mut request = json!({"model": "claude-3", "max_tokens": 10});
+
+        assert!(apply_header_overrides(&mut h, &mut request, RequestFormat::Anthropic).unwrap());
+        assert_eq!(request["max_tokens"], 256);
+        assert_eq!(request["temperature"], 0.5);
+        assert_eq!(request["stop_sequences"], json!(["END", "STOP"]));
+        assert_eq!(h.len(), 1);
+        assert!(h.contains_key("x-api-key"));
+    }
+
+    #[test]
+    fn test_stop_field_per_format() {
+        let mut request = json!({"model": "gpt-4"});
+        apply_header_overrides(
+            &mut headers(&[(SET_STOP_HEADER, "###")]),
+            &mut request,
+            RequestFormat::OpenAI,
+        )
+        .unwrap();
+        assert_eq!(request["stop"], json!(["###"]));
+        assert!(request.get("stop_sequences").is_none());
+    }
+
+    #[test]
+    fn test_invalid_values_name_the_header() {
+        for (name, value, format) in [
+            (SET_MAX_TOKENS_HEADER, "lots", RequestFormat::Anthropic),
+            (SET_MAX_TOKENS_HEADER, "0", RequestFormat::OpenAI),
+            (SET_TEMPERATURE_HEADER, "1.5", RequestFormat::Anthropic),
+            (SET_TEMPERATURE_HEADER, "hot", RequestFormat::OpenAI),
+            (SET_STOP_HEADER, "[1, 2]", RequestFormat::Anthropic),
+        ] {
+            let mut request = json!({"model": "m"});
+            let error = apply_header_overrides(&mut headers(&[(name, value)]), &mut request, format)
+                .unwrap_err();
+            assert!(
+                matches!(&error, ProxyError::Transform(msg) if msg.contains(name)),
+                "{}: {:?}",
+                name,
+                error
+            );
+        }
+
+        // OpenAI 允许更高的 temperature
+        let mut request = json!({"model": "gpt-4"});
+        apply_header_overrides(
+            &mut headers(&[(SET_TEMPERATURE_HEADER, "1.5")]),
+            &mut request,
+            RequestFormat::OpenAI,
+        )
+        .unwrap();
+        assert_eq!(request["temperature"], 1.5);
+    }
+}
@@ -0,0 +1,162 @@
+//! 日志中请求/响应体的裁剪
+//!
+//! VERBOSE / LOG_RAW_JSON 打印完整 JSON 时，一张截图的 base64 就有数 MB。
+//! 这里把较长的 base64 字符串替换为长度与哈希摘要，截断过长的文本，
+//! 并限制单条日志的总长度。只作用于日志输出，不影响实际转发的请求体
+
+use crate::audit::sha256_hex;
+use crate::config::Config;
+use serde::Serialize;
+use serde_json::Value;
+
+/// 超过该长度的 base64 字符串才会被省略
+const BASE64_ELIDE_THRESHOLD: usize = 256;
+
+/// 日志摘要中保留的 SHA-256 十六进制位数
+const DIGEST_HEX_LEN: usize = 16;
+
+/// 序列化为用于日志的 JSON 字符串：省略 base64、截断长文本并限制总长度
+pub fn log_json<T: Serialize>(value: &T, config: &Config) -> String {
+    let mut value = match serde_json::to_value(value) {
+        Ok(value) => value,
+        Err(_) => return String::new(),
+    };
+    sanitize_value(&mut value, config.log_max_text_len);
+    let pretty = serde_json::to_string_pretty(&value).unwrap_or_default();
+    truncate_for_log(&pretty, config.log_max_body_bytes)
+}
+
+/// 递归裁剪 JSON 中的字符串值；`max_text_len` 为 0 时不截断文本
+pub fn sanitize_value(value: &mut Value, max_text_len: usize) {
+    match value {
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                sanitize_value(item, max_text_len);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                sanitize_value(item, max_text_len);
+            }
+        }
+        Value::String(s) => {
+            if let Some(elided) = elide_string(s) {
+                *s = elided;
+            } else if max_text_len > 0 && s.len() > max_text_len {
+                let kept = floor_char_boundary(s, max_text_len);
+                *s = format!("{}… <{} bytes truncated>", &s[..kept], s.len() - kept);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 限制日志字符串的总长度；`max_bytes` 为 0 时不限制
+pub fn truncate_for_log(s: &str, max_bytes: usize) -> String {
+    if max_bytes == 0 || s.len() <= max_bytes {
+        return s.to_string();
+    }
+    let kept = floor_char_boundary(s, max_bytes);
+    format!("{}\n… <{} bytes truncated>", &s[..kept], s.len() - kept)
+}
+
+/// data URL 与裸 base64 字符串（Anthropic source.data、OpenAI input_audio.data 等）
+/// 超过阈值时返回省略后的文本
+fn elide_string(s: &str) -> Option<String> {
+    if s.len() <= BASE64_ELIDE_THRESHOLD {
+        return None;
+    }
+    if let Some(rest) = s.strip_prefix("data:") {
+        let (meta, payload) = rest.split_once(',')?;
+        if meta.ends_with(";base64") {
+            return Some(elide_base64(&s[..s.len() - payload.len()], payload));
+        }
+        return None;
+    }
+    if s.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'=' | b'-' | b'_')) {
+        return Some(elide_base64("", s));
+    }
+    None
+}
+
+fn elide_base64(prefix: &str, payload: &str) -> String {
+    let digest = sha256_hex(payload.as_bytes());
+    format!(
+        "{}<base64 elided, {} bytes, sha256:{}>",
+        prefix,
+        payload.len(),
+        &digest[..DIGEST_HEX_LEN]
+    )
+}
+
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    (0..=index.min(s.len())).rev().find(|&i| s.is_char_boundary(i)).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn image_block(data: &str) -> Value {
+        json!({"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": data}})
+    }
+
+    #[test]
+    fn test_nested_image_sources_elided() {
+        let data = "A".repeat(4096);
+        let mut value = json!({
+            "messages": [{"role": "user", "content": [image_block(&data), {"type": "text", "text": "describe"}]}]
+        });
+        sanitize_value(&mut value, 0);
+
+        let elided = value["messages"][0]["content"][0]["source"]["data"].as_str().unwrap();
+        let digest = sha256_hex(data.as_bytes());
+        assert_eq!(elided, format!("<base64 elided, 4096 bytes, sha256:{}>", &digest[..DIGEST_HEX_LEN]));
+        assert_eq!(value["messages"][0]["content"][0]["source"]["media_type"], "image/png");
+        assert_eq!(value["messages"][0]["content"][1]["text"], "describe");
+    }
+
+    #[test]
+    fn test_tool_result_blocks_elided_and_truncated() {
+        let mut value = json!({
+            "type": "tool_result",
+            "tool_use_id": "toolu_1",
+            "content": [image_block(&"B".repeat(1000)), {"type": "text", "text": "x ".repeat(100)}]
+        });
+        sanitize_value(&mut value, 50);
+
+        let content = &value["content"];
+        assert!(content[0]["source"]["data"].as_str().unwrap().starts_with("<base64 elided, 1000 bytes, sha256:"));
+        let text = content[1]["text"].as_str().unwrap();
+        assert_eq!(text, format!("{}… <150 bytes truncated>", "x ".repeat(25)));
+        assert_eq!(value["tool_use_id"], "toolu_1");
+    }
+
+    #[test]
+    fn test_data_url_keeps_media_prefix() {
+        let url = format!("data:image/jpeg;base64,{}", "C".repeat(500));
+        let mut value = json!({"image_url": {"url": url}});
+        sanitize_value(&mut value, 0);
+
+        let url = value["image_url"]["url"].as_str().unwrap();
+        assert!(url.starts_with("data:image/jpeg;base64,<base64 elided, 500 bytes, sha256:"));
+    }
+
+    #[test]
+    fn test_short_values_untouched() {
+        let mut value = json!({"data": "abc", "text": "hello", "n": 1});
+        let expected = value.clone();
+        sanitize_value(&mut value, 100);
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn test_truncate_for_log() {
+        assert_eq!(truncate_for_log("hello", 0), "hello");
+        assert_eq!(truncate_for_log("hello", 10), "hello");
+        assert_eq!(truncate_for_log("hello world", 5), "hello\n… <6 bytes truncated>");
+        // 不在多字节字符中间截断
+        assert_eq!(truncate_for_log("日本語", 4), "日\n… <6 bytes truncated>");
+    }
+}
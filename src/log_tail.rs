@@ -0,0 +1,171 @@
+//! 面板日志尾巴
+//!
+//! 作为 tracing 的一个 layer 接入订阅器：保留最近的日志行并通过广播通道推送给
+//! /dashboard/events。过滤级别与终端输出共用 RUST_LOG / DEBUG / VERBOSE
+
+use crate::log_sanitize::truncate_for_log;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// 保留的最近日志行数
+pub const DEFAULT_LOG_TAIL_CAPACITY: usize = 200;
+
+/// 广播通道容量，慢订阅者落后超过该值会丢失日志行
+const LOG_CHANNEL_CAPACITY: usize = 256;
+
+/// 单条日志推送给面板时的长度上限
+const MAX_LOG_LINE_BYTES: usize = 2048;
+
+/// 一条日志
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Unix 毫秒时间戳
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    /// 日志消息及 `key=value` 形式的其余字段
+    pub message: String,
+}
+
+/// 最近日志的环形缓冲区与实时广播
+pub struct LogTail {
+    history: Mutex<VecDeque<LogLine>>,
+    capacity: usize,
+    sender: broadcast::Sender<LogLine>,
+}
+
+impl LogTail {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(LOG_CHANNEL_CAPACITY);
+        Self {
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            sender,
+        }
+    }
+
+    pub fn push(&self, line: LogLine) {
+        {
+            let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(line.clone());
+        }
+        // 没有订阅者时发送失败，忽略即可
+        let _ = self.sender.send(line);
+    }
+
+    /// 最近的日志，按时间顺序排列
+    pub fn recent(&self) -> Vec<LogLine> {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        history.iter().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogLine> {
+        self.sender.subscribe()
+    }
+}
+
+/// 把日志事件写入 [`LogTail`] 的 tracing layer
+pub struct LogTailLayer {
+    tail: Arc<LogTail>,
+}
+
+impl LogTailLayer {
+    pub fn new(tail: Arc<LogTail>) -> Self {
+        Self { tail }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogTailLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let mut message = visitor.message;
+        if !visitor.fields.is_empty() {
+            if !message.is_empty() {
+                message.push(' ');
+            }
+            message.push_str(&visitor.fields);
+        }
+
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        self.tail.push(LogLine {
+            timestamp,
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: truncate_for_log(&message, MAX_LOG_LINE_BYTES),
+        });
+    }
+}
+
+/// 收集 `message` 字段与其余字段
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+            return;
+        }
+        if !self.fields.is_empty() {
+            self.fields.push(' ');
+        }
+        let _ = write!(self.fields, "{}={:?}", field.name(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_layer_captures_message_and_fields() {
+        let tail = Arc::new(LogTail::new(2));
+        let subscriber = tracing_subscriber::registry().with(LogTailLayer::new(tail.clone()));
+        let mut receiver = tail.subscribe();
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::warn!(status = 502, "upstream {}", "failed");
+            tracing::error!(path = "/v1/messages", "third");
+        });
+
+        // 只保留最近 2 条
+        let recent = tail.recent();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].level, "WARN");
+        assert_eq!(recent[0].message, "upstream failed status=502");
+        assert_eq!(recent[1].message, "third path=\"/v1/messages\"");
+
+        // 订阅者收到全部 3 条
+        assert_eq!(receiver.try_recv().unwrap().message, "first");
+        assert_eq!(receiver.try_recv().unwrap().level, "WARN");
+        assert_eq!(receiver.try_recv().unwrap().level, "ERROR");
+    }
+}
@@ -1,14 +1,30 @@
+mod audit;
 mod backends;
 mod cli;
 mod config;
+mod context;
+mod doctor;
 mod error;
 mod handlers;
+mod log_sanitize;
+mod log_tail;
+mod middleware;
 mod models;
+mod monitor;
+mod pid_file;
+mod policy;
+mod rate_limit;
 mod router;
 mod streaming;
+mod systemd;
+#[cfg(test)]
+mod test_utils;
+mod tokens;
 mod transform;
+mod validation;
 
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post},
     Extension, Router,
 };
@@ -16,7 +32,7 @@ use clap::Parser;
 use cli::{Cli, Command};
 use config::{Config, RoutingMode};
 use daemonize::Daemonize;
-use reqwest::Client;
+use monitor::Monitor;
 use std::sync::Arc;
 use tower_http::{
     cors::{Any, CorsLayer},
@@ -37,10 +53,21 @@ fn main() -> anyhow::Result<()> {
                 check_status(&pid_file)?;
                 return Ok(());
             }
+            Command::Check => {
+                check_config(cli.config)?;
+                return Ok(());
+            }
+            Command::Doctor => {
+                if !doctor::run_doctor(cli.config)? {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
         }
     }
-    
-    if cli.daemon {
+
+    // 守护进程退出时（包括优雅关闭后）删除 PID 文件
+    let _pid_file_guard = if cli.daemon {
         use std::fs::OpenOptions;
         
         let stdout = OpenOptions::new()
@@ -60,23 +87,15 @@ fn main() -> anyhow::Result<()> {
             .stderr(stderr)
             .umask(0o027);
 
-        match daemonize.start() {
-            Ok(_) => {},
-            Err(e) => {
-                eprintln!("✗ Failed to daemonize: {}", e);
-                std::process::exit(1);
-            }
-        }
+        daemonize
+            .start()
+            .map_err(|e| anyhow::anyhow!("Failed to daemonize: {}", e))?;
+        Some(pid_file::PidFileGuard::new(cli.pid_file.clone()))
     } else {
-        eprintln!("✓ Starting proxy in foreground mode");
-    }
-
-    let runtime = tokio::runtime::Runtime::new()?;
-    runtime.block_on(async_main(cli))
-}
+        None
+    };
 
-async fn async_main(cli: Cli) -> anyhow::Result<()> {
-    let mut config = Config::from_env_with_path(cli.config)?;
+    let (mut config, load_report) = Config::from_env_with_path(cli.config)?;
 
     if cli.debug {
         config.debug = true;
@@ -84,29 +103,82 @@ async fn async_main(cli: Cli) -> anyhow::Result<()> {
     if cli.verbose {
         config.verbose = true;
     }
+    if let Some(host) = cli.host {
+        config::bind_address(&host, config.port)?;
+        config.host = host;
+    }
     if let Some(port) = cli.port {
         config.port = port;
     }
+    if let Some(workers) = cli.workers {
+        if workers == 0 {
+            anyhow::bail!("--workers must be at least 1");
+        }
+        config.workers = Some(workers);
+    }
 
-    let log_level = if config.verbose {
-        tracing::Level::TRACE
-    } else if config.debug {
-        tracing::Level::DEBUG
-    } else {
-        tracing::Level::INFO
+    // 指定 worker 数时限制 tokio 线程数，否则每个 CPU 一个线程
+    let runtime = match config.workers {
+        Some(workers) => tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(workers)
+            .enable_all()
+            .build()?,
+        None => tokio::runtime::Runtime::new()?,
     };
+    runtime.block_on(async_main(config, load_report, cli.daemon, cli.log_level))
+}
 
-    tracing_subscriber::registry()
-        .with(
+async fn async_main(
+    config: Config,
+    load_report: config::LoadReport,
+    daemon: bool,
+    cli_log_level: Option<tracing::Level>,
+) -> anyhow::Result<()> {
+
+    // --log-level 优先于 RUST_LOG、VERBOSE 和 DEBUG；VERBOSE 对应 trace，DEBUG 对应 debug
+    let filter = match cli_log_level {
+        Some(level) => format!("anthropic_proxy={}", level).into(),
+        None => {
+            let log_level = if config.verbose {
+                tracing::Level::TRACE
+            } else if config.debug {
+                tracing::Level::DEBUG
+            } else {
+                tracing::Level::INFO
+            };
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("anthropic_proxy={}", log_level).into()),
-        )
+                .unwrap_or_else(|_| format!("anthropic_proxy={}", log_level).into())
+        }
+    };
+
+    // 面板启用时另外把日志送入日志尾巴，供 /dashboard/events 推送
+    let log_tail = config
+        .dashboard_enabled
+        .then(|| Arc::new(log_tail::LogTail::new(log_tail::DEFAULT_LOG_TAIL_CAPACITY)));
+
+    tracing_subscriber::registry()
+        .with(filter)
         .with(tracing_subscriber::fmt::layer())
+        .with(log_tail.clone().map(log_tail::LogTailLayer::new))
         .init();
 
-    tracing::info!("Starting Anthropic Proxy v{}", env!("CARGO_PKG_VERSION"));
-    tracing::info!("Routing Mode: {}", config.routing_mode);
-    tracing::info!("Port: {}", config.port);
+    tracing::info!(
+        version = env!("CARGO_PKG_VERSION"),
+        mode = if daemon { "daemon" } else { "foreground" },
+        "Starting Anthropic Proxy"
+    );
+    // 配置加载早于日志初始化，加载信息与警告在此统一输出
+    load_report.log();
+    for finding in doctor::static_checks(&config) {
+        tracing::warn!("{}", finding);
+    }
+    tracing::info!(
+        routing_mode = %config.routing_mode,
+        host = %config.host,
+        port = config.port,
+        workers = config.workers,
+        "Server settings"
+    );
 
     // 显示后端配置
     match config.routing_mode {
@@ -144,12 +216,29 @@ async fn async_main(cli: Cli) -> anyhow::Result<()> {
     } else {
         tracing::info!("API Key: not set");
     }
+    tracing::debug!("Effective configuration:\n{}", config.display_safe());
 
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(300))
-        .connect_timeout(std::time::Duration::from_secs(10))
-        .pool_max_idle_per_host(10)
-        .build()?;
+    // 列出各模型族能否被服务，提前暴露缺失的后端配置
+    for diagnostic in router::serving_diagnostics(&config) {
+        match diagnostic.result {
+            Ok(_) => tracing::info!("{}", diagnostic),
+            Err(_) => tracing::warn!("{}", diagnostic),
+        }
+    }
+
+    // 每个后端一个独立配置的 HTTP 客户端
+    let clients = backends::Clients::from_config(&config)?;
+
+    // 内容策略规则，收到 SIGHUP 时重新加载
+    let policy = policy::ContentPolicy::load(config.policy_rules_file.as_deref())?;
+    if let Some(path) = &config.policy_rules_file {
+        tracing::info!(
+            "Content policy enabled: {} rule(s) from {}",
+            policy.rule_hits().len(),
+            path
+        );
+        policy::spawn_reload_on_sighup(policy.clone());
+    }
 
     let config = Arc::new(config);
 
@@ -158,34 +247,192 @@ async fn async_main(cli: Cli) -> anyhow::Result<()> {
         .allow_methods(Any)
         .allow_headers(Any);
 
+    // Anthropic 端点的错误响应带 `"type": "error"` 信封
+    let anthropic_routes = Router::new()
+        .route("/v1/messages", post(handlers::anthropic_handler))
+        .route("/v1/messages/count_tokens", post(handlers::count_tokens_handler))
+        .route(
+            "/v1/messages/batches",
+            get(handlers::message_batches_handler).post(handlers::message_batches_handler),
+        )
+        .route(
+            "/v1/messages/batches/:id",
+            get(handlers::message_batches_handler).delete(handlers::message_batches_handler),
+        )
+        .route("/v1/messages/batches/:id/results", get(handlers::message_batches_handler))
+        .route("/v1/messages/batches/:id/cancel", post(handlers::message_batches_handler))
+        .route_layer(axum::middleware::from_fn(handlers::anthropic::anthropic_error_envelope));
+
     // 根据路由模式配置端点
     let mut app = Router::new()
-        .route("/v1/messages", post(handlers::anthropic_handler))
+        .merge(anthropic_routes)
+        .route("/v1/models/:model_id", get(handlers::model_info_handler))
+        .route("/v1/chat/completions", post(handlers::openai_handler))
+        .route("/v1/embeddings", post(handlers::embeddings_handler))
+        .route("/v1/batch", post(handlers::batch_handler))
         .route("/health", get(health_handler));
 
-    // Auto/Gateway 模式支持 OpenAI 端点
+    // OpenAI 端点总是注册：/v1/embeddings 仅 Auto/Gateway 模式可用，/v1/chat/completions
+    // 在 Transform 模式下透传到上游，不可用时返回 400 说明原因
     if matches!(config.routing_mode, RoutingMode::Auto | RoutingMode::Gateway) {
-        app = app.route("/v1/chat/completions", post(handlers::openai_handler));
-        tracing::info!("OpenAI endpoint enabled: /v1/chat/completions");
+        tracing::info!("OpenAI endpoints enabled: /v1/chat/completions, /v1/embeddings");
+    } else if router::openai_endpoint_enabled(&config) {
+        tracing::info!("OpenAI endpoint enabled: /v1/chat/completions (passthrough to upstream)");
+    }
+
+    // /metrics 只在独立端口上提供，与 API 端口相同时不启用
+    let metrics_port = match config.metrics_port {
+        Some(port) if port == config.port => {
+            tracing::warn!("METRICS_PORT is the same as PORT; /metrics is not served");
+            None
+        }
+        port => port,
+    };
+
+    // 监控面板：提供 /dashboard
+    if let Some(log_tail) = log_tail {
+        if config.admin_key.is_none() {
+            tracing::warn!("DASHBOARD_ENABLED is set but ADMIN_KEY is not; dashboard requests will be rejected");
+        }
+        let health = Arc::new(handlers::dashboard::BackendHealth::default());
+        handlers::dashboard::spawn_health_checks(health.clone(), config.clone(), clients.clone());
+        let dashboard = Router::new()
+            .route("/dashboard", get(handlers::dashboard::dashboard_page))
+            .route("/dashboard/api/status", get(handlers::dashboard::dashboard_status))
+            .route("/dashboard/api/requests", get(handlers::dashboard::dashboard_requests))
+            .route("/dashboard/api/events-token", post(handlers::dashboard::dashboard_events_token))
+            .route("/dashboard/events", get(handlers::dashboard::dashboard_events))
+            .layer(Extension(health))
+            .layer(Extension(log_tail));
+        app = app.merge(dashboard);
+        tracing::info!("Dashboard enabled: /dashboard");
+    }
+
+    // 审计日志：请求/响应哈希与签名记录
+    let mut audit_log = None;
+    if let (Some(path), Some(secret)) = (&config.audit_log, &config.audit_hmac_secret) {
+        let audit = Arc::new(
+            audit::AuditLog::open(path, secret)
+                .map_err(|e| anyhow::anyhow!("Failed to open AUDIT_LOG {}: {}", path, e))?,
+        );
+        app = app
+            .layer(axum::middleware::from_fn(audit::record_audit))
+            .layer(Extension(audit.clone()));
+        audit_log = Some(audit);
+        tracing::info!("Audit log enabled: {}", path);
+    }
+
+    // 面板与 /metrics 共用的请求记录
+    let monitor = (config.dashboard_enabled || metrics_port.is_some())
+        .then(|| Arc::new(Monitor::new(monitor::DEFAULT_HISTORY_CAPACITY)));
+    if let Some(monitor) = &monitor {
+        app = app
+            .layer(axum::middleware::from_fn(monitor::record_requests))
+            .layer(Extension(monitor.clone()));
     }
 
     let app = app
+        .layer(DefaultBodyLimit::max(handlers::REQUEST_BODY_LIMIT))
+        .layer(axum::middleware::from_fn(middleware::request_id::propagate_request_id))
+        .layer(axum::middleware::from_fn(middleware::client_ip::resolve_client_ip))
         .layer(Extension(config.clone()))
-        .layer(Extension(client))
+        .layer(Extension(clients))
+        .layer(Extension(rate_limit::RateLimitBuckets::default()))
+        .layer(Extension(policy.clone()))
         .layer(TraceLayer::new_for_http())
         .layer(cors);
 
-    let addr = format!("0.0.0.0:{}", config.port);
+    let addr = config::bind_address(&config.host, config.port)?;
     let listener = tokio::net::TcpListener::bind(&addr).await?;
 
-    tracing::info!("Listening on {}", addr);
-    tracing::info!("Proxy ready to accept requests");
+    let addr = listener.local_addr()?;
+
+    let metrics_server = match (metrics_port, &monitor) {
+        (Some(port), Some(monitor)) => {
+            let metrics_addr = config::bind_address(&config.host, port)?;
+            let metrics_listener = tokio::net::TcpListener::bind(&metrics_addr).await?;
+            let metrics_app = Router::new()
+                .route("/metrics", get(handlers::metrics::metrics_handler))
+                .route("/health", get(health_handler))
+                .layer(Extension(monitor.clone()))
+                .layer(Extension(policy));
+            tracing::info!(address = %metrics_listener.local_addr()?, "Metrics enabled: /metrics");
+            Some(tokio::spawn(async move {
+                if let Err(e) = axum::serve(metrics_listener, metrics_app).await {
+                    tracing::error!("Metrics server failed: {}", e);
+                }
+            }))
+        }
+        _ => None,
+    };
 
-    axum::serve(listener, app).await?;
+    // 结构化的启动完成日志，便于不依赖 systemd 的就绪检查
+    tracing::info!(
+        address = %addr,
+        routing_mode = %config.routing_mode,
+        backends = ?configured_backends(&config),
+        "Startup complete, proxy ready to accept requests"
+    );
+    systemd::notify_ready();
+    systemd::spawn_watchdog();
+
+    // 连接对端地址供 client_ip 中间件使用
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    // 指标请求很短，API 端口排空后直接停止
+    if let Some(metrics_server) = metrics_server {
+        metrics_server.abort();
+    }
+    // 等待排空的请求产生的审计记录写入文件
+    if let Some(audit) = audit_log {
+        audit.flush();
+    }
 
+    tracing::info!(address = %addr, "Shutdown complete");
     Ok(())
 }
 
+/// 已配置的后端名称，用于启动日志
+fn configured_backends(config: &Config) -> Vec<&'static str> {
+    [
+        ("anthropic", config.anthropic_base_url.is_some()),
+        ("openai", config.openai_base_url.is_some()),
+        ("upstream", config.base_url.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, configured)| configured.then_some(name))
+    .collect()
+}
+
+/// 等待 Ctrl+C 或 SIGTERM，随后通知 systemd 并开始优雅关闭
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    let signal = tokio::select! {
+        _ = ctrl_c => "SIGINT",
+        _ = terminate => "SIGTERM",
+    };
+    tracing::info!(signal, "Shutdown started, draining in-flight requests");
+    systemd::notify_stopping();
+}
+
 async fn health_handler() -> &'static str {
     "OK"
 }
@@ -208,13 +455,13 @@ fn stop_daemon(pid_file: &std::path::Path) -> anyhow::Result<()> {
             .arg(pid.to_string())
             .output()?;
 
+        // 守护进程优雅关闭时会自行删除 PID 文件，这里只清理残留
+        let _ = std::fs::remove_file(pid_file);
         if output.status.success() {
-            std::fs::remove_file(pid_file)?;
             eprintln!("✓ Daemon stopped (PID: {})", pid);
         } else {
             eprintln!("✗ Failed to stop daemon (PID: {})", pid);
             eprintln!("  Process may have already exited");
-            std::fs::remove_file(pid_file)?;
             std::process::exit(1);
         }
     }
@@ -265,3 +512,36 @@ fn check_status(pid_file: &std::path::Path) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// `check` 子命令：加载配置并输出隐藏密钥后的配置、警告和各模型族的可服务情况
+fn check_config(config_path: Option<std::path::PathBuf>) -> anyhow::Result<()> {
+    let (config, load_report) = Config::from_env_with_path(config_path)?;
+
+    match &load_report.source {
+        Some(path) => println!("# Loaded config from: {}", path.display()),
+        None => println!("# No .env file found, using environment variables only"),
+    }
+    println!("{}", config.display_safe());
+    for warning in &load_report.warnings {
+        eprintln!("⚠ {}", warning);
+    }
+    for finding in doctor::static_checks(&config) {
+        eprintln!("⚠ {}", finding);
+    }
+
+    let mut ok = true;
+    for diagnostic in router::serving_diagnostics(&config) {
+        match diagnostic.result {
+            Ok(_) => eprintln!("✓ {}", diagnostic),
+            Err(_) => {
+                ok = false;
+                eprintln!("✗ {}", diagnostic);
+            }
+        }
+    }
+    if !ok {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
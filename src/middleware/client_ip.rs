@@ -0,0 +1,190 @@
+//! 真实客户端 IP 中间件
+//!
+//! 默认使用连接的对端地址。对端地址属于 TRUSTED_PROXIES 时，从右向左解析
+//! X-Forwarded-For（没有时使用 X-Real-IP），第一个不受信任的地址即为客户端。
+//! 开启 FORWARD_CLIENT_IP 时把整理后的转发链写回请求头，由后端透传到上游
+
+use crate::config::{Cidr, Config};
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+tokio::task_local! {
+    static CLIENT_IP: IpAddr;
+}
+
+/// 当前请求的客户端 IP（仅在中间件作用范围内可用）
+pub fn current_client_ip() -> Option<IpAddr> {
+    CLIENT_IP.try_with(|ip| *ip).ok()
+}
+
+/// 解析出的客户端地址
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientAddr {
+    pub ip: IpAddr,
+    /// 从客户端到对端的转发链，已去掉客户端左侧无法验证的地址
+    pub chain: Vec<IpAddr>,
+}
+
+/// 解析转发头中的单个地址，接受 `1.2.3.4`、`1.2.3.4:5678`、`2001:db8::1` 与 `[2001:db8::1]:443`
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| hop.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+/// 按对端地址和受信任网段确定客户端地址
+pub fn resolve_client_addr(peer: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> ClientAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    let mut chain = vec![peer];
+    if !is_trusted(peer) {
+        return ClientAddr { ip: peer, chain };
+    }
+
+    // 多个 X-Forwarded-For 头按出现顺序拼接
+    let mut hops: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    if hops.is_empty() {
+        hops.extend(headers.get(X_REAL_IP).and_then(|v| v.to_str().ok()));
+    }
+
+    for hop in hops.into_iter().rev() {
+        // 无法解析的地址之后的内容都不可信
+        let Some(ip) = parse_hop(hop) else {
+            break;
+        };
+        chain.insert(0, ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    ClientAddr { ip: chain[0], chain }
+}
+
+/// 客户端 IP 中间件：没有连接信息时（如测试中直接调用路由）不做处理
+pub async fn resolve_client_ip(
+    Extension(config): Extension<Arc<Config>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(peer) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip())
+    else {
+        return next.run(request).await;
+    };
+
+    let client = resolve_client_addr(peer, request.headers(), &config.trusted_proxies);
+    if config.forward_client_ip {
+        let chain: Vec<String> = client.chain.iter().map(ToString::to_string).collect();
+        if let Ok(value) = HeaderValue::from_str(&chain.join(", ")) {
+            request.headers_mut().insert(X_FORWARDED_FOR, value);
+        }
+    }
+
+    CLIENT_IP.scope(client.ip, next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted(ranges: &[&str]) -> Vec<Cidr> {
+        ranges.iter().map(|r| Cidr::parse(r).unwrap()).collect()
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, value.parse().unwrap());
+        headers
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_multiple_hops_resolved_right_to_left() {
+        let trusted = trusted(&["127.0.0.1", "10.0.0.0/8"]);
+        let headers = forwarded("198.51.100.7, 203.0.113.9, 10.1.2.3");
+        let client = resolve_client_addr(ip("127.0.0.1"), &headers, &trusted);
+        // 10.1.2.3 是受信任的内部代理，203.0.113.9 是第一个不受信任的地址，
+        // 更左侧的 198.51.100.7 可能是客户端伪造的
+        assert_eq!(client.ip, ip("203.0.113.9"));
+        assert_eq!(client.chain, vec![ip("203.0.113.9"), ip("10.1.2.3"), ip("127.0.0.1")]);
+
+        // 多个头按顺序拼接
+        let mut headers = forwarded("198.51.100.7");
+        headers.append(X_FORWARDED_FOR, "10.1.2.3".parse().unwrap());
+        assert_eq!(resolve_client_addr(ip("127.0.0.1"), &headers, &trusted).ip, ip("198.51.100.7"));
+
+        // 全部受信任时取最左侧的地址
+        let headers = forwarded("10.9.9.9, 10.1.2.3");
+        assert_eq!(resolve_client_addr(ip("127.0.0.1"), &headers, &trusted).ip, ip("10.9.9.9"));
+
+        // 无法解析的地址截断转发链
+        let headers = forwarded("198.51.100.7, unknown, 10.1.2.3");
+        assert_eq!(resolve_client_addr(ip("127.0.0.1"), &headers, &trusted).ip, ip("10.1.2.3"));
+    }
+
+    #[test]
+    fn test_spoofed_headers_from_untrusted_peer_ignored() {
+        let mut headers = forwarded("1.1.1.1");
+        headers.insert(X_REAL_IP, "2.2.2.2".parse().unwrap());
+        let client = resolve_client_addr(ip("203.0.113.50"), &headers, &trusted(&["127.0.0.1"]));
+        assert_eq!(client.ip, ip("203.0.113.50"));
+        assert_eq!(client.chain, vec![ip("203.0.113.50")]);
+
+        // 未配置 TRUSTED_PROXIES 时总是使用对端地址
+        let client = resolve_client_addr(ip("127.0.0.1"), &headers, &[]);
+        assert_eq!(client.ip, ip("127.0.0.1"));
+
+        // 受信任的对端只带 X-Real-IP 时使用它
+        let mut headers = HeaderMap::new();
+        headers.insert(X_REAL_IP, "2.2.2.2".parse().unwrap());
+        let client = resolve_client_addr(ip("127.0.0.1"), &headers, &trusted(&["127.0.0.1"]));
+        assert_eq!(client.ip, ip("2.2.2.2"));
+    }
+
+    #[test]
+    fn test_ipv6_addresses() {
+        let trusted = trusted(&["::1", "fd00::/8"]);
+        let headers = forwarded("2001:db8::7, [2001:db8::9]:443, fd12::1");
+        let client = resolve_client_addr(ip("::1"), &headers, &trusted);
+        assert_eq!(client.ip, ip("2001:db8::9"));
+
+        // IPv4 映射的 IPv6 对端地址匹配 IPv4 网段
+        let headers = forwarded("198.51.100.7:5123");
+        let client = resolve_client_addr(ip("::ffff:127.0.0.1"), &headers, &[Cidr::parse("127.0.0.0/8").unwrap()]);
+        assert_eq!(client.ip, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn test_cidr_parsing() {
+        let cidr = Cidr::parse("192.168.0.0/16").unwrap();
+        assert!(cidr.contains(ip("192.168.44.1")));
+        assert!(!cidr.contains(ip("192.169.0.1")));
+        assert!(!cidr.contains(ip("::1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+        assert_eq!(Cidr::parse("10.0.0.1").unwrap().to_string(), "10.0.0.1/32");
+        assert!(Cidr::parse("2001:db8::/32").unwrap().contains(ip("2001:db8:1::5")));
+        assert!(Cidr::parse("10.0.0.0/33").is_none());
+        assert!(Cidr::parse("localhost").is_none());
+    }
+}
@@ -0,0 +1,6 @@
+//! 中间件模块
+//!
+//! 作用于所有端点的请求级处理
+
+pub mod client_ip;
+pub mod request_id;
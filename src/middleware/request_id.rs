@@ -0,0 +1,161 @@
+//! 请求关联 ID 中间件
+//!
+//! 从 REQUEST_ID_HEADER 指定的请求头读取关联 ID（缺失时生成），
+//! 写回请求头以便透传到上游，并设置到响应头和错误响应体中
+
+use crate::config::Config;
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// 当前请求的关联 ID（仅在中间件作用范围内可用）
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// 生成新的请求 ID：纳秒时间戳加进程内计数器
+fn generate_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let sequence = COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff;
+    format!("req_{:x}{:04x}", nanos, sequence)
+}
+
+/// 请求 ID 中间件
+pub async fn propagate_request_id(
+    Extension(config): Extension<Arc<Config>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let header_name = HeaderName::from_bytes(config.request_id_header.as_bytes())
+        .unwrap_or_else(|_| HeaderName::from_static("x-request-id"));
+
+    let request_id = request
+        .headers()
+        .get(&header_name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .unwrap_or_else(generate_request_id);
+
+    // 写回请求头，后端按 FORWARD_HEADERS 逻辑透传到上游
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert(header_name.clone(), value);
+    }
+
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(header_name, value);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ProxyError;
+    use crate::test_utils::test_config;
+    use axum::{body::Body, http::HeaderMap, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn create_test_config() -> Config {
+        Config {
+            routing_mode: crate::config::RoutingMode::Transform,
+            request_id_header: "X-Correlation-Id".to_string(),
+            ..test_config()
+        }
+    }
+
+    fn test_app() -> Router {
+        async fn echo(headers: HeaderMap) -> String {
+            headers
+                .get("x-correlation-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        }
+        async fn fail() -> Result<String, ProxyError> {
+            Err(ProxyError::Internal("boom".into()))
+        }
+
+        Router::new()
+            .route("/echo", get(echo))
+            .route("/fail", get(fail))
+            .layer(axum::middleware::from_fn(propagate_request_id))
+            .layer(Extension(Arc::new(create_test_config())))
+    }
+
+    async fn body_string(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_id_from_configured_header() {
+        let request = Request::builder()
+            .uri("/echo")
+            .header("X-Correlation-Id", "abc-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers().get("x-correlation-id").unwrap(), "abc-123");
+        assert!(response.headers().get("x-request-id").is_none());
+        assert_eq!(body_string(response).await, "abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated_when_missing() {
+        let request = Request::builder().uri("/echo").body(Body::empty()).unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        let header = response
+            .headers()
+            .get("x-correlation-id")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(header.starts_with("req_"));
+        // 生成的 ID 同时写回请求头，供上游透传
+        assert_eq!(body_string(response).await, header);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_in_error_body() {
+        let request = Request::builder()
+            .uri("/fail")
+            .header("X-Correlation-Id", "abc-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+        let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+
+        assert_eq!(body["error"]["request_id"], "abc-123");
+        assert_eq!(body["error"]["message"], "boom");
+    }
+}
@@ -23,6 +23,9 @@ pub struct AnthropicRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Value>,
+    /// 容器上下文（如代码执行的容器 ID），原样转发
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<Value>,
     #[serde(flatten)]
     pub extra: Value,
 }
@@ -108,9 +111,9 @@ impl ToolResultContent {
             ToolResultContent::Blocks(blocks) => {
                 blocks
                     .iter()
-                    .filter_map(|b| match b {
-                        ToolResultBlock::Text { text } => Some(text.clone()),
-                        ToolResultBlock::Image { .. } => Some("[image]".to_string()),
+                    .map(|b| match b {
+                        ToolResultBlock::Text { text } => text.clone(),
+                        ToolResultBlock::Image { .. } => "[image]".to_string(),
                     })
                     .collect::<Vec<_>>()
                     .join("\n")
@@ -129,12 +132,26 @@ pub enum ToolResultBlock {
     Image { source: ImageSource },
 }
 
+/// Image source: inline base64 data or a URL fetched by Anthropic
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImageSource {
-    #[serde(rename = "type")]
-    pub source_type: String,
-    pub media_type: String,
-    pub data: String,
+#[serde(tag = "type")]
+pub enum ImageSource {
+    #[serde(rename = "base64")]
+    Base64 { media_type: String, data: String },
+    #[serde(rename = "url")]
+    Url { url: String },
+}
+
+impl ImageSource {
+    /// Convert to a URL usable in an OpenAI `image_url` part (data URL for base64 sources)
+    pub fn to_url(&self) -> String {
+        match self {
+            ImageSource::Base64 { media_type, data } => {
+                format!("data:{};base64,{}", media_type, data)
+            }
+            ImageSource::Url { url } => url.clone(),
+        }
+    }
 }
 
 /// Tool definition
@@ -143,6 +160,8 @@ pub struct Tool {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// computer use 等内置工具没有 input_schema
+    #[serde(default)]
     pub input_schema: Value,
     #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
     pub tool_type: Option<String>,
@@ -206,22 +225,45 @@ pub enum StreamEvent {
     #[serde(rename = "content_block_stop")]
     ContentBlockStop { index: usize },
     #[serde(rename = "message_delta")]
-    MessageDelta { delta: MessageDeltaData },
+    MessageDelta {
+        delta: MessageDeltaData,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        usage: Option<MessageDeltaUsage>,
+    },
     #[serde(rename = "message_stop")]
     MessageStop,
-    #[serde(rename = "ping")]
-    Ping,
     #[serde(rename = "error")]
     Error { error: ErrorData },
 }
 
+impl StreamEvent {
+    /// SSE `event:` 字段，与 JSON 中的 `type` 相同
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            StreamEvent::MessageStart { .. } => "message_start",
+            StreamEvent::ContentBlockStart { .. } => "content_block_start",
+            StreamEvent::ContentBlockDelta { .. } => "content_block_delta",
+            StreamEvent::ContentBlockStop { .. } => "content_block_stop",
+            StreamEvent::MessageDelta { .. } => "message_delta",
+            StreamEvent::MessageStop => "message_stop",
+            StreamEvent::Error { .. } => "error",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageStartData {
     pub id: String,
     #[serde(rename = "type")]
     pub message_type: String,
     pub role: String,
+    #[serde(default)]
+    pub content: Vec<ResponseContent>,
     pub model: String,
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub stop_sequence: Option<String>,
     pub usage: Usage,
 }
 
@@ -231,28 +273,39 @@ pub enum ContentBlockStart {
     #[serde(rename = "text")]
     Text { text: String },
     #[serde(rename = "tool_use")]
-    ToolUse { id: String, name: String },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
     #[serde(rename = "thinking")]
     Thinking { thinking: String },
 }
 
+/// `content_block_delta` 事件的 `delta`，JSON 中的 `type` 为 `text_delta` 等
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Delta {
     #[serde(rename = "text_delta")]
-    TextDelta { text: String },
+    Text { text: String },
     #[serde(rename = "input_json_delta")]
-    InputJsonDelta { partial_json: String },
+    InputJson { partial_json: String },
     #[serde(rename = "thinking_delta")]
-    ThinkingDelta { thinking: String },
+    Thinking { thinking: String },
 }
 
+/// `message_delta` 事件的 `delta`；usage 与 delta 同级，见 [`MessageDeltaUsage`]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageDeltaData {
     pub stop_reason: Option<String>,
     pub stop_sequence: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub usage: Option<Usage>,
+}
+
+/// `message_delta` 事件中的累计输出 token 数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageDeltaUsage {
+    pub output_tokens: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -261,3 +314,30 @@ pub struct ErrorData {
     pub error_type: String,
     pub message: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_container_round_trips() {
+        let body = json!({
+            "model": "claude-3-5-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "container": {"id": "container_011CPR5CNjB747bTd36fQLFk", "skills": [{"type": "anthropic", "skill_id": "xlsx"}]}
+        });
+        let req: AnthropicRequest = serde_json::from_value(body.clone()).unwrap();
+        assert_eq!(req.container.as_ref().unwrap()["id"], "container_011CPR5CNjB747bTd36fQLFk");
+        assert_eq!(serde_json::to_value(&req).unwrap()["container"], body["container"]);
+
+        let req: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "max_tokens": 100,
+            "messages": []
+        }))
+        .unwrap();
+        assert!(serde_json::to_value(&req).unwrap().get("container").is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 
 /// OpenAI API request structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,8 +21,44 @@ pub struct OpenAIRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<Value>,
+    /// 是否允许并行工具调用，与 Anthropic 的 `tool_choice.disable_parallel_tool_use` 互相映射
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_effort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    /// 终端用户标识，与 Anthropic 的 `metadata.user_id` 互相映射
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// 服务层级（auto/default/flex/priority），与 Anthropic 的 `service_tier` 互相映射
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    /// token ID → 偏置值（-100 到 100），Anthropic 没有对应参数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<String, f32>>,
+    /// 输出格式（如 `{"type": "json_object"}`），A→O 时来自 Anthropic 请求的扩展字段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    /// 是否在 OpenAI 侧存储本次补全，Anthropic 没有对应参数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    /// 键值对元数据，O→A 时其中的 `user_id` 映射到 Anthropic 的 `metadata.user_id`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+    /// 旧版函数定义（已被 `tools` 取代），O→A 时转换为 tools
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub functions: Option<Vec<Function>>,
+    /// 旧版函数选择（`"auto"`、`"none"` 或 `{"name": ...}`），O→A 时转换为 tool_choice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<Value>,
+}
+
+/// 流式选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +71,12 @@ pub struct Message {
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// 历史 assistant 消息中的推理内容（DeepSeek/vLLM 等兼容字段）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    /// 旧版函数调用（已被 `tool_calls` 取代）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +93,12 @@ pub enum ContentPart {
     Text { text: String },
     #[serde(rename = "image_url")]
     ImageUrl { image_url: ImageUrl },
+    /// 音频输入（gpt-4o-audio 等），Anthropic 没有对应的内容类型
+    #[serde(rename = "input_audio")]
+    InputAudio { input_audio: InputAudio },
+    /// 未识别的内容类型，保留原始 JSON，避免新的类型导致整个请求解析失败
+    #[serde(untagged)]
+    Unknown(Value),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,8 +106,19 @@ pub struct ImageUrl {
     pub url: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputAudio {
+    /// base64 编码的音频数据
+    pub data: String,
+    /// 音频格式，如 `wav`、`mp3`
+    pub format: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
+    /// 部分客户端回放历史时会带上流式阶段的 index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
     pub id: String,
     #[serde(rename = "type")]
     pub call_type: String,
@@ -84,6 +144,8 @@ pub struct Function {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     pub parameters: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 /// OpenAI API response
@@ -99,10 +161,22 @@ pub struct OpenAIResponse {
     pub system_fingerprint: Option<String>,
 }
 
+impl OpenAIResponse {
+    /// 为每个 choice 补上 `"logprobs": null`
+    pub fn fill_null_logprobs(&mut self) {
+        for choice in &mut self.choices {
+            choice.logprobs.get_or_insert(None);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Choice {
     pub index: usize,
     pub message: ChoiceMessage,
+    /// 省略时不输出该字段；`Some(None)` 输出 `"logprobs": null`（STRICT_OPENAI_SHAPE）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Option<Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub finish_reason: Option<String>,
 }
@@ -114,6 +188,9 @@ pub struct ChoiceMessage {
     pub content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// 旧版 API 网关返回的函数调用，没有调用 id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -131,19 +208,23 @@ pub struct StreamChunk {
     pub created: u64,
     pub model: String,
     pub choices: Vec<StreamChoice>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub usage: Option<Usage>,
+    /// 省略时不输出该字段；`Some(None)` 输出 `"usage": null`
+    /// （请求了 `include_usage` 时的中间 chunk）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Option<Usage>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamChoice {
     pub index: usize,
     pub delta: Delta,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// 省略时不输出该字段；`Some(None)` 输出 `"logprobs": null`（STRICT_OPENAI_SHAPE）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Option<Value>>,
     pub finish_reason: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Delta {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
@@ -151,8 +232,17 @@ pub struct Delta {
     pub content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<DeltaToolCall>>,
+    /// 旧版流式函数调用：第一段带函数名，之后的 chunk 只带参数片段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<DeltaFunctionCall>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning: Option<String>,
+    /// 模型因内容策略拒绝回答时的说明
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    /// 其余字段，如按 REASONING_FIELD 命名的推理增量（`reasoning_content` 等）
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -174,3 +264,50 @@ pub struct DeltaFunctionCall {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub arguments: Option<String>,
 }
+
+/// 流式响应中途的错误：`data: {"error": {...}}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamError {
+    pub error: ErrorDetail,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    #[serde(default)]
+    pub message: String,
+    #[serde(rename = "type", default)]
+    pub error_type: String,
+    /// 字符串或数字
+    #[serde(default)]
+    pub code: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_store_and_metadata_round_trip() {
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "store": true,
+            "metadata": {"user_id": "user-42", "session": "abc"}
+        });
+        let req: OpenAIRequest = serde_json::from_value(body.clone()).unwrap();
+        assert_eq!(req.store, Some(true));
+        let value = serde_json::to_value(&req).unwrap();
+        assert_eq!(value["store"], true);
+        assert_eq!(value["metadata"], body["metadata"]);
+
+        let req: OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": []
+        }))
+        .unwrap();
+        let value = serde_json::to_value(&req).unwrap();
+        assert!(value.get("store").is_none());
+        assert!(value.get("metadata").is_none());
+    }
+}
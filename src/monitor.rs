@@ -0,0 +1,563 @@
+//! 请求监控模块
+//!
+//! 记录最近的请求摘要（环形缓冲区）、累计计数与 token 用量/费用，并通过广播通道推送给 /dashboard
+
+use crate::config::Config;
+use crate::middleware::client_ip::current_client_ip;
+use crate::streaming::sse::SseParser;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// 默认保留的请求历史条数
+pub const DEFAULT_HISTORY_CAPACITY: usize = 200;
+
+/// 广播通道容量，慢订阅者落后超过该值会丢失事件
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// 为统计用量缓存的非流式响应体上限，超过时不计入用量
+const USAGE_BODY_LIMIT: usize = 8 * 1024 * 1024;
+
+/// 单个请求的摘要
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestSummary {
+    /// Unix 时间戳（毫秒）
+    pub timestamp: u64,
+    pub method: String,
+    pub path: String,
+    pub category: RequestCategory,
+    pub status: u16,
+    pub duration_ms: u64,
+    /// 终端用户标识（来自 `metadata.user_id` / `user`）
+    pub user_id: Option<String>,
+    /// 客户端 IP（经 TRUSTED_PROXIES 解析）
+    pub client_ip: Option<String>,
+}
+
+/// 请求所属的端点类别，作为 requests_by_category_total 指标的 category 标签
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestCategory {
+    /// /v1/messages 及其子路径（count_tokens、Message Batches）
+    Messages,
+    ChatCompletions,
+    Embeddings,
+    Models,
+    /// /v1/batch
+    Batch,
+    Other,
+}
+
+impl RequestCategory {
+    pub const ALL: [RequestCategory; 6] = [
+        RequestCategory::Messages,
+        RequestCategory::ChatCompletions,
+        RequestCategory::Embeddings,
+        RequestCategory::Models,
+        RequestCategory::Batch,
+        RequestCategory::Other,
+    ];
+
+    pub fn from_path(path: &str) -> Self {
+        if path.starts_with("/v1/messages") {
+            RequestCategory::Messages
+        } else if path == "/v1/chat/completions" {
+            RequestCategory::ChatCompletions
+        } else if path == "/v1/embeddings" {
+            RequestCategory::Embeddings
+        } else if path.starts_with("/v1/models") {
+            RequestCategory::Models
+        } else if path == "/v1/batch" {
+            RequestCategory::Batch
+        } else {
+            RequestCategory::Other
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RequestCategory::Messages => "messages",
+            RequestCategory::ChatCompletions => "chat_completions",
+            RequestCategory::Embeddings => "embeddings",
+            RequestCategory::Models => "models",
+            RequestCategory::Batch => "batch",
+            RequestCategory::Other => "other",
+        }
+    }
+}
+
+/// 处理器附加到响应扩展中的终端用户标识，供监控记录归属
+#[derive(Debug, Clone)]
+pub struct UserAttribution(pub String);
+
+/// 将终端用户标识附加到响应，供 [`record_requests`] 读取
+pub fn attribute_user(response: &mut Response, user_id: Option<String>) {
+    if let Some(user_id) = user_id {
+        response.extensions_mut().insert(UserAttribution(user_id));
+    }
+}
+
+/// 累计计数快照
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MonitorCounters {
+    pub total_requests: u64,
+    pub error_requests: u64,
+    pub uptime_secs: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// 按 MODEL_PRICING 估算的累计费用（美元），未配置单价的模型不计费
+    pub cost_usd: f64,
+}
+
+/// 转换失败的原因，作为 transform_failures_total 指标的 reason 标签
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransformFailure {
+    /// 请求体不是合法的 JSON
+    InvalidJson,
+    /// 请求中含有目标后端不支持的内容块或参数
+    UnsupportedBlock,
+    /// JSON 结构不符合对应 API 的格式（缺少字段、字段类型或取值错误）
+    Schema,
+    /// tool_use 与 tool_result 无法配对
+    ToolPairing,
+}
+
+impl TransformFailure {
+    pub const ALL: [TransformFailure; 4] = [
+        TransformFailure::InvalidJson,
+        TransformFailure::UnsupportedBlock,
+        TransformFailure::Schema,
+        TransformFailure::ToolPairing,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransformFailure::InvalidJson => "invalid_json",
+            TransformFailure::UnsupportedBlock => "unsupported_block",
+            TransformFailure::Schema => "schema",
+            TransformFailure::ToolPairing => "tool_pairing",
+        }
+    }
+}
+
+/// 按原因累计的转换失败次数；转换代码不持有 [`Monitor`]，因此使用进程级计数
+static TRANSFORM_FAILURES: [AtomicU64; TransformFailure::ALL.len()] =
+    [const { AtomicU64::new(0) }; TransformFailure::ALL.len()];
+
+/// 记录一次转换失败
+pub fn record_transform_failure(reason: TransformFailure) {
+    TRANSFORM_FAILURES[reason as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// 某个原因的累计转换失败次数
+pub fn transform_failures(reason: TransformFailure) -> u64 {
+    TRANSFORM_FAILURES[reason as usize].load(Ordering::Relaxed)
+}
+
+/// 对冲中备用后端胜出的次数与累计节省的毫秒数；对冲代码不持有 [`Monitor`]，同样使用进程级计数
+static HEDGE_SECONDARY_WINS: AtomicU64 = AtomicU64::new(0);
+static HEDGE_SAVED_MS: AtomicU64 = AtomicU64::new(0);
+
+/// 记录一次备用后端胜出的对冲及其节省的时间
+pub fn record_hedge_saved(saved: Duration) {
+    HEDGE_SECONDARY_WINS.fetch_add(1, Ordering::Relaxed);
+    HEDGE_SAVED_MS.fetch_add(saved.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// 备用后端胜出的累计次数与累计节省的毫秒数
+pub fn hedge_saved() -> (u64, u64) {
+    (
+        HEDGE_SECONDARY_WINS.load(Ordering::Relaxed),
+        HEDGE_SAVED_MS.load(Ordering::Relaxed),
+    )
+}
+
+/// 请求监控器
+pub struct Monitor {
+    history: Mutex<VecDeque<RequestSummary>>,
+    capacity: usize,
+    total_requests: AtomicU64,
+    error_requests: AtomicU64,
+    category_requests: [AtomicU64; RequestCategory::ALL.len()],
+    input_tokens: AtomicU64,
+    output_tokens: AtomicU64,
+    /// 累计费用，单位为 10^-9 美元
+    cost_nano_usd: AtomicU64,
+    started_at: Instant,
+    events: broadcast::Sender<RequestSummary>,
+}
+
+impl Monitor {
+    pub fn new(capacity: usize) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            total_requests: AtomicU64::new(0),
+            error_requests: AtomicU64::new(0),
+            category_requests: [const { AtomicU64::new(0) }; RequestCategory::ALL.len()],
+            input_tokens: AtomicU64::new(0),
+            output_tokens: AtomicU64::new(0),
+            cost_nano_usd: AtomicU64::new(0),
+            started_at: Instant::now(),
+            events,
+        }
+    }
+
+    /// 记录一个请求摘要：写入环形缓冲区、更新计数并广播给所有订阅者
+    pub fn record(&self, summary: RequestSummary) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.category_requests[summary.category as usize].fetch_add(1, Ordering::Relaxed);
+        if summary.status >= 400 {
+            self.error_requests.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Ok(mut history) = self.history.lock() {
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(summary.clone());
+        }
+
+        // 没有订阅者时发送失败是正常情况
+        let _ = self.events.send(summary);
+    }
+
+    /// 累计一次响应的 token 用量；`config` 中有该模型的单价时同时累计费用
+    pub fn record_usage(&self, config: Option<&Config>, model: Option<&str>, input: u64, output: u64) {
+        self.input_tokens.fetch_add(input, Ordering::Relaxed);
+        self.output_tokens.fetch_add(output, Ordering::Relaxed);
+        if let Some(price) = config.zip(model).and_then(|(config, model)| config.price_for(model)) {
+            // 美元/百万 token 乘以 token 数即为 10^-6 美元，再乘 1000 得到 10^-9 美元
+            let nano = (input as f64 * price.input_per_mtok + output as f64 * price.output_per_mtok) * 1000.0;
+            self.cost_nano_usd.fetch_add(nano.round() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// 最近的请求，按时间顺序排列
+    pub fn recent(&self) -> Vec<RequestSummary> {
+        self.history
+            .lock()
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn counters(&self) -> MonitorCounters {
+        MonitorCounters {
+            total_requests: self.total_requests.load(Ordering::Relaxed),
+            error_requests: self.error_requests.load(Ordering::Relaxed),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            input_tokens: self.input_tokens.load(Ordering::Relaxed),
+            output_tokens: self.output_tokens.load(Ordering::Relaxed),
+            cost_usd: self.cost_nano_usd.load(Ordering::Relaxed) as f64 / 1e9,
+        }
+    }
+
+    /// 某个类别的累计请求数
+    pub fn category_requests(&self, category: RequestCategory) -> u64 {
+        self.category_requests[category as usize].load(Ordering::Relaxed)
+    }
+
+    /// 订阅实时请求事件
+    pub fn subscribe(&self) -> broadcast::Receiver<RequestSummary> {
+        self.events.subscribe()
+    }
+}
+
+/// 响应中的 `usage`（Anthropic 流的 `message_start` 中为 `message.usage`）
+pub fn usage_objects(value: &Value) -> impl Iterator<Item = &Map<String, Value>> {
+    [value.get("usage"), value.pointer("/message/usage")]
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object)
+}
+
+/// 从响应体（或流式响应的各个事件）中收集用量与实际模型，结束或被丢弃（客户端断开）时计入 [`Monitor`]
+struct UsageTap {
+    monitor: Arc<Monitor>,
+    config: Option<Arc<Config>>,
+    /// 流式响应逐个事件解析；非流式响应缓存完整响应体
+    parser: Option<SseParser>,
+    body: Vec<u8>,
+    usage: Map<String, Value>,
+    model: Option<String>,
+    finished: bool,
+}
+
+impl UsageTap {
+    fn observe(&mut self, bytes: &[u8]) {
+        match &mut self.parser {
+            Some(parser) => {
+                for event in parser.feed(bytes) {
+                    self.observe_json(&event.data);
+                }
+            }
+            None if self.body.len() + bytes.len() <= USAGE_BODY_LIMIT => self.body.extend_from_slice(bytes),
+            None => self.body.clear(),
+        }
+    }
+
+    fn observe_json(&mut self, data: &str) {
+        let Ok(value) = serde_json::from_str::<Value>(data) else {
+            return;
+        };
+        // 各事件中的用量字段合并，后出现的覆盖先出现的
+        for usage in usage_objects(&value) {
+            self.usage.extend(usage.clone());
+        }
+        if self.model.is_none() {
+            self.model = value
+                .get("model")
+                .or_else(|| value.pointer("/message/model"))
+                .and_then(Value::as_str)
+                .map(String::from);
+        }
+    }
+
+    fn finish(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        match self.parser.as_mut().map(SseParser::finish) {
+            Some(Some(event)) => self.observe_json(&event.data),
+            Some(None) => {}
+            None => {
+                let body = std::mem::take(&mut self.body);
+                self.observe_json(&String::from_utf8_lossy(&body));
+            }
+        }
+        // Anthropic 为 input_tokens/output_tokens，OpenAI 为 prompt_tokens/completion_tokens
+        let tokens = |names: [&str; 2]| names.iter().find_map(|name| self.usage.get(*name)?.as_u64());
+        let input = tokens(["input_tokens", "prompt_tokens"]).unwrap_or(0);
+        let output = tokens(["output_tokens", "completion_tokens"]).unwrap_or(0);
+        if input > 0 || output > 0 {
+            self.monitor
+                .record_usage(self.config.as_deref(), self.model.as_deref(), input, output);
+        }
+    }
+}
+
+impl Drop for UsageTap {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// 记录请求摘要的中间件（不记录 /dashboard 自身的请求）；
+/// /v1/messages 与 /v1/chat/completions 的成功响应旁路解析 token 用量
+pub async fn record_requests(
+    Extension(monitor): Extension<Arc<Monitor>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let config = request.extensions().get::<Arc<Config>>().cloned();
+    let started = Instant::now();
+
+    let mut response = next.run(request).await;
+
+    let category = RequestCategory::from_path(&path);
+    if response.status().is_success()
+        && matches!(category, RequestCategory::Messages | RequestCategory::ChatCompletions)
+    {
+        response = tap_usage(response, monitor.clone(), config);
+    }
+
+    if !path.starts_with("/dashboard") {
+        monitor.record(RequestSummary {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            method,
+            category,
+            path,
+            status: response.status().as_u16(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            user_id: response
+                .extensions()
+                .get::<UserAttribution>()
+                .map(|user| user.0.clone()),
+            client_ip: current_client_ip().map(|ip| ip.to_string()),
+        });
+    }
+
+    response
+}
+
+/// 把响应体包装为逐块转发的流，旁路收集用量
+fn tap_usage(response: Response, monitor: Arc<Monitor>, config: Option<Arc<Config>>) -> Response {
+    let is_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    let (parts, body) = response.into_parts();
+    let mut tap = UsageTap {
+        monitor,
+        config,
+        parser: is_stream.then(SseParser::default),
+        body: Vec::new(),
+        usage: Map::new(),
+        model: None,
+        finished: false,
+    };
+    let mut upstream = body.into_data_stream();
+    let stream = async_stream::stream! {
+        while let Some(chunk) = upstream.next().await {
+            if let Ok(bytes) = &chunk {
+                tap.observe(bytes);
+            }
+            yield chunk;
+        }
+        tap.finish();
+    };
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelPrice;
+    use crate::test_utils::test_config;
+    use axum::{routing::post, Router};
+    use tower::ServiceExt;
+
+    fn summary(path: &str, status: u16) -> RequestSummary {
+        RequestSummary {
+            timestamp: 0,
+            method: "POST".to_string(),
+            path: path.to_string(),
+            category: RequestCategory::from_path(path),
+            status,
+            duration_ms: 1,
+            user_id: None,
+            client_ip: None,
+        }
+    }
+
+    #[test]
+    fn test_history_ring_buffer() {
+        let monitor = Monitor::new(2);
+        monitor.record(summary("/a", 200));
+        monitor.record(summary("/b", 502));
+        monitor.record(summary("/c", 200));
+
+        let paths: Vec<String> = monitor.recent().into_iter().map(|s| s.path).collect();
+        assert_eq!(paths, vec!["/b", "/c"]);
+
+        let counters = monitor.counters();
+        assert_eq!(counters.total_requests, 3);
+        assert_eq!(counters.error_requests, 1);
+    }
+
+    #[test]
+    fn test_requests_counted_by_category() {
+        let monitor = Monitor::new(DEFAULT_HISTORY_CAPACITY);
+        for path in ["/v1/embeddings", "/v1/embeddings", "/v1/messages/count_tokens", "/health"] {
+            monitor.record(summary(path, 200));
+        }
+
+        assert_eq!(monitor.category_requests(RequestCategory::Embeddings), 2);
+        assert_eq!(monitor.category_requests(RequestCategory::Messages), 1);
+        assert_eq!(monitor.category_requests(RequestCategory::ChatCompletions), 0);
+        assert_eq!(monitor.category_requests(RequestCategory::Other), 1);
+        assert_eq!(
+            serde_json::to_value(summary("/v1/chat/completions", 200)).unwrap()["category"],
+            "chat_completions"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_fan_out_to_all_subscribers() {
+        let monitor = Monitor::new(DEFAULT_HISTORY_CAPACITY);
+        let mut first = monitor.subscribe();
+        let mut second = monitor.subscribe();
+
+        monitor.record(summary("/v1/messages", 200));
+
+        assert_eq!(first.recv().await.unwrap().path, "/v1/messages");
+        assert_eq!(second.recv().await.unwrap().path, "/v1/messages");
+    }
+
+    /// 经过 [`record_requests`] 调用一个返回固定响应体的处理器，读完响应体后返回监控
+    async fn record_response(path: &'static str, content_type: &'static str, body: &'static str) -> Arc<Monitor> {
+        let monitor = Arc::new(Monitor::new(DEFAULT_HISTORY_CAPACITY));
+        let config = Arc::new(Config {
+            model_pricing: ModelPrice::parse_list("claude-sonnet-*=3:15"),
+            ..test_config()
+        });
+        let app = Router::new()
+            .route(path, post(move || async move { ([(CONTENT_TYPE, content_type)], body) }))
+            .layer(axum::middleware::from_fn(record_requests))
+            .layer(Extension(monitor.clone()))
+            .layer(Extension(config));
+
+        let response = app
+            .oneshot(Request::post(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        monitor
+    }
+
+    #[tokio::test]
+    async fn test_json_usage_and_cost_counted() {
+        let monitor = record_response(
+            "/v1/messages",
+            "application/json",
+            r#"{"model":"claude-sonnet-4-5","usage":{"input_tokens":1000,"output_tokens":200}}"#,
+        )
+        .await;
+
+        let counters = monitor.counters();
+        assert_eq!(counters.input_tokens, 1000);
+        assert_eq!(counters.output_tokens, 200);
+        // 1000 × $3/M + 200 × $15/M
+        assert!((counters.cost_usd - 0.006).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn test_stream_usage_merged_across_events() {
+        let monitor = record_response(
+            "/v1/messages",
+            "text/event-stream",
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-sonnet-4-5\",\"usage\":{\"input_tokens\":50,\"output_tokens\":1}}}\n\n\
+             event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":30}}\n\n",
+        )
+        .await;
+
+        let counters = monitor.counters();
+        assert_eq!(counters.input_tokens, 50);
+        assert_eq!(counters.output_tokens, 30);
+        assert!(counters.cost_usd > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_openai_usage_without_price_not_billed() {
+        let monitor = record_response(
+            "/v1/chat/completions",
+            "application/json",
+            r#"{"model":"gpt-4o","usage":{"prompt_tokens":7,"completion_tokens":3}}"#,
+        )
+        .await;
+
+        let counters = monitor.counters();
+        assert_eq!((counters.input_tokens, counters.output_tokens), (7, 3));
+        assert_eq!(counters.cost_usd, 0.0);
+    }
+}
@@ -0,0 +1,89 @@
+//! 守护进程 PID 文件清理
+//!
+//! 守护进程正常退出（包括 SIGTERM/SIGINT 触发的优雅关闭）时删除 PID 文件，
+//! 避免 `status` 子命令把残留文件报告为已停止的进程
+
+use std::path::{Path, PathBuf};
+
+/// 离开作用域时删除 PID 文件
+///
+/// 仅当文件内容仍是当前进程的 PID 时才删除，避免误删新启动的守护进程写入的文件
+#[derive(Debug)]
+pub struct PidFileGuard {
+    path: PathBuf,
+    pid: u32,
+}
+
+impl PidFileGuard {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            pid: std::process::id(),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_pid(path: PathBuf, pid: u32) -> Self {
+        Self { path, pid }
+    }
+}
+
+impl Drop for PidFileGuard {
+    fn drop(&mut self) {
+        match remove_if_owned(&self.path, self.pid) {
+            Ok(true) => tracing::info!(path = %self.path.display(), "Removed PID file"),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to remove PID file {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// PID 文件记录的是 `pid` 时删除它，返回是否删除；文件不存在不算错误
+fn remove_if_owned(path: &Path, pid: u32) -> std::io::Result<bool> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if contents.trim().parse::<u32>().ok() != Some(pid) {
+        return Ok(false);
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_pid_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "anthropic-proxy-{}-{}.pid",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_guard_removes_own_pid_file() {
+        let path = temp_pid_file("own", "4242\n");
+        drop(PidFileGuard::with_pid(path.clone(), 4242));
+        assert!(!path.exists());
+
+        // 文件已被 stop 子命令删除时不报错
+        drop(PidFileGuard::with_pid(path.clone(), 4242));
+    }
+
+    #[test]
+    fn test_guard_keeps_foreign_pid_file() {
+        let path = temp_pid_file("foreign", "1234\n");
+        drop(PidFileGuard::with_pid(path.clone(), 4242));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1234\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
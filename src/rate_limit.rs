@@ -0,0 +1,133 @@
+//! 按模型的令牌桶限流
+//!
+//! RATE_LIMITS 中的每条规则对应一个令牌桶，处理器在路由前为请求消耗一个令牌
+//! （流式请求同样只计一次），桶耗尽时返回 429 并附带 `retry-after`
+
+use crate::config::Config;
+use crate::error::{ProxyError, ProxyResult};
+use crate::middleware::client_ip::current_client_ip;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 各规则的令牌桶，以规则的模型模式为键，通过 `Extension` 在处理器间共享
+pub type RateLimitBuckets = Arc<Mutex<HashMap<String, TokenBucket>>>;
+
+/// 令牌桶：容量为时间窗口内允许的请求数，按窗口长度匀速补充
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, now: Instant) -> Self {
+        Self {
+            tokens: capacity as f64,
+            updated: now,
+        }
+    }
+
+    /// 补充令牌后尝试消耗一个，不足时返回需等待的时长
+    fn try_acquire(&mut self, capacity: u32, period: Duration, now: Instant) -> Result<(), Duration> {
+        let rate = capacity as f64 / period.as_secs_f64();
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity as f64);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// 为请求的模型消耗一个令牌，没有匹配规则时直接放行
+pub fn check(config: &Config, buckets: &RateLimitBuckets, model: &str) -> ProxyResult<()> {
+    check_at(config, buckets, model, Instant::now())
+}
+
+fn check_at(
+    config: &Config,
+    buckets: &RateLimitBuckets,
+    model: &str,
+    now: Instant,
+) -> ProxyResult<()> {
+    let Some(limit) = config.rate_limit_for(model) else {
+        return Ok(());
+    };
+
+    let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+    let bucket = buckets
+        .entry(limit.pattern.clone())
+        .or_insert_with(|| TokenBucket::new(limit.requests, now));
+
+    bucket
+        .try_acquire(limit.requests, limit.period, now)
+        .map_err(|wait| {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::warn!(
+                "Rate limit exceeded for model {} (client {}), retry after {}s",
+                model,
+                current_client_ip().map(|ip| ip.to_string()).as_deref().unwrap_or("-"),
+                retry_after
+            );
+            ProxyError::RateLimited(
+                format!(
+                    "Rate limit of {} requests per {}s exceeded for model {}",
+                    limit.requests,
+                    limit.period.as_secs(),
+                    model
+                ),
+                retry_after,
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimit;
+    use crate::test_utils::test_config;
+
+    fn create_test_config() -> Config {
+        Config {
+            anthropic_base_url: Some("https://api.anthropic.com".to_string()),
+            anthropic_api_key: Some("sk-ant".to_string()),
+            rate_limits: RateLimit::parse_list("gpt-4o=2/min"),
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn test_requests_beyond_limit_rejected() {
+        let config = create_test_config();
+        let buckets = RateLimitBuckets::default();
+        let now = Instant::now();
+
+        assert!(check_at(&config, &buckets, "gpt-4o", now).is_ok());
+        assert!(check_at(&config, &buckets, "GPT-4o", now).is_ok());
+        match check_at(&config, &buckets, "gpt-4o", now) {
+            Err(ProxyError::RateLimited(_, retry_after)) => assert_eq!(retry_after, 30),
+            other => panic!("Expected rate limit error, got {:?}", other),
+        }
+
+        // 未配置限流的模型不受影响
+        assert!(check_at(&config, &buckets, "gpt-4o-mini", now).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let config = create_test_config();
+        let buckets = RateLimitBuckets::default();
+        let now = Instant::now();
+
+        assert!(check_at(&config, &buckets, "gpt-4o", now).is_ok());
+        assert!(check_at(&config, &buckets, "gpt-4o", now).is_ok());
+        assert!(check_at(&config, &buckets, "gpt-4o", now + Duration::from_secs(10)).is_err());
+        assert!(check_at(&config, &buckets, "gpt-4o", now + Duration::from_secs(31)).is_ok());
+        assert!(check_at(&config, &buckets, "gpt-4o", now + Duration::from_secs(32)).is_err());
+    }
+}
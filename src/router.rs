@@ -4,18 +4,39 @@
 
 use crate::config::{Config, RoutingMode};
 use crate::error::ProxyError;
+use crate::models::{anthropic, openai};
+use serde_json::Value;
 
 /// 目标后端
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Backend {
     /// Anthropic 官方 API
     Anthropic,
     /// OpenAI 官方 API
+    #[default]
     OpenAI,
     /// 通用上游（用于转换模式）
     Upstream,
 }
 
+impl Backend {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "anthropic" => Backend::Anthropic,
+            "upstream" => Backend::Upstream,
+            _ => Backend::OpenAI,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Anthropic => "anthropic",
+            Backend::OpenAI => "openai",
+            Backend::Upstream => "upstream",
+        }
+    }
+}
+
 /// 请求格式
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RequestFormat {
@@ -25,6 +46,22 @@ pub enum RequestFormat {
     OpenAI,
 }
 
+impl RequestFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RequestFormat::Anthropic => "anthropic",
+            RequestFormat::OpenAI => "openai",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            RequestFormat::Anthropic => RequestFormat::OpenAI,
+            RequestFormat::OpenAI => RequestFormat::Anthropic,
+        }
+    }
+}
+
 /// 转换方向
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TransformDirection {
@@ -43,6 +80,8 @@ pub struct RoutingDecision {
     pub needs_transform: bool,
     /// 转换方向
     pub transform_direction: Option<TransformDirection>,
+    /// 请求将发往的上游地址
+    pub target_url: Option<String>,
 }
 
 impl RoutingDecision {
@@ -61,30 +100,57 @@ impl RoutingDecision {
         }
     }
 
-    /// Transform 模式：仅支持 Anthropic 请求，转换为 OpenAI 格式发送到上游
+    /// Message Batches API（/v1/messages/batches）：没有对应的转换，只能透传到 Anthropic 后端
+    pub fn decide_message_batches(config: &Config) -> Result<Self, ProxyError> {
+        if config.routing_mode == RoutingMode::Transform {
+            return Err(ProxyError::UnsupportedOperation(
+                "Message Batches API is not supported in Transform mode. \
+                Change ROUTING_MODE to 'passthrough', 'auto' or 'gateway'."
+                    .into(),
+            ));
+        }
+        if config.anthropic_base_url.is_none() || config.anthropic_api_key.is_none() {
+            return Err(ProxyError::Config(
+                "ANTHROPIC_BASE_URL and ANTHROPIC_API_KEY are required for the Message Batches API"
+                    .into(),
+            ));
+        }
+        Ok(Self {
+            backend: Backend::Anthropic,
+            needs_transform: false,
+            transform_direction: None,
+            target_url: Some(config.anthropic_message_batches_url()),
+        })
+    }
+
+    /// Transform 模式：Anthropic 请求转换为 OpenAI 格式发送到上游，
+    /// OpenAI 请求原样透传到同一上游（TRANSFORM_REJECT_OPENAI=true 时拒绝）
     fn decide_transform_mode(
         request_format: RequestFormat,
         config: &Config,
     ) -> Result<Self, ProxyError> {
-        match request_format {
-            RequestFormat::Anthropic => {
-                if config.base_url.is_none() {
-                    return Err(ProxyError::Config(
-                        "UPSTREAM_BASE_URL is required in Transform mode".into(),
-                    ));
-                }
-                Ok(Self {
-                    backend: Backend::Upstream,
-                    needs_transform: true,
-                    transform_direction: Some(TransformDirection::AnthropicToOpenAI),
-                })
-            }
-            RequestFormat::OpenAI => Err(ProxyError::Transform(
-                "OpenAI endpoint is not supported in Transform mode. \
+        if request_format == RequestFormat::OpenAI && config.transform_reject_openai {
+            return Err(ProxyError::UnsupportedOperation(
+                "OpenAI endpoint is disabled in Transform mode (TRANSFORM_REJECT_OPENAI=true). \
                 Please use /v1/messages or change ROUTING_MODE to 'auto' or 'gateway'."
                     .into(),
-            )),
+            ));
         }
+        if config.base_url.is_none() {
+            return Err(ProxyError::Config(
+                "UPSTREAM_BASE_URL is required in Transform mode".into(),
+            ));
+        }
+        let (needs_transform, transform_direction) = match request_format {
+            RequestFormat::Anthropic => (true, Some(TransformDirection::AnthropicToOpenAI)),
+            RequestFormat::OpenAI => (false, None),
+        };
+        Ok(Self {
+            backend: Backend::Upstream,
+            needs_transform,
+            transform_direction,
+            target_url: Some(config.chat_completions_url()),
+        })
     }
 
     /// Passthrough 模式：仅支持 Anthropic 请求，直接透传到 Anthropic API
@@ -104,9 +170,10 @@ impl RoutingDecision {
                     backend: Backend::Anthropic,
                     needs_transform: false,
                     transform_direction: None,
+                    target_url: Some(config.anthropic_messages_url()),
                 })
             }
-            RequestFormat::OpenAI => Err(ProxyError::Transform(
+            RequestFormat::OpenAI => Err(ProxyError::UnsupportedOperation(
                 "OpenAI endpoint is not supported in Passthrough mode. \
                 Please use /v1/messages or change ROUTING_MODE to 'auto' or 'gateway'."
                     .into(),
@@ -120,8 +187,16 @@ impl RoutingDecision {
         model: &str,
         config: &Config,
     ) -> Result<Self, ProxyError> {
-        let target_backend = Self::infer_backend_from_model(model);
+        let target_backend = Self::infer_backend_from_model(model, config);
+        Self::decide_auto_mode_for(request_format, target_backend, config)
+    }
 
+    /// 根据推断出的目标后端决定路由
+    fn decide_auto_mode_for(
+        request_format: RequestFormat,
+        target_backend: Backend,
+        config: &Config,
+    ) -> Result<Self, ProxyError> {
         match (request_format, target_backend) {
             // Anthropic 请求 → Anthropic 后端（透传）
             (RequestFormat::Anthropic, Backend::Anthropic) => {
@@ -135,6 +210,7 @@ impl RoutingDecision {
                     backend: Backend::Anthropic,
                     needs_transform: false,
                     transform_direction: None,
+                    target_url: Some(config.anthropic_messages_url()),
                 })
             }
 
@@ -149,29 +225,51 @@ impl RoutingDecision {
                     backend: Backend::OpenAI,
                     needs_transform: false,
                     transform_direction: None,
+                    target_url: Some(config.openai_chat_completions_url()),
+                })
+            }
+
+            // Anthropic 请求 → 通用上游（DEFAULT_BACKEND=upstream，需要 A→O 转换）
+            (RequestFormat::Anthropic, Backend::Upstream) => {
+                if config.base_url.is_none() {
+                    return Err(ProxyError::Config(
+                        "UPSTREAM_BASE_URL is required when DEFAULT_BACKEND=upstream".into(),
+                    ));
+                }
+                Ok(Self {
+                    backend: Backend::Upstream,
+                    needs_transform: true,
+                    transform_direction: Some(TransformDirection::AnthropicToOpenAI),
+                    target_url: Some(config.chat_completions_url()),
                 })
             }
 
+            // OpenAI 请求 → 通用上游：OpenAI 格式请求只能透传到 OpenAI 后端
+            (RequestFormat::OpenAI, Backend::Upstream) => {
+                Self::decide_auto_mode_for(request_format, Backend::OpenAI, config)
+            }
+
             // Anthropic 请求 → OpenAI 后端（需要 A→O 转换）
             (RequestFormat::Anthropic, Backend::OpenAI) => {
                 // 优先使用 OpenAI 后端，否则使用通用上游
-                let backend = if config.openai_base_url.is_some() && config.openai_api_key.is_some()
-                {
-                    Backend::OpenAI
-                } else if config.base_url.is_some() {
-                    Backend::Upstream
-                } else {
-                    return Err(ProxyError::Config(
-                        "No OpenAI-compatible backend configured. \
-                        Set OPENAI_BASE_URL + OPENAI_API_KEY or UPSTREAM_BASE_URL."
-                            .into(),
-                    ));
-                };
+                let (backend, target_url) =
+                    if config.openai_base_url.is_some() && config.openai_api_key.is_some() {
+                        (Backend::OpenAI, config.openai_chat_completions_url())
+                    } else if config.base_url.is_some() {
+                        (Backend::Upstream, config.chat_completions_url())
+                    } else {
+                        return Err(ProxyError::Config(
+                            "No OpenAI-compatible backend configured. \
+                            Set OPENAI_BASE_URL + OPENAI_API_KEY or UPSTREAM_BASE_URL."
+                                .into(),
+                        ));
+                    };
 
                 Ok(Self {
                     backend,
                     needs_transform: true,
                     transform_direction: Some(TransformDirection::AnthropicToOpenAI),
+                    target_url: Some(target_url),
                 })
             }
 
@@ -187,24 +285,38 @@ impl RoutingDecision {
                     backend: Backend::Anthropic,
                     needs_transform: true,
                     transform_direction: Some(TransformDirection::OpenAIToAnthropic),
+                    target_url: Some(config.anthropic_messages_url()),
                 })
             }
-
-            _ => Err(ProxyError::Internal("Invalid routing combination".into())),
         }
     }
 
     /// 根据模型名称推断目标后端
-    fn infer_backend_from_model(model: &str) -> Backend {
+    ///
+    /// 依次检查 ANTHROPIC_MODEL_PATTERNS、OPENAI_MODEL_PATTERNS、内置规则，
+    /// 都不匹配时使用 DEFAULT_BACKEND
+    fn infer_backend_from_model(model: &str, config: &Config) -> Backend {
         let model_lower = model.to_lowercase();
 
-        // Anthropic 模型模式
-        if model_lower.starts_with("claude")
-            || model_lower.contains("anthropic/")
-            || model_lower.contains("anthropic-")
+        // 用户自定义规则优先
+        if config
+            .anthropic_model_patterns
+            .iter()
+            .any(|p| matches_model_pattern(p, &model_lower))
         {
             return Backend::Anthropic;
         }
+        if config
+            .openai_model_patterns
+            .iter()
+            .any(|p| matches_model_pattern(p, &model_lower))
+        {
+            return Backend::OpenAI;
+        }
+
+        if is_anthropic_model_name(&model_lower) {
+            return Backend::Anthropic;
+        }
 
         // OpenAI 模型模式
         if model_lower.starts_with("gpt")
@@ -220,101 +332,262 @@ impl RoutingDecision {
             return Backend::OpenAI;
         }
 
-        // 默认使用 OpenAI（兼容性考虑）
-        Backend::OpenAI
+        // 默认使用 OpenAI（兼容性考虑），可通过 DEFAULT_BACKEND 修改
+        config.default_backend
+    }
+}
+
+/// 内置的 Anthropic 模型名模式（不含 ANTHROPIC_MODEL_PATTERNS）
+pub fn is_anthropic_model_name(model: &str) -> bool {
+    let model_lower = model.to_lowercase();
+    model_lower.starts_with("claude")
+        || model_lower.contains("anthropic/")
+        || model_lower.contains("anthropic-")
+}
+
+/// 某个模型族经某个端点能否被服务的诊断结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServingDiagnostic {
+    /// 模型族名称（Claude、OpenAI、其他）
+    pub family: &'static str,
+    /// 客户端请求的端点
+    pub endpoint: &'static str,
+    /// 可服务时为目标后端，否则为缺失配置的说明
+    pub result: Result<Backend, String>,
+}
+
+impl std::fmt::Display for ServingDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.result {
+            Ok(backend) => write!(
+                f,
+                "{} models via {}: served by {:?} backend",
+                self.family, self.endpoint, backend
+            ),
+            Err(reason) => write!(
+                f,
+                "{} models cannot be served via {}: {}",
+                self.family, self.endpoint, reason
+            ),
+        }
+    }
+}
+
+/// /v1/chat/completions 在当前路由模式下是否可用
+pub fn openai_endpoint_enabled(config: &Config) -> bool {
+    match config.routing_mode {
+        RoutingMode::Auto | RoutingMode::Gateway => true,
+        RoutingMode::Transform => !config.transform_reject_openai,
+        RoutingMode::Passthrough => false,
+    }
+}
+
+/// 按已配置的后端，列出各模型族在各端点上能否被服务
+///
+/// 用于启动时提前暴露路由配置缺失，而不是等到请求时才报错
+pub fn serving_diagnostics(config: &Config) -> Vec<ServingDiagnostic> {
+    let mut endpoints = vec![("/v1/messages", RequestFormat::Anthropic)];
+    if openai_endpoint_enabled(config) {
+        endpoints.push(("/v1/chat/completions", RequestFormat::OpenAI));
+    }
+    let families = [
+        ("Claude", Backend::Anthropic),
+        ("OpenAI", Backend::OpenAI),
+        ("Other", config.default_backend),
+    ];
+
+    let mut diagnostics = Vec::new();
+    for (endpoint, format) in endpoints {
+        for (family, target) in families {
+            let decision = match config.routing_mode {
+                RoutingMode::Transform => RoutingDecision::decide_transform_mode(format, config),
+                RoutingMode::Passthrough => RoutingDecision::decide_passthrough_mode(format, config),
+                RoutingMode::Auto | RoutingMode::Gateway => {
+                    RoutingDecision::decide_auto_mode_for(format, target, config)
+                }
+            };
+            let result = decision.map(|d| d.backend).map_err(|e| match e {
+                ProxyError::Config(msg)
+                | ProxyError::Transform(msg)
+                | ProxyError::UnsupportedOperation(msg) => msg,
+                other => other.to_string(),
+            });
+            diagnostics.push(ServingDiagnostic {
+                family,
+                endpoint,
+                result,
+            });
+        }
+    }
+    diagnostics
+}
+
+/// 模型名匹配：含 `*`/`?` 时按通配符整体匹配，否则按子串匹配（均为小写）
+fn matches_model_pattern(pattern: &str, model: &str) -> bool {
+    if pattern.contains(['*', '?']) {
+        glob_match(pattern.as_bytes(), model.as_bytes())
+    } else {
+        model.contains(pattern)
+    }
+}
+
+/// 嗅探请求体的实际格式（Gateway 模式下纠正发错端点的客户端）
+///
+/// 请求体只能解析为另一种格式，或两者都能解析但只带有另一种格式的特征时，判定为另一种格式；
+/// 无法区分（如纯文本消息）或两种都无法解析时以端点本身的格式为准
+pub fn detect_request_format(endpoint: RequestFormat, body: &Value) -> RequestFormat {
+    let other = endpoint.other();
+    if !parses_as(other, body) {
+        return endpoint;
+    }
+    if !parses_as(endpoint, body) || (has_format_markers(other, body) && !has_format_markers(endpoint, body)) {
+        other
+    } else {
+        endpoint
+    }
+}
+
+fn parses_as(format: RequestFormat, body: &Value) -> bool {
+    match format {
+        RequestFormat::Anthropic => {
+            serde_json::from_value::<anthropic::AnthropicRequest>(body.clone()).is_ok()
+        }
+        RequestFormat::OpenAI => serde_json::from_value::<openai::OpenAIRequest>(body.clone()).is_ok(),
+    }
+}
+
+/// 请求体是否带有该格式独有的字段或内容
+fn has_format_markers(format: RequestFormat, body: &Value) -> bool {
+    let messages = body["messages"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let tools = body["tools"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let content_types = || {
+        messages
+            .iter()
+            .filter_map(|m| m["content"].as_array())
+            .flatten()
+            .filter_map(|block| block["type"].as_str())
+    };
+
+    match format {
+        RequestFormat::Anthropic => {
+            ["system", "stop_sequences", "top_k"]
+                .iter()
+                .any(|key| body.get(key).is_some())
+                || tools.iter().any(|t| t.get("input_schema").is_some())
+                || content_types().any(|t| {
+                    matches!(
+                        t,
+                        "image" | "tool_use" | "tool_result" | "thinking" | "redacted_thinking" | "document"
+                    )
+                })
+        }
+        RequestFormat::OpenAI => {
+            ["max_completion_tokens", "stop", "n", "response_format", "stream_options"]
+                .iter()
+                .any(|key| body.get(key).is_some())
+                || tools.iter().any(|t| t.get("function").is_some())
+                || messages.iter().any(|m| {
+                    matches!(m["role"].as_str(), Some("system" | "developer" | "tool"))
+                        || m.get("tool_calls").is_some()
+                        || m.get("tool_call_id").is_some()
+                })
+                || content_types().any(|t| t == "image_url")
+        }
+    }
+}
+
+/// 简单通配符匹配，`*` 匹配任意长度，`?` 匹配单个字符
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
     }
+
+    pattern[p..].iter().all(|&c| c == b'*')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::test_config;
 
     fn create_transform_config() -> Config {
         Config {
-            port: 3000,
             routing_mode: RoutingMode::Transform,
-            anthropic_base_url: None,
-            anthropic_api_key: None,
-            openai_base_url: None,
-            openai_api_key: None,
             base_url: Some("https://api.example.com".to_string()),
             api_key: Some("test-key".to_string()),
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         }
     }
 
     fn create_passthrough_config() -> Config {
         Config {
-            port: 3000,
             routing_mode: RoutingMode::Passthrough,
             anthropic_base_url: Some("https://api.anthropic.com".to_string()),
             anthropic_api_key: Some("test-key".to_string()),
-            openai_base_url: None,
-            openai_api_key: None,
-            base_url: None,
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         }
     }
 
     fn create_auto_config() -> Config {
         Config {
-            port: 3000,
-            routing_mode: RoutingMode::Auto,
             anthropic_base_url: Some("https://api.anthropic.com".to_string()),
             anthropic_api_key: Some("test-key".to_string()),
             openai_base_url: Some("https://api.openai.com".to_string()),
             openai_api_key: Some("test-key".to_string()),
-            base_url: None,
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         }
     }
 
     #[test]
     fn test_infer_backend_claude() {
+        let config = create_auto_config();
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("claude-3-5-sonnet-20241022"),
+            RoutingDecision::infer_backend_from_model("claude-3-5-sonnet-20241022", &config),
             Backend::Anthropic
         );
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("anthropic/claude-3-opus"),
+            RoutingDecision::infer_backend_from_model("anthropic/claude-3-opus", &config),
             Backend::Anthropic
         );
     }
 
     #[test]
     fn test_infer_backend_openai() {
+        let config = create_auto_config();
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("gpt-4"),
+            RoutingDecision::infer_backend_from_model("gpt-4", &config),
             Backend::OpenAI
         );
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("o1-preview"),
+            RoutingDecision::infer_backend_from_model("o1-preview", &config),
             Backend::OpenAI
         );
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("openai/gpt-4-turbo"),
+            RoutingDecision::infer_backend_from_model("openai/gpt-4-turbo", &config),
             Backend::OpenAI
         );
     }
 
     #[test]
     fn test_infer_backend_default() {
+        let config = create_auto_config();
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("unknown-model"),
+            RoutingDecision::infer_backend_from_model("unknown-model", &config),
             Backend::OpenAI
         );
     }
@@ -330,11 +603,32 @@ mod tests {
     }
 
     #[test]
-    fn test_transform_mode_openai_request_fails() {
+    fn test_transform_mode_openai_request_passthrough() {
         let config = create_transform_config();
+        let decision = RoutingDecision::decide(RequestFormat::OpenAI, "gpt-4", &config).unwrap();
+
+        assert_eq!(decision.backend, Backend::Upstream);
+        assert!(!decision.needs_transform);
+        assert_eq!(decision.transform_direction, None);
+        assert_eq!(decision.target_url, Some(config.chat_completions_url()));
+    }
+
+    #[test]
+    fn test_transform_mode_openai_request_rejected_when_configured() {
+        let mut config = create_transform_config();
+        config.transform_reject_openai = true;
         let result = RoutingDecision::decide(RequestFormat::OpenAI, "gpt-4", &config);
-        
-        assert!(result.is_err());
+        assert!(matches!(result, Err(ProxyError::UnsupportedOperation(_))));
+
+        // Anthropic 请求不受影响
+        let decision = RoutingDecision::decide(RequestFormat::Anthropic, "claude-3", &config).unwrap();
+        assert!(decision.needs_transform);
+
+        // 没有上游时 OpenAI 请求同样报缺少配置
+        config.transform_reject_openai = false;
+        config.base_url = None;
+        let result = RoutingDecision::decide(RequestFormat::OpenAI, "gpt-4", &config);
+        assert!(matches!(result, Err(ProxyError::Config(_))));
     }
 
     #[test]
@@ -352,7 +646,31 @@ mod tests {
         let config = create_passthrough_config();
         let result = RoutingDecision::decide(RequestFormat::OpenAI, "gpt-4", &config);
         
-        assert!(result.is_err());
+        assert!(matches!(result, Err(ProxyError::UnsupportedOperation(_))));
+    }
+
+    #[test]
+    fn test_message_batches_route_to_anthropic() {
+        for config in [create_passthrough_config(), create_auto_config()] {
+            let decision = RoutingDecision::decide_message_batches(&config).unwrap();
+            assert_eq!(decision.backend, Backend::Anthropic);
+            assert!(!decision.needs_transform);
+            assert_eq!(
+                decision.target_url.as_deref(),
+                Some("https://api.anthropic.com/v1/messages/batches")
+            );
+        }
+    }
+
+    #[test]
+    fn test_message_batches_unavailable() {
+        let result = RoutingDecision::decide_message_batches(&create_transform_config());
+        assert!(matches!(result, Err(ProxyError::UnsupportedOperation(_))));
+
+        let mut config = create_auto_config();
+        config.anthropic_api_key = None;
+        let result = RoutingDecision::decide_message_batches(&config);
+        assert!(matches!(result, Err(ProxyError::Config(_))));
     }
 
     #[test]
@@ -362,6 +680,10 @@ mod tests {
         
         assert_eq!(decision.backend, Backend::Anthropic);
         assert!(!decision.needs_transform);
+        assert_eq!(
+            decision.target_url.as_deref(),
+            Some("https://api.anthropic.com/v1/messages")
+        );
     }
 
     #[test]
@@ -381,6 +703,10 @@ mod tests {
         assert_eq!(decision.backend, Backend::OpenAI);
         assert!(decision.needs_transform);
         assert_eq!(decision.transform_direction, Some(TransformDirection::AnthropicToOpenAI));
+        assert_eq!(
+            decision.target_url.as_deref(),
+            Some("https://api.openai.com/v1/chat/completions")
+        );
     }
 
     #[test]
@@ -395,25 +721,262 @@ mod tests {
 
     #[test]
     fn test_infer_backend_o3_model() {
+        let config = create_auto_config();
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("o3-mini"),
+            RoutingDecision::infer_backend_from_model("o3-mini", &config),
             Backend::OpenAI
         );
     }
 
     #[test]
     fn test_infer_backend_text_model() {
+        let config = create_auto_config();
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("text-davinci-003"),
+            RoutingDecision::infer_backend_from_model("text-davinci-003", &config),
             Backend::OpenAI
         );
     }
 
     #[test]
     fn test_infer_backend_davinci() {
+        let config = create_auto_config();
+        assert_eq!(
+            RoutingDecision::infer_backend_from_model("davinci", &config),
+            Backend::OpenAI
+        );
+    }
+
+    #[test]
+    fn test_user_patterns_override_builtins() {
+        let mut config = create_auto_config();
+        config.anthropic_model_patterns = vec!["gpt-4-bedrock".to_string()];
+        config.openai_model_patterns = vec!["claude-*-openrouter".to_string()];
+
+        assert_eq!(
+            RoutingDecision::infer_backend_from_model("GPT-4-Bedrock", &config),
+            Backend::Anthropic
+        );
+        assert_eq!(
+            RoutingDecision::infer_backend_from_model("claude-3-openrouter", &config),
+            Backend::OpenAI
+        );
+        // 未匹配的模型仍走内置规则
+        assert_eq!(
+            RoutingDecision::infer_backend_from_model("claude-3-opus", &config),
+            Backend::Anthropic
+        );
+        assert_eq!(
+            RoutingDecision::infer_backend_from_model("gpt-4", &config),
+            Backend::OpenAI
+        );
+    }
+
+    #[test]
+    fn test_user_pattern_routes_unknown_model() {
+        let mut config = create_auto_config();
+        config.anthropic_model_patterns = vec!["kimi".to_string()];
+
+        let decision =
+            RoutingDecision::decide(RequestFormat::Anthropic, "moonshot/kimi-k2", &config).unwrap();
+
+        assert_eq!(decision.backend, Backend::Anthropic);
+        assert!(!decision.needs_transform);
+    }
+
+    #[test]
+    fn test_default_backend() {
+        let mut config = create_auto_config();
+        config.default_backend = Backend::Anthropic;
+
         assert_eq!(
-            RoutingDecision::infer_backend_from_model("davinci"),
+            RoutingDecision::infer_backend_from_model("deepseek-chat", &config),
+            Backend::Anthropic
+        );
+        // 内置规则优先于默认后端
+        assert_eq!(
+            RoutingDecision::infer_backend_from_model("gpt-4", &config),
             Backend::OpenAI
         );
     }
+
+    #[test]
+    fn test_default_backend_upstream() {
+        let mut config = create_auto_config();
+        config.base_url = Some("https://upstream.example.com".to_string());
+        config.default_backend = Backend::Upstream;
+
+        let decision =
+            RoutingDecision::decide(RequestFormat::Anthropic, "glm-4", &config).unwrap();
+        assert_eq!(decision.backend, Backend::Upstream);
+        assert_eq!(decision.transform_direction, Some(TransformDirection::AnthropicToOpenAI));
+
+        // OpenAI 格式请求仍透传到 OpenAI 后端
+        let decision = RoutingDecision::decide(RequestFormat::OpenAI, "glm-4", &config).unwrap();
+        assert_eq!(decision.backend, Backend::OpenAI);
+        assert!(!decision.needs_transform);
+    }
+
+    #[test]
+    fn test_backend_from_str() {
+        assert_eq!(Backend::from_str("Anthropic"), Backend::Anthropic);
+        assert_eq!(Backend::from_str("upstream"), Backend::Upstream);
+        assert_eq!(Backend::from_str("openai"), Backend::OpenAI);
+        assert_eq!(Backend::from_str("unknown"), Backend::OpenAI);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(matches_model_pattern("grok-*", "grok-2-latest"));
+        assert!(matches_model_pattern("*/mistral-?", "mistralai/mistral-7"));
+        assert!(!matches_model_pattern("grok-*", "xai/grok-2"));
+        assert!(matches_model_pattern("grok", "xai/grok-2"));
+    }
+
+    #[test]
+    fn test_serving_diagnostics_partial_config() {
+        let mut config = create_auto_config();
+        config.anthropic_base_url = None;
+        config.anthropic_api_key = None;
+        config.openai_base_url = None;
+        config.openai_api_key = None;
+        config.base_url = Some("http://localhost:8000".to_string());
+
+        let lines: Vec<String> = serving_diagnostics(&config)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "Claude models cannot be served via /v1/messages: \
+            ANTHROPIC_BASE_URL and ANTHROPIC_API_KEY are required for Claude models"
+        );
+        assert_eq!(lines[1], "OpenAI models via /v1/messages: served by Upstream backend");
+        assert_eq!(lines[2], "Other models via /v1/messages: served by Upstream backend");
+        assert!(lines[3].starts_with("Claude models cannot be served via /v1/chat/completions"));
+        assert!(lines[4].starts_with("OpenAI models cannot be served via /v1/chat/completions"));
+    }
+
+    #[test]
+    fn test_serving_diagnostics_transform_mode() {
+        let mut config = create_transform_config();
+        let diagnostics = serving_diagnostics(&config);
+        assert_eq!(diagnostics.len(), 6);
+        assert!(diagnostics.iter().all(|d| d.result == Ok(Backend::Upstream)));
+
+        config.transform_reject_openai = true;
+        let diagnostics = serving_diagnostics(&config);
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.iter().all(|d| d.endpoint == "/v1/messages"));
+    }
+
+    mod format_detection {
+        use super::*;
+        use serde_json::json;
+
+        fn text_only() -> Value {
+            json!({
+                "model": "some-model",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+        }
+
+        #[test]
+        fn test_native_formats_kept() {
+            let anthropic_body = json!({
+                "model": "claude-3",
+                "max_tokens": 100,
+                "system": "Be brief",
+                "messages": [{"role": "user", "content": "Hello"}]
+            });
+            let openai_body = json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "system", "content": "Be brief"}, {"role": "user", "content": "Hello"}]
+            });
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &anthropic_body), RequestFormat::Anthropic);
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &openai_body), RequestFormat::OpenAI);
+        }
+
+        #[test]
+        fn test_ambiguous_text_only_prefers_endpoint() {
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &text_only()), RequestFormat::Anthropic);
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &text_only()), RequestFormat::OpenAI);
+        }
+
+        #[test]
+        fn test_anthropic_system_string_on_openai_endpoint() {
+            let mut body = text_only();
+            body["system"] = json!("Be brief");
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &body), RequestFormat::Anthropic);
+        }
+
+        #[test]
+        fn test_anthropic_blocks_on_openai_endpoint() {
+            let body = json!({
+                "model": "claude-3",
+                "max_tokens": 100,
+                "messages": [
+                    {"role": "user", "content": "Weather?"},
+                    {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "weather", "input": {}}]},
+                    {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "Sunny"}]}
+                ]
+            });
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &body), RequestFormat::Anthropic);
+
+            // Anthropic 的 text 块与 OpenAI 的 text part 结构相同，无法区分
+            let body = json!({
+                "model": "claude-3",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}]
+            });
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &body), RequestFormat::OpenAI);
+        }
+
+        #[test]
+        fn test_openai_without_max_tokens_on_anthropic_endpoint() {
+            let body = json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hello"}]
+            });
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &body), RequestFormat::OpenAI);
+        }
+
+        #[test]
+        fn test_openai_markers_on_anthropic_endpoint() {
+            let body = json!({
+                "model": "gpt-4o",
+                "max_tokens": 100,
+                "messages": [{"role": "system", "content": "Be brief"}, {"role": "user", "content": "Hello"}]
+            });
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &body), RequestFormat::OpenAI);
+
+            let body = json!({
+                "model": "gpt-4o",
+                "max_tokens": 100,
+                "messages": [
+                    {"role": "assistant", "content": null, "tool_calls": [{"id": "c1", "type": "function", "function": {"name": "f", "arguments": "{}"}}]},
+                    {"role": "tool", "tool_call_id": "c1", "content": "done"}
+                ]
+            });
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &body), RequestFormat::OpenAI);
+        }
+
+        #[test]
+        fn test_conflicting_markers_prefer_endpoint() {
+            let mut body = text_only();
+            body["system"] = json!("Be brief");
+            body["stop"] = json!(["END"]);
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &body), RequestFormat::Anthropic);
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &body), RequestFormat::OpenAI);
+        }
+
+        #[test]
+        fn test_unparseable_body_prefers_endpoint() {
+            let body = json!({"prompt": "Hello"});
+            assert_eq!(detect_request_format(RequestFormat::Anthropic, &body), RequestFormat::Anthropic);
+            assert_eq!(detect_request_format(RequestFormat::OpenAI, &body), RequestFormat::OpenAI);
+        }
+    }
 }
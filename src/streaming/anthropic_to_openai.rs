@@ -1,194 +1,991 @@
 //! Anthropic 流 → OpenAI 流转换
 
+use crate::context::RequestContext;
+use crate::error::STATUS_OVERLOADED;
+use crate::streaming::coalesce::{next_or_flush, Coalescer, DeltaKind, Wait};
+use crate::streaming::sse::SseParser;
+use crate::streaming::{idle_timeout_message, IDLE_TIMEOUT_ERROR_TYPE};
+use crate::models::openai::{
+    Delta, DeltaFunctionCall, DeltaToolCall, ErrorDetail, StreamChoice, StreamChunk, StreamError,
+    Usage,
+};
+use crate::transform::utils::{map_stop_reason, Direction, OPENAI_COMPLETION_ID_PREFIX};
 use bytes::Bytes;
 use futures::stream::Stream;
-use futures::StreamExt;
-use serde_json::json;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// OpenAI 流式 chunk 的 `object` 字段
+const CHUNK_OBJECT: &str = "chat.completion.chunk";
+
+/// 单个流内所有 chunk 共享的字段，每个流只计算一次
+struct ChunkContext {
+    id: String,
+    created: u64,
+    model: String,
+    include_usage: bool,
+    reasoning_field: String,
+    /// choice 是否带 `"logprobs": null`
+    null_logprobs: bool,
+}
+
+impl ChunkContext {
+    /// 所有 chunk 的统一出口：每个 chunk 都带相同的 id、`object`、`created` 和 model
+    fn stream_chunk(&self, choices: Vec<StreamChoice>, usage: Option<Option<Usage>>) -> Bytes {
+        Self::to_sse(&StreamChunk {
+            id: self.id.clone(),
+            object: CHUNK_OBJECT.to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices,
+            usage,
+        })
+    }
+
+    /// 构造普通 chunk；请求了 include_usage 时中间 chunk 带 `"usage": null`
+    fn chunk(&self, delta: Delta, finish_reason: Option<String>) -> Bytes {
+        self.stream_chunk(
+            vec![StreamChoice {
+                index: 0,
+                delta,
+                logprobs: self.null_logprobs.then_some(None),
+                finish_reason,
+            }],
+            self.include_usage.then_some(None),
+        )
+    }
+
+    /// 构造工具调用参数增量 chunk
+    fn tool_arguments_chunk(&self, tool_call_index: usize, arguments: &str) -> Bytes {
+        self.chunk(
+            Delta {
+                tool_calls: Some(vec![DeltaToolCall {
+                    index: tool_call_index,
+                    id: None,
+                    call_type: None,
+                    function: Some(DeltaFunctionCall {
+                        name: None,
+                        arguments: Some(arguments.to_string()),
+                    }),
+                }]),
+                ..Default::default()
+            },
+            None,
+        )
+    }
+
+    /// 构造工具调用起始 chunk：带 id、类型、函数名和空参数
+    fn tool_start_chunk(&self, tool_call_index: usize, id: &str, name: &str) -> Bytes {
+        self.chunk(
+            Delta {
+                tool_calls: Some(vec![DeltaToolCall {
+                    index: tool_call_index,
+                    id: Some(id.to_string()),
+                    call_type: Some("function".to_string()),
+                    function: Some(DeltaFunctionCall {
+                        name: Some(name.to_string()),
+                        arguments: Some(String::new()),
+                    }),
+                }]),
+                ..Default::default()
+            },
+            None,
+        )
+    }
+
+    /// 构造文本、thinking 或工具参数增量 chunk
+    fn delta_chunk(&self, kind: DeltaKind, text: String) -> Bytes {
+        match kind {
+            DeltaKind::Text => self.chunk(
+                Delta {
+                    content: Some(text),
+                    ..Default::default()
+                },
+                None,
+            ),
+            DeltaKind::Thinking => {
+                let mut delta = Delta::default();
+                delta.extra.insert(self.reasoning_field.clone(), json!(text));
+                self.chunk(delta, None)
+            }
+            DeltaKind::ToolArguments(index) => self.tool_arguments_chunk(index, &text),
+        }
+    }
+
+    /// 构造最后的 usage chunk（choices 为空数组）
+    fn usage_chunk(&self, usage: &StreamUsage) -> Bytes {
+        let prompt_tokens = usage.prompt_tokens() as u32;
+        let completion_tokens = usage.output_tokens as u32;
+        self.stream_chunk(
+            Vec::new(),
+            Some(Some(Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            })),
+        )
+    }
+
+    /// 流结束时的 chunk：请求了 include_usage 时先发 usage chunk，最后是 `[DONE]`
+    fn done_chunks(&self, usage: &StreamUsage) -> Vec<Bytes> {
+        let mut chunks = Vec::with_capacity(2);
+        if self.include_usage {
+            chunks.push(self.usage_chunk(usage));
+        }
+        chunks.push(Bytes::from("data: [DONE]\n\n"));
+        chunks
+    }
+
+    /// 将 Anthropic `error` 事件转换为 OpenAI 流式错误 chunk；过载错误的 code 为 529
+    fn error_chunk(error: Option<&Value>) -> Bytes {
+        let error_type = error
+            .and_then(|e| e.get("type"))
+            .and_then(|t| t.as_str())
+            .unwrap_or("api_error");
+        let message = error
+            .and_then(|e| e.get("message"))
+            .and_then(|m| m.as_str())
+            .unwrap_or("Upstream stream error");
+        let code = if error_type == "overloaded_error" {
+            json!(STATUS_OVERLOADED)
+        } else {
+            Value::Null
+        };
+        Self::to_sse(&StreamError {
+            error: ErrorDetail {
+                message: message.to_string(),
+                error_type: error_type.to_string(),
+                code,
+            },
+        })
+    }
+
+    fn to_sse<T: Serialize>(openai_chunk: &T) -> Bytes {
+        Bytes::from(format!(
+            "data: {}\n\n",
+            serde_json::to_string(openai_chunk).unwrap_or_default()
+        ))
+    }
+}
+
+/// 流中累计的 token 用量
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct StreamUsage {
+    input_tokens: u64,
+    cache_read_input_tokens: u64,
+    cache_creation_input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl StreamUsage {
+    /// 合并 `message_start.message.usage` 或 `message_delta.usage`
+    ///
+    /// Anthropic 的用量字段都是累计值，出现即覆盖，未出现的字段保持不变
+    fn update(&mut self, usage: &Value) {
+        let field = |name: &str| usage.get(name).and_then(|t| t.as_u64());
+
+        if let Some(tokens) = field("input_tokens") {
+            self.input_tokens = tokens;
+        }
+        if let Some(tokens) = field("cache_read_input_tokens") {
+            self.cache_read_input_tokens = tokens;
+        }
+        if let Some(tokens) = field("cache_creation_input_tokens") {
+            self.cache_creation_input_tokens = tokens;
+        }
+        if let Some(tokens) = field("output_tokens") {
+            self.output_tokens = tokens;
+        }
+    }
+
+    /// OpenAI 的 prompt_tokens 包含缓存命中/写入的 token
+    fn prompt_tokens(&self) -> u64 {
+        self.input_tokens + self.cache_read_input_tokens + self.cache_creation_input_tokens
+    }
+}
+
+/// 当前 Unix 时间戳（秒）；系统时钟早于 epoch 时返回 0 而不是 panic
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
 /// 创建 Anthropic → OpenAI 流转换器
+///
+/// 请求上下文提供 `stream_options.include_usage` 以及 thinking 增量在 delta 中使用的字段名
 pub fn create_stream(
     stream: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    ctx: Arc<RequestContext>,
 ) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send {
     async_stream::stream! {
-        let mut buffer = String::new();
-        let mut message_id = String::new();
-        let mut model = String::new();
-        let mut current_content = String::new();
-        let _current_tool_calls: Vec<serde_json::Value> = Vec::new();
+        let mut parser = SseParser::default();
+        let mut context = ChunkContext {
+            id: String::new(),
+            created: unix_timestamp(),
+            model: String::new(),
+            include_usage: ctx.include_usage,
+            reasoning_field: ctx.reasoning_field.clone(),
+            null_logprobs: ctx.strict_openai_shape,
+        };
+        let mut usage = StreamUsage::default();
+        // 下一个工具调用在 OpenAI tool_calls 数组中的下标
+        let mut tool_call_index: usize = 0;
+        // 当前未结束的工具调用：(OpenAI 下标, 是否已收到参数)
+        let mut current_tool_call: Option<(usize, bool)> = None;
+        // 已收到 content_block_start（或已补发起始）的 Anthropic 块下标
+        let mut started_blocks: HashSet<u64> = HashSet::new();
+        // 是否已发出 [DONE]
+        let mut finished = false;
+        let mut coalescer = Coalescer::new(ctx.stream_coalesce);
 
         tokio::pin!(stream);
 
-        while let Some(chunk) = stream.next().await {
-            match chunk {
-                Ok(bytes) => {
-                    let text = String::from_utf8_lossy(&bytes);
-                    buffer.push_str(&text);
+        let mut upstream_done = false;
+        while !upstream_done {
+            let next = match next_or_flush(&mut stream, ctx.stream_idle_timeout, &coalescer).await {
+                Wait::Next(next) => next,
+                Wait::Flush => {
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(context.delta_chunk(kind, text));
+                    }
+                    continue;
+                }
+                Wait::IdleTimeout => {
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(context.delta_chunk(kind, text));
+                    }
+                    // 已发出 [DONE] 时上游只是没有及时关闭连接，直接结束
+                    if !finished {
+                        let message = idle_timeout_message(ctx.stream_idle_timeout);
+                        tracing::warn!("{}, closing stream", message);
+                        yield Ok(ChunkContext::error_chunk(Some(&json!({
+                            "type": IDLE_TIMEOUT_ERROR_TYPE,
+                            "message": message,
+                        }))));
+                    }
+                    break;
+                }
+            };
+            let events = match next {
+                Some(Ok(bytes)) => parser.feed(&bytes),
+                Some(Err(e)) => {
+                    tracing::error!("Stream error: {}", e);
+                    break;
+                }
+                None => {
+                    upstream_done = true;
+                    parser.finish().into_iter().collect()
+                }
+            };
+
+            for sse in events {
+                let data = sse.data.as_str();
+                // 上游已是 OpenAI 风格的结束标记（如双重转换）时按 message_stop 处理
+                if data.trim() == "[DONE]" {
+                    if !finished {
+                        finished = true;
+                        for chunk in context.done_chunks(&usage) {
+                            yield Ok(chunk);
+                        }
+                    }
+                    continue;
+                }
 
-                    while let Some(pos) = buffer.find("\n\n") {
-                        let line = buffer[..pos].to_string();
-                        buffer = buffer[pos + 2..].to_string();
+                if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
+                    let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
 
-                        if line.trim().is_empty() {
-                            continue;
+                    // 块边界和结束事件之前先发出合并中的增量
+                    if event_type != "content_block_delta" {
+                        if let Some((kind, text)) = coalescer.flush() {
+                            yield Ok(context.delta_chunk(kind, text));
                         }
+                    }
 
-                        for l in line.lines() {
-                            if let Some(data) = l.strip_prefix("data: ") {
-                                if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
-                                    let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+                    match event_type {
+                        "message_start" => {
+                            if let Some(msg) = event.get("message") {
+                                context.id = ctx.response_id(
+                                    msg.get("id").and_then(|i| i.as_str()).unwrap_or(""),
+                                    OPENAI_COMPLETION_ID_PREFIX,
+                                );
+                                context.model = ctx.response_model(msg.get("model").and_then(|m| m.as_str()).unwrap_or(""));
+                                if let Some(message_usage) = msg.get("usage") {
+                                    usage.update(message_usage);
+                                }
+                            }
+                        }
+                        "content_block_delta" => {
+                            if let Some(delta) = event.get("delta") {
+                                let delta_type = delta.get("type").and_then(|t| t.as_str()).unwrap_or("");
 
-                                    match event_type {
-                                        "message_start" => {
-                                            if let Some(msg) = event.get("message") {
-                                                message_id = msg.get("id").and_then(|i| i.as_str()).unwrap_or("").to_string();
-                                                model = msg.get("model").and_then(|m| m.as_str()).unwrap_or("").to_string();
-                                            }
+                                // 上游漏发 content_block_start 时补上块的起始状态；
+                                // 文本和 thinking 块没有需要发出的起始内容，工具块要补发工具调用起始
+                                let block_index = event.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+                                if started_blocks.insert(block_index) {
+                                    tracing::warn!(
+                                        "content_block_delta ({}) for block {} without content_block_start",
+                                        delta_type,
+                                        block_index
+                                    );
+                                    if delta_type == "input_json_delta" {
+                                        if let Some((kind, text)) = coalescer.flush() {
+                                            yield Ok(context.delta_chunk(kind, text));
                                         }
-                                        "content_block_delta" => {
-                                            if let Some(delta) = event.get("delta") {
-                                                let delta_type = delta.get("type").and_then(|t| t.as_str()).unwrap_or("");
-
-                                                match delta_type {
-                                                    "text_delta" => {
-                                                        if let Some(text) = delta.get("text").and_then(|t| t.as_str()) {
-                                                            current_content.push_str(text);
-
-                                                            let openai_chunk = json!({
-                                                                "id": message_id,
-                                                                "object": "chat.completion.chunk",
-                                                                "created": std::time::SystemTime::now()
-                                                                    .duration_since(std::time::UNIX_EPOCH)
-                                                                    .unwrap()
-                                                                    .as_secs(),
-                                                                "model": model,
-                                                                "choices": [{
-                                                                    "index": 0,
-                                                                    "delta": {
-                                                                        "content": text
-                                                                    },
-                                                                    "finish_reason": serde_json::Value::Null
-                                                                }]
-                                                            });
-                                                            let sse_data = format!("data: {}\n\n",
-                                                                serde_json::to_string(&openai_chunk).unwrap_or_default());
-                                                            yield Ok(Bytes::from(sse_data));
-                                                        }
-                                                    }
-                                                    "input_json_delta" => {
-                                                        if let Some(json_str) = delta.get("partial_json").and_then(|j| j.as_str()) {
-                                                            // Tool call argument streaming
-                                                            let openai_chunk = json!({
-                                                                "id": message_id,
-                                                                "object": "chat.completion.chunk",
-                                                                "created": std::time::SystemTime::now()
-                                                                    .duration_since(std::time::UNIX_EPOCH)
-                                                                    .unwrap()
-                                                                    .as_secs(),
-                                                                "model": model,
-                                                                "choices": [{
-                                                                    "index": 0,
-                                                                    "delta": {
-                                                                        "tool_calls": [{
-                                                                            "index": 0,
-                                                                            "function": {
-                                                                                "arguments": json_str
-                                                                            }
-                                                                        }]
-                                                                    },
-                                                                    "finish_reason": serde_json::Value::Null
-                                                                }]
-                                                            });
-                                                            let sse_data = format!("data: {}\n\n",
-                                                                serde_json::to_string(&openai_chunk).unwrap_or_default());
-                                                            yield Ok(Bytes::from(sse_data));
-                                                        }
-                                                    }
-                                                    _ => {}
-                                                }
-                                            }
-                                        }
-                                        "content_block_start" => {
-                                            if let Some(block) = event.get("content_block") {
-                                                let block_type = block.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                                                if block_type == "tool_use" {
-                                                    let tool_id = block.get("id").and_then(|i| i.as_str()).unwrap_or("");
-                                                    let tool_name = block.get("name").and_then(|n| n.as_str()).unwrap_or("");
-
-                                                    let openai_chunk = json!({
-                                                        "id": message_id,
-                                                        "object": "chat.completion.chunk",
-                                                        "created": std::time::SystemTime::now()
-                                                            .duration_since(std::time::UNIX_EPOCH)
-                                                            .unwrap()
-                                                            .as_secs(),
-                                                        "model": model,
-                                                        "choices": [{
-                                                            "index": 0,
-                                                            "delta": {
-                                                                "tool_calls": [{
-                                                                    "index": 0,
-                                                                    "id": tool_id,
-                                                                    "type": "function",
-                                                                    "function": {
-                                                                        "name": tool_name,
-                                                                        "arguments": ""
-                                                                    }
-                                                                }]
-                                                            },
-                                                            "finish_reason": serde_json::Value::Null
-                                                        }]
-                                                    });
-                                                    let sse_data = format!("data: {}\n\n",
-                                                        serde_json::to_string(&openai_chunk).unwrap_or_default());
-                                                    yield Ok(Bytes::from(sse_data));
-                                                }
-                                            }
+                                        if let Some((index, false)) = current_tool_call.take() {
+                                            yield Ok(context.tool_arguments_chunk(index, "{}"));
                                         }
-                                        "message_delta" => {
-                                            if let Some(delta) = event.get("delta") {
-                                                if let Some(stop_reason) = delta.get("stop_reason").and_then(|s| s.as_str()) {
-                                                    let finish_reason = match stop_reason {
-                                                        "end_turn" => "stop",
-                                                        "tool_use" => "tool_calls",
-                                                        "max_tokens" => "length",
-                                                        _ => "stop",
-                                                    };
-
-                                                    let openai_chunk = json!({
-                                                        "id": message_id,
-                                                        "object": "chat.completion.chunk",
-                                                        "created": std::time::SystemTime::now()
-                                                            .duration_since(std::time::UNIX_EPOCH)
-                                                            .unwrap()
-                                                            .as_secs(),
-                                                        "model": model,
-                                                        "choices": [{
-                                                            "index": 0,
-                                                            "delta": {},
-                                                            "finish_reason": finish_reason
-                                                        }]
-                                                    });
-                                                    let sse_data = format!("data: {}\n\n",
-                                                        serde_json::to_string(&openai_chunk).unwrap_or_default());
-                                                    yield Ok(Bytes::from(sse_data));
-                                                }
+                                        // 工具 id 和名称未知：按块下标生成 id，名称留空
+                                        let index = tool_call_index;
+                                        tool_call_index += 1;
+                                        current_tool_call = Some((index, false));
+                                        yield Ok(context.tool_start_chunk(index, &format!("call_{}", block_index), ""));
+                                    }
+                                }
+
+                                let ready = match delta_type {
+                                    "text_delta" => delta
+                                        .get("text")
+                                        .and_then(|t| t.as_str())
+                                        .map(|text| coalescer.push(DeltaKind::Text, text)),
+                                    "thinking_delta" => delta
+                                        .get("thinking")
+                                        .and_then(|t| t.as_str())
+                                        .map(|thinking| coalescer.push(DeltaKind::Thinking, thinking)),
+                                    "input_json_delta" => {
+                                        // Tool call argument streaming
+                                        match (delta.get("partial_json").and_then(|j| j.as_str()), current_tool_call.as_mut()) {
+                                            (Some(json_str), Some((index, has_arguments))) => {
+                                                *has_arguments |= !json_str.is_empty();
+                                                Some(coalescer.push(DeltaKind::ToolArguments(*index), json_str))
                                             }
+                                            _ => None,
                                         }
-                                        "message_stop" => {
-                                            yield Ok(Bytes::from("data: [DONE]\n\n"));
-                                        }
-                                        _ => {}
                                     }
+                                    _ => None,
+                                };
+                                for (kind, text) in ready.into_iter().flatten() {
+                                    yield Ok(context.delta_chunk(kind, text));
+                                }
+                            }
+                        }
+                        "content_block_start" => {
+                            // 上一个工具调用未收到 content_block_stop 时先结束它
+                            if let Some((index, false)) = current_tool_call.take() {
+                                yield Ok(context.tool_arguments_chunk(index, "{}"));
+                            }
+
+                            started_blocks.insert(event.get("index").and_then(|i| i.as_u64()).unwrap_or(0));
+                            if let Some(block) = event.get("content_block") {
+                                let block_type = block.get("type").and_then(|t| t.as_str()).unwrap_or("");
+                                if block_type == "tool_use" {
+                                    let tool_id = block.get("id").and_then(|i| i.as_str()).unwrap_or("");
+                                    let tool_name = block.get("name").and_then(|n| n.as_str()).unwrap_or("");
+                                    let index = tool_call_index;
+                                    tool_call_index += 1;
+                                    current_tool_call = Some((index, false));
+
+                                    yield Ok(context.tool_start_chunk(index, tool_id, tool_name));
+                                }
+                            }
+                        }
+                        "content_block_stop" => {
+                            // 没有参数的工具调用补全为空对象，保证 arguments 是合法 JSON
+                            if let Some((index, false)) = current_tool_call.take() {
+                                yield Ok(context.tool_arguments_chunk(index, "{}"));
+                            }
+                        }
+                        "message_delta" => {
+                            if let Some(delta_usage) = event.get("usage") {
+                                usage.update(delta_usage);
+                            }
+
+                            if let Some(delta) = event.get("delta") {
+                                if let Some(stop_reason) = delta.get("stop_reason").and_then(|s| s.as_str()) {
+                                    let finish_reason =
+                                        map_stop_reason(Some(stop_reason), Direction::AnthropicToOpenAI);
+
+                                    yield Ok(context.chunk(Delta::default(), finish_reason));
                                 }
                             }
                         }
+                        // 流中途的上游错误（如 overloaded_error）作为终止错误 chunk 转发，
+                        // 保留错误类型让客户端判断是否可重试
+                        "error" if !finished => {
+                            finished = true;
+                            yield Ok(ChunkContext::error_chunk(event.get("error")));
+                        }
+                        "message_stop" if !finished => {
+                            finished = true;
+                            for chunk in context.done_chunks(&usage) {
+                                yield Ok(chunk);
+                            }
+                        }
+                        _ => {}
                     }
                 }
-                Err(e) => {
-                    tracing::error!("Stream error: {}", e);
-                    break;
+            }
+        }
+        if let Some((kind, text)) = coalescer.flush() {
+            yield Ok(context.delta_chunk(kind, text));
+        }
+
+        ctx.log_summary("stream finished");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    const TEXT_EVENTS: &[&str] = &[
+        r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3","usage":{"input_tokens":12,"output_tokens":0}}}"#,
+        r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+        r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hel"}}"#,
+        r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"lo"}}"#,
+        r#"{"type":"content_block_stop","index":0}"#,
+        r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":5}}"#,
+        r#"{"type":"message_stop"}"#,
+    ];
+
+    fn anthropic_fixture(events: &[&str]) -> Vec<Result<Bytes, reqwest::Error>> {
+        events
+            .iter()
+            .map(|e| Ok(Bytes::from(format!("event: x\ndata: {}\n\n", e))))
+            .collect()
+    }
+
+    async fn collect_chunks(include_usage: bool) -> Vec<String> {
+        collect_event_chunks(TEXT_EVENTS, usage_ctx(include_usage)).await
+    }
+
+    fn usage_ctx(include_usage: bool) -> RequestContext {
+        RequestContext {
+            include_usage,
+            ..Default::default()
+        }
+    }
+
+    async fn collect_event_chunks(events: &[&str], ctx: RequestContext) -> Vec<String> {
+        let stream = create_stream(futures::stream::iter(anthropic_fixture(events)), Arc::new(ctx));
+        let output: Vec<_> = stream.collect().await;
+        output
+            .into_iter()
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| s.trim_start_matches("data: ").trim_end().to_string())
+            .collect()
+    }
+
+    fn parse(chunks: &[String]) -> Vec<Value> {
+        chunks
+            .iter()
+            .filter(|c| c.as_str() != "[DONE]")
+            .map(|c| serde_json::from_str(c).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_created_identical_across_chunks() {
+        let chunks = parse(&collect_chunks(true).await);
+
+        assert!(chunks.len() >= 3);
+        let created = chunks[0]["created"].as_u64().unwrap();
+        assert!(chunks.iter().all(|c| c["created"].as_u64() == Some(created)));
+    }
+
+    #[tokio::test]
+    async fn test_usage_null_on_intermediate_chunks_when_requested() {
+        let raw = collect_chunks(true).await;
+        assert_eq!(raw.last().unwrap(), "[DONE]");
+
+        let chunks = parse(&raw);
+        let (last, intermediate) = chunks.split_last().unwrap();
+        for chunk in intermediate {
+            assert!(chunk.get("usage").unwrap().is_null());
+        }
+
+        assert_eq!(last["choices"], json!([]));
+        assert_eq!(last["usage"]["prompt_tokens"], 12);
+        assert_eq!(last["usage"]["completion_tokens"], 5);
+        assert_eq!(last["usage"]["total_tokens"], 17);
+    }
+
+    #[tokio::test]
+    async fn test_requested_model_on_every_chunk() {
+        let ctx = RequestContext {
+            include_usage: true,
+            original_model: "gpt-4o".to_string(),
+            response_model_mode: crate::config::ResponseModelMode::Requested,
+            ..Default::default()
+        };
+        let raw = collect_event_chunks(TEXT_EVENTS, ctx).await;
+
+        let chunks = parse(&raw);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c["model"] == "gpt-4o"));
+    }
+
+    #[tokio::test]
+    async fn test_response_ids_normalized_when_enabled() {
+        let collect = |normalize_response_ids: bool| async move {
+            let ctx = RequestContext {
+                normalize_response_ids,
+                ..Default::default()
+            };
+            parse(&collect_event_chunks(TEXT_EVENTS, ctx).await)
+        };
+
+        let chunks = collect(true).await;
+        assert!(chunks.iter().all(|c| c["id"] == "chatcmpl-1"));
+        let chunks = collect(false).await;
+        assert!(chunks.iter().all(|c| c["id"] == "msg_1"));
+    }
+
+    #[tokio::test]
+    async fn test_no_usage_field_when_not_requested() {
+        let chunks = parse(&collect_chunks(false).await);
+
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.get("usage").is_none()));
+        assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn test_multiple_tool_calls_indexed() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3","usage":{"input_tokens":12,"output_tokens":0}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"tool_use","id":"toolu_1","name":"search","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"{\"q\":"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"\"rust\"}"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            // 第二个工具调用没有参数，也没有 content_block_stop
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_2","name":"now","input":{}}}"#,
+            r#"{"type":"content_block_start","index":2,"content_block":{"type":"tool_use","id":"toolu_3","name":"fetch","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":2,"delta":{"type":"input_json_delta","partial_json":"{\"url\":\"a\"}"}}"#,
+            r#"{"type":"content_block_stop","index":2}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":5}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let chunks = parse(&collect_event_chunks(&events, usage_ctx(true)).await);
+
+        // 工具调用的起始、参数增量和 usage chunk 同样带 object 与 created
+        let created = chunks[0]["created"].as_u64().unwrap();
+        assert!(chunks
+            .iter()
+            .all(|c| c["object"] == CHUNK_OBJECT && c["created"].as_u64() == Some(created)));
+
+        let mut ids = Vec::new();
+        let mut arguments = vec![String::new(); 3];
+        for chunk in &chunks {
+            if let Some(tool_calls) = chunk["choices"][0]["delta"]["tool_calls"].as_array() {
+                for tool_call in tool_calls {
+                    let index = tool_call["index"].as_u64().unwrap() as usize;
+                    if let Some(id) = tool_call["id"].as_str() {
+                        ids.push((index, id.to_string()));
+                    }
+                    if let Some(args) = tool_call["function"]["arguments"].as_str() {
+                        arguments[index].push_str(args);
+                    }
                 }
             }
         }
+
+        assert_eq!(
+            ids,
+            vec![
+                (0, "toolu_1".to_string()),
+                (1, "toolu_2".to_string()),
+                (2, "toolu_3".to_string())
+            ]
+        );
+        assert_eq!(arguments, vec![r#"{"q":"rust"}"#, "{}", r#"{"url":"a"}"#]);
+    }
+
+    #[tokio::test]
+    async fn test_tool_delta_without_block_start() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3","usage":{"input_tokens":12,"output_tokens":0}}}"#,
+            // 文本块和工具块都缺少 content_block_start
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Looking"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"q\":"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"\"rust\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"content_block_start","index":2,"content_block":{"type":"tool_use","id":"toolu_2","name":"now","input":{}}}"#,
+            r#"{"type":"content_block_stop","index":2}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":5}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let chunks = parse(&collect_event_chunks(&events, usage_ctx(false)).await);
+
+        assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "Looking");
+
+        let tool_calls: Vec<&Value> = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["tool_calls"].as_array())
+            .flatten()
+            .collect();
+        // 补发的起始在参数增量之前，带生成的 id 和空名称
+        assert_eq!(tool_calls[0]["index"], 0);
+        assert_eq!(tool_calls[0]["id"], "call_1");
+        assert_eq!(tool_calls[0]["type"], "function");
+        assert_eq!(tool_calls[0]["function"]["name"], "");
+
+        let mut arguments = vec![String::new(); 2];
+        for tool_call in &tool_calls {
+            let index = tool_call["index"].as_u64().unwrap() as usize;
+            arguments[index].push_str(tool_call["function"]["arguments"].as_str().unwrap_or(""));
+        }
+        assert_eq!(arguments, vec![r#"{"q":"rust"}"#, "{}"]);
+        assert_eq!(tool_calls.iter().filter(|t| t["id"] == "toolu_2").count(), 1);
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn test_stream_usage_update() {
+        let mut usage = StreamUsage::default();
+        usage.update(&json!({
+            "input_tokens": 20,
+            "cache_read_input_tokens": 100,
+            "cache_creation_input_tokens": 30,
+            "output_tokens": 1
+        }));
+        usage.update(&json!({"output_tokens": 7}));
+        usage.update(&json!({"output_tokens": 42}));
+
+        assert_eq!(usage.input_tokens, 20);
+        assert_eq!(usage.output_tokens, 42);
+        assert_eq!(usage.prompt_tokens(), 150);
+    }
+
+    #[tokio::test]
+    async fn test_usage_captured_from_anthropic_events() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3","usage":{"input_tokens":20,"cache_read_input_tokens":100,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":9}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let chunks = parse(&collect_event_chunks(&events, usage_ctx(true)).await);
+        let usage = &chunks.last().unwrap()["usage"];
+
+        assert_eq!(usage["prompt_tokens"], 120);
+        assert_eq!(usage["completion_tokens"], 9);
+        assert_eq!(usage["total_tokens"], 129);
+    }
+
+    #[tokio::test]
+    async fn test_thinking_delta_emitted_as_reasoning() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3","usage":{"input_tokens":1,"output_tokens":0}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"Let me "}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"think."}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"signature_delta","signature":"sig"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"text_delta","text":"Answer"}}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":5}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let stream = create_stream(
+            futures::stream::iter(anthropic_fixture(&events)),
+            Arc::new(RequestContext {
+                reasoning_field: "reasoning_content".to_string(),
+                ..Default::default()
+            }),
+        );
+        let raw: Vec<String> = stream
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| s.trim_start_matches("data: ").trim_end().to_string())
+            .collect()
+            .await;
+        let chunks = parse(&raw);
+
+        let reasoning: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["reasoning_content"].as_str())
+            .collect();
+        assert_eq!(reasoning, "Let me think.");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["content"], "Answer");
+        assert!(chunks[2]["choices"][0]["delta"].get("reasoning_content").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_done_marker_terminates_stream() {
+        let mut events = TEXT_EVENTS[..TEXT_EVENTS.len() - 1].to_vec();
+        events.push("[DONE]");
+
+        let raw = collect_event_chunks(&events, usage_ctx(true)).await;
+        assert_eq!(raw.last().unwrap(), "[DONE]");
+        assert_eq!(raw.iter().filter(|c| c.as_str() == "[DONE]").count(), 1);
+
+        let chunks = parse(&raw);
+        let last = chunks.last().unwrap();
+        assert_eq!(last["choices"], json!([]));
+        assert_eq!(last["usage"]["completion_tokens"], 5);
+        assert!(chunks.iter().any(|c| c["choices"][0]["finish_reason"] == "stop"));
+    }
+
+    #[tokio::test]
+    async fn test_done_marker_after_message_stop_not_duplicated() {
+        let mut events = TEXT_EVENTS.to_vec();
+        events.push("[DONE]");
+
+        let raw = collect_event_chunks(&events, usage_ctx(false)).await;
+        assert_eq!(raw.last().unwrap(), "[DONE]");
+        assert_eq!(raw.iter().filter(|c| c.as_str() == "[DONE]").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_error_mid_stream() {
+        let events = [
+            TEXT_EVENTS[0],
+            TEXT_EVENTS[2],
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            "[DONE]",
+        ];
+
+        let raw = collect_event_chunks(&events, usage_ctx(false)).await;
+        assert!(!raw.iter().any(|c| c.as_str() == "[DONE]"));
+
+        let chunks = parse(&raw);
+        let error = &chunks.last().unwrap()["error"];
+        assert_eq!(error["type"], "overloaded_error");
+        assert_eq!(error["code"], 529);
+        assert_eq!(error["message"], "Overloaded");
+    }
+
+    /// 发出 `events` 后既不发送数据也不关闭连接的上游
+    async fn collect_stalled_chunks(events: &[&str]) -> Vec<String> {
+        let stream = create_stream(
+            futures::stream::iter(anthropic_fixture(events)).chain(futures::stream::pending()),
+            Arc::new(RequestContext {
+                stream_idle_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            }),
+        );
+        let output: Vec<_> = tokio::time::timeout(Duration::from_secs(5), stream.collect())
+            .await
+            .expect("stream should end after the idle timeout");
+        output
+            .into_iter()
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| s.trim_start_matches("data: ").trim_end().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stalled_upstream_ends_with_timeout_error() {
+        let raw = collect_stalled_chunks(&TEXT_EVENTS[..3]).await;
+        assert!(!raw.iter().any(|c| c.as_str() == "[DONE]"));
+
+        let chunks = parse(&raw);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "Hel");
+        assert_eq!(chunks.last().unwrap()["error"]["type"], IDLE_TIMEOUT_ERROR_TYPE);
+
+        // 已正常结束的流不再追加错误
+        let raw = collect_stalled_chunks(TEXT_EVENTS).await;
+        assert_eq!(raw.last().unwrap(), "[DONE]");
+    }
+
+    #[tokio::test]
+    async fn test_strict_openai_shape_adds_null_logprobs() {
+        let stream = create_stream(
+            futures::stream::iter(anthropic_fixture(TEXT_EVENTS)),
+            Arc::new(RequestContext {
+                strict_openai_shape: true,
+                ..Default::default()
+            }),
+        );
+        let output: Vec<_> = stream.collect().await;
+        let raw: Vec<String> = output
+            .into_iter()
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| s.trim_start_matches("data: ").trim_end().to_string())
+            .collect();
+        let chunks = parse(&raw);
+
+        assert!(chunks.len() >= 3);
+        for chunk in &chunks {
+            let choice = chunk["choices"][0].as_object().unwrap();
+            assert_eq!(choice.get("logprobs"), Some(&Value::Null));
+        }
+
+        // 默认不输出该字段
+        let chunks = parse(&collect_chunks(false).await);
+        assert!(chunks.iter().all(|c| c["choices"][0].get("logprobs").is_none()));
+    }
+
+    /// 每个增量只有一个字符的长输出：`count` 个 text_delta 后接一个参数同样被拆碎的工具调用
+    fn tiny_delta_events(count: usize) -> Vec<String> {
+        let mut events = vec![
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3","usage":{"input_tokens":1,"output_tokens":0}}}"#.to_string(),
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#.to_string(),
+        ];
+        let text = |i: usize| char::from(b'a' + (i % 26) as u8);
+        events.extend((0..count).map(|i| {
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": text(i).to_string()}})
+                .to_string()
+        }));
+        events.push(r#"{"type":"content_block_stop","index":0}"#.to_string());
+        events.push(r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"write","input":{}}}"#.to_string());
+        let arguments = json!({"content": "x".repeat(count / 10)}).to_string();
+        events.extend(arguments.chars().map(|c| {
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": c.to_string()}})
+                .to_string()
+        }));
+        events.push(r#"{"type":"content_block_stop","index":1}"#.to_string());
+        events.push(r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":5}}"#.to_string());
+        events.push(r#"{"type":"message_stop"}"#.to_string());
+        events
+    }
+
+    async fn collect_coalesced(events: &[String], coalesce: Option<Duration>) -> Vec<String> {
+        let events: Vec<&str> = events.iter().map(String::as_str).collect();
+        let stream = create_stream(
+            futures::stream::iter(anthropic_fixture(&events)),
+            Arc::new(RequestContext {
+                stream_coalesce: coalesce,
+                ..Default::default()
+            }),
+        );
+        stream
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| s.trim_start_matches("data: ").trim_end().to_string())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_tiny_deltas() {
+        let events = tiny_delta_events(50_000);
+        let plain = collect_coalesced(&events, None).await;
+        let coalesced = collect_coalesced(&events, Some(Duration::from_secs(60))).await;
+
+        // 50k 个文本增量加约 5k 个参数增量，合并后只剩按 16 KiB 切分的少数 chunk
+        assert!(plain.len() > 55_000);
+        assert!(coalesced.len() < 20, "{} chunks", coalesced.len());
+        assert_eq!(coalesced.last().unwrap(), "[DONE]");
+
+        let concat = |raw: &[String]| {
+            let chunks = parse(raw);
+            let text: String = chunks
+                .iter()
+                .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+                .collect();
+            let arguments: String = chunks
+                .iter()
+                .filter_map(|c| c["choices"][0]["delta"]["tool_calls"][0]["function"]["arguments"].as_str())
+                .collect();
+            (text, arguments)
+        };
+        assert_eq!(concat(&coalesced), concat(&plain));
+
+        // 工具调用的起始 chunk 在全部文本之后、全部参数之前
+        let chunks = parse(&coalesced);
+        let start = chunks
+            .iter()
+            .position(|c| c["choices"][0]["delta"]["tool_calls"][0]["id"] == "toolu_1")
+            .unwrap();
+        assert!(chunks[..start]
+            .iter()
+            .all(|c| c["choices"][0]["delta"]["tool_calls"].is_null()));
+        assert!(chunks[start + 1..]
+            .iter()
+            .all(|c| c["choices"][0]["delta"]["content"].is_null()));
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_window_flushes_before_idle_timeout() {
+        let stream = create_stream(
+            futures::stream::iter(anthropic_fixture(&TEXT_EVENTS[..4])).chain(futures::stream::pending()),
+            Arc::new(RequestContext {
+                stream_idle_timeout: Some(Duration::from_millis(200)),
+                stream_coalesce: Some(Duration::from_millis(10)),
+                ..Default::default()
+            }),
+        );
+        tokio::pin!(stream);
+
+        // 上游停顿时窗口到期即发出已合并的增量，而不是等到空闲超时
+        let first = tokio::time::timeout(Duration::from_millis(150), stream.next())
+            .await
+            .expect("coalesced delta should be flushed when the window expires")
+            .unwrap()
+            .unwrap();
+        let chunk: Value = serde_json::from_str(
+            String::from_utf8(first.to_vec()).unwrap().trim_start_matches("data: ").trim_end(),
+        )
+        .unwrap();
+        assert_eq!(chunk["choices"][0]["delta"]["content"], "Hello");
+    }
+
+    fn wire(bytes: Bytes) -> Value {
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        serde_json::from_str(text.strip_prefix("data: ").unwrap().trim_end()).unwrap()
+    }
+
+    #[test]
+    fn test_typed_chunk_wire_format() {
+        let context = ChunkContext {
+            id: "msg_1".to_string(),
+            created: 42,
+            model: "claude-3".to_string(),
+            include_usage: true,
+            reasoning_field: "reasoning_content".to_string(),
+            null_logprobs: false,
+        };
+
+        let mut delta = Delta::default();
+        delta.extra.insert(context.reasoning_field.clone(), json!("hmm"));
+        assert_eq!(
+            wire(context.chunk(delta, None)),
+            json!({
+                "id": "msg_1",
+                "object": "chat.completion.chunk",
+                "created": 42,
+                "model": "claude-3",
+                "choices": [{"index": 0, "delta": {"reasoning_content": "hmm"}, "finish_reason": null}],
+                "usage": null
+            })
+        );
+
+        assert_eq!(
+            wire(context.tool_arguments_chunk(1, "{}"))["choices"][0],
+            json!({
+                "index": 0,
+                "delta": {"tool_calls": [{"index": 1, "function": {"arguments": "{}"}}]},
+                "finish_reason": null
+            })
+        );
+
+        let usage = StreamUsage {
+            input_tokens: 3,
+            output_tokens: 4,
+            ..Default::default()
+        };
+        let usage_chunk = wire(context.usage_chunk(&usage));
+        assert_eq!(usage_chunk["choices"], json!([]));
+        assert_eq!(
+            usage_chunk["usage"],
+            json!({"prompt_tokens": 3, "completion_tokens": 4, "total_tokens": 7})
+        );
+
+        let context = ChunkContext {
+            include_usage: false,
+            ..context
+        };
+        let stop = wire(context.chunk(Delta::default(), Some("stop".to_string())));
+        assert_eq!(stop["choices"][0]["delta"], json!({}));
+        assert_eq!(stop["choices"][0]["finish_reason"], "stop");
+        assert!(stop.get("usage").is_none());
     }
 }
@@ -0,0 +1,167 @@
+//! 流式增量合并（STREAM_COALESCE_MS）
+//!
+//! 上游高频推送极小的增量时（如 fine-grained tool streaming 下的 input_json_delta），
+//! 把窗口内连续的同类增量合并为一个输出 chunk，减少客户端需要解析的事件数。
+//! 转换器在处理块边界和结束事件之前先发出缓冲内容，这些事件本身从不延迟
+
+use crate::streaming::next_within;
+use futures::stream::Stream;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 单个合并增量的字节上限，达到后立即发出
+pub const MAX_COALESCED_BYTES: usize = 16 * 1024;
+
+/// 增量所属的输出位置，只有同类增量才会合并
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaKind {
+    Text,
+    Thinking,
+    /// 工具调用参数，携带输出中的工具调用或内容块下标
+    ToolArguments(usize),
+}
+
+#[derive(Debug)]
+struct Pending {
+    kind: DeltaKind,
+    text: String,
+    started: Instant,
+}
+
+/// 增量合并缓冲；未启用时每个增量原样发出
+#[derive(Debug)]
+pub struct Coalescer {
+    window: Option<Duration>,
+    pending: Option<Pending>,
+}
+
+impl Coalescer {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            pending: None,
+        }
+    }
+
+    /// 加入一个增量，返回需要立即发出的增量：
+    /// 类型变化时先前的缓冲、未启用时的当前增量、超过窗口或字节上限时的合并结果
+    pub fn push(&mut self, kind: DeltaKind, text: &str) -> Vec<(DeltaKind, String)> {
+        let Some(window) = self.window else {
+            return vec![(kind, text.to_string())];
+        };
+
+        let mut ready = Vec::new();
+        if self.pending.as_ref().is_some_and(|p| p.kind != kind) {
+            ready.extend(self.flush());
+        }
+        let pending = self.pending.get_or_insert_with(|| Pending {
+            kind,
+            text: String::new(),
+            started: Instant::now(),
+        });
+        pending.text.push_str(text);
+        if pending.text.len() >= MAX_COALESCED_BYTES || pending.started.elapsed() >= window {
+            ready.extend(self.flush());
+        }
+        ready
+    }
+
+    /// 取出缓冲的增量
+    pub fn flush(&mut self) -> Option<(DeltaKind, String)> {
+        self.pending.take().map(|p| (p.kind, p.text))
+    }
+
+    /// 缓冲内容最迟应发出的时间
+    fn deadline(&self) -> Option<Instant> {
+        let window = self.window?;
+        self.pending.as_ref().map(|p| p.started + window)
+    }
+}
+
+/// [`next_or_flush`] 的结果
+pub enum Wait<T> {
+    /// 上游的下一个数据块，None 表示上游已结束
+    Next(Option<T>),
+    /// 合并窗口到期，应先发出缓冲内容
+    Flush,
+    /// 超过空闲超时仍未收到数据
+    IdleTimeout,
+}
+
+/// 等待上游的下一个数据块；缓冲中有增量时最迟在合并窗口到期时返回 [`Wait::Flush`]
+pub async fn next_or_flush<S>(
+    stream: &mut S,
+    idle_timeout: Option<Duration>,
+    coalescer: &Coalescer,
+) -> Wait<S::Item>
+where
+    S: Stream + Unpin,
+{
+    let next = async {
+        next_within(stream, idle_timeout)
+            .await
+            .map_or(Wait::IdleTimeout, Wait::Next)
+    };
+    match coalescer.deadline() {
+        Some(deadline) => tokio::time::timeout_at(deadline, next)
+            .await
+            .unwrap_or(Wait::Flush),
+        None => next.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_passes_through() {
+        let mut coalescer = Coalescer::new(None);
+        assert_eq!(
+            coalescer.push(DeltaKind::Text, "a"),
+            vec![(DeltaKind::Text, "a".to_string())]
+        );
+        assert!(coalescer.flush().is_none());
+    }
+
+    #[test]
+    fn test_merges_same_kind_and_flushes_on_kind_change() {
+        let mut coalescer = Coalescer::new(Some(Duration::from_secs(60)));
+        assert!(coalescer.push(DeltaKind::ToolArguments(0), "{\"a\"").is_empty());
+        assert!(coalescer.push(DeltaKind::ToolArguments(0), ":1}").is_empty());
+        assert_eq!(
+            coalescer.push(DeltaKind::ToolArguments(1), "{"),
+            vec![(DeltaKind::ToolArguments(0), "{\"a\":1}".to_string())]
+        );
+        assert_eq!(
+            coalescer.flush(),
+            Some((DeltaKind::ToolArguments(1), "{".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_flushes_at_byte_limit() {
+        let mut coalescer = Coalescer::new(Some(Duration::from_secs(60)));
+        let half = "x".repeat(MAX_COALESCED_BYTES / 2);
+        assert!(coalescer.push(DeltaKind::Text, &half).is_empty());
+        let ready = coalescer.push(DeltaKind::Text, &half);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].1.len(), MAX_COALESCED_BYTES);
+        assert!(coalescer.flush().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_window_expiry_wakes_waiting_stream() {
+        let mut coalescer = Coalescer::new(Some(Duration::from_millis(20)));
+        assert!(coalescer.push(DeltaKind::Text, "a").is_empty());
+
+        let mut stream = futures::stream::pending::<()>();
+        let wait = tokio::time::timeout(
+            Duration::from_secs(5),
+            next_or_flush(&mut stream, None, &coalescer),
+        )
+        .await
+        .expect("pending delta should be flushed after the window");
+        assert!(matches!(wait, Wait::Flush));
+    }
+}
@@ -2,5 +2,40 @@
 //!
 //! 负责 SSE 流的转换处理
 
+use futures::stream::{Stream, StreamExt};
+use std::time::Duration;
+
 pub mod anthropic_to_openai;
+pub mod coalesce;
 pub mod openai_to_anthropic;
+pub mod simulate;
+pub mod sse;
+#[cfg(test)]
+mod snapshot_tests;
+
+/// 上游长时间没有数据时结束流所用的错误类型
+pub const IDLE_TIMEOUT_ERROR_TYPE: &str = "timeout_error";
+
+/// 等待上游流的下一个数据块，超过 `idle_timeout` 仍未收到时返回 Err
+///
+/// 与保活 ping 无关：只要上游发来任何字节（包括它自己的 ping 事件）计时就会重置
+pub async fn next_within<S>(
+    stream: &mut S,
+    idle_timeout: Option<Duration>,
+) -> Result<Option<S::Item>, tokio::time::error::Elapsed>
+where
+    S: Stream + Unpin,
+{
+    match idle_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, stream.next()).await,
+        None => Ok(stream.next().await),
+    }
+}
+
+/// 空闲超时错误事件中的说明
+pub fn idle_timeout_message(idle_timeout: Option<Duration>) -> String {
+    format!(
+        "Upstream stream sent no data for {}s",
+        idle_timeout.unwrap_or_default().as_secs()
+    )
+}
@@ -1,261 +1,897 @@
 //! OpenAI 流 → Anthropic 流转换
 
+use crate::context::RequestContext;
+use crate::error::STATUS_OVERLOADED;
+use crate::models::anthropic::{
+    ContentBlockStart, Delta, ErrorData, MessageDeltaData, MessageDeltaUsage, MessageStartData,
+    StreamEvent, Usage,
+};
 use crate::models::openai;
-use crate::transform::utils::map_stop_reason;
+use crate::streaming::coalesce::{next_or_flush, Coalescer, DeltaKind, Wait};
+use crate::streaming::sse::SseParser;
+use crate::streaming::{idle_timeout_message, IDLE_TIMEOUT_ERROR_TYPE};
+use crate::transform::utils::{
+    legacy_function_call_id, map_stop_reason, Direction, ANTHROPIC_MESSAGE_ID_PREFIX, PRIMARY_CHOICE_INDEX,
+};
 use bytes::Bytes;
 use futures::stream::Stream;
-use futures::StreamExt;
 use serde_json::json;
+use std::sync::Arc;
+
+/// 尚未发出 content_block_start 的工具调用
+///
+/// 部分提供商（Groq、部分 vLLM 版本）会把函数名拆成多段，或在 id 之后、
+/// 甚至在参数之后才发送函数名，因此先缓冲到函数名完整后再开始 tool_use 块
+#[derive(Debug, Default)]
+struct PendingToolCall {
+    /// OpenAI tool_calls 中的 index
+    index: usize,
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+impl PendingToolCall {
+    /// 合并函数名片段：片段比已有函数名长且以其为前缀时视为累积发送的完整函数名并覆盖，
+    /// 其余情况一律拼接（`get` 之后再收到 `get` 得到 `getget`）
+    fn merge_name(&mut self, fragment: &str) {
+        if fragment.len() > self.name.len() && fragment.starts_with(self.name.as_str()) {
+            self.name = fragment.to_string();
+        } else {
+            self.name.push_str(fragment);
+        }
+    }
+}
+
+pub fn sse_event(event: &StreamEvent) -> Bytes {
+    Bytes::from(format!(
+        "event: {}\ndata: {}\n\n",
+        event.event_type(),
+        serde_json::to_string(event).unwrap_or_default()
+    ))
+}
+
+fn block_delta(index: usize, delta: Delta) -> Bytes {
+    sse_event(&StreamEvent::ContentBlockDelta { index, delta })
+}
+
+/// 合并后的增量 → 当前块的 content_block_delta
+fn coalesced_delta(content_index: usize, kind: DeltaKind, text: String) -> Bytes {
+    match kind {
+        DeltaKind::Text => block_delta(content_index, Delta::Text { text }),
+        DeltaKind::Thinking => block_delta(content_index, Delta::Thinking { thinking: text }),
+        DeltaKind::ToolArguments(index) => {
+            block_delta(index, Delta::InputJson { partial_json: text })
+        }
+    }
+}
+
+/// 只延续当前内容块的 chunk（同类型的文本、reasoning 或已开始工具调用的参数）返回其增量，
+/// 可以进入合并缓冲；涉及块边界或带完成原因的 chunk 返回 None
+fn continuation_delta<'a>(
+    choice: &'a openai::StreamChoice,
+    current_block_type: Option<&str>,
+    active_tool_call: Option<usize>,
+    has_pending_tool_call: bool,
+    content_index: usize,
+) -> Option<(DeltaKind, &'a str)> {
+    if choice.finish_reason.is_some() || choice.delta.refusal.as_deref().is_some_and(|r| !r.is_empty()) {
+        return None;
+    }
+    let delta = &choice.delta;
+    let content = delta.content.as_deref().filter(|c| !c.is_empty());
+    match (delta.reasoning.as_deref(), content, delta.tool_calls.as_deref()) {
+        (Some(reasoning), None, None)
+            if current_block_type == Some("thinking") && !has_pending_tool_call =>
+        {
+            Some((DeltaKind::Thinking, reasoning))
+        }
+        (None, Some(content), None) if current_block_type == Some("text") && !has_pending_tool_call => {
+            Some((DeltaKind::Text, content))
+        }
+        (None, None, Some([tool_call]))
+            if active_tool_call == Some(tool_call.index) && tool_call.id.is_none() =>
+        {
+            let function = tool_call.function.as_ref()?;
+            if function.name.as_deref().is_some_and(|n| !n.is_empty()) {
+                return None;
+            }
+            let arguments = function.arguments.as_deref().filter(|a| !a.is_empty())?;
+            Some((DeltaKind::ToolArguments(content_index), arguments))
+        }
+        _ => None,
+    }
+}
+
+/// 旧版 `function_call` 增量改写为 index 为 0 的 tool_calls 增量，之后按普通工具调用处理；
+/// 旧版 API 不提供调用 id，第一段增量带上由响应 id 生成的 id
+fn normalize_legacy_function_call(chunk: &mut openai::StreamChunk, started: &mut bool) {
+    for choice in chunk
+        .choices
+        .iter_mut()
+        .filter(|choice| choice.index == PRIMARY_CHOICE_INDEX)
+    {
+        let Some(function_call) = choice.delta.function_call.take() else {
+            continue;
+        };
+        if choice.delta.tool_calls.is_some() {
+            continue;
+        }
+        let first = !std::mem::replace(started, true);
+        choice.delta.tool_calls = Some(vec![openai::DeltaToolCall {
+            index: 0,
+            id: first.then(|| legacy_function_call_id(&chunk.id)),
+            call_type: first.then(|| "function".to_string()),
+            function: Some(function_call),
+        }]);
+    }
+}
+
+/// OpenAI 流式错误对象 → Anthropic `error` 事件；过载类错误保留 `overloaded_error` 类型，
+/// 客户端据此判断可重试
+fn error_event(error: openai::ErrorDetail) -> Bytes {
+    let overloaded = error.error_type == "overloaded_error"
+        || error.code == json!(STATUS_OVERLOADED)
+        || error.code == "overloaded"
+        || error.message.to_lowercase().contains("overloaded");
+    let error_type = if overloaded { "overloaded_error" } else { "api_error" };
+    sse_event(&StreamEvent::Error {
+        error: ErrorData {
+            error_type: error_type.to_string(),
+            message: error.message,
+        },
+    })
+}
+
+/// 结束当前块并为缓冲的工具调用开始 tool_use 块，随后发出已缓冲的参数
+///
+/// 与 Anthropic 流式格式一致，content_block_start 中的 `input` 总是空对象 `{}`，
+/// 参数只通过 input_json_delta 发出，由客户端拼接。工具名按请求上下文还原为客户端原始名称
+fn start_tool_block(
+    ctx: &RequestContext,
+    pending: PendingToolCall,
+    content_index: &mut usize,
+    current_block_type: &mut Option<String>,
+) -> Vec<Bytes> {
+    let mut events = Vec::new();
+
+    if current_block_type.is_some() {
+        events.push(sse_event(&StreamEvent::ContentBlockStop {
+            index: *content_index,
+        }));
+        *content_index += 1;
+    }
+
+    events.push(sse_event(&StreamEvent::ContentBlockStart {
+        index: *content_index,
+        content_block: ContentBlockStart::ToolUse {
+            id: pending.id.unwrap_or_default(),
+            name: ctx.original_tool_name(&pending.name).to_string(),
+            input: json!({}),
+        },
+    }));
+    if !pending.arguments.is_empty() {
+        events.push(block_delta(
+            *content_index,
+            Delta::InputJson {
+                partial_json: pending.arguments,
+            },
+        ));
+    }
+    *current_block_type = Some("tool_use".to_string());
+
+    events
+}
 
 /// 创建 OpenAI → Anthropic 流转换器
 pub fn create_stream(
     stream: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    ctx: Arc<RequestContext>,
 ) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send {
     async_stream::stream! {
-        let mut buffer = String::new();
+        let mut parser = SseParser::default();
         let mut message_id = None;
         let mut current_model = None;
         let mut content_index = 0;
-        let mut tool_call_id = None;
-        let mut _tool_call_name = None;
-        let mut tool_call_args = String::new();
+        // 等待函数名完整的工具调用
+        let mut pending_tool_call: Option<PendingToolCall> = None;
+        // 已开始 tool_use 块的工具调用 index
+        let mut active_tool_call: Option<usize> = None;
         let mut has_sent_message_start = false;
         let mut current_block_type: Option<String> = None;
+        let mut coalescer = Coalescer::new(ctx.stream_coalesce);
+        // 丢弃的非主 choice 增量数，流结束时记录一次
+        let mut discarded_choices = 0usize;
+        // 是否已收到旧版 function_call 增量
+        let mut legacy_function_call = false;
 
         tokio::pin!(stream);
 
-        while let Some(chunk) = stream.next().await {
-            match chunk {
-                Ok(bytes) => {
-                    let text = String::from_utf8_lossy(&bytes);
-                    buffer.push_str(&text);
+        let mut upstream_done = false;
+        // 是否已发出 message_stop
+        let mut message_stopped = false;
+        while !upstream_done {
+            let next = match next_or_flush(&mut stream, ctx.stream_idle_timeout, &coalescer).await {
+                Wait::Next(next) => next,
+                Wait::Flush => {
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(coalesced_delta(content_index, kind, text));
+                    }
+                    continue;
+                }
+                Wait::IdleTimeout => {
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(coalesced_delta(content_index, kind, text));
+                    }
+                    // 已发出 message_stop 时上游只是没有及时关闭连接，直接结束
+                    if !message_stopped {
+                        let message = idle_timeout_message(ctx.stream_idle_timeout);
+                        tracing::warn!("{}, closing stream", message);
+                        yield Ok(sse_event(&StreamEvent::Error {
+                            error: ErrorData {
+                                error_type: IDLE_TIMEOUT_ERROR_TYPE.to_string(),
+                                message,
+                            },
+                        }));
+                    }
+                    break;
+                }
+            };
+            let events = match next {
+                Some(Ok(bytes)) => parser.feed(&bytes),
+                Some(Err(e)) => {
+                    tracing::error!("Stream error: {}", e);
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(coalesced_delta(content_index, kind, text));
+                    }
+                    yield Ok(sse_event(&StreamEvent::Error {
+                        error: ErrorData {
+                            error_type: "stream_error".to_string(),
+                            message: format!("Stream error: {}", e),
+                        },
+                    }));
+                    break;
+                }
+                None => {
+                    upstream_done = true;
+                    parser.finish().into_iter().collect()
+                }
+            };
+
+            for sse in events {
+                let data = sse.data.as_str();
+                if data.trim() == "[DONE]" {
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(coalesced_delta(content_index, kind, text));
+                    }
+                    if let Some(pending) = pending_tool_call.take() {
+                        for event in start_tool_block(&ctx, pending, &mut content_index, &mut current_block_type) {
+                            yield Ok(event);
+                        }
+                    }
+                    // 上游没有发送 finish_reason 时，在 message_stop 之前结束仍打开的块
+                    if current_block_type.take().is_some() {
+                        yield Ok(sse_event(&StreamEvent::ContentBlockStop {
+                            index: content_index,
+                        }));
+                    }
+                    message_stopped = true;
+                    yield Ok(sse_event(&StreamEvent::MessageStop));
+                    continue;
+                }
+
+                let mut chunk = match serde_json::from_str::<openai::StreamChunk>(data) {
+                    Ok(chunk) => chunk,
+                    Err(_) => {
+                        // 上游在流中途返回的错误对象转为 Anthropic error 事件
+                        if let Ok(stream_error) = serde_json::from_str::<openai::StreamError>(data) {
+                            if let Some((kind, text)) = coalescer.flush() {
+                                yield Ok(coalesced_delta(content_index, kind, text));
+                            }
+                            yield Ok(error_event(stream_error.error));
+                        }
+                        continue;
+                    }
+                };
+                normalize_legacy_function_call(&mut chunk, &mut legacy_function_call);
+                if message_id.is_none() {
+                    message_id = Some(ctx.response_id(&chunk.id, ANTHROPIC_MESSAGE_ID_PREFIX));
+                }
+                if current_model.is_none() {
+                    current_model = Some(ctx.response_model(&chunk.model));
+                }
 
-                    while let Some(pos) = buffer.find("\n\n") {
-                        let line = buffer[..pos].to_string();
-                        buffer = buffer[pos + 2..].to_string();
+                // 不同 choice 的 chunk 交错到达，只转换主 choice，避免其他 choice 的文本混入
+                discarded_choices += chunk
+                    .choices
+                    .iter()
+                    .filter(|choice| choice.index != PRIMARY_CHOICE_INDEX)
+                    .count();
+                if let Some(choice) = chunk
+                    .choices
+                    .iter()
+                    .find(|choice| choice.index == PRIMARY_CHOICE_INDEX)
+                {
+                    // 发送 message_start
+                    if !has_sent_message_start {
+                        yield Ok(sse_event(&StreamEvent::MessageStart {
+                            message: MessageStartData {
+                                id: message_id.clone().unwrap_or_default(),
+                                message_type: "message".to_string(),
+                                role: "assistant".to_string(),
+                                content: Vec::new(),
+                                model: current_model.clone().unwrap_or_default(),
+                                stop_reason: None,
+                                stop_sequence: None,
+                                usage: Usage {
+                                    input_tokens: 0,
+                                    output_tokens: 0,
+                                },
+                            },
+                        }));
+                        has_sent_message_start = true;
+                    }
+
+                    // 只延续当前块的增量进入合并缓冲，其他 chunk 处理前先发出缓冲内容
+                    let continuation = continuation_delta(
+                        choice,
+                        current_block_type.as_deref(),
+                        active_tool_call,
+                        pending_tool_call.is_some(),
+                        content_index,
+                    );
+                    if let Some((kind, text)) = continuation {
+                        for (kind, text) in coalescer.push(kind, text) {
+                            yield Ok(coalesced_delta(content_index, kind, text));
+                        }
+                        continue;
+                    }
+                    if let Some((kind, text)) = coalescer.flush() {
+                        yield Ok(coalesced_delta(content_index, kind, text));
+                    }
 
-                        if line.trim().is_empty() {
-                            continue;
+                    // 收到非工具调用内容时，先开始仍在缓冲的工具调用
+                    if choice.delta.reasoning.is_some()
+                        || choice.delta.content.as_deref().is_some_and(|c| !c.is_empty())
+                        || choice.delta.refusal.as_deref().is_some_and(|r| !r.is_empty())
+                    {
+                        if let Some(pending) = pending_tool_call.take() {
+                            for event in start_tool_block(&ctx, pending, &mut content_index, &mut current_block_type) {
+                                yield Ok(event);
+                            }
                         }
+                        active_tool_call = None;
+                    }
 
-                        for l in line.lines() {
-                            if let Some(data) = l.strip_prefix("data: ") {
-                                if data.trim() == "[DONE]" {
-                                    let event = json!({"type": "message_stop"});
-                                    let sse_data = format!("event: message_stop\ndata: {}\n\n",
-                                        serde_json::to_string(&event).unwrap_or_default());
-                                    yield Ok(Bytes::from(sse_data));
-                                    continue;
+                    // 处理 reasoning/thinking
+                    if let Some(reasoning) = &choice.delta.reasoning {
+                        if current_block_type.is_none() {
+                            yield Ok(sse_event(&StreamEvent::ContentBlockStart {
+                                index: content_index,
+                                content_block: ContentBlockStart::Thinking {
+                                    thinking: String::new(),
+                                },
+                            }));
+                            current_block_type = Some("thinking".to_string());
+                        }
+
+                        yield Ok(block_delta(content_index, Delta::Thinking {
+                            thinking: reasoning.clone(),
+                        }));
+                    }
+
+                    // 处理文本内容；内容策略拒绝的说明同样作为文本输出，避免客户端收到空响应
+                    for content in [&choice.delta.content, &choice.delta.refusal].into_iter().flatten() {
+                        if !content.is_empty() {
+                            if current_block_type.as_deref() != Some("text") {
+                                if current_block_type.is_some() {
+                                    yield Ok(sse_event(&StreamEvent::ContentBlockStop {
+                                        index: content_index,
+                                    }));
+                                    content_index += 1;
                                 }
 
-                                if let Ok(chunk) = serde_json::from_str::<openai::StreamChunk>(data) {
-                                    if message_id.is_none() {
-                                        message_id = Some(chunk.id.clone());
-                                    }
-                                    if current_model.is_none() {
-                                        current_model = Some(chunk.model.clone());
+                                yield Ok(sse_event(&StreamEvent::ContentBlockStart {
+                                    index: content_index,
+                                    content_block: ContentBlockStart::Text {
+                                        text: String::new(),
+                                    },
+                                }));
+                                current_block_type = Some("text".to_string());
+                            }
+
+                            yield Ok(block_delta(content_index, Delta::Text {
+                                text: content.clone(),
+                            }));
+                        }
+                    }
+
+                    // 处理工具调用
+                    if let Some(tool_calls) = &choice.delta.tool_calls {
+                        for tool_call in tool_calls {
+                            let name_fragment = tool_call
+                                .function
+                                .as_ref()
+                                .and_then(|f| f.name.as_deref())
+                                .filter(|n| !n.is_empty());
+                            let args = tool_call
+                                .function
+                                .as_ref()
+                                .and_then(|f| f.arguments.as_deref())
+                                .unwrap_or("");
+
+                            // 已开始的工具调用：直接转发参数
+                            if active_tool_call == Some(tool_call.index) {
+                                if !args.is_empty() {
+                                    yield Ok(block_delta(content_index, Delta::InputJson {
+                                        partial_json: args.to_string(),
+                                    }));
+                                }
+                                continue;
+                            }
+
+                            // 新的工具调用：先开始上一个仍在缓冲的工具调用
+                            if pending_tool_call.as_ref().is_some_and(|p| p.index != tool_call.index) {
+                                if let Some(pending) = pending_tool_call.take() {
+                                    for event in start_tool_block(&ctx, pending, &mut content_index, &mut current_block_type) {
+                                        yield Ok(event);
                                     }
+                                }
+                            }
+
+                            let pending = pending_tool_call.get_or_insert_with(|| PendingToolCall {
+                                index: tool_call.index,
+                                ..Default::default()
+                            });
+                            if let Some(id) = &tool_call.id {
+                                pending.id = Some(id.clone());
+                            }
+                            if let Some(name) = name_fragment {
+                                pending.merge_name(name);
+                            }
+                            pending.arguments.push_str(args);
 
-                                    if let Some(choice) = chunk.choices.first() {
-                                        // 发送 message_start
-                                        if !has_sent_message_start {
-                                            let event = json!({
-                                                "type": "message_start",
-                                                "message": {
-                                                    "id": message_id.clone().unwrap_or_default(),
-                                                    "type": "message",
-                                                    "role": "assistant",
-                                                    "model": current_model.clone().unwrap_or_default(),
-                                                    "usage": {
-                                                        "input_tokens": 0,
-                                                        "output_tokens": 0
-                                                    }
-                                                }
-                                            });
-                                            let sse_data = format!("event: message_start\ndata: {}\n\n",
-                                                serde_json::to_string(&event).unwrap_or_default());
-                                            yield Ok(Bytes::from(sse_data));
-                                            has_sent_message_start = true;
-                                        }
-
-                                        // 处理 reasoning/thinking
-                                        if let Some(reasoning) = &choice.delta.reasoning {
-                                            if current_block_type.is_none() {
-                                                let event = json!({
-                                                    "type": "content_block_start",
-                                                    "index": content_index,
-                                                    "content_block": {
-                                                        "type": "thinking",
-                                                        "thinking": ""
-                                                    }
-                                                });
-                                                let sse_data = format!("event: content_block_start\ndata: {}\n\n",
-                                                    serde_json::to_string(&event).unwrap_or_default());
-                                                yield Ok(Bytes::from(sse_data));
-                                                current_block_type = Some("thinking".to_string());
-                                            }
-
-                                            let event = json!({
-                                                "type": "content_block_delta",
-                                                "index": content_index,
-                                                "delta": {
-                                                    "type": "thinking_delta",
-                                                    "thinking": reasoning
-                                                }
-                                            });
-                                            let sse_data = format!("event: content_block_delta\ndata: {}\n\n",
-                                                serde_json::to_string(&event).unwrap_or_default());
-                                            yield Ok(Bytes::from(sse_data));
-                                        }
-
-                                        // 处理文本内容
-                                        if let Some(content) = &choice.delta.content {
-                                            if !content.is_empty() {
-                                                if current_block_type.as_deref() != Some("text") {
-                                                    if current_block_type.is_some() {
-                                                        let event = json!({
-                                                            "type": "content_block_stop",
-                                                            "index": content_index
-                                                        });
-                                                        let sse_data = format!("event: content_block_stop\ndata: {}\n\n",
-                                                            serde_json::to_string(&event).unwrap_or_default());
-                                                        yield Ok(Bytes::from(sse_data));
-                                                        content_index += 1;
-                                                    }
-
-                                                    let event = json!({
-                                                        "type": "content_block_start",
-                                                        "index": content_index,
-                                                        "content_block": {
-                                                            "type": "text",
-                                                            "text": ""
-                                                        }
-                                                    });
-                                                    let sse_data = format!("event: content_block_start\ndata: {}\n\n",
-                                                        serde_json::to_string(&event).unwrap_or_default());
-                                                    yield Ok(Bytes::from(sse_data));
-                                                    current_block_type = Some("text".to_string());
-                                                }
-
-                                                let event = json!({
-                                                    "type": "content_block_delta",
-                                                    "index": content_index,
-                                                    "delta": {
-                                                        "type": "text_delta",
-                                                        "text": content
-                                                    }
-                                                });
-                                                let sse_data = format!("event: content_block_delta\ndata: {}\n\n",
-                                                    serde_json::to_string(&event).unwrap_or_default());
-                                                yield Ok(Bytes::from(sse_data));
-                                            }
-                                        }
-
-                                        // 处理工具调用
-                                        if let Some(tool_calls) = &choice.delta.tool_calls {
-                                            for tool_call in tool_calls {
-                                                if let Some(id) = &tool_call.id {
-                                                    if current_block_type.is_some() {
-                                                        let event = json!({
-                                                            "type": "content_block_stop",
-                                                            "index": content_index
-                                                        });
-                                                        let sse_data = format!("event: content_block_stop\ndata: {}\n\n",
-                                                            serde_json::to_string(&event).unwrap_or_default());
-                                                        yield Ok(Bytes::from(sse_data));
-                                                        content_index += 1;
-                                                    }
-
-                                                    tool_call_id = Some(id.clone());
-                                                    tool_call_args.clear();
-                                                }
-
-                                                if let Some(function) = &tool_call.function {
-                                                    if let Some(name) = &function.name {
-                                                        _tool_call_name = Some(name.clone());
-
-                                                        let event = json!({
-                                                            "type": "content_block_start",
-                                                            "index": content_index,
-                                                            "content_block": {
-                                                                "type": "tool_use",
-                                                                "id": tool_call_id.clone().unwrap_or_default(),
-                                                                "name": name
-                                                            }
-                                                        });
-                                                        let sse_data = format!("event: content_block_start\ndata: {}\n\n",
-                                                            serde_json::to_string(&event).unwrap_or_default());
-                                                        yield Ok(Bytes::from(sse_data));
-                                                        current_block_type = Some("tool_use".to_string());
-                                                    }
-
-                                                    if let Some(args) = &function.arguments {
-                                                        tool_call_args.push_str(args);
-
-                                                        let event = json!({
-                                                            "type": "content_block_delta",
-                                                            "index": content_index,
-                                                            "delta": {
-                                                                "type": "input_json_delta",
-                                                                "partial_json": args
-                                                            }
-                                                        });
-                                                        let sse_data = format!("event: content_block_delta\ndata: {}\n\n",
-                                                            serde_json::to_string(&event).unwrap_or_default());
-                                                        yield Ok(Bytes::from(sse_data));
-                                                    }
-                                                }
-                                            }
-                                        }
-
-                                        // 处理完成原因
-                                        if let Some(finish_reason) = &choice.finish_reason {
-                                            if current_block_type.is_some() {
-                                                let event = json!({
-                                                    "type": "content_block_stop",
-                                                    "index": content_index
-                                                });
-                                                let sse_data = format!("event: content_block_stop\ndata: {}\n\n",
-                                                    serde_json::to_string(&event).unwrap_or_default());
-                                                yield Ok(Bytes::from(sse_data));
-                                            }
-
-                                            let stop_reason = map_stop_reason(Some(finish_reason));
-                                            let event = json!({
-                                                "type": "message_delta",
-                                                "delta": {
-                                                    "stop_reason": stop_reason,
-                                                    "stop_sequence": serde_json::Value::Null
-                                                },
-                                                "usage": chunk.usage.as_ref().map(|u| json!({
-                                                    "output_tokens": u.completion_tokens
-                                                }))
-                                            });
-                                            let sse_data = format!("event: message_delta\ndata: {}\n\n",
-                                                serde_json::to_string(&event).unwrap_or_default());
-                                            yield Ok(Bytes::from(sse_data));
-                                        }
+                            // 函数名已出现，且参数开始到达或函数名不再变化时开始 tool_use 块
+                            let ready = !pending.name.is_empty()
+                                && (name_fragment.is_none() || !pending.arguments.is_empty());
+                            if ready {
+                                if let Some(pending) = pending_tool_call.take() {
+                                    active_tool_call = Some(pending.index);
+                                    for event in start_tool_block(&ctx, pending, &mut content_index, &mut current_block_type) {
+                                        yield Ok(event);
                                     }
                                 }
                             }
                         }
                     }
-                }
-                Err(e) => {
-                    tracing::error!("Stream error: {}", e);
-                    let error_event = json!({
-                        "type": "error",
-                        "error": {
-                            "type": "stream_error",
-                            "message": format!("Stream error: {}", e)
+
+                    // 处理完成原因
+                    if let Some(finish_reason) = &choice.finish_reason {
+                        if let Some(pending) = pending_tool_call.take() {
+                            for event in start_tool_block(&ctx, pending, &mut content_index, &mut current_block_type) {
+                                yield Ok(event);
+                            }
                         }
-                    });
-                    let sse_data = format!("event: error\ndata: {}\n\n",
-                        serde_json::to_string(&error_event).unwrap_or_default());
-                    yield Ok(Bytes::from(sse_data));
-                    break;
+                        active_tool_call = None;
+                        if current_block_type.take().is_some() {
+                            yield Ok(sse_event(&StreamEvent::ContentBlockStop {
+                                index: content_index,
+                            }));
+                        }
+
+                        let stop_reason = map_stop_reason(Some(finish_reason), Direction::OpenAIToAnthropic);
+                        yield Ok(sse_event(&StreamEvent::MessageDelta {
+                            delta: MessageDeltaData {
+                                stop_reason,
+                                stop_sequence: None,
+                            },
+                            usage: chunk.usage.as_ref().and_then(Option::as_ref).map(|u| {
+                                MessageDeltaUsage {
+                                    output_tokens: u.completion_tokens,
+                                }
+                            }),
+                        }));
+                    }
+                }
+            }
+        }
+        if let Some((kind, text)) = coalescer.flush() {
+            yield Ok(coalesced_delta(content_index, kind, text));
+        }
+        if discarded_choices > 0 {
+            tracing::info!(
+                "Discarded {} delta(s) for choices other than index {}",
+                discarded_choices,
+                PRIMARY_CHOICE_INDEX
+            );
+        }
+
+        ctx.log_summary("stream finished");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    fn tool_chunk(tool_call: Value, finish_reason: Option<&str>) -> String {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "delta": {"tool_calls": [tool_call]},
+                "finish_reason": finish_reason
+            }]
+        })
+        .to_string()
+    }
+
+    /// 每个 chunk 作为一个 `data:` 事件，末尾追加 [DONE]
+    fn sse_frames(chunks: &[String]) -> Vec<Bytes> {
+        chunks
+            .iter()
+            .map(|c| Bytes::from(format!("data: {}\n\n", c)))
+            .chain([Bytes::from("data: [DONE]\n\n")])
+            .collect()
+    }
+
+    /// 按给定分块把上游字节送入转换器，返回每个输出事件的 data
+    async fn collect_with(ctx: RequestContext, pieces: Vec<Bytes>) -> Vec<Value> {
+        let input: Vec<Result<Bytes, reqwest::Error>> = pieces.into_iter().map(Ok).collect();
+        let output: Vec<_> = create_stream(futures::stream::iter(input), Arc::new(ctx)).collect().await;
+        output
+            .into_iter()
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| serde_json::from_str(s.lines().find_map(|l| l.strip_prefix("data: ")).unwrap()).unwrap())
+            .collect()
+    }
+
+    async fn collect_events(chunks: Vec<String>) -> Vec<Value> {
+        collect_with(RequestContext::default(), sse_frames(&chunks)).await
+    }
+
+    /// 提取 (块 index, 工具名, 拼接后的参数)
+    fn tool_blocks(events: &[Value]) -> Vec<(u64, String, String)> {
+        let mut blocks: Vec<(u64, String, String)> = Vec::new();
+        for event in events {
+            match event["type"].as_str().unwrap() {
+                "content_block_start" if event["content_block"]["type"] == "tool_use" => {
+                    blocks.push((
+                        event["index"].as_u64().unwrap(),
+                        event["content_block"]["name"].as_str().unwrap().to_string(),
+                        String::new(),
+                    ));
                 }
+                "content_block_delta" if event["delta"]["type"] == "input_json_delta" => {
+                    let block = blocks
+                        .iter_mut()
+                        .find(|b| b.0 == event["index"].as_u64().unwrap())
+                        .unwrap();
+                    block.2.push_str(event["delta"]["partial_json"].as_str().unwrap());
+                }
+                _ => {}
             }
         }
+        blocks
+    }
+
+    #[tokio::test]
+    async fn test_split_tool_name_buffered() {
+        let events = collect_events(vec![
+            tool_chunk(json!({"index": 0, "id": "call_1", "type": "function", "function": {"name": "get_we"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"name": "ather"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"arguments": "{\"city\":"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"arguments": "\"Paris\"}"}}), Some("tool_calls")),
+        ])
+        .await;
+
+        assert_eq!(
+            tool_blocks(&events),
+            vec![(0, "get_weather".to_string(), r#"{"city":"Paris"}"#.to_string())]
+        );
+        let start = events
+            .iter()
+            .find(|e| e["type"] == "content_block_start")
+            .unwrap();
+        assert_eq!(start["content_block"]["id"], "call_1");
+    }
+
+    #[tokio::test]
+    async fn test_arguments_before_name() {
+        let events = collect_events(vec![
+            tool_chunk(json!({"index": 0, "id": "call_1", "function": {"arguments": "{\"q\":"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"name": "search"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"arguments": "\"rust\"}"}}), None),
+            tool_chunk(json!({"index": 1, "id": "call_2", "function": {"name": "now", "arguments": "{}"}}), Some("tool_calls")),
+        ])
+        .await;
+
+        assert_eq!(
+            tool_blocks(&events),
+            vec![
+                (0, "search".to_string(), r#"{"q":"rust"}"#.to_string()),
+                (1, "now".to_string(), "{}".to_string()),
+            ]
+        );
+        let stop_reason = events
+            .iter()
+            .find(|e| e["type"] == "message_delta")
+            .map(|e| e["delta"]["stop_reason"].clone())
+            .unwrap();
+        assert_eq!(stop_reason, "tool_use");
+    }
+
+    #[tokio::test]
+    async fn test_tool_block_start_has_empty_object_input() {
+        let events = collect_events(vec![
+            tool_chunk(json!({"index": 0, "id": "call_1", "function": {"name": "search", "arguments": "{\"q\":"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"arguments": "\"rust\"}"}}), None),
+            tool_chunk(json!({"index": 1, "id": "call_2", "function": {"name": "now"}}), None),
+            tool_chunk(json!({"index": 1, "function": {"arguments": ""}}), Some("tool_calls")),
+        ])
+        .await;
+
+        let starts: Vec<&Value> = events
+            .iter()
+            .filter(|e| e["type"] == "content_block_start")
+            .collect();
+        assert_eq!(starts.len(), 2);
+        for start in starts {
+            assert_eq!(start["content_block"]["input"], json!({}));
+        }
+        assert_eq!(
+            tool_blocks(&events),
+            vec![
+                (0, "search".to_string(), r#"{"q":"rust"}"#.to_string()),
+                (1, "now".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_done_without_finish_reason_closes_tool_block() {
+        let events = collect_events(vec![
+            tool_chunk(json!({"index": 0, "id": "call_1", "function": {"name": "get_we"}}), None),
+            tool_chunk(json!({"index": 0, "function": {"name": "ather"}}), None),
+        ])
+        .await;
+
+        assert_eq!(tool_blocks(&events), vec![(0, "get_weather".to_string(), String::new())]);
+        let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            vec!["message_start", "content_block_start", "content_block_stop", "message_stop"]
+        );
+        assert_eq!(events[2]["index"], 0);
+    }
+
+    #[test]
+    fn test_merge_name_rule() {
+        // 累积发送：后一个片段包含已有函数名
+        let mut pending = PendingToolCall::default();
+        pending.merge_name("get");
+        pending.merge_name("get_weather");
+        assert_eq!(pending.name, "get_weather");
+
+        // 与已有函数名相同的片段是名字的一部分，拼接
+        let mut pending = PendingToolCall::default();
+        pending.merge_name("get");
+        pending.merge_name("get");
+        assert_eq!(pending.name, "getget");
+
+        let mut pending = PendingToolCall::default();
+        pending.merge_name("get_");
+        pending.merge_name("weather");
+        assert_eq!(pending.name, "get_weather");
+    }
+
+    #[tokio::test]
+    async fn test_crlf_and_split_lines() {
+        let chunk = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": null}]
+        })
+        .to_string();
+        let raw = format!("data: {}\r\n\r\ndata: [DONE]\r\n\r\n", chunk);
+        let (head, tail) = raw.split_at(12);
+        let events = collect_with(
+            RequestContext::default(),
+            vec![Bytes::from(head.to_string()), Bytes::from(tail.to_string())],
+        )
+        .await;
+
+        assert!(events
+            .iter()
+            .any(|e| e["type"] == "content_block_delta" && e["delta"]["text"] == "Hi"));
+        assert_eq!(events.last().unwrap()["type"], "message_stop");
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_error_mid_stream() {
+        let events = collect_events(vec![
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gpt-4",
+                "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": null}]
+            })
+            .to_string(),
+            json!({"error": {"message": "Server is overloaded", "type": "server_error", "code": 529}})
+                .to_string(),
+        ])
+        .await;
+
+        let error = events.iter().find(|e| e["type"] == "error").unwrap();
+        assert_eq!(error["error"]["type"], "overloaded_error");
+        assert_eq!(error["error"]["message"], "Server is overloaded");
+
+        let events = collect_events(vec![json!({"error": {"message": "boom"}}).to_string()]).await;
+        assert_eq!(events[0]["error"]["type"], "api_error");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_upstream_ends_with_timeout_error() {
+        let chunk = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": null}]
+        });
+        // 发出一个数据块后既不发送数据也不关闭连接
+        let input = futures::stream::iter(vec![Ok(Bytes::from(format!("data: {}\n\n", chunk)))])
+            .chain(futures::stream::pending());
+        let ctx = Arc::new(RequestContext {
+            stream_idle_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+
+        let output: Vec<_> = tokio::time::timeout(Duration::from_secs(5), create_stream(input, ctx).collect())
+            .await
+            .expect("stream should end after the idle timeout");
+        let last = String::from_utf8(output.last().unwrap().as_ref().unwrap().to_vec()).unwrap();
+        assert!(last.starts_with("event: error\n"));
+        let data: Value = serde_json::from_str(last.lines().nth(1).unwrap().strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(data["error"]["type"], IDLE_TIMEOUT_ERROR_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_response_ids_normalized_when_enabled() {
+        let chunk = json!({
+            "id": "gen-123",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": "stop"}]
+        });
+        let message_id = |normalize_response_ids: bool| {
+            let ctx = RequestContext {
+                normalize_response_ids,
+                ..Default::default()
+            };
+            let frames = sse_frames(&[chunk.to_string()]);
+            async move {
+                let events = collect_with(ctx, frames).await;
+                assert_eq!(events[0]["type"], "message_start");
+                events[0]["message"]["id"].as_str().unwrap().to_string()
+            }
+        };
+
+        assert_eq!(message_id(true).await, "msg_gen-123");
+        assert_eq!(message_id(false).await, "gen-123");
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_tiny_deltas() {
+        let delta_chunk = |delta: Value, finish_reason: Option<&str>| {
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gpt-4",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+            })
+            .to_string()
+        };
+        let mut chunks: Vec<String> = (0..50_000)
+            .map(|i| delta_chunk(json!({"content": char::from(b'a' + (i % 26) as u8).to_string()}), None))
+            .collect();
+        chunks.push(tool_chunk(
+            json!({"index": 0, "id": "call_1", "type": "function", "function": {"name": "write", "arguments": ""}}),
+            None,
+        ));
+        let arguments = json!({"content": "x".repeat(5_000)}).to_string();
+        chunks.extend(
+            arguments
+                .chars()
+                .map(|c| tool_chunk(json!({"index": 0, "function": {"arguments": c.to_string()}}), None)),
+        );
+        chunks.push(delta_chunk(json!({}), Some("tool_calls")));
+
+        let coalesced_ctx = |stream_coalesce: Option<Duration>| RequestContext {
+            stream_coalesce,
+            ..Default::default()
+        };
+        let plain = collect_with(coalesced_ctx(None), sse_frames(&chunks)).await;
+        let coalesced = collect_with(coalesced_ctx(Some(Duration::from_secs(60))), sse_frames(&chunks)).await;
+
+        assert!(plain.len() > 55_000);
+        assert!(coalesced.len() < 20, "{} events", coalesced.len());
+        assert_eq!(coalesced.last().unwrap()["type"], "message_stop");
+
+        let concat = |events: &[Value]| {
+            let text: String = events.iter().filter_map(|e| e["delta"]["text"].as_str()).collect();
+            (text, tool_blocks(events))
+        };
+        assert_eq!(concat(&coalesced), concat(&plain));
+
+        // 块边界事件的顺序与未合并时一致
+        let boundaries = |events: &[Value]| {
+            events
+                .iter()
+                .filter(|e| e["type"] != "content_block_delta")
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(boundaries(&coalesced), boundaries(&plain));
+    }
+
+    #[test]
+    fn test_typed_event_wire_format() {
+        let event = sse_event(&StreamEvent::ContentBlockStart {
+            index: 1,
+            content_block: ContentBlockStart::ToolUse {
+                id: "call_1".to_string(),
+                name: "lookup".to_string(),
+                input: json!({}),
+            },
+        });
+        assert_eq!(
+            event,
+            concat!(
+                "event: content_block_start\n",
+                r#"data: {"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"call_1","name":"lookup","input":{}}}"#,
+                "\n\n"
+            )
+        );
+
+        let event = sse_event(&StreamEvent::MessageDelta {
+            delta: MessageDeltaData {
+                stop_reason: Some("end_turn".to_string()),
+                stop_sequence: None,
+            },
+            usage: Some(MessageDeltaUsage { output_tokens: 5 }),
+        });
+        assert_eq!(
+            event,
+            concat!(
+                "event: message_delta\n",
+                r#"data: {"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":5}}"#,
+                "\n\n"
+            )
+        );
+
+        assert_eq!(
+            block_delta(0, Delta::Thinking { thinking: "hm".to_string() }),
+            concat!(
+                "event: content_block_delta\n",
+                r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"hm"}}"#,
+                "\n\n"
+            )
+        );
+        assert_eq!(
+            sse_event(&StreamEvent::MessageStop),
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"
+        );
     }
 }
@@ -0,0 +1,149 @@
+//! 由完整响应模拟流式输出
+//!
+//! 流式请求退回为非流式请求时（见 FALLBACK_TO_NON_STREAMING_ON_CONNECT_FAIL），
+//! 把完整的 Anthropic 响应还原成 Anthropic SSE 事件序列，再交给正常的流转换器，
+//! 客户端收到的 chunk 与真实流式响应的格式一致
+
+use crate::context::RequestContext;
+use crate::models::anthropic::{
+    AnthropicResponse, ContentBlockStart, Delta, MessageDeltaData, MessageDeltaUsage,
+    MessageStartData, ResponseContent, StreamEvent, Usage,
+};
+use crate::streaming::anthropic_to_openai::create_stream;
+use crate::streaming::openai_to_anthropic::sse_event;
+use bytes::Bytes;
+use futures::stream::Stream;
+use std::sync::Arc;
+
+/// 完整响应对应的 Anthropic SSE 事件：每个内容块一次性作为单个增量发出
+pub fn anthropic_events(resp: &AnthropicResponse) -> Vec<Bytes> {
+    let mut events = vec![sse_event(&StreamEvent::MessageStart {
+        message: MessageStartData {
+            id: resp.id.clone(),
+            message_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: Vec::new(),
+            model: resp.model.clone(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: resp.usage.input_tokens,
+                output_tokens: 0,
+            },
+        },
+    })];
+
+    for (index, block) in resp.content.iter().enumerate() {
+        let (content_block, delta) = match block {
+            ResponseContent::Text { text, .. } => (
+                ContentBlockStart::Text {
+                    text: String::new(),
+                },
+                Delta::Text { text: text.clone() },
+            ),
+            ResponseContent::ToolUse { id, name, input, .. } => (
+                ContentBlockStart::ToolUse {
+                    id: id.clone(),
+                    name: name.clone(),
+                    input: serde_json::json!({}),
+                },
+                Delta::InputJson {
+                    partial_json: input.to_string(),
+                },
+            ),
+            ResponseContent::Thinking { thinking, .. } => (
+                ContentBlockStart::Thinking {
+                    thinking: String::new(),
+                },
+                Delta::Thinking {
+                    thinking: thinking.clone(),
+                },
+            ),
+        };
+        events.push(sse_event(&StreamEvent::ContentBlockStart {
+            index,
+            content_block,
+        }));
+        events.push(sse_event(&StreamEvent::ContentBlockDelta { index, delta }));
+        events.push(sse_event(&StreamEvent::ContentBlockStop { index }));
+    }
+
+    events.push(sse_event(&StreamEvent::MessageDelta {
+        delta: MessageDeltaData {
+            stop_reason: resp.stop_reason.clone(),
+            stop_sequence: resp.stop_sequence.clone(),
+        },
+        usage: Some(MessageDeltaUsage {
+            output_tokens: resp.usage.output_tokens,
+        }),
+    }));
+    events.push(sse_event(&StreamEvent::MessageStop));
+    events
+}
+
+/// 完整 Anthropic 响应 → OpenAI 流
+pub fn openai_stream(
+    resp: &AnthropicResponse,
+    ctx: Arc<RequestContext>,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send {
+    let events = anthropic_events(resp)
+        .into_iter()
+        .map(Ok::<_, reqwest::Error>);
+    create_stream(futures::stream::iter(events), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_simulated_stream_matches_response() {
+        let resp: AnthropicResponse = serde_json::from_value(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type": "thinking", "thinking": "Need the weather."},
+                {"type": "text", "text": "Checking."},
+                {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}
+            ],
+            "model": "claude-3",
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 12, "output_tokens": 7}
+        }))
+        .unwrap();
+        let ctx = Arc::new(RequestContext {
+            include_usage: true,
+            ..Default::default()
+        });
+
+        let output: Vec<_> = openai_stream(&resp, ctx).collect().await;
+        let chunks: Vec<String> = output
+            .into_iter()
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .map(|s| s.trim_start_matches("data: ").trim_end().to_string())
+            .collect();
+        assert_eq!(chunks.last().unwrap(), "[DONE]");
+
+        let chunks: Vec<Value> = chunks[..chunks.len() - 1]
+            .iter()
+            .map(|c| serde_json::from_str(c).unwrap())
+            .collect();
+        let deltas: Vec<&Value> = chunks.iter().map(|c| &c["choices"][0]["delta"]).collect();
+        assert_eq!(deltas[0]["reasoning"], "Need the weather.");
+        assert_eq!(deltas[1]["content"], "Checking.");
+        assert_eq!(deltas[2]["tool_calls"][0]["function"]["name"], "get_weather");
+        assert_eq!(
+            deltas[3]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "tool_calls");
+
+        let usage = &chunks.last().unwrap()["usage"];
+        assert_eq!(usage["prompt_tokens"], 12);
+        assert_eq!(usage["completion_tokens"], 7);
+    }
+}
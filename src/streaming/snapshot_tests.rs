@@ -0,0 +1,158 @@
+//! 流转换快照测试
+//!
+//! `tests/fixtures/streaming/<方向>/` 下每个 `<名称>.sse` 是一段上游 SSE 原文，
+//! 同名 `.expected` 是转换后的输出。每个输入按多种方式切分成 HTTP chunk 逐块送入转换器，
+//! 不同切分方式的输出必须完全一致，且与 `.expected` 相同。
+//!
+//! 修改转换逻辑后以 `UPDATE_SNAPSHOTS=1 cargo test snapshot` 重新生成 `.expected`，
+//! 再检查 diff 是否符合预期
+
+use crate::context::RequestContext;
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type Converter = fn(Vec<Bytes>) -> BoxStream<'static, Result<Bytes, std::io::Error>>;
+
+/// 输入的切分方式
+#[derive(Debug, Clone, Copy)]
+enum Chunking {
+    /// 整个输入作为一个 chunk
+    WholeBody,
+    /// 每行（含换行符）一个 chunk
+    LineAtATime,
+    /// 每个字节一个 chunk，会拆开多字节字符和 `\r\n`
+    ByteAtATime,
+    /// 固定 7 字节一个 chunk，边界落在行和事件中间
+    FixedSize,
+}
+
+const CHUNKINGS: [Chunking; 4] = [
+    Chunking::WholeBody,
+    Chunking::LineAtATime,
+    Chunking::ByteAtATime,
+    Chunking::FixedSize,
+];
+
+impl Chunking {
+    fn split(self, input: &[u8]) -> Vec<Bytes> {
+        match self {
+            Chunking::WholeBody => vec![Bytes::copy_from_slice(input)],
+            Chunking::LineAtATime => input
+                .split_inclusive(|&b| b == b'\n')
+                .map(Bytes::copy_from_slice)
+                .collect(),
+            Chunking::ByteAtATime => input.chunks(1).map(Bytes::copy_from_slice).collect(),
+            Chunking::FixedSize => input.chunks(7).map(Bytes::copy_from_slice).collect(),
+        }
+    }
+}
+
+fn anthropic_to_openai(chunks: Vec<Bytes>) -> BoxStream<'static, Result<Bytes, std::io::Error>> {
+    let input = futures::stream::iter(chunks.into_iter().map(Ok));
+    super::anthropic_to_openai::create_stream(input, Arc::new(RequestContext::default())).boxed()
+}
+
+fn openai_to_anthropic(chunks: Vec<Bytes>) -> BoxStream<'static, Result<Bytes, std::io::Error>> {
+    let input = futures::stream::iter(chunks.into_iter().map(Ok));
+    super::openai_to_anthropic::create_stream(input, Arc::new(RequestContext::default())).boxed()
+}
+
+async fn convert(converter: Converter, chunks: Vec<Bytes>) -> String {
+    let output: Vec<_> = converter(chunks).collect().await;
+    let bytes: Vec<u8> = output
+        .into_iter()
+        .flat_map(|chunk| chunk.unwrap().to_vec())
+        .collect();
+    normalize(&String::from_utf8(bytes).unwrap())
+}
+
+/// 把随时间变化的 `"created":<时间戳>` 替换为 0
+fn normalize(output: &str) -> String {
+    const FIELD: &str = "\"created\":";
+    let mut result = String::with_capacity(output.len());
+    let mut rest = output;
+    while let Some(pos) = rest.find(FIELD) {
+        let (head, tail) = rest.split_at(pos + FIELD.len());
+        result.push_str(head);
+        let digits = tail.bytes().take_while(u8::is_ascii_digit).count();
+        if digits > 0 {
+            result.push('0');
+        }
+        rest = &tail[digits..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn fixtures(direction: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/streaming")
+        .join(direction);
+    let mut inputs: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sse"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no fixtures in {}", dir.display());
+    inputs
+}
+
+async fn check_snapshots(direction: &str, converter: Converter) {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut failures = Vec::new();
+
+    for input_path in fixtures(direction) {
+        let name = input_path.file_stem().unwrap().to_string_lossy().into_owned();
+        let input = std::fs::read(&input_path).unwrap();
+
+        let baseline = convert(converter, Chunking::WholeBody.split(&input)).await;
+        for chunking in &CHUNKINGS[1..] {
+            let output = convert(converter, chunking.split(&input)).await;
+            assert_eq!(
+                output, baseline,
+                "{}/{}: {:?} output differs from {:?}",
+                direction, name, chunking, Chunking::WholeBody
+            );
+        }
+
+        let expected_path = input_path.with_extension("expected");
+        if update {
+            std::fs::write(&expected_path, &baseline).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+        if expected != baseline {
+            failures.push(format!(
+                "{}/{}\n--- expected\n{}\n--- actual\n{}",
+                direction, name, expected, baseline
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "snapshot mismatch (rerun with UPDATE_SNAPSHOTS=1 to accept):\n{}",
+        failures.join("\n")
+    );
+}
+
+#[tokio::test]
+async fn test_anthropic_to_openai_snapshots() {
+    check_snapshots("anthropic_to_openai", anthropic_to_openai).await;
+}
+
+#[tokio::test]
+async fn test_openai_to_anthropic_snapshots() {
+    check_snapshots("openai_to_anthropic", openai_to_anthropic).await;
+}
+
+#[test]
+fn test_normalize_created() {
+    assert_eq!(
+        normalize(r#"{"created":1712345678,"model":"m"}{"created":0}"#),
+        r#"{"created":0,"model":"m"}{"created":0}"#
+    );
+}
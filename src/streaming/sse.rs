@@ -0,0 +1,184 @@
+//! SSE 事件解析器
+//!
+//! 按字节增量解析上游 SSE 流：支持 `\r\n`、`\n`、`\r` 三种换行，
+//! 跨 HTTP chunk 拆分的行（包括被拆开的多字节字符）会缓冲到行结束后再处理
+
+/// 一个完整的 SSE 事件
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    /// `event:` 字段
+    pub event: Option<String>,
+    /// 所有 `data:` 字段以换行拼接
+    pub data: String,
+    /// `id:` 字段（包含 NUL 字符的值按规范忽略）
+    pub id: Option<String>,
+}
+
+/// 解析器状态
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ParserState {
+    /// 尚未收到当前事件的任何字段
+    #[default]
+    BetweenEvents,
+    /// 已收到当前事件的字段，等待空行结束事件
+    InEvent,
+    /// 正在跳过以 `:` 开头的注释行（如 keep-alive）
+    InComment,
+}
+
+#[derive(Debug, Default)]
+pub struct SseParser {
+    state: ParserState,
+    /// 当前未结束的行
+    line: Vec<u8>,
+    /// 上一行以 `\r` 结束，下一个字节若为 `\n` 则属于同一个换行
+    skip_lf: bool,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+}
+
+impl SseParser {
+    /// 输入一段字节，返回其中完成的事件
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        for &byte in bytes {
+            if std::mem::take(&mut self.skip_lf) && byte == b'\n' {
+                continue;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.skip_lf = byte == b'\r';
+                    events.extend(self.end_line());
+                }
+                _ => match self.state {
+                    ParserState::InComment => {}
+                    _ if self.line.is_empty() && byte == b':' => {
+                        self.state = ParserState::InComment;
+                    }
+                    _ => self.line.push(byte),
+                },
+            }
+        }
+        events
+    }
+
+    /// 上游结束时处理剩余内容：缺少结尾空行的最后一个事件同样返回
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if self.state != ParserState::InComment && !self.line.is_empty() {
+            self.process_field();
+        }
+        self.dispatch()
+    }
+
+    fn end_line(&mut self) -> Option<SseEvent> {
+        match self.state {
+            ParserState::InComment => {
+                self.state = if self.event.is_some() || self.data.is_some() {
+                    ParserState::InEvent
+                } else {
+                    ParserState::BetweenEvents
+                };
+                None
+            }
+            _ if self.line.is_empty() => self.dispatch(),
+            _ => {
+                self.process_field();
+                self.state = ParserState::InEvent;
+                None
+            }
+        }
+    }
+
+    fn process_field(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        let line = String::from_utf8_lossy(&line);
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "event" => self.event = Some(value.to_string()),
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    /// 空行结束事件；没有 data 字段的事件按规范丢弃
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        self.state = ParserState::BetweenEvents;
+        let event = self.event.take();
+        let id = self.id.take();
+        self.data.take().map(|data| SseEvent { event, data, id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_of(events: Vec<SseEvent>) -> Vec<String> {
+        events.into_iter().map(|e| e.data).collect()
+    }
+
+    #[test]
+    fn test_line_ending_variants() {
+        for input in [
+            "data: a\n\ndata: b\n\n",
+            "data: a\r\n\r\ndata: b\r\n\r\n",
+            "data: a\r\rdata: b\r\r",
+        ] {
+            let mut parser = SseParser::default();
+            assert_eq!(data_of(parser.feed(input.as_bytes())), vec!["a", "b"], "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_partial_lines_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b"data: {\"type").is_empty());
+        assert!(parser.feed(b"\": \"text\"}\r").is_empty());
+        assert!(parser.feed(b"\n").is_empty());
+        assert_eq!(data_of(parser.feed(b"\r\n")), vec![r#"{"type": "text"}"#]);
+    }
+
+    #[test]
+    fn test_multibyte_character_split_across_chunks() {
+        let bytes = "data: 你好\n\n".as_bytes();
+        let mut parser = SseParser::default();
+        assert!(parser.feed(&bytes[..8]).is_empty());
+        assert_eq!(data_of(parser.feed(&bytes[8..])), vec!["你好"]);
+    }
+
+    #[test]
+    fn test_event_field_comments_and_multiline_data() {
+        let mut parser = SseParser::default();
+        let events = parser.feed(b": keep-alive\n\nevent: ping\n: note\nid: 7\ndata: a\ndata:b\n\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: Some("ping".to_string()),
+                data: "a\nb".to_string(),
+                id: Some("7".to_string()),
+            }]
+        );
+
+        // id 只属于所在的事件
+        assert_eq!(parser.feed(b"data: c\n\n")[0].id, None);
+    }
+
+    #[test]
+    fn test_finish_flushes_unterminated_event() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b"data: [DONE]").is_empty());
+        assert_eq!(parser.finish().map(|e| e.data), Some("[DONE]".to_string()));
+        assert_eq!(parser.finish(), None);
+    }
+}
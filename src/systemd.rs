@@ -0,0 +1,100 @@
+//! systemd 服务通知
+//!
+//! 以 `Type=notify` 运行时 systemd 会设置 `NOTIFY_SOCKET`：监听端口绑定后发送 `READY=1`，
+//! 优雅关闭时发送 `STOPPING=1`；配置了 `WatchdogSec` 时按其一半的间隔发送 `WATCHDOG=1`。
+//! 未设置 `NOTIFY_SOCKET` 时所有通知均为空操作
+
+use sd_notify::NotifyState;
+use std::time::Duration;
+
+/// 当前进程是否由 systemd 以 notify 方式管理
+pub fn enabled() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// 监听端口已绑定，服务可以接受请求
+pub fn notify_ready() {
+    notify(NotifyState::Ready);
+}
+
+/// 开始优雅关闭
+pub fn notify_stopping() {
+    notify(NotifyState::Stopping);
+}
+
+fn notify(state: NotifyState) {
+    if !enabled() {
+        return;
+    }
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        tracing::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// systemd 要求的看门狗心跳间隔：`WATCHDOG_USEC` 的一半，未启用看门狗时为 None
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) || usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// 启用看门狗时在后台周期性发送 `WATCHDOG=1`
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    tracing::info!(interval_ms = interval.as_millis() as u64, "systemd watchdog enabled");
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify(NotifyState::Watchdog);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 256];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    // 环境变量为进程级状态，所有依赖 NOTIFY_SOCKET 的断言放在同一个测试中
+    #[test]
+    fn test_notifications_sent_to_notify_socket() {
+        assert!(!enabled());
+        notify_ready();
+        assert_eq!(watchdog_interval(), None);
+
+        let path = std::env::temp_dir().join(format!("anthropic-proxy-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        std::env::set_var("NOTIFY_SOCKET", &path);
+
+        notify_ready();
+        assert_eq!(recv(&socket), "READY=1\n");
+        notify(NotifyState::Watchdog);
+        assert_eq!(recv(&socket), "WATCHDOG=1\n");
+        notify_stopping();
+        assert_eq!(recv(&socket), "STOPPING=1\n");
+
+        std::env::set_var("WATCHDOG_USEC", "10000000");
+        std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+        assert_eq!(watchdog_interval(), Some(Duration::from_secs(5)));
+        std::env::set_var("WATCHDOG_PID", "1");
+        assert_eq!(watchdog_interval(), None);
+
+        for var in ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"] {
+            std::env::remove_var(var);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
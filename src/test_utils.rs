@@ -0,0 +1,338 @@
+//! 测试辅助工具
+
+use crate::config::{
+    Config, DeveloperMessageHandling, HttpClientSettings, MultipleTextBlocksMode, ResponseModelMode,
+    RoutingMode, SchemaProfile, ThinkingInHistory, ToolsStrictMode, UrlV1Check, ValidationMode,
+};
+use crate::router::Backend;
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    body::{Body, Bytes},
+    http::{Method, Uri},
+    routing::{any, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 测试用配置：Auto 模式、未配置任何后端，其余设置取环境变量未设置时的默认值；
+/// 测试按需用 `Config { 字段: 值, ..test_config() }` 覆盖
+pub fn test_config() -> Config {
+    Config {
+        host: "0.0.0.0".to_string(),
+        port: 3000,
+        workers: None,
+        metrics_port: None,
+        routing_mode: RoutingMode::Auto,
+        anthropic_base_url: None,
+        anthropic_api_key: None,
+        anthropic_metadata_user_id: None,
+        anthropic_version: None,
+        openai_base_url: None,
+        openai_api_key: None,
+        openai_organization: None,
+        openai_project: None,
+        forward_authorization: false,
+        base_url: None,
+        api_key: None,
+        url_v1_check: UrlV1Check::Warn,
+        upstream_path_prefix: None,
+        upstream_chat_completions_path: None,
+        forward_headers: Vec::new(),
+        upstream_user_agent: None,
+        trusted_proxies: Vec::new(),
+        forward_client_ip: false,
+        request_id_header: "X-Request-Id".to_string(),
+        stream_from_accept: true,
+        strip_model_prefixes: Vec::new(),
+        anthropic_model_patterns: Vec::new(),
+        openai_model_patterns: Vec::new(),
+        default_backend: Backend::OpenAI,
+        transform_reject_openai: false,
+        reasoning_model: None,
+        completion_model: None,
+        reasoning_model_fallback: false,
+        model_fallbacks: Vec::new(),
+        hedge_after_ms: 0,
+        hedge_models: Vec::new(),
+        merge_consecutive_messages: true,
+        min_max_tokens: 16,
+        min_max_tokens_per_model: std::collections::HashMap::new(),
+        context_window_limits: std::collections::HashMap::new(),
+        tools_strict_mode: ToolsStrictMode::Off,
+        filtered_tool_types: vec!["BatchTool".to_string()],
+        deduplicate_tools: true,
+        max_tools: None,
+        max_schema_depth: None,
+        default_stop: Vec::new(),
+        json_mode_system_marker: None,
+        strict_params: false,
+        validation: ValidationMode::Lenient,
+        policy_rules_file: None,
+        audio_input_placeholder: false,
+        developer_message_handling: DeveloperMessageHandling::AsSystem,
+        thinking_in_history: ThinkingInHistory::Strip,
+        multiple_text_blocks: MultipleTextBlocksMode::Concatenate,
+        strict_openai_shape: false,
+        response_model_mode: ResponseModelMode::Upstream,
+        normalize_response_ids: false,
+        reasoning_field: "reasoning".to_string(),
+        openai_schema_profile: SchemaProfile::Minimal,
+        upstream_schema_profile: SchemaProfile::Minimal,
+        schema_strip_keywords: Vec::new(),
+        schema_collapse_nullable: true,
+        max_retry_after_secs: 60,
+        stream_idle_timeout_secs: 60,
+        stream_coalesce_ms: 0,
+        fallback_to_non_streaming_on_connect_fail: false,
+        anthropic_http: HttpClientSettings::default(),
+        openai_http: HttpClientSettings::default(),
+        upstream_http: HttpClientSettings::default(),
+        rate_limits: Vec::new(),
+        batch_max_concurrency: 4,
+        dashboard_enabled: false,
+        admin_key: None,
+        model_pricing: Vec::new(),
+        audit_log: None,
+        audit_hmac_secret: None,
+        debug: false,
+        verbose: false,
+        log_raw_json: false,
+        log_max_text_len: 0,
+        log_max_body_bytes: 0,
+    }
+}
+
+/// 启动模拟上游（同时提供 OpenAI 和 Anthropic 端点）：名称含 `missing-` 的模型返回模型不存在，
+/// Anthropic 端点名称含 `overloaded` 的模型返回 529 过载（`retry-after: 0`），
+/// `echo-headers` 模型以逗号分隔的形式回显收到的请求头名，
+/// `echo-tool` 模型以收到的第一个工具名发起工具调用（同时作为文本回显），
+/// `echo-body` 模型以文本形式回显收到的原始请求体，其余模型正常回显；
+/// `/v1/messages/batches`、`/v1/models` 下的请求和 `/v1/messages/count_tokens`、`/v1/embeddings` 以 JSONL 回显方法、路径、查询参数、认证头和请求体
+pub async fn spawn_mock_upstream() -> String {
+    async fn chat_completions(headers: HeaderMap, body: Bytes) -> Response {
+        let req: Value = serde_json::from_slice(&body).unwrap_or_default();
+        let model = req["model"].as_str().unwrap_or_default().to_string();
+        if model == "echo-tool" {
+            return echo_tool(&req);
+        }
+        if model.contains("missing-") {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "error": {
+                        "message": format!("The model `{}` does not exist", model),
+                        "type": "invalid_request_error",
+                        "code": "model_not_found"
+                    }
+                })),
+            )
+                .into_response();
+        }
+        let content = if model == "echo-headers" {
+            let mut names: Vec<&str> = headers.keys().map(|k| k.as_str()).collect();
+            names.sort_unstable();
+            names.join(",")
+        } else if model == "echo-body" {
+            String::from_utf8_lossy(&body).into_owned()
+        } else {
+            "ok".to_string()
+        };
+        Json(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response()
+    }
+
+    async fn messages(body: Bytes) -> Response {
+        let req: Value = serde_json::from_slice(&body).unwrap_or_default();
+        let model = req["model"].as_str().unwrap_or_default().to_string();
+        if model.contains("missing-") {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "type": "error",
+                    "error": {"type": "not_found_error", "message": format!("model: {}", model)}
+                })),
+            )
+                .into_response();
+        }
+        if model.contains("overloaded") {
+            return (
+                StatusCode::from_u16(529).unwrap(),
+                [("retry-after", "0")],
+                Json(json!({
+                    "type": "error",
+                    "error": {"type": "overloaded_error", "message": "Overloaded"}
+                })),
+            )
+                .into_response();
+        }
+        let text = if model == "echo-body" {
+            String::from_utf8_lossy(&body).into_owned()
+        } else {
+            "ok".to_string()
+        };
+        Json(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": text}],
+            "model": model,
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 1, "output_tokens": 1}
+        }))
+        .into_response()
+    }
+
+    async fn echo_request(method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        let echo = json!({
+            "method": method.as_str(),
+            "path": uri.path(),
+            "query": uri.query(),
+            "x-api-key": header("x-api-key"),
+            "authorization": header("authorization"),
+            "user-agent": header("user-agent"),
+            "anthropic-version": header("anthropic-version"),
+            "body": String::from_utf8_lossy(&body),
+        });
+        ([("content-type", "application/x-jsonl")], format!("{}\n", echo)).into_response()
+    }
+
+    let app = Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/messages", post(messages))
+        .route("/v1/messages/count_tokens", post(echo_request))
+        .route("/v1/messages/batches", any(echo_request))
+        .route("/v1/messages/batches/*rest", any(echo_request))
+        .route("/v1/models/*rest", any(echo_request))
+        .route("/v1/embeddings", post(echo_request));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+/// 启动响应缓慢的 OpenAI 兼容上游：等待 `delay` 后回复 `slow`。
+/// 返回的标志在请求处理被中途取消（客户端断开连接）时置位
+pub async fn spawn_slow_upstream(delay: Duration) -> (String, Arc<AtomicBool>) {
+    /// 处理未完成就被丢弃时置位
+    struct CancelGuard(Option<Arc<AtomicBool>>);
+
+    impl Drop for CancelGuard {
+        fn drop(&mut self) {
+            if let Some(cancelled) = self.0.take() {
+                cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move |Json(req): Json<Value>| {
+            let mut guard = CancelGuard(Some(flag.clone()));
+            async move {
+                tokio::time::sleep(delay).await;
+                guard.0.take();
+                Json(json!({
+                    "id": "chatcmpl-slow",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": req["model"],
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "slow"},
+                        "finish_reason": "stop"
+                    }],
+                    "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+                }))
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (format!("http://{}", addr), cancelled)
+}
+
+/// 以请求中第一个工具的名称发起工具调用，按 `stream` 返回流式或非流式响应
+fn echo_tool(req: &Value) -> Response {
+    let name = req["tools"][0]["function"]["name"].as_str().unwrap_or_default();
+
+    if req["stream"].as_bool() != Some(true) {
+        return Json(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "echo-tool",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": name,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": name, "arguments": "{}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+        }))
+        .into_response();
+    }
+
+    let chunk = |delta: Value, finish_reason: Option<&str>| {
+        format!(
+            "data: {}\n\n",
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "echo-tool",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+            })
+        )
+    };
+    let body = [
+        chunk(json!({"role": "assistant", "content": name}), None),
+        chunk(
+            json!({"tool_calls": [{
+                "index": 0,
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": name, "arguments": "{}"}
+            }]}),
+            None,
+        ),
+        chunk(json!({}), Some("tool_calls")),
+        "data: [DONE]\n\n".to_string(),
+    ]
+    .concat();
+
+    Response::builder()
+        .header("Content-Type", "text/event-stream")
+        .body(Body::from(body))
+        .unwrap()
+}
@@ -0,0 +1,260 @@
+//! Token 估算
+//!
+//! 启用 `tokenizers` feature 时使用 tiktoken 词表计数：OpenAI 模型按 o200k/cl100k，
+//! Claude 模型以 cl100k 计数再按经验系数放大近似。未启用 feature 或模型未知时，
+//! 回退到按字符类别的启发式估算（ASCII 约 4 字符一个 token，其余字符各算一个）
+
+use crate::models::{anthropic, openai};
+
+/// 每条消息的固定开销（角色与分隔符）
+pub const MESSAGE_OVERHEAD: usize = 3;
+/// 回复起始的固定开销
+pub const REPLY_OVERHEAD: usize = 3;
+/// 每个工具定义的固定开销
+pub const TOOL_OVERHEAD: usize = 8;
+/// 无法得知尺寸的图片按此估算（约 1092x1092 的图片）
+pub const IMAGE_TOKENS: usize = 1600;
+
+/// Claude 词表相对 cl100k 的经验放大系数（百分比）
+#[cfg(feature = "tokenizers")]
+const CLAUDE_SCALE_PERCENT: usize = 115;
+
+/// 模型所属的分词器族
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenizer {
+    /// gpt-4o、gpt-4.1、gpt-5 与 o 系列
+    O200k,
+    /// gpt-4、gpt-3.5 与 embedding 模型
+    Cl100k,
+    /// Claude 模型（以 cl100k 近似）
+    Claude,
+    /// 未知模型，使用启发式估算
+    Heuristic,
+}
+
+impl Tokenizer {
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        if model.contains("claude") {
+            Tokenizer::Claude
+        } else if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || model.starts_with("gpt-5")
+            || model.starts_with("chatgpt-4o")
+            || model.starts_with("o1")
+            || model.starts_with("o3")
+            || model.starts_with("o4")
+        {
+            Tokenizer::O200k
+        } else if model.starts_with("gpt-4")
+            || model.starts_with("gpt-3.5")
+            || model.starts_with("text-embedding")
+        {
+            Tokenizer::Cl100k
+        } else {
+            Tokenizer::Heuristic
+        }
+    }
+
+    /// 统计一段文本的 token 数
+    pub fn count(self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+        match self {
+            #[cfg(feature = "tokenizers")]
+            Tokenizer::O200k => tiktoken_rs::o200k_base_singleton().encode_ordinary(text).len(),
+            #[cfg(feature = "tokenizers")]
+            Tokenizer::Cl100k => tiktoken_rs::cl100k_base_singleton().encode_ordinary(text).len(),
+            #[cfg(feature = "tokenizers")]
+            Tokenizer::Claude => {
+                let tokens = tiktoken_rs::cl100k_base_singleton().encode_ordinary(text).len();
+                (tokens * CLAUDE_SCALE_PERCENT).div_ceil(100)
+            }
+            _ => heuristic_count(text),
+        }
+    }
+}
+
+/// 启发式估算：ASCII 字符约 4 个一个 token，CJK 等非 ASCII 字符各算一个
+pub fn heuristic_count(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0, 0), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    usize::div_ceil(ascii, 4) + other
+}
+
+/// 可估算 token 数的请求
+pub trait TokenEstimate {
+    /// 使用给定分词器估算输入 token 数（消息、系统提示与工具定义）
+    fn estimate_tokens(&self, tokenizer: Tokenizer) -> usize;
+}
+
+/// 按模型选择分词器估算请求的输入 token 数
+pub fn estimate<R: TokenEstimate + ?Sized>(model: &str, req: &R) -> usize {
+    req.estimate_tokens(Tokenizer::for_model(model))
+}
+
+impl TokenEstimate for anthropic::AnthropicRequest {
+    fn estimate_tokens(&self, tokenizer: Tokenizer) -> usize {
+        let mut total = REPLY_OVERHEAD;
+
+        match &self.system {
+            Some(anthropic::SystemPrompt::Single(text)) => {
+                total += MESSAGE_OVERHEAD + tokenizer.count(text);
+            }
+            Some(anthropic::SystemPrompt::Multiple(messages)) => {
+                total += MESSAGE_OVERHEAD;
+                total += messages.iter().map(|m| tokenizer.count(&m.text)).sum::<usize>();
+            }
+            None => {}
+        }
+
+        for msg in &self.messages {
+            total += MESSAGE_OVERHEAD;
+            total += match &msg.content {
+                anthropic::MessageContent::Text(text) => tokenizer.count(text),
+                anthropic::MessageContent::Blocks(blocks) => {
+                    blocks.iter().map(|b| content_block_tokens(b, tokenizer)).sum()
+                }
+            };
+        }
+
+        for tool in self.tools.iter().flatten() {
+            total += TOOL_OVERHEAD + tokenizer.count(&tool.name);
+            total += tool.description.as_deref().map_or(0, |d| tokenizer.count(d));
+            total += tokenizer.count(&tool.input_schema.to_string());
+        }
+
+        total
+    }
+}
+
+fn content_block_tokens(block: &anthropic::ContentBlock, tokenizer: Tokenizer) -> usize {
+    match block {
+        anthropic::ContentBlock::Text { text, .. } => tokenizer.count(text),
+        anthropic::ContentBlock::Image { .. } => IMAGE_TOKENS,
+        anthropic::ContentBlock::ToolUse { name, input, .. } => {
+            tokenizer.count(name) + tokenizer.count(&input.to_string())
+        }
+        anthropic::ContentBlock::ToolResult { content, .. } => match content {
+            anthropic::ToolResultContent::Text(text) => tokenizer.count(text),
+            anthropic::ToolResultContent::Blocks(blocks) => blocks
+                .iter()
+                .map(|b| match b {
+                    anthropic::ToolResultBlock::Text { text } => tokenizer.count(text),
+                    anthropic::ToolResultBlock::Image { .. } => IMAGE_TOKENS,
+                })
+                .sum(),
+        },
+        anthropic::ContentBlock::Thinking { thinking } => tokenizer.count(thinking),
+    }
+}
+
+impl TokenEstimate for openai::OpenAIRequest {
+    fn estimate_tokens(&self, tokenizer: Tokenizer) -> usize {
+        let mut total = REPLY_OVERHEAD;
+
+        for msg in &self.messages {
+            total += MESSAGE_OVERHEAD;
+            total += match &msg.content {
+                Some(openai::MessageContent::Text(text)) => tokenizer.count(text),
+                Some(openai::MessageContent::Parts(parts)) => parts
+                    .iter()
+                    .map(|p| match p {
+                        openai::ContentPart::Text { text } => tokenizer.count(text),
+                        openai::ContentPart::ImageUrl { .. } => IMAGE_TOKENS,
+                        // 音频和未知类型无法估算
+                        openai::ContentPart::InputAudio { .. } | openai::ContentPart::Unknown(_) => 0,
+                    })
+                    .sum(),
+                None => 0,
+            };
+            total += msg.name.as_deref().map_or(0, |n| tokenizer.count(n));
+            for call in msg.tool_calls.iter().flatten() {
+                total += tokenizer.count(&call.function.name);
+                total += tokenizer.count(&call.function.arguments);
+            }
+        }
+
+        for tool in self.tools.iter().flatten() {
+            total += TOOL_OVERHEAD + tokenizer.count(&tool.function.name);
+            total += tool
+                .function
+                .description
+                .as_deref()
+                .map_or(0, |d| tokenizer.count(d));
+            total += tokenizer.count(&tool.function.parameters.to_string());
+        }
+
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tokenizer_for_model() {
+        assert_eq!(Tokenizer::for_model("gpt-4o-mini"), Tokenizer::O200k);
+        assert_eq!(Tokenizer::for_model("o3-mini"), Tokenizer::O200k);
+        assert_eq!(Tokenizer::for_model("gpt-4-turbo"), Tokenizer::Cl100k);
+        assert_eq!(Tokenizer::for_model("claude-3-5-sonnet"), Tokenizer::Claude);
+        assert_eq!(Tokenizer::for_model("llama-3"), Tokenizer::Heuristic);
+    }
+
+    #[test]
+    fn test_heuristic_counts_cjk_per_char() {
+        assert_eq!(heuristic_count(""), 0);
+        assert_eq!(heuristic_count("hello world"), 3);
+        assert_eq!(heuristic_count("你好世界"), 4);
+        assert_eq!(Tokenizer::Heuristic.count("你好 world"), 2 + 2);
+    }
+
+    #[cfg(feature = "tokenizers")]
+    #[test]
+    fn test_openai_tokenizer_counts() {
+        assert_eq!(Tokenizer::Cl100k.count("hello world"), 2);
+        assert_eq!(Tokenizer::Cl100k.count("tiktoken is great!"), 6);
+        assert_eq!(Tokenizer::O200k.count("hello world"), 2);
+        assert_eq!(Tokenizer::O200k.count("tiktoken is great!"), 6);
+    }
+
+    #[cfg(feature = "tokenizers")]
+    #[test]
+    fn test_claude_tokenizer_approximation() {
+        assert_eq!(Tokenizer::Claude.count("hello world"), 3);
+        assert_eq!(Tokenizer::Claude.count("tiktoken is great!"), 7);
+    }
+
+    #[test]
+    fn test_estimate_includes_overheads_and_tools() {
+        let req: anthropic::AnthropicRequest = serde_json::from_value(json!({
+            "model": "unknown-model",
+            "max_tokens": 100,
+            "system": "abcd",
+            "messages": [{"role": "user", "content": "abcdefgh"}],
+            "tools": [{"name": "abcd", "input_schema": {}}]
+        }))
+        .unwrap();
+
+        // 回复 3 + system (3 + 1) + 消息 (3 + 2) + 工具 (8 + 1 + 1)
+        assert_eq!(estimate(&req.model, &req), 22);
+
+        let req: openai::OpenAIRequest = serde_json::from_value(json!({
+            "model": "unknown-model",
+            "messages": [
+                {"role": "system", "content": "abcd"},
+                {"role": "user", "content": "abcdefgh"}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(estimate(&req.model, &req), 3 + 4 + 5);
+    }
+}
@@ -4,6 +4,7 @@
 
 pub mod request;
 pub mod response;
+pub mod schema;
 pub mod utils;
 
 // 重新导出常用类型
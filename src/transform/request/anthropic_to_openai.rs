@@ -1,22 +1,33 @@
 //! Anthropic 请求转换为 OpenAI 格式
 
-use crate::config::Config;
+use crate::config::{Config, ThinkingInHistory, ToolsStrictMode};
 use crate::error::{ProxyError, ProxyResult};
 use crate::models::{anthropic, openai};
-use crate::transform::utils::{clean_schema, parse_model_with_effort};
+use crate::router::Backend;
+use crate::transform::schema::{sanitize_schema, SchemaOptions};
+use crate::transform::utils::{
+    check_tool_limits, enforce_strict_schema, is_strict_compatible, merge_stop_sequences, metadata_user_id,
+    openai_service_tier, parse_model_with_effort, response_format_extension, upstream_tool_name,
+    OPENAI_MAX_STOP,
+};
+use serde_json::json;
+use std::collections::HashSet;
+
+/// 默认过滤的 Anthropic 内置工具类型，OpenAI 后端没有对应工具
+pub const DEFAULT_FILTERED_TOOL_TYPES: &[&str] = &["BatchTool", "computer_20250124", "bash_20250124"];
 
 /// 将 Anthropic 请求转换为 OpenAI 格式
+///
+/// `backend` 为转换后请求的目标后端，用于选择工具 schema 的清理档位；
+/// 对请求内容的改写记录到 `report`（即 `RequestContext::transform_report`）
 pub fn anthropic_to_openai(
     req: anthropic::AnthropicRequest,
     config: &Config,
+    backend: Backend,
+    report: &mut Vec<String>,
 ) -> ProxyResult<openai::OpenAIRequest> {
     // 根据 thinking 参数决定模型
-    let has_thinking = req
-        .extra
-        .get("thinking")
-        .and_then(|v| v.as_object())
-        .map(|o| o.get("type").and_then(|t| t.as_str()) == Some("enabled"))
-        .unwrap_or(false);
+    let has_thinking = has_thinking(&req);
 
     // 使用配置的模型或请求中的模型
     let raw_model = if has_thinking {
@@ -36,6 +47,36 @@ pub fn anthropic_to_openai(
         tracing::debug!("Using reasoning_effort: {} for model: {}", effort, model);
     }
 
+    // metadata.user_id 映射为 OpenAI 的 user
+    let user = metadata_user_id(req.metadata.as_ref()).map(String::from);
+
+    let service_tier = req
+        .extra
+        .get("service_tier")
+        .and_then(|v| v.as_str())
+        .and_then(|tier| {
+            let mapped = openai_service_tier(tier);
+            if mapped.is_none() {
+                tracing::debug!("Dropping service_tier '{}' without an OpenAI equivalent", tier);
+            }
+            mapped
+        })
+        .map(String::from);
+
+    // Anthropic 协议没有 response_format，客户端可通过扩展字段请求 OpenAI JSON 模式；
+    // 未设置时系统提示包含 JSON_MODE_SYSTEM_MARKER 则强制 json_object
+    let response_format = response_format_extension(&req.extra, req.metadata.as_ref()).or_else(|| {
+        let marker = config.json_mode_system_marker.as_deref()?;
+        let matched = match &req.system {
+            Some(anthropic::SystemPrompt::Single(text)) => text.contains(marker),
+            Some(anthropic::SystemPrompt::Multiple(messages)) => {
+                messages.iter().any(|msg| msg.text.contains(marker))
+            }
+            None => false,
+        };
+        matched.then(|| json!({ "type": "json_object" }))
+    });
+
     // 转换消息
     let mut openai_messages = Vec::new();
 
@@ -49,6 +90,8 @@ pub fn anthropic_to_openai(
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    reasoning_content: None,
+                    function_call: None,
                 });
             }
             anthropic::SystemPrompt::Multiple(messages) => {
@@ -59,6 +102,8 @@ pub fn anthropic_to_openai(
                         tool_calls: None,
                         tool_call_id: None,
                         name: None,
+                        reasoning_content: None,
+                        function_call: None,
                     });
                 }
             }
@@ -67,53 +112,160 @@ pub fn anthropic_to_openai(
 
     // 转换用户/助手消息
     for msg in req.messages {
-        let converted = convert_message(msg)?;
+        let converted = convert_message(msg, config.thinking_in_history)?;
         openai_messages.extend(converted);
     }
 
     // 转换工具定义
-    let tools = req.tools.and_then(|tools| {
-        let filtered: Vec<_> = tools
+    let schema_options = SchemaOptions {
+        profile: config.schema_profile_for(backend),
+        collapse_nullable: config.schema_collapse_nullable,
+        strip_keywords: &config.schema_strip_keywords,
+    };
+    let filtered: Vec<_> = req
+        .tools
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|t| match t.tool_type.as_deref() {
+            Some(tool_type) if config.filtered_tool_types.iter().any(|f| f == tool_type) => {
+                tracing::debug!("Dropping tool '{}' of type '{}' with no OpenAI equivalent", t.name, tool_type);
+                false
+            }
+            _ => true,
+        })
+        .collect();
+    let filtered = if config.deduplicate_tools {
+        deduplicate_tools(filtered)
+    } else {
+        filtered
+    };
+    // 只限制实际发往上游的工具
+    let limits: Vec<_> = filtered
+        .iter()
+        .map(|t| (t.name.as_str(), &t.input_schema))
+        .collect();
+    check_tool_limits(&limits, config.max_tools, config.max_schema_depth)?;
+    let tools = (!filtered.is_empty()).then(|| {
+        filtered
             .into_iter()
-            .filter(|t| t.tool_type.as_deref() != Some("BatchTool"))
-            .collect();
-
-        if filtered.is_empty() {
-            None
-        } else {
-            Some(
-                filtered
-                    .into_iter()
-                    .map(|t| openai::Tool {
-                        tool_type: "function".to_string(),
-                        function: openai::Function {
-                            name: t.name,
-                            description: t.description,
-                            parameters: clean_schema(t.input_schema),
-                        },
-                    })
-                    .collect(),
-            )
-        }
+            .map(|t| convert_tool(t, &schema_options, config.tools_strict_mode, report))
+            .collect()
     });
 
+    // Anthropic 的 tool_choice.disable_parallel_tool_use 对应 OpenAI 的 parallel_tool_calls，
+    // OpenAI 只允许在有工具时设置
+    let parallel_tool_calls = req
+        .extra
+        .get("tool_choice")
+        .and_then(|choice| choice.get("disable_parallel_tool_use"))
+        .and_then(|disable| disable.as_bool())
+        .filter(|_| tools.is_some())
+        .map(|disable| !disable);
+
+    // 某些提供商要求最少 16 tokens，下限可通过 MIN_MAX_TOKENS 配置（0 表示不限制），
+    // 并按 MIN_MAX_TOKENS_PER_MODEL 对个别模型覆盖
+    let min_max_tokens = config.min_max_tokens_for(&model);
+    if req.max_tokens < min_max_tokens {
+        tracing::debug!(
+            "Raising max_tokens from {} to {} for model '{}'",
+            req.max_tokens,
+            min_max_tokens,
+            model
+        );
+    }
+
     Ok(openai::OpenAIRequest {
+        max_tokens: Some(req.max_tokens.max(min_max_tokens)),
         model,
         messages: openai_messages,
-        max_tokens: Some(req.max_tokens.max(16)), // 某些提供商要求最少 16 tokens
         temperature: req.temperature,
         top_p: req.top_p,
-        stop: req.stop_sequences,
+        stop: merge_stop_sequences(req.stop_sequences, &config.default_stop, Some(OPENAI_MAX_STOP)),
         stream: req.stream,
         tools,
         tool_choice: None,
+        parallel_tool_calls,
         reasoning_effort,
+        stream_options: None,
+        user,
+        service_tier,
+        logit_bias: None,
+        response_format,
+        store: None,
+        metadata: None,
+        functions: None,
+        function_call: None,
     })
 }
 
+/// 按名称去重工具定义：客户端在每轮对话中重复追加同一工具时，只保留最后（最新）的定义
+fn deduplicate_tools(tools: Vec<anthropic::Tool>) -> Vec<anthropic::Tool> {
+    let total = tools.len();
+    let mut seen = HashSet::new();
+    let mut deduplicated: Vec<_> = tools
+        .into_iter()
+        .rev()
+        .filter(|t| seen.insert(t.name.clone()))
+        .collect();
+    deduplicated.reverse();
+    if deduplicated.len() < total {
+        tracing::debug!("Removed {} duplicate tool definitions", total - deduplicated.len());
+    }
+    deduplicated
+}
+
+/// 转换单个工具定义，并按 TOOLS_STRICT_MODE 设置 strict
+fn convert_tool(
+    tool: anthropic::Tool,
+    schema_options: &SchemaOptions,
+    strict_mode: ToolsStrictMode,
+    report: &mut Vec<String>,
+) -> openai::Tool {
+    let parameters = sanitize_schema(tool.input_schema, schema_options);
+
+    let (parameters, strict) = match strict_mode {
+        ToolsStrictMode::Off => (parameters, None),
+        ToolsStrictMode::Auto => {
+            let compatible = is_strict_compatible(&parameters);
+            (parameters, compatible.then_some(true))
+        }
+        ToolsStrictMode::Force => {
+            if is_strict_compatible(&parameters) {
+                (parameters, Some(true))
+            } else {
+                report.push(format!("tool '{}' schema rewritten for strict mode", tool.name));
+                (enforce_strict_schema(parameters), Some(true))
+            }
+        }
+    };
+
+    openai::Tool {
+        tool_type: "function".to_string(),
+        function: openai::Function {
+            name: upstream_tool_name(&tool.name).into_owned(),
+            description: tool.description,
+            parameters,
+            strict,
+        },
+    }
+}
+
+/// 请求是否启用了 extended thinking
+pub fn has_thinking(req: &anthropic::AnthropicRequest) -> bool {
+    req.extra
+        .get("thinking")
+        .and_then(|v| v.as_object())
+        .map(|o| o.get("type").and_then(|t| t.as_str()) == Some("enabled"))
+        .unwrap_or(false)
+}
+
 /// 转换单条 Anthropic 消息为一条或多条 OpenAI 消息
-fn convert_message(msg: anthropic::Message) -> ProxyResult<Vec<openai::Message>> {
+fn convert_message(
+    msg: anthropic::Message,
+    thinking_in_history: ThinkingInHistory,
+) -> ProxyResult<Vec<openai::Message>> {
     let mut result = Vec::new();
+    let is_assistant = msg.role == "assistant";
 
     match msg.content {
         anthropic::MessageContent::Text(text) => {
@@ -123,11 +275,14 @@ fn convert_message(msg: anthropic::Message) -> ProxyResult<Vec<openai::Message>>
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_content: None,
+                function_call: None,
             });
         }
         anthropic::MessageContent::Blocks(blocks) => {
             let mut current_content_parts = Vec::new();
             let mut tool_calls = Vec::new();
+            let mut reasoning_parts: Vec<String> = Vec::new();
 
             for block in blocks {
                 match block {
@@ -135,22 +290,19 @@ fn convert_message(msg: anthropic::Message) -> ProxyResult<Vec<openai::Message>>
                         current_content_parts.push(openai::ContentPart::Text { text });
                     }
                     anthropic::ContentBlock::Image { source } => {
-                        let data_url = format!(
-                            "data:{};base64,{}",
-                            source.media_type, source.data
-                        );
                         current_content_parts.push(openai::ContentPart::ImageUrl {
-                            image_url: openai::ImageUrl { url: data_url },
+                            image_url: openai::ImageUrl { url: source.to_url() },
                         });
                     }
                     anthropic::ContentBlock::ToolUse { id, name, input } => {
                         tool_calls.push(openai::ToolCall {
+                            index: None,
                             id,
                             call_type: "function".to_string(),
                             function: openai::FunctionCall {
-                                name,
+                                name: upstream_tool_name(&name).into_owned(),
                                 arguments: serde_json::to_string(&input)
-                                    .map_err(|e| ProxyError::Serialization(e))?,
+                                    .map_err(ProxyError::Serialization)?,
                             },
                         });
                     }
@@ -166,16 +318,38 @@ fn convert_message(msg: anthropic::Message) -> ProxyResult<Vec<openai::Message>>
                             tool_calls: None,
                             tool_call_id: Some(tool_use_id),
                             name: None,
+                            reasoning_content: None,
+                            function_call: None,
                         });
                     }
-                    anthropic::ContentBlock::Thinking { .. } => {
-                        // 跳过 thinking 块
+                    anthropic::ContentBlock::Thinking { thinking } => {
+                        // 仅 assistant 历史中的 thinking 需要保留，其余情况跳过
+                        match (is_assistant, thinking_in_history) {
+                            (true, ThinkingInHistory::Wrap) => {
+                                current_content_parts.push(openai::ContentPart::Text {
+                                    text: format!("<thinking>{}</thinking>", thinking),
+                                });
+                            }
+                            (true, ThinkingInHistory::Passthrough) => {
+                                reasoning_parts.push(thinking);
+                            }
+                            _ => {}
+                        }
                     }
                 }
             }
 
-            // 添加包含内容和/或工具调用的消息
-            if !current_content_parts.is_empty() || !tool_calls.is_empty() {
+            let reasoning_content = if reasoning_parts.is_empty() {
+                None
+            } else {
+                Some(reasoning_parts.join("\n"))
+            };
+
+            // 添加包含内容、工具调用和/或推理内容的消息
+            if !current_content_parts.is_empty()
+                || !tool_calls.is_empty()
+                || reasoning_content.is_some()
+            {
                 let content = if current_content_parts.is_empty() {
                     None
                 } else if current_content_parts.len() == 1 {
@@ -199,6 +373,8 @@ fn convert_message(msg: anthropic::Message) -> ProxyResult<Vec<openai::Message>>
                     },
                     tool_call_id: None,
                     name: None,
+                    reasoning_content,
+                    function_call: None,
                 });
             }
         }
@@ -210,23 +386,15 @@ fn convert_message(msg: anthropic::Message) -> ProxyResult<Vec<openai::Message>>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use crate::test_utils::test_config;
+    use serde_json::{json, Value};
 
     fn create_test_config() -> Config {
         Config {
-            port: 3000,
             routing_mode: crate::config::RoutingMode::Transform,
-            anthropic_base_url: None,
-            anthropic_api_key: None,
-            openai_base_url: None,
-            openai_api_key: None,
             base_url: Some("https://api.example.com".to_string()),
             api_key: Some("test-key".to_string()),
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         }
     }
 
@@ -248,10 +416,11 @@ mod tests {
             stream: None,
             tools: None,
             metadata: None,
+            container: None,
             extra: json!({}),
         };
 
-        let result = anthropic_to_openai(req, &config).unwrap();
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
         
         assert_eq!(result.model, "claude-3-sonnet");
         assert_eq!(result.messages.len(), 1);
@@ -276,10 +445,11 @@ mod tests {
             stream: None,
             tools: None,
             metadata: None,
+            container: None,
             extra: json!({}),
         };
 
-        let result = anthropic_to_openai(req, &config).unwrap();
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
         
         assert_eq!(result.messages.len(), 2);
         assert_eq!(result.messages[0].role, "system");
@@ -314,10 +484,11 @@ mod tests {
                 tool_type: None,
             }]),
             metadata: None,
+            container: None,
             extra: json!({}),
         };
 
-        let result = anthropic_to_openai(req, &config).unwrap();
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
         
         assert!(result.tools.is_some());
         let tools = result.tools.unwrap();
@@ -345,11 +516,518 @@ mod tests {
             stream: None,
             tools: None,
             metadata: None,
+            container: None,
             extra: json!({"thinking": {"type": "enabled"}}),
         };
 
-        let result = anthropic_to_openai(req, &config).unwrap();
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
         
         assert_eq!(result.model, "gpt-4-turbo");
     }
+
+    #[test]
+    fn test_min_max_tokens_default_bumps_to_16() {
+        let config = create_test_config();
+        let req = anthropic::AnthropicRequest {
+            model: "claude-3-sonnet".to_string(),
+            messages: vec![anthropic::Message {
+                role: "user".to_string(),
+                content: anthropic::MessageContent::Text("Hi".to_string()),
+            }],
+            max_tokens: 1,
+            system: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: None,
+            tools: None,
+            metadata: None,
+            container: None,
+            extra: json!({}),
+        };
+
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
+
+        assert_eq!(result.max_tokens, Some(16));
+    }
+
+    #[test]
+    fn test_min_max_tokens_configured_preserves_request() {
+        let mut config = create_test_config();
+        config.min_max_tokens = 1;
+        let req = anthropic::AnthropicRequest {
+            model: "claude-3-sonnet".to_string(),
+            messages: vec![anthropic::Message {
+                role: "user".to_string(),
+                content: anthropic::MessageContent::Text("Hi".to_string()),
+            }],
+            max_tokens: 1,
+            system: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: None,
+            tools: None,
+            metadata: None,
+            container: None,
+            extra: json!({}),
+        };
+
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
+
+        assert_eq!(result.max_tokens, Some(1));
+    }
+
+    #[test]
+    fn test_default_stop_capped_at_four() {
+        let mut config = create_test_config();
+        config.default_stop = vec!["</tool>".to_string(), "END".to_string()];
+        let req = anthropic::AnthropicRequest {
+            model: "claude-3-sonnet".to_string(),
+            messages: vec![anthropic::Message {
+                role: "user".to_string(),
+                content: anthropic::MessageContent::Text("Hi".to_string()),
+            }],
+            max_tokens: 100,
+            system: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: Some(vec!["a".to_string(), "b".to_string(), "c".to_string()]),
+            stream: None,
+            tools: None,
+            metadata: None,
+            container: None,
+            extra: json!({}),
+        };
+
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
+
+        assert_eq!(
+            result.stop,
+            Some(vec!["a", "b", "c", "</tool>"].into_iter().map(String::from).collect())
+        );
+    }
+
+    #[test]
+    fn test_min_max_tokens_per_model() {
+        let mut config = create_test_config();
+        config.min_max_tokens_per_model = crate::config::parse_min_max_tokens_per_model("mistral-large=100");
+        let request = |model: &str| anthropic::AnthropicRequest {
+            model: model.to_string(),
+            messages: vec![anthropic::Message {
+                role: "user".to_string(),
+                content: anthropic::MessageContent::Text("Hi".to_string()),
+            }],
+            max_tokens: 1,
+            system: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: None,
+            tools: None,
+            metadata: None,
+            container: None,
+            extra: json!({}),
+        };
+        let max_tokens = |model: &str| {
+            anthropic_to_openai(request(model), &config, Backend::Upstream, &mut Vec::new())
+                .unwrap()
+                .max_tokens
+        };
+
+        assert_eq!(max_tokens("o1"), Some(1));
+        assert_eq!(max_tokens("openai/o3-mini"), Some(1));
+        assert_eq!(max_tokens("Mistral-Large-2411"), Some(100));
+        // 只匹配完整名称或 `名称-` 开头的变体
+        assert_eq!(max_tokens("o10"), Some(16));
+        assert_eq!(max_tokens("gpt-4o"), Some(16));
+    }
+
+    fn search_tool_request() -> anthropic::AnthropicRequest {
+        anthropic::AnthropicRequest {
+            model: "claude-3-sonnet".to_string(),
+            messages: vec![anthropic::Message {
+                role: "user".to_string(),
+                content: anthropic::MessageContent::Text("Search for rust".to_string()),
+            }],
+            max_tokens: 100,
+            system: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: None,
+            tools: Some(vec![anthropic::Tool {
+                name: "search".to_string(),
+                description: None,
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "query": {"type": "string"},
+                        "limit": {"type": "integer"}
+                    },
+                    "required": ["query"]
+                }),
+                tool_type: None,
+            }]),
+            metadata: None,
+            container: None,
+            extra: json!({}),
+        }
+    }
+
+    #[test]
+    fn test_tools_strict_mode_off_by_default() {
+        let config = create_test_config();
+
+        let result = anthropic_to_openai(search_tool_request(), &config, Backend::Upstream, &mut Vec::new()).unwrap();
+
+        let function = &result.tools.unwrap()[0].function;
+        assert_eq!(function.strict, None);
+        assert!(function.parameters.get("additionalProperties").is_none());
+    }
+
+    #[test]
+    fn test_tools_strict_mode_auto_skips_incompatible_schema() {
+        let mut config = create_test_config();
+        config.tools_strict_mode = ToolsStrictMode::Auto;
+
+        let mut report = Vec::new();
+        let result = anthropic_to_openai(search_tool_request(), &config, Backend::Upstream, &mut report).unwrap();
+
+        assert_eq!(result.tools.unwrap()[0].function.strict, None);
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_tools_strict_mode_force_rewrites_schema() {
+        let mut config = create_test_config();
+        config.tools_strict_mode = ToolsStrictMode::Force;
+
+        let mut report = Vec::new();
+        let result = anthropic_to_openai(search_tool_request(), &config, Backend::Upstream, &mut report).unwrap();
+
+        let function = &result.tools.unwrap()[0].function;
+        assert_eq!(function.strict, Some(true));
+        assert_eq!(function.parameters["additionalProperties"], false);
+        assert_eq!(function.parameters["required"], json!(["limit", "query"]));
+        assert_eq!(report, vec!["tool 'search' schema rewritten for strict mode"]);
+
+        // 已满足 strict 要求的 schema 不需要改写，也不记录
+        let mut report = Vec::new();
+        let mut req = search_tool_request();
+        req.tools = Some(vec![anthropic::Tool {
+            input_schema: function.parameters.clone(),
+            ..req.tools.unwrap().remove(0)
+        }]);
+        anthropic_to_openai(req, &config, Backend::Upstream, &mut report).unwrap();
+        assert!(report.is_empty());
+    }
+
+    fn assistant_with_thinking() -> anthropic::Message {
+        anthropic::Message {
+            role: "assistant".to_string(),
+            content: anthropic::MessageContent::Blocks(vec![
+                anthropic::ContentBlock::Thinking {
+                    thinking: "Let me reason".to_string(),
+                },
+                anthropic::ContentBlock::Text {
+                    text: "Answer".to_string(),
+                    cache_control: None,
+                },
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_thinking_in_history_strip() {
+        let result = convert_message(assistant_with_thinking(), ThinkingInHistory::Strip).unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert!(matches!(
+            &result[0].content,
+            Some(openai::MessageContent::Text(text)) if text == "Answer"
+        ));
+        assert_eq!(result[0].reasoning_content, None);
+    }
+
+    #[test]
+    fn test_thinking_in_history_wrap() {
+        let result = convert_message(assistant_with_thinking(), ThinkingInHistory::Wrap).unwrap();
+
+        match &result[0].content {
+            Some(openai::MessageContent::Parts(parts)) => {
+                assert_eq!(parts.len(), 2);
+                assert!(matches!(
+                    &parts[0],
+                    openai::ContentPart::Text { text } if text == "<thinking>Let me reason</thinking>"
+                ));
+            }
+            _ => panic!("Expected content parts"),
+        }
+    }
+
+    #[test]
+    fn test_thinking_in_history_passthrough() {
+        let result =
+            convert_message(assistant_with_thinking(), ThinkingInHistory::Passthrough).unwrap();
+
+        assert_eq!(result[0].reasoning_content, Some("Let me reason".to_string()));
+    }
+
+    #[test]
+    fn test_thinking_in_user_message_always_stripped() {
+        let mut msg = assistant_with_thinking();
+        msg.role = "user".to_string();
+
+        let result = convert_message(msg, ThinkingInHistory::Wrap).unwrap();
+
+        assert!(matches!(
+            &result[0].content,
+            Some(openai::MessageContent::Text(text)) if text == "Answer"
+        ));
+    }
+
+    #[test]
+    fn test_schema_profile_selected_per_backend() {
+        let mut config = create_test_config();
+        config.openai_schema_profile = crate::config::SchemaProfile::Aggressive;
+        config.schema_strip_keywords = vec!["const".to_string()];
+        let mut req = search_tool_request();
+        req.tools.as_mut().unwrap()[0].input_schema = json!({
+            "type": "object",
+            "properties": {"mode": {"type": "string", "const": "fast"}}
+        });
+
+        let upstream = anthropic_to_openai(req.clone(), &config, Backend::Upstream, &mut Vec::new()).unwrap();
+        let openai = anthropic_to_openai(req, &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+
+        let upstream_params = &upstream.tools.unwrap()[0].function.parameters;
+        let openai_params = &openai.tools.unwrap()[0].function.parameters;
+        assert_eq!(upstream_params["properties"]["mode"]["const"], "fast");
+        assert!(openai_params["properties"]["mode"].get("const").is_none());
+    }
+
+    #[test]
+    fn test_metadata_user_id_mapped_to_user() {
+        let config = create_test_config();
+        let req: anthropic::AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "metadata": {"user_id": "user-42"}
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
+        assert_eq!(result.user.as_deref(), Some("user-42"));
+    }
+
+    #[test]
+    fn test_filtered_tool_types_dropped() {
+        let mut config = create_test_config();
+        config.filtered_tool_types = DEFAULT_FILTERED_TOOL_TYPES.iter().map(|t| t.to_string()).collect();
+        let req: anthropic::AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "tools": [
+                {"type": "computer_20250124", "name": "computer", "display_width_px": 1024, "display_height_px": 768},
+                {"type": "bash_20250124", "name": "bash"},
+                {"name": "lookup", "input_schema": {"type": "object"}}
+            ]
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(req.clone(), &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+        let names: Vec<_> = result.tools.unwrap().into_iter().map(|t| t.function.name).collect();
+        assert_eq!(names, vec!["lookup"]);
+
+        config.filtered_tool_types = vec!["bash_20250124".to_string()];
+        let result = anthropic_to_openai(req, &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+        let names: Vec<_> = result.tools.unwrap().into_iter().map(|t| t.function.name).collect();
+        assert_eq!(names, vec!["computer", "lookup"]);
+    }
+
+    #[test]
+    fn test_max_tools_exceeded() {
+        let mut config = create_test_config();
+        config.max_tools = Some(2);
+        let req: anthropic::AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "tools": [
+                {"name": "a", "input_schema": {"type": "object"}},
+                {"name": "b", "input_schema": {"type": "object"}},
+                {"type": "BatchTool", "name": "batch", "input_schema": {"type": "object"}},
+                {"name": "c", "input_schema": {"type": "object"}}
+            ]
+        }))
+        .unwrap();
+
+        match anthropic_to_openai(req.clone(), &config, Backend::OpenAI, &mut Vec::new()) {
+            Err(ProxyError::Transform(message)) => {
+                assert_eq!(message, "Request has 3 tools, more than MAX_TOOLS=2")
+            }
+            other => panic!("expected transform error, got {:?}", other),
+        }
+
+        // 被过滤的工具不计入
+        config.max_tools = Some(3);
+        let result = anthropic_to_openai(req, &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+        assert_eq!(result.tools.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_duplicate_tools_keep_last_definition() {
+        let mut config = create_test_config();
+        let req: anthropic::AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "tools": [
+                {"name": "read", "description": "v1", "input_schema": {"type": "object"}},
+                {"name": "write", "input_schema": {"type": "object"}},
+                {"name": "read", "description": "v2", "input_schema": {"type": "object"}}
+            ]
+        }))
+        .unwrap();
+
+        let tools = anthropic_to_openai(req.clone(), &config, Backend::OpenAI, &mut Vec::new()).unwrap().tools.unwrap();
+        let names: Vec<_> = tools.iter().map(|t| t.function.name.as_str()).collect();
+        assert_eq!(names, vec!["write", "read"]);
+        assert_eq!(tools[1].function.description.as_deref(), Some("v2"));
+
+        // 去重后的数量才受 MAX_TOOLS 限制
+        config.max_tools = Some(2);
+        assert!(anthropic_to_openai(req.clone(), &config, Backend::OpenAI, &mut Vec::new()).is_ok());
+
+        config.max_tools = None;
+        config.deduplicate_tools = false;
+        let tools = anthropic_to_openai(req, &config, Backend::OpenAI, &mut Vec::new()).unwrap().tools.unwrap();
+        assert_eq!(tools.len(), 3);
+    }
+
+    #[test]
+    fn test_service_tier_mapped_to_openai() {
+        let config = create_test_config();
+        let request = |tier: &str| -> anthropic::AnthropicRequest {
+            serde_json::from_value(json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}],
+                "service_tier": tier
+            }))
+            .unwrap()
+        };
+
+        for (tier, expected) in [("auto", Some("auto")), ("standard_only", Some("default")), ("batch", None)] {
+            let result = anthropic_to_openai(request(tier), &config, Backend::Upstream, &mut Vec::new()).unwrap();
+            assert_eq!(result.service_tier.as_deref(), expected, "{}", tier);
+        }
+
+        let result = anthropic_to_openai(request("standard_only"), &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+        assert_eq!(serde_json::to_value(&result).unwrap()["service_tier"], "default");
+    }
+
+    #[test]
+    fn test_response_format_extension_carried_to_openai() {
+        let mut config = create_test_config();
+        let request = |extension: Value| -> anthropic::AnthropicRequest {
+            let mut body = json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 100,
+                "system": "Reply with a JSON object. [json-mode]",
+                "messages": [{"role": "user", "content": "Hello"}]
+            });
+            body.as_object_mut().unwrap().extend(extension.as_object().unwrap().clone());
+            serde_json::from_value(body).unwrap()
+        };
+        let schema = json!({"type": "json_schema", "json_schema": {"name": "answer", "schema": {"type": "object"}}});
+
+        let result = anthropic_to_openai(request(json!({"response_format": schema})), &config, Backend::OpenAI, &mut Vec::new())
+            .unwrap();
+        assert_eq!(serde_json::to_value(&result).unwrap()["response_format"], schema);
+
+        let result = anthropic_to_openai(
+            request(json!({"metadata": {"response_format": {"type": "json_object"}}})),
+            &config,
+            Backend::OpenAI,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(result.response_format, Some(json!({"type": "json_object"})));
+
+        let result = anthropic_to_openai(request(json!({})), &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+        assert!(serde_json::to_value(&result).unwrap().get("response_format").is_none());
+
+        // 系统提示包含标记时强制 JSON 模式，显式的扩展字段优先
+        config.json_mode_system_marker = Some("[json-mode]".to_string());
+        let result = anthropic_to_openai(request(json!({})), &config, Backend::OpenAI, &mut Vec::new()).unwrap();
+        assert_eq!(result.response_format, Some(json!({"type": "json_object"})));
+        let result = anthropic_to_openai(request(json!({"response_format": schema})), &config, Backend::OpenAI, &mut Vec::new())
+            .unwrap();
+        assert_eq!(result.response_format, Some(schema));
+    }
+
+    #[test]
+    fn test_disable_parallel_tool_use_mapped_to_openai() {
+        let config = create_test_config();
+        let request = |tool_choice: Value, tools: bool| -> anthropic::AnthropicRequest {
+            let mut body = json!({
+                "model": "claude-3-sonnet",
+                "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hello"}],
+                "tool_choice": tool_choice
+            });
+            if tools {
+                body["tools"] = json!([{"name": "get_weather", "input_schema": {"type": "object"}}]);
+            }
+            serde_json::from_value(body).unwrap()
+        };
+
+        for (tool_choice, expected) in [
+            (json!({"type": "auto", "disable_parallel_tool_use": true}), Some(false)),
+            (json!({"type": "any", "disable_parallel_tool_use": false}), Some(true)),
+            (json!({"type": "auto"}), None),
+        ] {
+            let result = anthropic_to_openai(request(tool_choice.clone(), true), &config, Backend::OpenAI, &mut Vec::new())
+                .unwrap();
+            assert_eq!(result.parallel_tool_calls, expected, "{}", tool_choice);
+        }
+
+        // OpenAI 不允许没有工具时设置 parallel_tool_calls
+        let result = anthropic_to_openai(
+            request(json!({"type": "auto", "disable_parallel_tool_use": true}), false),
+            &config,
+            Backend::OpenAI,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(serde_json::to_value(&result).unwrap().get("parallel_tool_calls").is_none());
+    }
+
+    #[test]
+    fn test_image_sources_converted_to_image_urls() {
+        let config = create_test_config();
+        let req: anthropic::AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": [
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}}
+            ]}]
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(req, &config, Backend::Upstream, &mut Vec::new()).unwrap();
+        let message = serde_json::to_value(&result.messages[0]).unwrap();
+        assert_eq!(message["content"][0]["image_url"]["url"], "data:image/png;base64,iVBORw0KGgo=");
+        assert_eq!(message["content"][1]["image_url"]["url"], "https://example.com/cat.png");
+    }
 }
@@ -1,39 +1,97 @@
 //! OpenAI 请求转换为 Anthropic 格式
 
-use crate::config::Config;
-use crate::error::ProxyResult;
+use crate::config::{Config, DeveloperMessageHandling};
+use crate::error::{ProxyError, ProxyResult};
+use crate::monitor::TransformFailure;
 use crate::models::{anthropic, openai};
+use crate::transform::utils::{
+    anthropic_service_tier, check_tool_limits, merge_stop_sequences, parse_data_url,
+};
 use serde_json::{json, Value};
 
+/// 消息内容中的文本，多个文本部分以换行连接，其他类型的部分被忽略
+fn content_text(content: &openai::MessageContent) -> String {
+    match content {
+        openai::MessageContent::Text(t) => t.clone(),
+        openai::MessageContent::Parts(parts) => parts
+            .iter()
+            .filter_map(|p| match p {
+                openai::ContentPart::Text { text } => Some(text.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// 系统消息中的图片部分，Anthropic 的 system 只接受文本
+fn content_images(content: &openai::MessageContent) -> Vec<anthropic::ContentBlock> {
+    let openai::MessageContent::Parts(parts) = content else {
+        return Vec::new();
+    };
+    parts
+        .iter()
+        .filter_map(|p| match p {
+            openai::ContentPart::ImageUrl { image_url } => image_source(&image_url.url),
+            _ => None,
+        })
+        .map(|source| anthropic::ContentBlock::Image { source })
+        .collect()
+}
+
+/// 把一条系统级消息的文本追加到 system 提示中；为空的一侧不产生分隔符
+fn append_system_text(system_prompt: &mut Option<anthropic::SystemPrompt>, text: String, separator: &str) {
+    let combined = match system_prompt.take() {
+        Some(anthropic::SystemPrompt::Single(existing)) if !existing.is_empty() && !text.is_empty() => {
+            format!("{}{}{}", existing, separator, text)
+        }
+        Some(anthropic::SystemPrompt::Single(existing)) if text.is_empty() => existing,
+        _ => text,
+    };
+    *system_prompt = Some(anthropic::SystemPrompt::Single(combined));
+}
+
 /// 将 OpenAI 请求转换为 Anthropic 格式
+///
+/// system（及作为 system 处理的 developer）消息中的图片无法放入 Anthropic 的 system 字段，
+/// 会按原顺序移到第一条 user 消息的开头；没有 user 消息时在最前面插入一条
 pub fn openai_to_anthropic_request(
-    req: openai::OpenAIRequest,
+    mut req: openai::OpenAIRequest,
     config: &Config,
 ) -> ProxyResult<anthropic::AnthropicRequest> {
+    normalize_legacy_functions(&mut req);
     let mut messages = Vec::new();
     let mut system_prompt = None;
+    let mut system_images = Vec::new();
 
     for msg in req.messages {
         match msg.role.as_str() {
             "system" => {
-                // 收集系统消息
+                // 收集系统消息，多条系统消息按顺序拼接
                 if let Some(content) = &msg.content {
-                    let text = match content {
-                        openai::MessageContent::Text(t) => t.clone(),
-                        openai::MessageContent::Parts(parts) => {
-                            parts
-                                .iter()
-                                .filter_map(|p| match p {
-                                    openai::ContentPart::Text { text } => Some(text.clone()),
-                                    _ => None,
-                                })
-                                .collect::<Vec<_>>()
-                                .join("\n")
-                        }
-                    };
-                    system_prompt = Some(anthropic::SystemPrompt::Single(text));
+                    append_system_text(&mut system_prompt, content_text(content), "\n\n");
+                    system_images.extend(content_images(content));
                 }
             }
+            // OpenAI 新增的 developer 角色，与 system 一样承载系统级指令
+            "developer" => match config.developer_message_handling {
+                DeveloperMessageHandling::AsSystem => {
+                    if let Some(content) = &msg.content {
+                        append_system_text(&mut system_prompt, content_text(content), "\n\n---\n");
+                        system_images.extend(content_images(content));
+                    }
+                }
+                DeveloperMessageHandling::AsUser => {
+                    let content = convert_openai_message_content(&msg)?;
+                    messages.push(anthropic::Message {
+                        role: "user".to_string(),
+                        content,
+                    });
+                }
+                DeveloperMessageHandling::Drop => {
+                    tracing::debug!("Dropping developer message (DEVELOPER_MESSAGE_HANDLING=drop)");
+                }
+            },
             "user" | "assistant" => {
                 let content = convert_openai_message_content(&msg)?;
                 messages.push(anthropic::Message {
@@ -44,25 +102,12 @@ pub fn openai_to_anthropic_request(
             "tool" => {
                 // 工具结果转换为 ToolResult 内容块
                 if let (Some(content), Some(tool_call_id)) = (&msg.content, &msg.tool_call_id) {
-                    let text = match content {
-                        openai::MessageContent::Text(t) => t.clone(),
-                        openai::MessageContent::Parts(parts) => {
-                            parts
-                                .iter()
-                                .filter_map(|p| match p {
-                                    openai::ContentPart::Text { text } => Some(text.clone()),
-                                    _ => None,
-                                })
-                                .collect::<Vec<_>>()
-                                .join("\n")
-                        }
-                    };
                     messages.push(anthropic::Message {
                         role: "user".to_string(),
                         content: anthropic::MessageContent::Blocks(vec![
                             anthropic::ContentBlock::ToolResult {
                                 tool_use_id: tool_call_id.clone(),
-                                content: anthropic::ToolResultContent::Text(text),
+                                content: convert_tool_result_content(content),
                                 is_error: None,
                             },
                         ]),
@@ -75,7 +120,43 @@ pub fn openai_to_anthropic_request(
         }
     }
 
+    if !system_images.is_empty() {
+        tracing::debug!(
+            "Moving {} system image(s) into the first user message",
+            system_images.len()
+        );
+        let images = anthropic::MessageContent::Blocks(system_images);
+        match messages.iter_mut().find(|m| m.role == "user") {
+            Some(first_user) => {
+                let content = std::mem::replace(
+                    &mut first_user.content,
+                    anthropic::MessageContent::Text(String::new()),
+                );
+                first_user.content = merge_message_content(images, content);
+            }
+            None => messages.insert(
+                0,
+                anthropic::Message {
+                    role: "user".to_string(),
+                    content: images,
+                },
+            ),
+        }
+    }
+
+    // Anthropic 不允许连续的同角色消息
+    if config.merge_consecutive_messages {
+        messages = merge_consecutive_same_role(messages);
+    }
+
     // 转换工具定义
+    if let Some(tools) = &req.tools {
+        let limits: Vec<_> = tools
+            .iter()
+            .map(|t| (t.function.name.as_str(), &t.function.parameters))
+            .collect();
+        check_tool_limits(&limits, config.max_tools, config.max_schema_depth)?;
+    }
     let tools = req.tools.map(|tools| {
         tools
             .into_iter()
@@ -94,6 +175,52 @@ pub fn openai_to_anthropic_request(
         .clone()
         .unwrap_or_else(|| req.model.clone());
 
+    // 配置的固定 user_id 优先，其次是 OpenAI 请求中的 user 和 metadata.user_id；
+    // Anthropic 的 metadata 只接受 user_id，其余键被丢弃
+    if let Some(metadata) = req.metadata.as_ref().and_then(Value::as_object) {
+        let dropped: Vec<&str> = metadata
+            .keys()
+            .map(String::as_str)
+            .filter(|key| *key != "user_id")
+            .collect();
+        if !dropped.is_empty() {
+            tracing::debug!("Dropping metadata keys without an Anthropic equivalent: {}", dropped.join(", "));
+        }
+    }
+    let metadata = config
+        .anthropic_metadata_user_id
+        .clone()
+        .or_else(|| req.user.clone())
+        .or_else(|| {
+            req.metadata
+                .as_ref()
+                .and_then(|m| m.get("user_id"))
+                .and_then(|id| id.as_str())
+                .map(String::from)
+        })
+        .map(|user_id| json!({ "user_id": user_id }));
+
+    let service_tier = req.service_tier.as_deref().and_then(|tier| {
+        let mapped = anthropic_service_tier(tier);
+        if mapped.is_none() {
+            tracing::debug!("Dropping service_tier '{}' without an Anthropic equivalent", tier);
+        }
+        mapped
+    });
+
+    let mut extra = serde_json::Map::new();
+    if let Some(tier) = service_tier {
+        extra.insert("service_tier".to_string(), json!(tier));
+    }
+    // parallel_tool_calls: false 对应 Anthropic 的 tool_choice.disable_parallel_tool_use，
+    // Anthropic 不允许没有工具时设置 tool_choice
+    if req.parallel_tool_calls == Some(false) && tools.is_some() {
+        extra.insert(
+            "tool_choice".to_string(),
+            json!({ "type": "auto", "disable_parallel_tool_use": true }),
+        );
+    }
+
     Ok(anthropic::AnthropicRequest {
         model,
         messages,
@@ -102,14 +229,157 @@ pub fn openai_to_anthropic_request(
         temperature: req.temperature,
         top_p: req.top_p,
         top_k: None,
-        stop_sequences: req.stop,
+        stop_sequences: merge_stop_sequences(req.stop, &config.default_stop, None),
         stream: req.stream,
         tools,
-        metadata: None,
-        extra: serde_json::Value::Null,
+        metadata,
+        container: None,
+        extra: if extra.is_empty() {
+            Value::Null
+        } else {
+            Value::Object(extra)
+        },
     })
 }
 
+/// 旧版函数调用 API 转换为 tools 形式：`functions` → `tools`，`function_call` → `tool_choice`，
+/// 历史中 assistant 的 `function_call` 补上调用 id，`function` 角色的结果改为对应的 `tool` 消息
+fn normalize_legacy_functions(req: &mut openai::OpenAIRequest) {
+    if let Some(functions) = req.functions.take() {
+        if req.tools.is_none() {
+            req.tools = Some(
+                functions
+                    .into_iter()
+                    .map(|function| openai::Tool {
+                        tool_type: "function".to_string(),
+                        function,
+                    })
+                    .collect(),
+            );
+        }
+    }
+    if let Some(function_call) = req.function_call.take() {
+        if req.tool_choice.is_none() {
+            // `{"name": ...}` 指定函数，`"auto"`/`"none"` 含义不变
+            req.tool_choice = Some(match function_call {
+                Value::Object(function) => json!({ "type": "function", "function": function }),
+                other => other,
+            });
+        }
+    }
+
+    let mut last_call_id = None;
+    for (i, msg) in req.messages.iter_mut().enumerate() {
+        if let Some(function_call) = msg.function_call.take() {
+            if msg.tool_calls.is_none() {
+                let id = format!("call_legacy_{}", i);
+                msg.tool_calls = Some(vec![openai::ToolCall {
+                    index: None,
+                    id: id.clone(),
+                    call_type: "function".to_string(),
+                    function: function_call,
+                }]);
+                last_call_id = Some(id);
+            }
+        }
+        if msg.role == "function" {
+            msg.role = "tool".to_string();
+            if msg.tool_call_id.is_none() {
+                msg.tool_call_id = last_call_id.take();
+            }
+        }
+    }
+}
+
+/// 只有 OpenAI 支持、转换到 Anthropic 时会被丢弃的请求字段
+pub const OPENAI_ONLY_FIELDS: &[&str] = &["logit_bias", "prediction", "audio", "modalities", "store"];
+
+/// 原始请求中设置了的 OpenAI 专有字段（null、false 与空对象/数组视为未设置）
+pub fn unsupported_fields(raw: &Value) -> Vec<&'static str> {
+    OPENAI_ONLY_FIELDS
+        .iter()
+        .copied()
+        .filter(|field| match raw.get(field) {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+            Some(Value::Object(map)) => !map.is_empty(),
+            Some(Value::Array(items)) => !items.is_empty(),
+            Some(_) => true,
+        })
+        .collect()
+}
+
+/// 检查转换到 Anthropic 时会被丢弃的字段：STRICT_PARAMS 下以 400 拒绝并列出这些字段，
+/// 否则记录警告并写入转换报告
+pub fn check_unsupported_fields(
+    raw: &Value,
+    strict: bool,
+    report: &mut Vec<String>,
+) -> ProxyResult<()> {
+    let fields = unsupported_fields(raw);
+    if fields.is_empty() {
+        return Ok(());
+    }
+    if strict {
+        return Err(ProxyError::transform(TransformFailure::UnsupportedBlock, format!(
+            "Unsupported parameters for the Anthropic backend: {}",
+            fields.join(", ")
+        )));
+    }
+    for field in fields {
+        tracing::warn!(
+            "Dropping '{}' from OpenAI request: not supported by the Anthropic API",
+            field
+        );
+        report.push(format!("dropped '{}': not supported by Anthropic", field));
+    }
+    Ok(())
+}
+
+/// 音频输入的文本占位（AUDIO_INPUT_PLACEHOLDER）
+pub const AUDIO_PLACEHOLDER_TEXT: &str = "[audio attachment omitted]";
+
+/// 处理 Anthropic 无法表示的内容类型：音频输入默认以 400 拒绝，
+/// 开启 AUDIO_INPUT_PLACEHOLDER 时替换为文本占位；未识别的类型被丢弃。两者都写入转换报告
+pub fn check_content_parts(
+    req: &mut openai::OpenAIRequest,
+    audio_placeholder: bool,
+    report: &mut Vec<String>,
+) -> ProxyResult<()> {
+    let mut audio_parts = 0;
+    for msg in &mut req.messages {
+        let Some(openai::MessageContent::Parts(parts)) = &mut msg.content else {
+            continue;
+        };
+        for part in parts.iter_mut() {
+            if let openai::ContentPart::InputAudio { .. } = part {
+                if !audio_placeholder {
+                    return Err(ProxyError::UnsupportedOperation(
+                        "Audio input (input_audio) is not supported for Anthropic backends".into(),
+                    ));
+                }
+                *part = openai::ContentPart::Text {
+                    text: AUDIO_PLACEHOLDER_TEXT.to_string(),
+                };
+                audio_parts += 1;
+            }
+        }
+        parts.retain(|part| {
+            let openai::ContentPart::Unknown(value) = part else {
+                return true;
+            };
+            let part_type = value.get("type").and_then(Value::as_str).unwrap_or("unknown");
+            tracing::warn!("Dropping content part of type '{}': not supported by Anthropic", part_type);
+            report.push(format!("dropped content part of type '{}'", part_type));
+            false
+        });
+    }
+    if audio_parts > 0 {
+        tracing::warn!("Replaced {} audio input part(s) with a text placeholder", audio_parts);
+        report.push(format!("replaced {} audio input part(s) with a text placeholder", audio_parts));
+    }
+    Ok(())
+}
+
 /// 转换 OpenAI 消息内容为 Anthropic 格式
 fn convert_openai_message_content(
     msg: &openai::Message,
@@ -137,26 +407,28 @@ fn convert_openai_message_content(
                             });
                         }
                         openai::ContentPart::ImageUrl { image_url } => {
-                            // 解析 data URL
-                            if let Some((media_type, data)) = parse_data_url(&image_url.url) {
-                                blocks.push(anthropic::ContentBlock::Image {
-                                    source: anthropic::ImageSource {
-                                        source_type: "base64".to_string(),
-                                        media_type,
-                                        data,
-                                    },
-                                });
+                            if let Some(source) = image_source(&image_url.url) {
+                                blocks.push(anthropic::ContentBlock::Image { source });
                             }
                         }
+                        // 已由 check_content_parts 拒绝、替换或丢弃
+                        openai::ContentPart::InputAudio { .. } | openai::ContentPart::Unknown(_) => {}
                     }
                 }
             }
         }
     }
 
-    // 处理工具调用（assistant 消息）
+    // 处理工具调用（assistant 消息），有 index 时按 index 排序，保证与工具结果的配对顺序
     if let Some(tool_calls) = &msg.tool_calls {
-        for tool_call in tool_calls {
+        let mut ordered: Vec<(usize, &openai::ToolCall)> = tool_calls
+            .iter()
+            .enumerate()
+            .map(|(position, tool_call)| (tool_call.index.unwrap_or(position), tool_call))
+            .collect();
+        ordered.sort_by_key(|(index, _)| *index);
+
+        for (_, tool_call) in ordered {
             let input: Value = serde_json::from_str(&tool_call.function.arguments)
                 .unwrap_or_else(|_| json!({}));
             blocks.push(anthropic::ContentBlock::ToolUse {
@@ -182,46 +454,123 @@ fn convert_openai_message_content(
     }
 }
 
-/// 解析 data URL
-fn parse_data_url(url: &str) -> Option<(String, String)> {
-    if url.starts_with("data:") {
-        let rest = &url[5..];
-        if let Some(comma_pos) = rest.find(',') {
-            let meta = &rest[..comma_pos];
-            let data = &rest[comma_pos + 1..];
-
-            // 提取 media type
-            let media_type = if let Some(semi_pos) = meta.find(';') {
-                meta[..semi_pos].to_string()
-            } else {
-                meta.to_string()
-            };
+/// 将 OpenAI 图片 URL 转换为 Anthropic 图片来源：data URL 转为 base64，HTTPS URL 直接引用
+fn image_source(url: &str) -> Option<anthropic::ImageSource> {
+    if let Some((media_type, data)) = parse_data_url(url) {
+        return Some(anthropic::ImageSource::Base64 { media_type, data });
+    }
+    if url.starts_with("https://") {
+        return Some(anthropic::ImageSource::Url {
+            url: url.to_string(),
+        });
+    }
+    None
+}
+
+/// 转换 tool 消息内容为 ToolResult 内容：含图片时保留为内容块，否则合并为文本
+fn convert_tool_result_content(content: &openai::MessageContent) -> anthropic::ToolResultContent {
+    let parts = match content {
+        openai::MessageContent::Text(t) => return anthropic::ToolResultContent::Text(t.clone()),
+        openai::MessageContent::Parts(parts) => parts,
+    };
 
-            return Some((media_type, data.to_string()));
+    let blocks: Vec<anthropic::ToolResultBlock> = parts
+        .iter()
+        .filter_map(|p| match p {
+            openai::ContentPart::Text { text } => {
+                Some(anthropic::ToolResultBlock::Text { text: text.clone() })
+            }
+            openai::ContentPart::ImageUrl { image_url } => {
+                image_source(&image_url.url)
+                    .map(|source| anthropic::ToolResultBlock::Image { source })
+            }
+            openai::ContentPart::InputAudio { .. } | openai::ContentPart::Unknown(_) => None,
+        })
+        .collect();
+
+    if blocks
+        .iter()
+        .any(|b| matches!(b, anthropic::ToolResultBlock::Image { .. }))
+    {
+        anthropic::ToolResultContent::Blocks(blocks)
+    } else {
+        anthropic::ToolResultContent::Text(
+            blocks
+                .into_iter()
+                .filter_map(|b| match b {
+                    anthropic::ToolResultBlock::Text { text } => Some(text),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+}
+
+/// 合并相邻的同角色消息：文本以 "\n" 连接，内容块直接拼接
+fn merge_consecutive_same_role(messages: Vec<anthropic::Message>) -> Vec<anthropic::Message> {
+    let mut merged: Vec<anthropic::Message> = Vec::with_capacity(messages.len());
+
+    for msg in messages {
+        match merged.last_mut() {
+            Some(last) if last.role == msg.role => {
+                let previous = std::mem::replace(
+                    &mut last.content,
+                    anthropic::MessageContent::Text(String::new()),
+                );
+                last.content = merge_message_content(previous, msg.content);
+            }
+            _ => merged.push(msg),
         }
     }
-    None
+
+    merged
+}
+
+fn merge_message_content(
+    first: anthropic::MessageContent,
+    second: anthropic::MessageContent,
+) -> anthropic::MessageContent {
+    match (first, second) {
+        (anthropic::MessageContent::Text(a), anthropic::MessageContent::Text(b)) => {
+            if a.is_empty() {
+                anthropic::MessageContent::Text(b)
+            } else if b.is_empty() {
+                anthropic::MessageContent::Text(a)
+            } else {
+                anthropic::MessageContent::Text(format!("{}\n{}", a, b))
+            }
+        }
+        (first, second) => {
+            let mut blocks = into_blocks(first);
+            blocks.extend(into_blocks(second));
+            anthropic::MessageContent::Blocks(blocks)
+        }
+    }
+}
+
+fn into_blocks(content: anthropic::MessageContent) -> Vec<anthropic::ContentBlock> {
+    match content {
+        anthropic::MessageContent::Text(text) if text.is_empty() => Vec::new(),
+        anthropic::MessageContent::Text(text) => vec![anthropic::ContentBlock::Text {
+            text,
+            cache_control: None,
+        }],
+        anthropic::MessageContent::Blocks(blocks) => blocks,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::test_config;
 
     fn create_test_config() -> Config {
         Config {
-            port: 3000,
             routing_mode: crate::config::RoutingMode::Transform,
             anthropic_base_url: Some("https://api.anthropic.com".to_string()),
             anthropic_api_key: Some("test-key".to_string()),
-            openai_base_url: None,
-            openai_api_key: None,
-            base_url: None,
-            api_key: None,
-            reasoning_model: None,
-            completion_model: None,
-            debug: false,
-            verbose: false,
-            log_raw_json: false,
+            ..test_config()
         }
     }
 
@@ -236,6 +585,8 @@ mod tests {
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_content: None,
+                function_call: None,
             }],
             max_tokens: Some(100),
             temperature: None,
@@ -244,7 +595,17 @@ mod tests {
             stream: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning_effort: None,
+            stream_options: None,
+            user: None,
+            service_tier: None,
+            logit_bias: None,
+            response_format: None,
+            store: None,
+            metadata: None,
+            functions: None,
+            function_call: None,
         };
 
         let result = openai_to_anthropic_request(req, &config).unwrap();
@@ -254,6 +615,26 @@ mod tests {
         assert_eq!(result.messages[0].role, "user");
     }
 
+    #[test]
+    fn test_default_stop_merged_without_cap() {
+        let mut config = create_test_config();
+        config.default_stop = vec!["</tool>".to_string(), "a".to_string()];
+        let req: openai::OpenAIRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "stop": ["a", "b", "c", "d"]
+        }))
+        .unwrap();
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        // Anthropic 没有 4 个的限制
+        assert_eq!(
+            result.stop_sequences,
+            Some(vec!["a", "b", "c", "d", "</tool>"].into_iter().map(String::from).collect())
+        );
+    }
+
     #[test]
     fn test_system_message_conversion() {
         let config = create_test_config();
@@ -266,6 +647,8 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    reasoning_content: None,
+                    function_call: None,
                 },
                 openai::Message {
                     role: "user".to_string(),
@@ -273,6 +656,8 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    reasoning_content: None,
+                    function_call: None,
                 },
             ],
             max_tokens: Some(100),
@@ -282,7 +667,17 @@ mod tests {
             stream: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning_effort: None,
+            stream_options: None,
+            user: None,
+            service_tier: None,
+            logit_bias: None,
+            response_format: None,
+            store: None,
+            metadata: None,
+            functions: None,
+            function_call: None,
         };
 
         let result = openai_to_anthropic_request(req, &config).unwrap();
@@ -312,4 +707,626 @@ mod tests {
         assert_eq!(media_type, "text/plain");
         assert_eq!(data, "Hello");
     }
+
+    #[test]
+    fn test_image_source_from_url() {
+        assert!(matches!(
+            image_source("data:image/png;base64,iVBORw0KGgo="),
+            Some(anthropic::ImageSource::Base64 { media_type, .. }) if media_type == "image/png"
+        ));
+        assert!(matches!(
+            image_source("https://example.com/cat.png"),
+            Some(anthropic::ImageSource::Url { url }) if url == "https://example.com/cat.png"
+        ));
+        assert!(image_source("http://example.com/cat.png").is_none());
+    }
+
+    fn text_message(role: &str, text: &str) -> openai::Message {
+        openai::Message {
+            role: role.to_string(),
+            content: Some(openai::MessageContent::Text(text.to_string())),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_content: None,
+            function_call: None,
+        }
+    }
+
+    fn request_with_messages(messages: Vec<openai::Message>) -> openai::OpenAIRequest {
+        openai::OpenAIRequest {
+            model: "claude-3-sonnet".to_string(),
+            messages,
+            max_tokens: Some(100),
+            temperature: None,
+            top_p: None,
+            stop: None,
+            stream: None,
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            reasoning_effort: None,
+            stream_options: None,
+            user: None,
+            service_tier: None,
+            logit_bias: None,
+            response_format: None,
+            store: None,
+            metadata: None,
+            functions: None,
+            function_call: None,
+        }
+    }
+
+    #[test]
+    fn test_multiple_system_messages_accumulated() {
+        let request = request_with_messages(vec![
+            text_message("system", "You are helpful"),
+            text_message("system", "Be concise"),
+            text_message("user", "Hello"),
+            text_message("system", "Reply in English"),
+        ]);
+        let result = openai_to_anthropic_request(request, &create_test_config()).unwrap();
+        assert!(matches!(
+            result.system,
+            Some(anthropic::SystemPrompt::Single(ref s))
+                if s == "You are helpful\n\nBe concise\n\nReply in English"
+        ));
+        assert_eq!(result.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_developer_message_handling() {
+        let messages = || {
+            vec![
+                text_message("system", "You are helpful"),
+                text_message("developer", "Answer in French"),
+                text_message("user", "Hello"),
+            ]
+        };
+        let mut config = create_test_config();
+
+        let result = openai_to_anthropic_request(request_with_messages(messages()), &config).unwrap();
+        assert!(matches!(
+            result.system,
+            Some(anthropic::SystemPrompt::Single(ref s)) if s == "You are helpful\n\n---\nAnswer in French"
+        ));
+        assert_eq!(result.messages.len(), 1);
+
+        config.developer_message_handling = DeveloperMessageHandling::AsUser;
+        let result = openai_to_anthropic_request(request_with_messages(messages()), &config).unwrap();
+        assert!(matches!(
+            result.system,
+            Some(anthropic::SystemPrompt::Single(ref s)) if s == "You are helpful"
+        ));
+        match &result.messages[0].content {
+            anthropic::MessageContent::Text(text) => assert_eq!(text, "Answer in French\nHello"),
+            _ => panic!("Expected merged text content"),
+        }
+
+        config.developer_message_handling = DeveloperMessageHandling::Drop;
+        let result = openai_to_anthropic_request(request_with_messages(messages()), &config).unwrap();
+        assert!(matches!(
+            result.system,
+            Some(anthropic::SystemPrompt::Single(ref s)) if s == "You are helpful"
+        ));
+        assert_eq!(result.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_system_image_moved_to_first_user_message() {
+        let config = create_test_config();
+        let system = openai::Message {
+            content: Some(openai::MessageContent::Parts(vec![
+                openai::ContentPart::Text {
+                    text: "Describe images like this logo".to_string(),
+                },
+                openai::ContentPart::ImageUrl {
+                    image_url: openai::ImageUrl {
+                        url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    },
+                },
+            ])),
+            ..text_message("system", "")
+        };
+
+        let req = request_with_messages(vec![
+            system.clone(),
+            text_message("assistant", "Hi"),
+            text_message("user", "What is this?"),
+        ]);
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+        assert!(matches!(
+            result.system,
+            Some(anthropic::SystemPrompt::Single(ref s)) if s == "Describe images like this logo"
+        ));
+        assert_eq!(result.messages[0].role, "assistant");
+        match &result.messages[1].content {
+            anthropic::MessageContent::Blocks(blocks) => {
+                assert_eq!(blocks.len(), 2);
+                assert!(matches!(
+                    &blocks[0],
+                    anthropic::ContentBlock::Image {
+                        source: anthropic::ImageSource::Base64 { media_type, .. }
+                    } if media_type == "image/png"
+                ));
+                assert!(matches!(
+                    &blocks[1],
+                    anthropic::ContentBlock::Text { text, .. } if text == "What is this?"
+                ));
+            }
+            _ => panic!("Expected image block before user text"),
+        }
+
+        // 没有 user 消息时单独插入一条
+        let result = openai_to_anthropic_request(request_with_messages(vec![system]), &config).unwrap();
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(result.messages[0].role, "user");
+        assert!(matches!(
+            &result.messages[0].content,
+            anthropic::MessageContent::Blocks(blocks) if blocks.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_max_schema_depth_exceeded() {
+        let mut config = create_test_config();
+        config.max_schema_depth = Some(3);
+        let mut req = request_with_messages(vec![text_message("user", "Hello")]);
+        req.tools = Some(vec![openai::Tool {
+            tool_type: "function".to_string(),
+            function: openai::Function {
+                name: "create_order".to_string(),
+                description: None,
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "items": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {"sku": {"type": "string"}}
+                            }
+                        }
+                    }
+                }),
+                strict: None,
+            },
+        }]);
+
+        match openai_to_anthropic_request(req.clone(), &config) {
+            Err(ProxyError::Transform(message)) => assert_eq!(
+                message,
+                "Schema of tool 'create_order' is nested 4 levels deep, more than MAX_SCHEMA_DEPTH=3"
+            ),
+            other => panic!("expected transform error, got {:?}", other),
+        }
+
+        config.max_schema_depth = Some(4);
+        assert!(openai_to_anthropic_request(req, &config).is_ok());
+    }
+
+    #[test]
+    fn test_merge_consecutive_user_messages() {
+        let config = create_test_config();
+        let req = request_with_messages(vec![
+            text_message("user", "Hello"),
+            text_message("user", "Are you there?"),
+            text_message("assistant", "Yes"),
+        ]);
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        assert_eq!(result.messages.len(), 2);
+        match &result.messages[0].content {
+            anthropic::MessageContent::Text(text) => assert_eq!(text, "Hello\nAre you there?"),
+            _ => panic!("Expected merged text content"),
+        }
+        assert_eq!(result.messages[1].role, "assistant");
+    }
+
+    #[test]
+    fn test_merge_consecutive_tool_results() {
+        let config = create_test_config();
+        let mut first = text_message("tool", "result 1");
+        first.tool_call_id = Some("call_1".to_string());
+        let mut second = text_message("tool", "result 2");
+        second.tool_call_id = Some("call_2".to_string());
+        let req = request_with_messages(vec![first, second, text_message("user", "Continue")]);
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        assert_eq!(result.messages.len(), 1);
+        match &result.messages[0].content {
+            anthropic::MessageContent::Blocks(blocks) => {
+                assert_eq!(blocks.len(), 3);
+                assert!(matches!(blocks[0], anthropic::ContentBlock::ToolResult { .. }));
+                assert!(matches!(blocks[1], anthropic::ContentBlock::ToolResult { .. }));
+                assert!(matches!(blocks[2], anthropic::ContentBlock::Text { .. }));
+            }
+            _ => panic!("Expected merged content blocks"),
+        }
+    }
+
+    #[test]
+    fn test_merge_consecutive_messages_disabled() {
+        let mut config = create_test_config();
+        config.merge_consecutive_messages = false;
+        let req = request_with_messages(vec![
+            text_message("user", "Hello"),
+            text_message("user", "Are you there?"),
+        ]);
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        assert_eq!(result.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_tool_calls_ordered_by_index() {
+        let config = create_test_config();
+        let tool_call = |index: usize, id: &str| openai::ToolCall {
+            index: Some(index),
+            id: id.to_string(),
+            call_type: "function".to_string(),
+            function: openai::FunctionCall {
+                name: "search".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let mut assistant = text_message("assistant", "");
+        assistant.content = None;
+        assistant.tool_calls = Some(vec![
+            tool_call(2, "call_c"),
+            tool_call(0, "call_a"),
+            tool_call(5, "call_d"),
+            tool_call(1, "call_b"),
+        ]);
+        let req = request_with_messages(vec![text_message("user", "Search"), assistant]);
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        match &result.messages[1].content {
+            anthropic::MessageContent::Blocks(blocks) => {
+                let ids: Vec<&str> = blocks
+                    .iter()
+                    .filter_map(|b| match b {
+                        anthropic::ContentBlock::ToolUse { id, .. } => Some(id.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(ids, vec!["call_a", "call_b", "call_c", "call_d"]);
+            }
+            _ => panic!("Expected tool_use blocks"),
+        }
+    }
+
+    #[test]
+    fn test_tool_message_with_image_part() {
+        let config = create_test_config();
+        let mut tool = text_message("tool", "");
+        tool.tool_call_id = Some("call_1".to_string());
+        tool.content = Some(openai::MessageContent::Parts(vec![
+            openai::ContentPart::Text {
+                text: "Screenshot taken".to_string(),
+            },
+            openai::ContentPart::ImageUrl {
+                image_url: openai::ImageUrl {
+                    url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                },
+            },
+        ]));
+        let req = request_with_messages(vec![tool]);
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        match &result.messages[0].content {
+            anthropic::MessageContent::Blocks(blocks) => match &blocks[0] {
+                anthropic::ContentBlock::ToolResult {
+                    tool_use_id,
+                    content: anthropic::ToolResultContent::Blocks(parts),
+                    ..
+                } => {
+                    assert_eq!(tool_use_id, "call_1");
+                    assert_eq!(parts.len(), 2);
+                    assert!(matches!(&parts[0], anthropic::ToolResultBlock::Text { text } if text == "Screenshot taken"));
+                    match &parts[1] {
+                        anthropic::ToolResultBlock::Image {
+                            source: anthropic::ImageSource::Base64 { media_type, data },
+                        } => {
+                            assert_eq!(media_type, "image/png");
+                            assert_eq!(data, "iVBORw0KGgo=");
+                        }
+                        _ => panic!("Expected image tool result block"),
+                    }
+                }
+                _ => panic!("Expected tool result with blocks"),
+            },
+            _ => panic!("Expected content blocks"),
+        }
+    }
+
+    #[test]
+    fn test_tool_message_text_parts_stay_text() {
+        let config = create_test_config();
+        let mut tool = text_message("tool", "");
+        tool.tool_call_id = Some("call_1".to_string());
+        tool.content = Some(openai::MessageContent::Parts(vec![
+            openai::ContentPart::Text { text: "a".to_string() },
+            openai::ContentPart::Text { text: "b".to_string() },
+        ]));
+        let req = request_with_messages(vec![tool]);
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+
+        match &result.messages[0].content {
+            anthropic::MessageContent::Blocks(blocks) => assert!(matches!(
+                &blocks[0],
+                anthropic::ContentBlock::ToolResult {
+                    content: anthropic::ToolResultContent::Text(text),
+                    ..
+                } if text == "a\nb"
+            )),
+            _ => panic!("Expected content blocks"),
+        }
+    }
+
+    #[test]
+    fn test_user_mapped_to_metadata() {
+        let mut config = create_test_config();
+        let req: openai::OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "user": "user-42"
+        }))
+        .unwrap();
+
+        let result = openai_to_anthropic_request(req.clone(), &config).unwrap();
+        assert_eq!(result.metadata, Some(json!({"user_id": "user-42"})));
+
+        config.anthropic_metadata_user_id = Some("tenant-a".to_string());
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+        assert_eq!(result.metadata, Some(json!({"user_id": "tenant-a"})));
+    }
+
+    #[test]
+    fn test_legacy_functions_request() {
+        let config = create_test_config();
+        let req: openai::OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-3.5-turbo-0613",
+            "messages": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"role": "assistant", "content": null, "function_call": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                {"role": "function", "name": "get_weather", "content": "sunny"}
+            ],
+            "functions": [{"name": "get_weather", "description": "Look up weather", "parameters": {"type": "object"}}],
+            "function_call": {"name": "get_weather"}
+        }))
+        .unwrap();
+
+        let mut normalized = req.clone();
+        normalize_legacy_functions(&mut normalized);
+        assert_eq!(
+            normalized.tool_choice,
+            Some(json!({"type": "function", "function": {"name": "get_weather"}}))
+        );
+
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+        let tools = result.tools.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "get_weather");
+        assert_eq!(tools[0].description.as_deref(), Some("Look up weather"));
+
+        // 历史中的函数调用与函数结果通过生成的 id 配对
+        let value = serde_json::to_value(&result.messages).unwrap();
+        assert_eq!(value[1]["role"], "assistant");
+        assert_eq!(value[1]["content"][0]["type"], "tool_use");
+        assert_eq!(value[1]["content"][0]["id"], "call_legacy_1");
+        assert_eq!(value[1]["content"][0]["input"], json!({"city": "Paris"}));
+        assert_eq!(value[2]["role"], "user");
+        assert_eq!(value[2]["content"][0]["type"], "tool_result");
+        assert_eq!(value[2]["content"][0]["tool_use_id"], "call_legacy_1");
+    }
+
+    #[test]
+    fn test_openai_metadata_mapped_to_anthropic_metadata() {
+        let config = create_test_config();
+        let mut req: openai::OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "store": true,
+            "metadata": {"user_id": "user-7", "session": "abc"}
+        }))
+        .unwrap();
+
+        // 只保留 Anthropic 支持的 user_id
+        let result = openai_to_anthropic_request(req.clone(), &config).unwrap();
+        assert_eq!(result.metadata, Some(json!({"user_id": "user-7"})));
+        let value = serde_json::to_value(&result).unwrap();
+        assert!(value.get("store").is_none());
+
+        // user 字段优先于 metadata.user_id
+        req.user = Some("user-42".to_string());
+        let result = openai_to_anthropic_request(req.clone(), &config).unwrap();
+        assert_eq!(result.metadata, Some(json!({"user_id": "user-42"})));
+
+        // 没有 user_id 时不设置 metadata
+        req.user = None;
+        req.metadata = Some(json!({"session": "abc"}));
+        let result = openai_to_anthropic_request(req, &config).unwrap();
+        assert_eq!(result.metadata, None);
+    }
+
+    #[test]
+    fn test_openai_only_fields_reported() {
+        let raw = json!({
+            "model": "claude-3",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "logit_bias": {"50256": -100},
+            "prediction": {"type": "content", "content": "draft"},
+            "modalities": ["text"],
+            "store": false,
+            "audio": null
+        });
+        assert_eq!(unsupported_fields(&raw), vec!["logit_bias", "prediction", "modalities"]);
+
+        let mut report = Vec::new();
+        check_unsupported_fields(&raw, false, &mut report).unwrap();
+        assert_eq!(
+            report,
+            vec![
+                "dropped 'logit_bias': not supported by Anthropic",
+                "dropped 'prediction': not supported by Anthropic",
+                "dropped 'modalities': not supported by Anthropic",
+            ]
+        );
+
+        let req: openai::OpenAIRequest = serde_json::from_value(raw).unwrap();
+        let result = openai_to_anthropic_request(req, &create_test_config()).unwrap();
+        let body = serde_json::to_value(&result).unwrap();
+        assert!(body.get("logit_bias").is_none());
+        assert!(body.get("prediction").is_none());
+    }
+
+    #[test]
+    fn test_strict_params_rejects_openai_only_fields() {
+        let raw = json!({
+            "model": "claude-3",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "prediction": {"type": "content", "content": "draft"},
+            "store": true
+        });
+        let mut report = Vec::new();
+        let error = check_unsupported_fields(&raw, true, &mut report).unwrap_err();
+        assert!(
+            matches!(&error, ProxyError::Transform(msg) if msg.ends_with("Anthropic backend: prediction, store")),
+            "{:?}",
+            error
+        );
+        assert!(report.is_empty());
+
+        let raw = json!({"model": "claude-3", "messages": []});
+        assert!(check_unsupported_fields(&raw, true, &mut report).is_ok());
+    }
+
+    fn audio_request() -> openai::OpenAIRequest {
+        serde_json::from_value(json!({
+            "model": "claude-3",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "Transcribe this"},
+                    {"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}},
+                    {"type": "file", "file": {"file_id": "file-1"}}
+                ]
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_audio_and_unknown_parts_parse() {
+        let req = audio_request();
+        let Some(openai::MessageContent::Parts(parts)) = &req.messages[0].content else {
+            panic!("expected content parts");
+        };
+        assert!(matches!(
+            &parts[1],
+            openai::ContentPart::InputAudio { input_audio } if input_audio.format == "wav"
+        ));
+        assert!(matches!(&parts[2], openai::ContentPart::Unknown(v) if v["type"] == "file"));
+
+        // 未知类型序列化时原样保留
+        let body = serde_json::to_value(&req).unwrap();
+        assert_eq!(body["messages"][0]["content"][2]["file"]["file_id"], "file-1");
+    }
+
+    #[test]
+    fn test_audio_input_rejected_by_default() {
+        let mut report = Vec::new();
+        let error = check_content_parts(&mut audio_request(), false, &mut report).unwrap_err();
+        assert!(
+            matches!(&error, ProxyError::UnsupportedOperation(msg) if msg.contains("input_audio")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_audio_input_placeholder() {
+        let mut req = audio_request();
+        let mut report = Vec::new();
+        check_content_parts(&mut req, true, &mut report).unwrap();
+        assert_eq!(
+            report,
+            vec![
+                "dropped content part of type 'file'",
+                "replaced 1 audio input part(s) with a text placeholder",
+            ]
+        );
+
+        let result = openai_to_anthropic_request(req, &create_test_config()).unwrap();
+        let body = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            body["messages"][0]["content"],
+            json!([
+                {"type": "text", "text": "Transcribe this"},
+                {"type": "text", "text": AUDIO_PLACEHOLDER_TEXT}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_service_tier_mapped_to_anthropic() {
+        let config = create_test_config();
+        for (tier, expected) in [
+            ("auto", Some("auto")),
+            ("priority", Some("auto")),
+            ("default", Some("standard_only")),
+            ("flex", None),
+        ] {
+            let req: openai::OpenAIRequest = serde_json::from_value(json!({
+                "model": "gpt-4",
+                "messages": [{"role": "user", "content": "Hello"}],
+                "service_tier": tier
+            }))
+            .unwrap();
+
+            let result = openai_to_anthropic_request(req, &config).unwrap();
+            let body = serde_json::to_value(&result).unwrap();
+            assert_eq!(body.get("service_tier").and_then(|t| t.as_str()), expected, "{}", tier);
+        }
+    }
+
+    #[test]
+    fn test_parallel_tool_calls_mapped_to_anthropic() {
+        let config = create_test_config();
+        let request = |parallel: Value, tools: bool| -> openai::OpenAIRequest {
+            let mut body = json!({
+                "model": "gpt-4",
+                "messages": [{"role": "user", "content": "Hello"}],
+                "parallel_tool_calls": parallel
+            });
+            if tools {
+                body["tools"] = json!([{
+                    "type": "function",
+                    "function": {"name": "get_weather", "parameters": {"type": "object"}}
+                }]);
+            }
+            serde_json::from_value(body).unwrap()
+        };
+
+        let result = openai_to_anthropic_request(request(json!(false), true), &config).unwrap();
+        let body = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            body["tool_choice"],
+            json!({"type": "auto", "disable_parallel_tool_use": true})
+        );
+
+        // 允许并行（默认）或没有工具时不设置 tool_choice
+        for (parallel, tools) in [(json!(true), true), (Value::Null, true), (json!(false), false)] {
+            let result = openai_to_anthropic_request(request(parallel, tools), &config).unwrap();
+            assert!(serde_json::to_value(&result).unwrap().get("tool_choice").is_none());
+        }
+    }
 }
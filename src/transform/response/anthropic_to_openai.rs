@@ -1,24 +1,31 @@
 //! Anthropic 响应转换为 OpenAI 格式
 
+use crate::config::MultipleTextBlocksMode;
 use crate::error::ProxyResult;
 use crate::models::{anthropic, openai};
+use crate::transform::utils::{map_stop_reason, Direction};
 
 /// 将 Anthropic 响应转换为 OpenAI 格式
+///
+/// 多个文本块按 `text_blocks` 拼接、只取第一个或拆分为多个 choice；
+/// 拆分时工具调用只挂在第一个 choice 上
 pub fn anthropic_to_openai_response(
     resp: anthropic::AnthropicResponse,
+    text_blocks: MultipleTextBlocksMode,
 ) -> ProxyResult<openai::OpenAIResponse> {
-    let mut content = None;
+    let mut texts = Vec::new();
     let mut tool_calls = Vec::new();
 
     for block in resp.content {
         match block {
             anthropic::ResponseContent::Text { text, .. } => {
-                content = Some(text);
+                texts.push(text);
             }
             anthropic::ResponseContent::ToolUse {
                 id, name, input, ..
             } => {
                 tool_calls.push(openai::ToolCall {
+                    index: None,
                     id,
                     call_type: "function".to_string(),
                     function: openai::FunctionCall {
@@ -33,12 +40,39 @@ pub fn anthropic_to_openai_response(
         }
     }
 
-    let finish_reason = resp.stop_reason.map(|r| match r.as_str() {
-        "end_turn" => "stop".to_string(),
-        "tool_use" => "tool_calls".to_string(),
-        "max_tokens" => "length".to_string(),
-        _ => "stop".to_string(),
-    });
+    let finish_reason = map_stop_reason(resp.stop_reason.as_deref(), Direction::AnthropicToOpenAI);
+
+    let contents: Vec<Option<String>> = match text_blocks {
+        _ if texts.is_empty() => vec![None],
+        MultipleTextBlocksMode::Concatenate => vec![Some(texts.join("\n\n"))],
+        MultipleTextBlocksMode::FirstOnly => vec![texts.into_iter().next()],
+        MultipleTextBlocksMode::AllChoices => texts.into_iter().map(Some).collect(),
+    };
+
+    let mut tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+    let choices = contents
+        .into_iter()
+        .enumerate()
+        .map(|(index, content)| {
+            let tool_calls = tool_calls.take();
+            // 额外的 choice 不带工具调用，不能以 tool_calls 结束
+            let finish_reason = match finish_reason.as_deref() {
+                Some("tool_calls") if index > 0 => Some("stop".to_string()),
+                _ => finish_reason.clone(),
+            };
+            openai::Choice {
+                index,
+                message: openai::ChoiceMessage {
+                    role: "assistant".to_string(),
+                    content,
+                    tool_calls,
+                    function_call: None,
+                },
+                logprobs: None,
+                finish_reason,
+            }
+        })
+        .collect();
 
     Ok(openai::OpenAIResponse {
         id: resp.id,
@@ -48,19 +82,7 @@ pub fn anthropic_to_openai_response(
             .unwrap()
             .as_secs(),
         model: resp.model,
-        choices: vec![openai::Choice {
-            index: 0,
-            message: openai::ChoiceMessage {
-                role: "assistant".to_string(),
-                content,
-                tool_calls: if tool_calls.is_empty() {
-                    None
-                } else {
-                    Some(tool_calls)
-                },
-            },
-            finish_reason,
-        }],
+        choices,
         usage: openai::Usage {
             prompt_tokens: resp.usage.input_tokens,
             completion_tokens: resp.usage.output_tokens,
@@ -94,7 +116,7 @@ mod tests {
             },
         };
 
-        let result = anthropic_to_openai_response(resp).unwrap();
+        let result = anthropic_to_openai_response(resp, MultipleTextBlocksMode::Concatenate).unwrap();
         
         assert_eq!(result.id, "msg_123");
         assert_eq!(result.object, "chat.completion");
@@ -128,7 +150,7 @@ mod tests {
             },
         };
 
-        let result = anthropic_to_openai_response(resp).unwrap();
+        let result = anthropic_to_openai_response(resp, MultipleTextBlocksMode::Concatenate).unwrap();
         
         assert_eq!(result.choices[0].finish_reason, Some("tool_calls".to_string()));
         assert!(result.choices[0].message.tool_calls.is_some());
@@ -166,8 +188,83 @@ mod tests {
                 },
             };
 
-            let result = anthropic_to_openai_response(resp).unwrap();
+            let result = anthropic_to_openai_response(resp, MultipleTextBlocksMode::Concatenate).unwrap();
             assert_eq!(result.choices[0].finish_reason, Some(expected_openai.to_string()));
         }
     }
+
+    fn multi_block_response() -> anthropic::AnthropicResponse {
+        let text = |text: &str| anthropic::ResponseContent::Text {
+            content_type: "text".to_string(),
+            text: text.to_string(),
+        };
+        anthropic::AnthropicResponse {
+            id: "msg_multi".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![
+                text("First."),
+                anthropic::ResponseContent::Thinking {
+                    content_type: "thinking".to_string(),
+                    thinking: "hmm".to_string(),
+                },
+                text("Second."),
+                anthropic::ResponseContent::ToolUse {
+                    content_type: "tool_use".to_string(),
+                    id: "call_1".to_string(),
+                    name: "search".to_string(),
+                    input: json!({}),
+                },
+            ],
+            model: "claude-3".to_string(),
+            stop_reason: Some("tool_use".to_string()),
+            stop_sequence: None,
+            usage: anthropic::Usage {
+                input_tokens: 1,
+                output_tokens: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_multiple_text_blocks_modes() {
+        let result =
+            anthropic_to_openai_response(multi_block_response(), MultipleTextBlocksMode::Concatenate)
+                .unwrap();
+        assert_eq!(result.choices.len(), 1);
+        assert_eq!(result.choices[0].message.content.as_deref(), Some("First.\n\nSecond."));
+        assert!(result.choices[0].message.tool_calls.is_some());
+
+        let result =
+            anthropic_to_openai_response(multi_block_response(), MultipleTextBlocksMode::FirstOnly)
+                .unwrap();
+        assert_eq!(result.choices.len(), 1);
+        assert_eq!(result.choices[0].message.content.as_deref(), Some("First."));
+
+        let result =
+            anthropic_to_openai_response(multi_block_response(), MultipleTextBlocksMode::AllChoices)
+                .unwrap();
+        assert_eq!(result.choices.len(), 2);
+        assert_eq!(result.choices[1].index, 1);
+        assert_eq!(result.choices[1].message.content.as_deref(), Some("Second."));
+        assert_eq!(result.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        assert!(result.choices[0].message.tool_calls.is_some());
+        assert_eq!(result.choices[1].finish_reason.as_deref(), Some("stop"));
+        assert!(result.choices[1].message.tool_calls.is_none());
+    }
+
+    #[test]
+    fn test_null_logprobs_serialized_only_when_filled() {
+        let mut result =
+            anthropic_to_openai_response(multi_block_response(), MultipleTextBlocksMode::AllChoices)
+                .unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert!(json["choices"][0].get("logprobs").is_none());
+
+        result.fill_null_logprobs();
+        let json = serde_json::to_value(&result).unwrap();
+        for choice in json["choices"].as_array().unwrap() {
+            assert_eq!(choice.get("logprobs"), Some(&serde_json::Value::Null));
+        }
+    }
 }
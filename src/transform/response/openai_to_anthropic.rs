@@ -1,17 +1,27 @@
 //! OpenAI 响应转换为 Anthropic 格式
 
 use crate::error::{ProxyError, ProxyResult};
+use crate::monitor::TransformFailure;
 use crate::models::{anthropic, openai};
+use crate::transform::utils::{
+    legacy_function_call_id, map_stop_reason, Direction, PRIMARY_CHOICE_INDEX,
+};
 use serde_json::json;
 
 /// 将 OpenAI 响应转换为 Anthropic 格式
 pub fn openai_to_anthropic(
     resp: openai::OpenAIResponse,
 ) -> ProxyResult<anthropic::AnthropicResponse> {
+    // 按 index 选取主 choice，其余 choice 丢弃；没有该 index 时退回第一个
+    if resp.choices.len() > 1 {
+        tracing::debug!("Discarding {} non-primary choice(s)", resp.choices.len() - 1);
+    }
     let choice = resp
         .choices
-        .first()
-        .ok_or_else(|| ProxyError::Transform("No choices in response".to_string()))?;
+        .iter()
+        .find(|choice| choice.index == PRIMARY_CHOICE_INDEX)
+        .or_else(|| resp.choices.first())
+        .ok_or_else(|| ProxyError::transform(TransformFailure::Schema, "No choices in response"))?;
 
     let mut content = Vec::new();
 
@@ -40,16 +50,22 @@ pub fn openai_to_anthropic(
         }
     }
 
-    let stop_reason = choice
-        .finish_reason
-        .as_ref()
-        .map(|r| match r.as_str() {
-            "tool_calls" => "tool_use",
-            "stop" => "end_turn",
-            "length" => "max_tokens",
-            _ => "end_turn",
-        })
-        .map(String::from);
+    // 旧版网关返回的 function_call 没有调用 id，按响应 id 生成
+    if let Some(function_call) = &choice.message.function_call {
+        if choice.message.tool_calls.as_ref().is_none_or(|calls| calls.is_empty()) {
+            let input: serde_json::Value = serde_json::from_str(&function_call.arguments)
+                .unwrap_or_else(|_| json!({}));
+
+            content.push(anthropic::ResponseContent::ToolUse {
+                content_type: "tool_use".to_string(),
+                id: legacy_function_call_id(&resp.id),
+                name: function_call.name.clone(),
+                input,
+            });
+        }
+    }
+
+    let stop_reason = map_stop_reason(choice.finish_reason.as_deref(), Direction::OpenAIToAnthropic);
 
     Ok(anthropic::AnthropicResponse {
         id: resp.id,
@@ -79,10 +95,12 @@ mod tests {
             model: "gpt-4".to_string(),
             choices: vec![openai::Choice {
                 index: 0,
+                logprobs: None,
                 message: openai::ChoiceMessage {
                     role: "assistant".to_string(),
                     content: Some("Hello!".to_string()),
                     tool_calls: None,
+                    function_call: None,
                 },
                 finish_reason: Some("stop".to_string()),
             }],
@@ -104,6 +122,41 @@ mod tests {
         assert_eq!(result.usage.output_tokens, 5);
     }
 
+    #[test]
+    fn test_primary_choice_selected_by_index() {
+        let choice = |index: usize, text: &str| openai::Choice {
+            index,
+            logprobs: None,
+            message: openai::ChoiceMessage {
+                role: "assistant".to_string(),
+                content: Some(text.to_string()),
+                tool_calls: None,
+                function_call: None,
+            },
+            finish_reason: Some("stop".to_string()),
+        };
+        let resp = openai::OpenAIResponse {
+            id: "chatcmpl-123".to_string(),
+            object: "chat.completion".to_string(),
+            created: 1234567890,
+            model: "gpt-4".to_string(),
+            choices: vec![choice(1, "second"), choice(0, "first")],
+            usage: openai::Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+            },
+            system_fingerprint: None,
+        };
+
+        let result = openai_to_anthropic(resp).unwrap();
+        assert_eq!(result.content.len(), 1);
+        assert!(matches!(
+            &result.content[0],
+            anthropic::ResponseContent::Text { text, .. } if text == "first"
+        ));
+    }
+
     #[test]
     fn test_tool_call_response_conversion() {
         let resp = openai::OpenAIResponse {
@@ -113,10 +166,12 @@ mod tests {
             model: "gpt-4".to_string(),
             choices: vec![openai::Choice {
                 index: 0,
+                logprobs: None,
                 message: openai::ChoiceMessage {
                     role: "assistant".to_string(),
                     content: None,
                     tool_calls: Some(vec![openai::ToolCall {
+                        index: None,
                         id: "call_123".to_string(),
                         call_type: "function".to_string(),
                         function: openai::FunctionCall {
@@ -124,6 +179,7 @@ mod tests {
                             arguments: r#"{"query":"rust"}"#.to_string(),
                         },
                     }]),
+                    function_call: None,
                 },
                 finish_reason: Some("tool_calls".to_string()),
             }],
@@ -149,6 +205,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_legacy_function_call_response() {
+        let resp: openai::OpenAIResponse = serde_json::from_value(json!({
+            "id": "chatcmpl-legacy1",
+            "object": "chat.completion",
+            "created": 1234567890,
+            "model": "gpt-3.5-turbo-0613",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "function_call": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                },
+                "finish_reason": "function_call"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}
+        }))
+        .unwrap();
+
+        let result = openai_to_anthropic(resp).unwrap();
+
+        assert_eq!(result.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(result.content.len(), 1);
+        match &result.content[0] {
+            anthropic::ResponseContent::ToolUse { id, name, input, .. } => {
+                assert_eq!(id, "call_legacy1");
+                assert_eq!(name, "get_weather");
+                assert_eq!(input, &json!({"city": "Paris"}));
+            }
+            _ => panic!("Expected ToolUse content"),
+        }
+    }
+
     #[test]
     fn test_stop_reason_mapping() {
         let test_cases = vec![
@@ -166,10 +256,12 @@ mod tests {
                 model: "gpt-4".to_string(),
                 choices: vec![openai::Choice {
                     index: 0,
+                    logprobs: None,
                     message: openai::ChoiceMessage {
                         role: "assistant".to_string(),
                         content: Some("test".to_string()),
                         tool_calls: None,
+                        function_call: None,
                     },
                     finish_reason: Some(openai_reason.to_string()),
                 }],
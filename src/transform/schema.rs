@@ -0,0 +1,379 @@
+//! 工具 JSON Schema 清理
+//!
+//! minimal 档位仅做最小兼容性清理（即 `clean_schema`）；aggressive 档位会内联本地
+//! `$ref`、展开组合关键字并移除上游不支持的关键字，适用于 Gemini 兼容网关、
+//! 部分 vLLM 函数调用模板等对 schema 要求严格的上游
+
+use crate::config::SchemaProfile;
+use crate::transform::utils::clean_schema;
+use serde_json::{Map, Value};
+
+/// aggressive 档位默认移除的关键字
+pub const DEFAULT_STRIP_KEYWORDS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "examples",
+    "const",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+];
+
+/// schema 清理选项
+#[derive(Debug, Clone, Copy)]
+pub struct SchemaOptions<'a> {
+    pub profile: SchemaProfile,
+    /// 将 `anyOf`/`oneOf` 的 "类型 + null" 合并为 `nullable: true`
+    pub collapse_nullable: bool,
+    /// 需要移除的关键字
+    pub strip_keywords: &'a [String],
+}
+
+/// 按档位清理工具 schema
+pub fn sanitize_schema(schema: Value, options: &SchemaOptions) -> Value {
+    match options.profile {
+        SchemaProfile::Minimal => clean_schema(schema),
+        SchemaProfile::Aggressive => {
+            let mut sanitizer = Sanitizer {
+                root: &schema,
+                options,
+                // 根节点视为已展开，避免 "#" 自引用被内联一层
+                ref_stack: vec!["#".to_string()],
+            };
+            sanitizer.sanitize(&schema)
+        }
+    }
+}
+
+struct Sanitizer<'a> {
+    root: &'a Value,
+    options: &'a SchemaOptions<'a>,
+    /// 正在展开的 $ref 链，用于检测循环引用
+    ref_stack: Vec<String>,
+}
+
+impl Sanitizer<'_> {
+    fn sanitize(&mut self, node: &Value) -> Value {
+        let Some(obj) = node.as_object() else {
+            return node.clone();
+        };
+
+        if let Some(reference) = obj.get("$ref").and_then(|v| v.as_str()) {
+            if let Some(resolved) = self.resolve_ref(reference, obj) {
+                return resolved;
+            }
+        }
+
+        let mut out = Map::new();
+        for (key, value) in obj {
+            // 本地定义在引用处已内联
+            if key == "$defs" || key == "definitions" {
+                continue;
+            }
+            if self.options.strip_keywords.iter().any(|k| k == key) {
+                continue;
+            }
+
+            let value = match key.as_str() {
+                "properties" | "patternProperties" => match value.as_object() {
+                    Some(map) => Value::Object(
+                        map.iter()
+                            .map(|(name, schema)| (name.clone(), self.sanitize(schema)))
+                            .collect(),
+                    ),
+                    None => value.clone(),
+                },
+                "items" | "additionalProperties" | "not" => match value {
+                    Value::Array(arr) => Value::Array(arr.iter().map(|v| self.sanitize(v)).collect()),
+                    _ => self.sanitize(value),
+                },
+                "prefixItems" | "anyOf" | "oneOf" | "allOf" => match value.as_array() {
+                    Some(arr) => Value::Array(arr.iter().map(|v| self.sanitize(v)).collect()),
+                    None => value.clone(),
+                },
+                _ => value.clone(),
+            };
+            out.insert(key.clone(), value);
+        }
+
+        // 保留 minimal 档位的行为
+        if out.get("format").and_then(|v| v.as_str()) == Some("uri") {
+            out.remove("format");
+        }
+
+        flatten_single_all_of(&mut out);
+        if self.options.collapse_nullable {
+            collapse_nullable(&mut out, "anyOf");
+            collapse_nullable(&mut out, "oneOf");
+        }
+
+        Value::Object(out)
+    }
+
+    /// 内联本地 `$ref`；非本地引用返回 None 保持原样
+    fn resolve_ref(&mut self, reference: &str, node: &Map<String, Value>) -> Option<Value> {
+        let pointer = reference.strip_prefix('#')?;
+
+        if self.ref_stack.iter().any(|r| r == reference) {
+            tracing::debug!("Recursive schema $ref {} replaced with placeholder", reference);
+            let mut placeholder = Map::new();
+            if let Some(ty) = self.root.pointer(pointer).and_then(|t| t.get("type")) {
+                placeholder.insert("type".to_string(), ty.clone());
+            }
+            return Some(Value::Object(placeholder));
+        }
+
+        let Some(target) = self.root.pointer(pointer) else {
+            tracing::debug!("Unresolvable schema $ref {} dropped", reference);
+            return Some(Value::Object(Map::new()));
+        };
+
+        // 引用处的兄弟关键字（如 description）优先于定义中的同名关键字
+        let mut merged = target.as_object().cloned().unwrap_or_default();
+        for (key, value) in node {
+            if key != "$ref" {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+
+        self.ref_stack.push(reference.to_string());
+        let resolved = self.sanitize(&Value::Object(merged));
+        self.ref_stack.pop();
+
+        Some(resolved)
+    }
+}
+
+/// 只有一个分支的 `allOf` 合并到父 schema
+fn flatten_single_all_of(obj: &mut Map<String, Value>) {
+    let is_single = obj
+        .get("allOf")
+        .and_then(|v| v.as_array())
+        .map(|arr| arr.len() == 1 && arr[0].is_object())
+        .unwrap_or(false);
+    if !is_single {
+        return;
+    }
+
+    if let Some(Value::Array(mut branches)) = obj.remove("allOf") {
+        if let Some(Value::Object(branch)) = branches.pop() {
+            for (key, value) in branch {
+                obj.entry(key).or_insert(value);
+            }
+        }
+    }
+}
+
+/// `anyOf: [X, {"type": "null"}]` 合并为 X + `nullable: true`
+fn collapse_nullable(obj: &mut Map<String, Value>, keyword: &str) {
+    let is_null = |v: &Value| {
+        v.as_object()
+            .map(|o| o.len() == 1 && o.get("type").and_then(|t| t.as_str()) == Some("null"))
+            .unwrap_or(false)
+    };
+
+    let collapsible = obj
+        .get(keyword)
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.len() == 2
+                && arr.iter().filter(|v| is_null(v)).count() == 1
+                && arr.iter().all(|v| v.is_object())
+        })
+        .unwrap_or(false);
+    if !collapsible {
+        return;
+    }
+
+    if let Some(Value::Array(branches)) = obj.remove(keyword) {
+        if let Some(Value::Object(branch)) = branches.into_iter().find(|v| !is_null(v)) {
+            for (key, value) in branch {
+                obj.entry(key).or_insert(value);
+            }
+            obj.insert("nullable".to_string(), Value::Bool(true));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn default_keywords() -> Vec<String> {
+        DEFAULT_STRIP_KEYWORDS.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn aggressive(schema: Value) -> Value {
+        let keywords = default_keywords();
+        sanitize_schema(
+            schema,
+            &SchemaOptions {
+                profile: SchemaProfile::Aggressive,
+                collapse_nullable: true,
+                strip_keywords: &keywords,
+            },
+        )
+    }
+
+    #[test]
+    fn test_minimal_profile_matches_clean_schema() {
+        let schema = json!({
+            "type": "object",
+            "$defs": {"Url": {"type": "string", "format": "uri"}},
+            "properties": {"url": {"$ref": "#/$defs/Url"}, "site": {"type": "string", "format": "uri"}}
+        });
+        let keywords = default_keywords();
+
+        let cleaned = sanitize_schema(
+            schema.clone(),
+            &SchemaOptions {
+                profile: SchemaProfile::Minimal,
+                collapse_nullable: true,
+                strip_keywords: &keywords,
+            },
+        );
+
+        assert_eq!(cleaned, clean_schema(schema));
+        assert_eq!(cleaned["properties"]["url"]["$ref"], "#/$defs/Url");
+    }
+
+    #[test]
+    fn test_resolves_defs_refs() {
+        // 典型的 pydantic 生成的 MCP schema
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "$defs": {
+                "Priority": {"type": "string", "enum": ["low", "high"], "title": "Priority"},
+                "Assignee": {
+                    "type": "object",
+                    "properties": {"email": {"type": "string", "format": "uri"}},
+                    "required": ["email"]
+                }
+            },
+            "properties": {
+                "priority": {"allOf": [{"$ref": "#/$defs/Priority"}], "description": "Task priority"},
+                "assignee": {"$ref": "#/$defs/Assignee", "description": "Who owns it"},
+                "legacy": {"$ref": "#/definitions/Legacy"}
+            },
+            "definitions": {"Legacy": {"type": "integer", "exclusiveMinimum": 0}}
+        });
+
+        let cleaned = aggressive(schema);
+
+        assert!(cleaned.get("$defs").is_none());
+        assert!(cleaned.get("definitions").is_none());
+        assert!(cleaned.get("$schema").is_none());
+        let props = &cleaned["properties"];
+        assert_eq!(props["priority"]["type"], "string");
+        assert_eq!(props["priority"]["enum"], json!(["low", "high"]));
+        assert_eq!(props["priority"]["description"], "Task priority");
+        assert!(props["priority"].get("allOf").is_none());
+        assert_eq!(props["assignee"]["type"], "object");
+        assert_eq!(props["assignee"]["description"], "Who owns it");
+        assert!(props["assignee"]["properties"]["email"].get("format").is_none());
+        assert_eq!(props["legacy"], json!({"type": "integer"}));
+    }
+
+    #[test]
+    fn test_recursive_ref_terminates() {
+        let schema = json!({
+            "type": "object",
+            "$defs": {
+                "Node": {
+                    "type": "object",
+                    "properties": {
+                        "name": {"type": "string"},
+                        "children": {"type": "array", "items": {"$ref": "#/$defs/Node"}}
+                    }
+                }
+            },
+            "properties": {"root": {"$ref": "#/$defs/Node"}}
+        });
+
+        let cleaned = aggressive(schema);
+
+        let root = &cleaned["properties"]["root"];
+        assert_eq!(root["properties"]["name"]["type"], "string");
+        assert_eq!(root["properties"]["children"]["items"], json!({"type": "object"}));
+    }
+
+    #[test]
+    fn test_root_self_reference_terminates() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"next": {"$ref": "#"}}
+        });
+
+        let cleaned = aggressive(schema);
+
+        assert_eq!(cleaned["properties"]["next"], json!({"type": "object"}));
+    }
+
+    #[test]
+    fn test_collapses_nullable_any_of() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "limit": {"anyOf": [{"type": "integer"}, {"type": "null"}], "default": null, "title": "Limit"},
+                "mode": {"anyOf": [{"type": "string"}, {"type": "integer"}]}
+            }
+        });
+
+        let cleaned = aggressive(schema);
+
+        let limit = &cleaned["properties"]["limit"];
+        assert_eq!(limit["type"], "integer");
+        assert_eq!(limit["nullable"], true);
+        assert!(limit.get("anyOf").is_none());
+        assert_eq!(cleaned["properties"]["mode"]["anyOf"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_collapse_nullable_disabled() {
+        let schema = json!({"anyOf": [{"type": "string"}, {"type": "null"}]});
+        let keywords = default_keywords();
+
+        let cleaned = sanitize_schema(
+            schema,
+            &SchemaOptions {
+                profile: SchemaProfile::Aggressive,
+                collapse_nullable: false,
+                strip_keywords: &keywords,
+            },
+        );
+
+        assert!(cleaned.get("anyOf").is_some());
+    }
+
+    #[test]
+    fn test_strips_keywords_but_not_property_names() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "const": {"type": "string", "const": "fixed"},
+                "count": {"type": "number", "exclusiveMinimum": 0, "examples": [1, 2]}
+            },
+            "patternProperties": {"^x-": {"type": "string", "const": "y"}},
+            "additionalProperties": {"type": "string", "$comment": "free form"}
+        });
+
+        let cleaned = aggressive(schema);
+
+        let props = &cleaned["properties"];
+        assert_eq!(props["const"], json!({"type": "string"}));
+        assert_eq!(props["count"], json!({"type": "number"}));
+        assert_eq!(cleaned["patternProperties"]["^x-"], json!({"type": "string"}));
+        assert_eq!(cleaned["additionalProperties"], json!({"type": "string"}));
+    }
+
+    #[test]
+    fn test_keeps_non_local_refs() {
+        let schema = json!({"$ref": "https://example.com/schema.json"});
+
+        let cleaned = aggressive(schema.clone());
+
+        assert_eq!(cleaned, schema);
+    }
+}
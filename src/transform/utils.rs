@@ -1,6 +1,14 @@
 //! 转换工具函数
 
+use crate::error::{ProxyError, ProxyResult};
+use crate::monitor::TransformFailure;
 use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// O→A 转换只使用这个 index 的 choice；部分网关即使未请求 `n` 也会返回多个 choice
+pub const PRIMARY_CHOICE_INDEX: usize = 0;
 
 /// 有效的 reasoning effort 级别
 pub const EFFORT_LEVELS: &[&str] = &["minimal", "low", "medium", "high"];
@@ -41,21 +49,328 @@ pub fn clean_schema(mut schema: Value) -> Value {
     schema
 }
 
-/// 映射 OpenAI finish_reason 到 Anthropic stop_reason
-pub fn map_stop_reason(finish_reason: Option<&str>) -> Option<String> {
-    finish_reason.map(|r| match r {
-        "tool_calls" => "tool_use",
-        "stop" => "end_turn",
-        "length" => "max_tokens",
-        _ => "end_turn",
-    }.to_string())
+/// schema 的嵌套深度，与 `clean_schema` 一样沿 `properties` 和 `items` 递归；非对象 schema 深度为 0
+pub fn schema_depth(schema: &Value) -> usize {
+    let Some(obj) = schema.as_object() else {
+        return 0;
+    };
+    let properties = obj
+        .get("properties")
+        .and_then(|v| v.as_object())
+        .into_iter()
+        .flat_map(|properties| properties.values());
+    1 + properties
+        .chain(obj.get("items"))
+        .map(schema_depth)
+        .max()
+        .unwrap_or(0)
+}
+
+/// 检查工具数量（MAX_TOOLS）和每个工具参数 schema 的嵌套深度（MAX_SCHEMA_DEPTH）
+pub fn check_tool_limits<'a>(
+    tools: &[(&'a str, &'a Value)],
+    max_tools: Option<usize>,
+    max_schema_depth: Option<usize>,
+) -> ProxyResult<()> {
+    if let Some(max) = max_tools {
+        if tools.len() > max {
+            return Err(ProxyError::transform(
+                TransformFailure::Schema,
+                format!("Request has {} tools, more than MAX_TOOLS={}", tools.len(), max),
+            ));
+        }
+    }
+    if let Some(max) = max_schema_depth {
+        for (name, schema) in tools {
+            let depth = schema_depth(schema);
+            if depth > max {
+                return Err(ProxyError::transform(
+                    TransformFailure::Schema,
+                    format!(
+                        "Schema of tool '{}' is nested {} levels deep, more than MAX_SCHEMA_DEPTH={}",
+                        name, depth, max
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 检查 schema 是否满足 OpenAI strict 模式约束
+///
+/// 每个 object schema 都需要 `additionalProperties: false`，且 `required` 覆盖全部属性
+pub fn is_strict_compatible(schema: &Value) -> bool {
+    let Some(obj) = schema.as_object() else {
+        return true;
+    };
+
+    if let Some(properties) = obj.get("properties").and_then(|v| v.as_object()) {
+        if obj.get("additionalProperties") != Some(&Value::Bool(false)) {
+            return false;
+        }
+
+        let required: Vec<&str> = obj
+            .get("required")
+            .and_then(|v| v.as_array())
+            .map(|arr| arr.iter().filter_map(|v| v.as_str()).collect())
+            .unwrap_or_default();
+        if !properties.keys().all(|k| required.contains(&k.as_str())) {
+            return false;
+        }
+
+        if !properties.values().all(is_strict_compatible) {
+            return false;
+        }
+    } else if obj.get("type").and_then(|v| v.as_str()) == Some("object")
+        && obj.get("additionalProperties") != Some(&Value::Bool(false))
+    {
+        return false;
+    }
+
+    obj.get("items").map(is_strict_compatible).unwrap_or(true)
+}
+
+/// 最小改写 schema 使其满足 strict 模式：注入 `additionalProperties: false`，并用全部属性填充 `required`
+pub fn enforce_strict_schema(mut schema: Value) -> Value {
+    if let Some(obj) = schema.as_object_mut() {
+        let is_object = obj.contains_key("properties")
+            || obj.get("type").and_then(|v| v.as_str()) == Some("object");
+
+        if let Some(properties) = obj.get_mut("properties").and_then(|v| v.as_object_mut()) {
+            for (_, value) in properties.iter_mut() {
+                *value = enforce_strict_schema(value.take());
+            }
+            let keys: Vec<Value> = properties.keys().cloned().map(Value::String).collect();
+            obj.insert("required".to_string(), Value::Array(keys));
+        }
+
+        if is_object {
+            obj.insert("additionalProperties".to_string(), Value::Bool(false));
+        }
+
+        if let Some(items) = obj.get_mut("items") {
+            *items = enforce_strict_schema(items.take());
+        }
+    }
+
+    schema
+}
+
+/// stop reason 映射方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// OpenAI finish_reason → Anthropic stop_reason
+    OpenAIToAnthropic,
+    /// Anthropic stop_reason → OpenAI finish_reason
+    AnthropicToOpenAI,
+}
+
+/// 在 OpenAI finish_reason 与 Anthropic stop_reason 之间映射，未知值映射为正常结束
+pub fn map_stop_reason(reason: Option<&str>, direction: Direction) -> Option<String> {
+    reason.map(|r| {
+        match direction {
+            Direction::OpenAIToAnthropic => match r {
+                "tool_calls" | "function_call" => "tool_use",
+                "length" => "max_tokens",
+                "stop" | "content_filter" => "end_turn",
+                _ => "end_turn",
+            },
+            Direction::AnthropicToOpenAI => match r {
+                "tool_use" => "tool_calls",
+                "max_tokens" => "length",
+                "refusal" => "content_filter",
+                "end_turn" | "stop_sequence" => "stop",
+                _ => "stop",
+            },
+        }
+        .to_string()
+    })
+}
+
+
+/// Anthropic 消息 id 的前缀
+pub const ANTHROPIC_MESSAGE_ID_PREFIX: &str = "msg_";
+/// OpenAI 补全 id 的前缀
+pub const OPENAI_COMPLETION_ID_PREFIX: &str = "chatcmpl-";
+
+/// 把响应 id 改写为目标格式的前缀：已带该前缀时原样返回，否则去掉另一种格式的前缀，
+/// 把其余非 `[A-Za-z0-9_-]` 字符替换为 `_` 后加上目标前缀。相同的上游 id 总是得到相同的结果；
+/// 上游 id 为空时按时间生成
+pub fn normalize_response_id(id: &str, prefix: &str) -> String {
+    if id.starts_with(prefix) {
+        return id.to_string();
+    }
+    let rest = [ANTHROPIC_MESSAGE_ID_PREFIX, OPENAI_COMPLETION_ID_PREFIX]
+        .iter()
+        .find_map(|other| id.strip_prefix(other))
+        .unwrap_or(id);
+    if rest.is_empty() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        return format!("{}{:x}", prefix, nanos);
+    }
+    let rest: String = rest
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}{}", prefix, rest)
 }
 
+/// 为旧版 `function_call` 生成调用 id：旧版 API 每个响应最多一个函数调用，
+/// 由响应 id 派生即可保证流式和非流式结果一致
+pub fn legacy_function_call_id(response_id: &str) -> String {
+    let suffix = response_id.strip_prefix("chatcmpl-").unwrap_or(response_id);
+    if suffix.is_empty() {
+        "call_function".to_string()
+    } else {
+        format!("call_{}", suffix)
+    }
+}
+
+/// 提取 Anthropic `metadata.user_id`
+pub fn metadata_user_id(metadata: Option<&Value>) -> Option<&str> {
+    metadata?
+        .get("user_id")
+        .and_then(|v| v.as_str())
+        .filter(|id| !id.is_empty())
+}
+
+/// Anthropic `service_tier` → OpenAI `service_tier`，没有对应值时返回 None
+pub fn openai_service_tier(tier: &str) -> Option<&'static str> {
+    match tier {
+        "auto" => Some("auto"),
+        "standard_only" => Some("default"),
+        _ => None,
+    }
+}
+
+/// OpenAI `service_tier` → Anthropic `service_tier`，没有对应值（如 flex、scale）时返回 None
+///
+/// Anthropic 的 `auto` 在有 Priority Tier 容量时优先使用，因此 `priority` 也映射为 `auto`
+pub fn anthropic_service_tier(tier: &str) -> Option<&'static str> {
+    match tier {
+        "auto" | "priority" => Some("auto"),
+        "default" => Some("standard_only"),
+        _ => None,
+    }
+}
+
+/// 设置请求 JSON 中的 `metadata.user_id`，保留 metadata 中的其他字段
+pub fn set_metadata_user_id(request: &mut Value, user_id: &str) {
+    let Some(obj) = request.as_object_mut() else {
+        return;
+    };
+    let metadata = obj
+        .entry("metadata")
+        .or_insert_with(|| Value::Object(Default::default()));
+    if !metadata.is_object() {
+        *metadata = Value::Object(Default::default());
+    }
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert("user_id".to_string(), Value::String(user_id.to_string()));
+    }
+}
+
+/// Anthropic 请求中携带 OpenAI `response_format` 的扩展字段：顶层 `response_format`
+/// 或 `metadata.response_format`
+pub fn response_format_extension(
+    extra: &Value,
+    metadata: Option<&Value>,
+) -> Option<Value> {
+    extra
+        .get("response_format")
+        .or_else(|| metadata?.get("response_format"))
+        .filter(|v| !v.is_null())
+        .cloned()
+}
+
+/// 从原始请求 JSON 中删除 `response_format` 扩展字段（透传模式），
+/// Anthropic API 会以 400 拒绝未知字段。返回是否改写了请求
+pub fn strip_response_format_extension(request: &mut Value) -> bool {
+    let Some(obj) = request.as_object_mut() else {
+        return false;
+    };
+    let mut stripped = obj.remove("response_format").is_some();
+    if let Some(metadata) = obj.get_mut("metadata").and_then(|m| m.as_object_mut()) {
+        stripped |= metadata.remove("response_format").is_some();
+    }
+    stripped
+}
+
+/// OpenAI `stop` 最多允许的停止序列数
+pub const OPENAI_MAX_STOP: usize = 4;
+
+/// 把 DEFAULT_STOP 合并到请求的停止序列：保留客户端的序列，追加其中没有的默认序列，
+/// 超过 `cap` 时丢弃放不下的默认序列并记录警告
+pub fn merge_stop_sequences(
+    stops: Option<Vec<String>>,
+    defaults: &[String],
+    cap: Option<usize>,
+) -> Option<Vec<String>> {
+    if defaults.is_empty() {
+        return stops;
+    }
+    let mut merged = stops.unwrap_or_default();
+    let mut dropped = Vec::new();
+    for stop in defaults {
+        if merged.contains(stop) {
+            continue;
+        }
+        if cap.is_some_and(|cap| merged.len() >= cap) {
+            dropped.push(stop.as_str());
+        } else {
+            merged.push(stop.clone());
+        }
+    }
+    if !dropped.is_empty() {
+        tracing::warn!(
+            "Dropping default stop sequences {:?}: the request already has {} of at most {}",
+            dropped,
+            merged.len(),
+            cap.unwrap_or_default()
+        );
+    }
+    (!merged.is_empty()).then_some(merged)
+}
+
+/// 在原始请求 JSON 上合并停止序列（透传模式），`field` 为字符串或字符串数组，
+/// 返回是否改写了请求
+pub fn merge_stop_field(request: &mut Value, field: &str, defaults: &[String], cap: Option<usize>) -> bool {
+    if defaults.is_empty() {
+        return false;
+    }
+    let Some(obj) = request.as_object_mut() else {
+        return false;
+    };
+    let stops = match obj.get(field) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(stop)) => Some(vec![stop.clone()]),
+        Some(Value::Array(items)) => {
+            let stops: Option<Vec<String>> = items
+                .iter()
+                .map(|item| item.as_str().map(String::from))
+                .collect();
+            // 格式不符时原样转发，由上游报告错误
+            match stops {
+                Some(stops) => Some(stops),
+                None => return false,
+            }
+        }
+        Some(_) => return false,
+    };
+    let Some(merged) = merge_stop_sequences(stops, defaults, cap) else {
+        return false;
+    };
+    obj.insert(field.to_string(), Value::from(merged));
+    true
+}
 
 /// 解析 data URL
 pub fn parse_data_url(url: &str) -> Option<(String, String)> {
-    if url.starts_with("data:") {
-        let rest = &url[5..];
+    if let Some(rest) = url.strip_prefix("data:") {
         if let Some(comma_pos) = rest.find(',') {
             let meta = &rest[..comma_pos];
             let data = &rest[comma_pos + 1..];
@@ -73,9 +388,104 @@ pub fn parse_data_url(url: &str) -> Option<(String, String)> {
     None
 }
 
+/// OpenAI 函数名的最大长度
+pub const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// 转换为 OpenAI 兼容的函数名（`^[a-zA-Z0-9_-]{1,64}$`）
+///
+/// 合法名称原样返回；否则替换非法字符、截断，并追加原名的哈希后缀以避免冲突。
+/// 结果是确定的，因此请求转换和响应还原可以各自独立计算
+pub fn upstream_tool_name(name: &str) -> Cow<'_, str> {
+    let is_valid = !name.is_empty()
+        && name.len() <= MAX_TOOL_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if is_valid {
+        return Cow::Borrowed(name);
+    }
+
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    let suffix = format!("_{:08x}", hasher.finish() as u32);
+
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .take(MAX_TOOL_NAME_LEN - suffix.len())
+        .collect();
+    Cow::Owned(sanitized + &suffix)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_schema_depth() {
+        assert_eq!(schema_depth(&json!(true)), 0);
+        assert_eq!(schema_depth(&json!({"type": "string"})), 1);
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "flat": {"type": "string"},
+                "list": {"type": "array", "items": {"type": "array", "items": {"type": "string"}}}
+            }
+        });
+        assert_eq!(schema_depth(&schema), 4);
+        assert!(check_tool_limits(&[("t", &schema)], Some(1), Some(4)).is_ok());
+        assert!(check_tool_limits(&[("t", &schema)], None, Some(3)).is_err());
+        assert!(check_tool_limits(&[("t", &schema), ("u", &schema)], Some(1), None).is_err());
+    }
+
+    #[test]
+    fn test_merge_stop_sequences_dedups_and_appends() {
+        let defaults = strings(&["</tool>", "END"]);
+        assert_eq!(
+            merge_stop_sequences(Some(strings(&["END", "\n\n"])), &defaults, Some(OPENAI_MAX_STOP)),
+            Some(strings(&["END", "\n\n", "</tool>"]))
+        );
+        assert_eq!(merge_stop_sequences(None, &defaults, None), Some(defaults.clone()));
+        assert_eq!(merge_stop_sequences(None, &[], None), None);
+    }
+
+    #[test]
+    fn test_merge_stop_sequences_respects_cap() {
+        let defaults = strings(&["</tool>", "END"]);
+        let client = strings(&["a", "b", "c"]);
+        assert_eq!(
+            merge_stop_sequences(Some(client.clone()), &defaults, Some(OPENAI_MAX_STOP)),
+            Some(strings(&["a", "b", "c", "</tool>"]))
+        );
+
+        // 客户端的序列总是保留，即使已超过上限
+        let client = strings(&["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            merge_stop_sequences(Some(client.clone()), &defaults, Some(OPENAI_MAX_STOP)),
+            Some(client)
+        );
+    }
+
+    #[test]
+    fn test_merge_stop_field() {
+        let defaults = strings(&["</tool>"]);
+        let mut request = json!({"stop": "END"});
+        assert!(merge_stop_field(&mut request, "stop", &defaults, Some(OPENAI_MAX_STOP)));
+        assert_eq!(request["stop"], json!(["END", "</tool>"]));
+
+        let mut request = json!({"model": "m"});
+        assert!(merge_stop_field(&mut request, "stop_sequences", &defaults, None));
+        assert_eq!(request["stop_sequences"], json!(["</tool>"]));
+
+        let mut request = json!({"stop": [1]});
+        assert!(!merge_stop_field(&mut request, "stop", &defaults, None));
+        assert!(!merge_stop_field(&mut json!({}), "stop", &[], None));
+    }
 
     #[test]
     fn test_parse_model_with_effort_high() {
@@ -133,23 +543,112 @@ mod tests {
     }
 
     #[test]
-    fn test_map_stop_reason_tool_calls() {
-        assert_eq!(map_stop_reason(Some("tool_calls")), Some("tool_use".to_string()));
+    fn test_is_strict_compatible() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "query": {"type": "string"},
+                "filters": {
+                    "type": "object",
+                    "properties": {"lang": {"type": "string"}},
+                    "required": ["lang"],
+                    "additionalProperties": false
+                }
+            },
+            "required": ["query", "filters"],
+            "additionalProperties": false
+        });
+        assert!(is_strict_compatible(&schema));
     }
 
     #[test]
-    fn test_map_stop_reason_stop() {
-        assert_eq!(map_stop_reason(Some("stop")), Some("end_turn".to_string()));
+    fn test_is_strict_compatible_rejects_missing_constraints() {
+        let missing_required = serde_json::json!({
+            "type": "object",
+            "properties": {"query": {"type": "string"}, "limit": {"type": "integer"}},
+            "required": ["query"],
+            "additionalProperties": false
+        });
+        assert!(!is_strict_compatible(&missing_required));
+
+        let nested_open = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "filters": {"type": "object", "properties": {"lang": {"type": "string"}}, "required": ["lang"]}
+            },
+            "required": ["filters"],
+            "additionalProperties": false
+        });
+        assert!(!is_strict_compatible(&nested_open));
+    }
+
+    #[test]
+    fn test_enforce_strict_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "query": {"type": "string"},
+                "tags": {
+                    "type": "array",
+                    "items": {"type": "object", "properties": {"name": {"type": "string"}}}
+                }
+            },
+            "required": ["query"]
+        });
+
+        let strict = enforce_strict_schema(schema);
+
+        assert!(is_strict_compatible(&strict));
+        assert_eq!(strict["additionalProperties"], false);
+        assert_eq!(strict["required"], serde_json::json!(["query", "tags"]));
+        assert_eq!(strict["properties"]["tags"]["items"]["additionalProperties"], false);
+        assert_eq!(strict["properties"]["query"].get("additionalProperties"), None);
+    }
+
+    #[test]
+    fn test_map_stop_reason_openai_to_anthropic() {
+        let cases = [
+            ("tool_calls", "tool_use"),
+            ("function_call", "tool_use"),
+            ("stop", "end_turn"),
+            ("length", "max_tokens"),
+            ("content_filter", "end_turn"),
+            ("unknown", "end_turn"),
+        ];
+        for (openai_reason, anthropic_reason) in cases {
+            assert_eq!(
+                map_stop_reason(Some(openai_reason), Direction::OpenAIToAnthropic),
+                Some(anthropic_reason.to_string()),
+                "{}",
+                openai_reason
+            );
+        }
     }
 
     #[test]
-    fn test_map_stop_reason_length() {
-        assert_eq!(map_stop_reason(Some("length")), Some("max_tokens".to_string()));
+    fn test_map_stop_reason_anthropic_to_openai() {
+        let cases = [
+            ("end_turn", "stop"),
+            ("stop_sequence", "stop"),
+            ("tool_use", "tool_calls"),
+            ("max_tokens", "length"),
+            ("refusal", "content_filter"),
+            ("unknown", "stop"),
+        ];
+        for (anthropic_reason, openai_reason) in cases {
+            assert_eq!(
+                map_stop_reason(Some(anthropic_reason), Direction::AnthropicToOpenAI),
+                Some(openai_reason.to_string()),
+                "{}",
+                anthropic_reason
+            );
+        }
     }
 
     #[test]
     fn test_map_stop_reason_none() {
-        assert_eq!(map_stop_reason(None), None);
+        assert_eq!(map_stop_reason(None, Direction::OpenAIToAnthropic), None);
+        assert_eq!(map_stop_reason(None, Direction::AnthropicToOpenAI), None);
     }
 
     #[test]
@@ -178,4 +677,56 @@ mod tests {
         let result = parse_data_url(url);
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_upstream_tool_name_keeps_valid_names() {
+        assert_eq!(upstream_tool_name("get_weather"), "get_weather");
+        assert!(matches!(upstream_tool_name("mcp__github__create-issue"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_upstream_tool_name_rewrites_invalid_names() {
+        let long = format!("mcp__server__{}", "a".repeat(80));
+        let shortened = upstream_tool_name(&long);
+        assert_eq!(shortened.len(), MAX_TOOL_NAME_LEN);
+        assert_eq!(shortened, upstream_tool_name(&long));
+
+        let dotted = upstream_tool_name("files.read");
+        assert!(dotted.starts_with("files_read_"));
+        assert_ne!(dotted, upstream_tool_name("files:read"));
+    }
+
+    #[test]
+    fn test_metadata_user_id() {
+        let metadata = serde_json::json!({"user_id": "user-42"});
+        assert_eq!(metadata_user_id(Some(&metadata)), Some("user-42"));
+        assert_eq!(metadata_user_id(Some(&serde_json::json!({"user_id": ""}))), None);
+        assert_eq!(metadata_user_id(None), None);
+    }
+
+    #[test]
+    fn test_set_metadata_user_id_keeps_other_fields() {
+        let mut request = serde_json::json!({"model": "claude", "metadata": {"trace": "t1", "user_id": "old"}});
+        set_metadata_user_id(&mut request, "tenant-a");
+        assert_eq!(request["metadata"], serde_json::json!({"trace": "t1", "user_id": "tenant-a"}));
+
+        let mut request = serde_json::json!({"model": "claude"});
+        set_metadata_user_id(&mut request, "tenant-a");
+        assert_eq!(request["metadata"]["user_id"], "tenant-a");
+    }
+
+    #[test]
+    fn test_normalize_response_id() {
+        assert_eq!(normalize_response_id("msg_01abc", ANTHROPIC_MESSAGE_ID_PREFIX), "msg_01abc");
+        assert_eq!(normalize_response_id("chatcmpl-xyz", ANTHROPIC_MESSAGE_ID_PREFIX), "msg_xyz");
+        assert_eq!(normalize_response_id("msg_01abc", OPENAI_COMPLETION_ID_PREFIX), "chatcmpl-01abc");
+        assert_eq!(normalize_response_id("gen/42:a", OPENAI_COMPLETION_ID_PREFIX), "chatcmpl-gen_42_a");
+        // 相同输入得到相同结果
+        assert_eq!(
+            normalize_response_id("resp-7", ANTHROPIC_MESSAGE_ID_PREFIX),
+            normalize_response_id("resp-7", ANTHROPIC_MESSAGE_ID_PREFIX)
+        );
+        let generated = normalize_response_id("", ANTHROPIC_MESSAGE_ID_PREFIX);
+        assert!(generated.len() > ANTHROPIC_MESSAGE_ID_PREFIX.len() && generated.starts_with("msg_"));
+    }
 }
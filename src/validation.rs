@@ -0,0 +1,431 @@
+//! 转发前的请求校验（VALIDATION）
+//!
+//! 在路由和转换之前检查上游必然会拒绝的请求，直接返回指明字段和取值的 400，
+//! 省去一次上游往返，也避免客户端收到各上游格式不一的错误。
+//! lenient 只检查所有上游都会拒绝的问题；strict 额外按调用方的 API 格式检查。
+//! 上下文窗口预检（CONTEXT_WINDOW_LIMITS）独立于校验级别，在转换后按目标模型进行
+
+use crate::config::{Config, ValidationMode};
+use crate::error::{ProxyError, ProxyResult};
+use crate::models::openai;
+use crate::router::RequestFormat;
+use crate::tokens;
+use serde_json::{Map, Value};
+
+/// 两种 API 中出现的全部消息角色
+const KNOWN_ROLES: [&str; 6] = ["system", "developer", "user", "assistant", "tool", "function"];
+
+/// Anthropic Messages API 的 messages 中允许的角色
+const ANTHROPIC_ROLES: [&str; 2] = ["user", "assistant"];
+
+/// 按配置的级别校验请求，`raw_json` 不是对象时交给后续的反序列化报错
+pub fn validate(raw_json: &Value, format: RequestFormat, mode: ValidationMode) -> ProxyResult<()> {
+    let Some(request) = raw_json.as_object() else {
+        return Ok(());
+    };
+    let validator = match mode {
+        ValidationMode::Off => return Ok(()),
+        ValidationMode::Lenient => Validator { format, strict: false },
+        ValidationMode::Strict => Validator { format, strict: true },
+    };
+
+    validator.messages(request)?;
+    validator.max_tokens(request)?;
+    validator.sampling(request)?;
+    validator.tools(request)?;
+    Ok(())
+}
+
+/// 估算的输入 token 数加上 max_tokens 超过目标模型的上下文窗口时拒绝请求；模型未配置上限时不检查
+pub fn context_window(config: &Config, req: &openai::OpenAIRequest, format: RequestFormat) -> ProxyResult<()> {
+    let Some(limit) = config.context_window_limit_for(&req.model) else {
+        return Ok(());
+    };
+    let input_tokens = tokens::estimate(&req.model, req);
+    let max_tokens = req.max_tokens.unwrap_or_default();
+    if input_tokens + max_tokens as usize > limit as usize {
+        return Err(ProxyError::Validation {
+            param: "max_tokens".to_string(),
+            message: format!(
+                "estimated input tokens ({}) plus max_tokens ({}) exceed the {} token context window of '{}'",
+                input_tokens, max_tokens, limit, req.model
+            ),
+            client_format: format,
+        });
+    }
+    Ok(())
+}
+
+struct Validator {
+    format: RequestFormat,
+    strict: bool,
+}
+
+impl Validator {
+    fn error(&self, param: impl Into<String>, message: impl Into<String>) -> ProxyError {
+        ProxyError::Validation {
+            param: param.into(),
+            message: message.into(),
+            client_format: self.format,
+        }
+    }
+
+    /// messages 必须是非空数组，每条消息带有已知角色；strict 下 Anthropic 请求的 user 与 assistant 必须交替出现
+    fn messages(&self, request: &Map<String, Value>) -> ProxyResult<()> {
+        let messages = match request.get("messages") {
+            None => return Err(self.error("messages", "field is required")),
+            Some(Value::Array(messages)) => messages,
+            Some(other) => {
+                return Err(self.error(
+                    "messages",
+                    format!("must be an array, got {}", type_name(other)),
+                ))
+            }
+        };
+        if messages.is_empty() {
+            return Err(self.error("messages", "must contain at least one message"));
+        }
+
+        let anthropic_strict = self.strict && self.format == RequestFormat::Anthropic;
+        let allowed: &[&str] = if anthropic_strict { &ANTHROPIC_ROLES } else { &KNOWN_ROLES };
+        let mut previous: Option<&str> = None;
+        for (i, message) in messages.iter().enumerate() {
+            let param = format!("messages[{}].role", i);
+            let role = match message.get("role") {
+                Some(Value::String(role)) => role,
+                Some(other) => {
+                    return Err(self.error(param, format!("must be a string, got {}", other)))
+                }
+                None if message.is_object() => return Err(self.error(param, "field is required")),
+                None => {
+                    return Err(self.error(
+                        format!("messages[{}]", i),
+                        format!("must be an object, got {}", type_name(message)),
+                    ))
+                }
+            };
+            if !allowed.contains(&role.as_str()) {
+                return Err(self.error(
+                    param,
+                    format!("unknown role '{}', expected one of: {}", role, allowed.join(", ")),
+                ));
+            }
+            if anthropic_strict && previous == Some(role.as_str()) {
+                let expected = if role == "user" { "assistant" } else { "user" };
+                return Err(self.error(param, format!("expected '{}' after '{}'", expected, role)));
+            }
+            previous = Some(role);
+        }
+        Ok(())
+    }
+
+    /// max_tokens / max_completion_tokens 必须是正整数；strict 下 Anthropic 请求必须带 max_tokens
+    fn max_tokens(&self, request: &Map<String, Value>) -> ProxyResult<()> {
+        for field in ["max_tokens", "max_completion_tokens"] {
+            match request.get(field) {
+                None | Some(Value::Null) => {}
+                Some(value) if value.as_u64().is_some_and(|n| n >= 1) => {}
+                Some(value) => {
+                    return Err(
+                        self.error(field, format!("must be a positive integer, got {}", value))
+                    )
+                }
+            }
+        }
+        if self.strict && self.format == RequestFormat::Anthropic && !request.contains_key("max_tokens")
+        {
+            return Err(self.error("max_tokens", "field is required"));
+        }
+        Ok(())
+    }
+
+    /// temperature 在 0..=2 之间（strict 下 Anthropic 请求为 0..=1），top_p 在 0..=1 之间
+    fn sampling(&self, request: &Map<String, Value>) -> ProxyResult<()> {
+        let max_temperature = if self.strict && self.format == RequestFormat::Anthropic {
+            1.0
+        } else {
+            2.0
+        };
+        for (field, max) in [("temperature", max_temperature), ("top_p", 1.0)] {
+            let Some(value) = request.get(field).filter(|v| !v.is_null()) else {
+                continue;
+            };
+            match value.as_f64() {
+                Some(n) if (0.0..=max).contains(&n) => {}
+                Some(_) => {
+                    return Err(self.error(field, format!("must be between 0 and {}, got {}", max, value)))
+                }
+                None => return Err(self.error(field, format!("must be a number, got {}", value))),
+            }
+        }
+        Ok(())
+    }
+
+    /// 工具的参数 schema 存在时必须是 JSON 对象
+    fn tools(&self, request: &Map<String, Value>) -> ProxyResult<()> {
+        let tools = match request.get("tools") {
+            None | Some(Value::Null) => return Ok(()),
+            Some(Value::Array(tools)) => tools,
+            Some(other) => {
+                return Err(self.error(
+                    "tools",
+                    format!("must be an array, got {}", type_name(other)),
+                ))
+            }
+        };
+        for (i, tool) in tools.iter().enumerate() {
+            let (param, schema) = match self.format {
+                RequestFormat::Anthropic => {
+                    (format!("tools[{}].input_schema", i), tool.get("input_schema"))
+                }
+                RequestFormat::OpenAI => (
+                    format!("tools[{}].function.parameters", i),
+                    tool.get("function").and_then(|f| f.get("parameters")),
+                ),
+            };
+            if let Some(schema) = schema.filter(|s| !s.is_object()) {
+                return Err(self.error(
+                    param,
+                    format!("must be a JSON object, got {}", type_name(schema)),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use serde_json::json;
+
+    fn anthropic_request() -> Value {
+        json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        })
+    }
+
+    /// 校验失败时返回出错的字段和说明
+    fn rejection(request: &Value, format: RequestFormat, mode: ValidationMode) -> (String, String) {
+        match validate(request, format, mode) {
+            Err(ProxyError::Validation { param, message, .. }) => (param, message),
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_request_passes_every_mode() {
+        for mode in [ValidationMode::Strict, ValidationMode::Lenient, ValidationMode::Off] {
+            assert!(validate(&anthropic_request(), RequestFormat::Anthropic, mode).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_off_skips_all_rules() {
+        let request = json!({"messages": [], "max_tokens": 0});
+        assert!(validate(&request, RequestFormat::Anthropic, ValidationMode::Off).is_ok());
+    }
+
+    #[test]
+    fn test_messages_required_and_non_empty() {
+        let mut request = anthropic_request();
+        request["messages"] = json!([]);
+        let (param, message) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(param, "messages");
+        assert!(message.contains("at least one"));
+
+        request["messages"] = json!("Hello");
+        let (_, message) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(message, "must be an array, got string");
+
+        request.as_object_mut().unwrap().remove("messages");
+        let (param, _) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(param, "messages");
+    }
+
+    #[test]
+    fn test_unknown_role() {
+        let mut request = anthropic_request();
+        request["messages"] = json!([
+            {"role": "user", "content": "Hi"},
+            {"role": "bot", "content": "Hello"}
+        ]);
+        let (param, message) =
+            rejection(&request, RequestFormat::Anthropic, ValidationMode::Lenient);
+        assert_eq!(param, "messages[1].role");
+        assert!(message.starts_with("unknown role 'bot'"));
+
+        // system 角色两种 API 中都存在，只在 strict 下按 Anthropic 格式拒绝
+        request["messages"][1]["role"] = json!("system");
+        assert!(validate(&request, RequestFormat::Anthropic, ValidationMode::Lenient).is_ok());
+        assert!(validate(&request, RequestFormat::OpenAI, ValidationMode::Strict).is_ok());
+        let (param, message) =
+            rejection(&request, RequestFormat::Anthropic, ValidationMode::Strict);
+        assert_eq!(param, "messages[1].role");
+        assert_eq!(message, "unknown role 'system', expected one of: user, assistant");
+
+        request["messages"][1] = json!({"content": "Hello"});
+        let (_, message) = rejection(&request, RequestFormat::Anthropic, ValidationMode::Lenient);
+        assert_eq!(message, "field is required");
+    }
+
+    #[test]
+    fn test_strict_anthropic_roles_must_alternate() {
+        let mut request = anthropic_request();
+        request["messages"] = json!([
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello"},
+            {"role": "user", "content": "Question"},
+            {"role": "user", "content": "Another question"}
+        ]);
+        assert!(validate(&request, RequestFormat::Anthropic, ValidationMode::Lenient).is_ok());
+        assert!(validate(&request, RequestFormat::OpenAI, ValidationMode::Strict).is_ok());
+
+        let (param, message) = rejection(&request, RequestFormat::Anthropic, ValidationMode::Strict);
+        assert_eq!(param, "messages[3].role");
+        assert_eq!(message, "expected 'assistant' after 'user'");
+
+        request["messages"][2]["role"] = json!("assistant");
+        let (param, message) = rejection(&request, RequestFormat::Anthropic, ValidationMode::Strict);
+        assert_eq!(param, "messages[2].role");
+        assert_eq!(message, "expected 'user' after 'assistant'");
+    }
+
+    #[test]
+    fn test_max_tokens_must_be_positive() {
+        let mut request = anthropic_request();
+        request["max_tokens"] = json!(0);
+        let (param, message) =
+            rejection(&request, RequestFormat::Anthropic, ValidationMode::Lenient);
+        assert_eq!(param, "max_tokens");
+        assert_eq!(message, "must be a positive integer, got 0");
+
+        request["max_tokens"] = json!(100);
+        request["max_completion_tokens"] = json!(-5);
+        let (param, _) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(param, "max_completion_tokens");
+    }
+
+    #[test]
+    fn test_strict_requires_anthropic_max_tokens() {
+        let mut request = anthropic_request();
+        request.as_object_mut().unwrap().remove("max_tokens");
+        assert!(validate(&request, RequestFormat::Anthropic, ValidationMode::Lenient).is_ok());
+        assert!(validate(&request, RequestFormat::OpenAI, ValidationMode::Strict).is_ok());
+        let (param, _) = rejection(&request, RequestFormat::Anthropic, ValidationMode::Strict);
+        assert_eq!(param, "max_tokens");
+    }
+
+    #[test]
+    fn test_sampling_ranges() {
+        let mut request = anthropic_request();
+        request["temperature"] = json!(1.5);
+        assert!(validate(&request, RequestFormat::Anthropic, ValidationMode::Lenient).is_ok());
+        assert!(validate(&request, RequestFormat::OpenAI, ValidationMode::Strict).is_ok());
+        let (param, message) =
+            rejection(&request, RequestFormat::Anthropic, ValidationMode::Strict);
+        assert_eq!(param, "temperature");
+        assert_eq!(message, "must be between 0 and 1, got 1.5");
+
+        request["temperature"] = json!(3);
+        let (_, message) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(message, "must be between 0 and 2, got 3");
+
+        request["temperature"] = json!("hot");
+        let (_, message) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(message, "must be a number, got \"hot\"");
+
+        request["temperature"] = json!(0.5);
+        request["top_p"] = json!(1.2);
+        let (param, _) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(param, "top_p");
+    }
+
+    #[test]
+    fn test_tool_schema_must_be_object() {
+        let mut request = anthropic_request();
+        request["tools"] = json!([
+            {"name": "ok", "input_schema": {"type": "object"}},
+            {"name": "bad", "input_schema": "object"}
+        ]);
+        let (param, message) =
+            rejection(&request, RequestFormat::Anthropic, ValidationMode::Lenient);
+        assert_eq!(param, "tools[1].input_schema");
+        assert_eq!(message, "must be a JSON object, got string");
+
+        request["tools"] = json!([{"type": "function", "function": {"name": "f", "parameters": []}}]);
+        let (param, _) = rejection(&request, RequestFormat::OpenAI, ValidationMode::Lenient);
+        assert_eq!(param, "tools[0].function.parameters");
+
+        // 没有 input_schema 的内置工具不受影响
+        request["tools"] = json!([{"type": "computer_20241022", "name": "computer"}]);
+        assert!(validate(&request, RequestFormat::Anthropic, ValidationMode::Strict).is_ok());
+    }
+
+    #[test]
+    fn test_context_window_limit() {
+        let request: openai::OpenAIRequest = serde_json::from_value(json!({
+            "model": "gpt-4-0613",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .unwrap();
+        let input_tokens = tokens::estimate(&request.model, &request) as u32;
+        let config = |limit: u32| Config {
+            context_window_limits: crate::config::parse_context_window_limits(&format!("gpt-4={}", limit)),
+            ..crate::test_utils::test_config()
+        };
+
+        assert!(context_window(&crate::test_utils::test_config(), &request, RequestFormat::Anthropic).is_ok());
+        assert!(context_window(&config(input_tokens + 100), &request, RequestFormat::Anthropic).is_ok());
+        match context_window(&config(input_tokens + 99), &request, RequestFormat::Anthropic) {
+            Err(ProxyError::Validation { param, message, .. }) => {
+                assert_eq!(param, "max_tokens");
+                assert_eq!(
+                    message,
+                    format!(
+                        "estimated input tokens ({}) plus max_tokens (100) exceed the {} token context window of 'gpt-4-0613'",
+                        input_tokens,
+                        input_tokens + 99
+                    )
+                );
+            }
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_shape_per_client_format() {
+        let mut request = anthropic_request();
+        request["max_tokens"] = json!(0);
+
+        for (format, has_param) in [(RequestFormat::OpenAI, true), (RequestFormat::Anthropic, false)] {
+            let response = validate(&request, format, ValidationMode::Lenient)
+                .unwrap_err()
+                .into_response();
+            assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+            assert_eq!(
+                body["error"]["message"],
+                "max_tokens: must be a positive integer, got 0"
+            );
+            assert_eq!(body["error"].get("param").is_some(), has_param);
+        }
+    }
+}